@@ -0,0 +1,40 @@
+// Draws coloured lines in world space, used to visualise raycasts and bounding boxes.
+
+struct Globals {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    sun_pos: vec3<f32>,
+    enable_lighting: u32,
+    atlas_size: u32,
+    tile_width: u32,
+    tile_height: u32,
+    tile_padding: u32,
+    current_frame: u32,
+    animated_tile_start: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct VertexInput {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = globals.proj * globals.view * vec4<f32>(in.pos, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,153 @@
+// FXAA 3.11 with the settings of quality preset 12.
+// Smooths the edges of the world in screen space, by blending each pixel on an edge with its
+// neighbour across the edge, depending on how far it is from the ends of the edge.
+
+@group(0) @binding(0)
+var color_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var color_sampler: sampler;
+
+// How much sub-pixel aliasing is removed, from 0 (sharper) to 1 (softer)
+const SUBPIX: f32 = 0.75;
+// The minimum contrast, relative to the brightest neighbour, for a pixel to be on an edge
+const EDGE_THRESHOLD: f32 = 0.166;
+// Dark pixels below this contrast are skipped
+const EDGE_THRESHOLD_MIN: f32 = 0.0833;
+const SEARCH_STEPS: u32 = 5u;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) v_index: u32) -> VertexOutput {
+    // A single triangle covering the whole screen
+    let uv = vec2<f32>(f32((v_index << 1u) & 2u), f32(v_index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // Texture coordinates grow downwards
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    // The colours are sampled in linear space, the square root brings them close to perceptual
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(color_texture, color_sampler, uv, 0.0).rgb);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Distance in pixels of each step of the search for the ends of the edge
+    var search_steps = array<f32, 5>(1.0, 1.5, 2.0, 4.0, 12.0);
+    let texel = 1.0 / vec2<f32>(textureDimensions(color_texture));
+    let uv = in.uv;
+    let color = textureSampleLevel(color_texture, color_sampler, uv, 0.0);
+
+    // "Down" and "left" are towards lower texture coordinates
+    let luma_center = luma(color.rgb);
+    let luma_down = luma_at(uv + vec2<f32>(0.0, -1.0) * texel);
+    let luma_up = luma_at(uv + vec2<f32>(0.0, 1.0) * texel);
+    let luma_left = luma_at(uv + vec2<f32>(-1.0, 0.0) * texel);
+    let luma_right = luma_at(uv + vec2<f32>(1.0, 0.0) * texel);
+
+    let luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+    if luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+        return color;
+    }
+
+    let luma_down_left = luma_at(uv + vec2<f32>(-1.0, -1.0) * texel);
+    let luma_up_right = luma_at(uv + vec2<f32>(1.0, 1.0) * texel);
+    let luma_up_left = luma_at(uv + vec2<f32>(-1.0, 1.0) * texel);
+    let luma_down_right = luma_at(uv + vec2<f32>(1.0, -1.0) * texel);
+
+    let luma_down_up = luma_down + luma_up;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_down_left + luma_up_left;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let luma_right_corners = luma_down_right + luma_up_right;
+    let luma_up_corners = luma_up_right + luma_up_left;
+
+    // Whether the edge runs along the x axis, from the gradients across both axes
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // The edge lies on the side of the pixel with the steepest gradient
+    let luma1 = select(luma_left, luma_down, is_horizontal);
+    let luma2 = select(luma_right, luma_up, is_horizontal);
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let is_1_steepest = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average = 0.5 * (luma2 + luma_center);
+    if is_1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    }
+
+    // Walk along the edge, halfway between the pixel and its neighbour across it
+    var current_uv = uv;
+    if is_horizontal {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv1 = current_uv - offset * search_steps[0];
+    var uv2 = current_uv + offset * search_steps[0];
+    var luma_end1 = luma_at(uv1) - luma_local_average;
+    var luma_end2 = luma_at(uv2) - luma_local_average;
+    var reached1 = abs(luma_end1) >= gradient_scaled;
+    var reached2 = abs(luma_end2) >= gradient_scaled;
+    for (var i = 1u; i < SEARCH_STEPS && !(reached1 && reached2); i++) {
+        if !reached1 {
+            uv1 -= offset * search_steps[i];
+            luma_end1 = luma_at(uv1) - luma_local_average;
+            reached1 = abs(luma_end1) >= gradient_scaled;
+        }
+        if !reached2 {
+            uv2 += offset * search_steps[i];
+            luma_end2 = luma_at(uv2) - luma_local_average;
+            reached2 = abs(luma_end2) >= gradient_scaled;
+        }
+    }
+
+    // The closer the pixel is to an end of the edge, the more it is blended
+    let distance1 = select(current_uv.y - uv1.y, current_uv.x - uv1.x, is_horizontal);
+    let distance2 = select(uv2.y - current_uv.y, uv2.x - current_uv.x, is_horizontal);
+    let is_direction1 = distance1 < distance2;
+    let pixel_offset = -min(distance1, distance2) / (distance1 + distance2) + 0.5;
+    // Only blend if the end is on the same side of the edge as the pixel
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let correct_variation =
+        (select(luma_end2, luma_end1, is_direction1) < 0.0) != is_luma_center_smaller;
+    var final_offset = select(0.0, pixel_offset, correct_variation);
+
+    // Sub-pixel aliasing, e.g. thin lines, is blended from the contrast with the 3x3 average
+    let luma_average = (1.0 / 12.0)
+        * (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners);
+    let sub_pixel_offset1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let sub_pixel_offset2 = (-2.0 * sub_pixel_offset1 + 3.0) * sub_pixel_offset1 * sub_pixel_offset1;
+    final_offset = max(final_offset, sub_pixel_offset2 * sub_pixel_offset2 * SUBPIX);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return textureSampleLevel(color_texture, color_sampler, final_uv, 0.0);
+}
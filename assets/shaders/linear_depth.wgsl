@@ -0,0 +1,51 @@
+struct Globals {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    sun_pos: vec3<f32>,
+    enable_lighting: u32,
+    atlas_size: u32,
+    tile_width: u32,
+    tile_height: u32,
+    tile_padding: u32,
+    current_frame: u32,
+    animated_tile_start: u32,
+    near: f32,
+    far: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+// Only one of the two is bound, depending on the sample count of the depth texture.
+// They are read as plain float textures since GLSL can't load from depth textures.
+@group(0) @binding(1)
+var depth_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var depth_texture_multisampled: texture_multisampled_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) v_index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle covering the whole screen
+    let uv = vec2<f32>(f32((v_index << 1u) & 2u), f32(v_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn linearize(depth: f32) -> f32 {
+    // Inverse of the perspective_lh_no projection of the camera
+    let near = globals.near;
+    let far = globals.far;
+    return 2.0 * near * far / (far + near - depth * (far - near));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let depth = textureLoad(depth_texture, vec2<i32>(position.xy), 0).r;
+    return vec4<f32>(linearize(depth), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main_multisampled(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // The first sample is close enough for a blur
+    let depth = textureLoad(depth_texture_multisampled, vec2<i32>(position.xy), 0).r;
+    return vec4<f32>(linearize(depth), 0.0, 0.0, 1.0);
+}
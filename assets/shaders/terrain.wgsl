@@ -4,18 +4,28 @@ struct Globals {
     sun_pos: vec3<f32>,
     enable_lighting: u32,
     atlas_size: u32,
-    tile_size: u32,
+    tile_width: u32,
+    tile_height: u32,
+    tile_padding: u32,
+    current_frame: u32,
+    animated_tile_start: u32,
+    near: f32,
+    far: f32,
+    reflective_tile: u32,
 };
 
 @group(0) @binding(0)
 var<uniform> globals: Globals;
 
+// Same as atlas::FRAMES_PER_ANIMATION
+const FRAMES_PER_ANIMATION: u32 = 4u;
+
 @group(1) @binding(0)
 var<uniform> chunk_pos: vec2<i32>;
 
 struct VertexInput {
     @builtin(vertex_index) v_index: u32,
-    @location(0) data: u32,
+    @location(0) data: vec4<u32>,
 };
 
 struct VertexOutput {
@@ -23,19 +33,29 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) normal: vec3<i32>,
     @location(2) local_pos: vec3<f32>,
+    @location(3) light: f32,
+    @location(4) ao: f32,
+    @location(5) world_pos: vec3<f32>,
+    @location(6) reflective: u32,
 };
 
-fn calculate_texture_coordinates(v_index: u32, data: u32) -> vec2<f32> {
+fn calculate_texture_coordinates(v_index: u32, data: vec4<u32>) -> vec2<f32> {
     // Calculate the texture coordinates based on the texture id
-    // let texture_id = data & 0x1FFFu; // mask 13 bits
-    // mask 10 bits
-    let texture_id = data & 0x3FFu;
-    let texture_width = globals.tile_size;
-    let texture_height = globals.tile_size;
+    // mask 16 bits
+    var texture_id = data.y & 0xFFFFu;
+    // Each animated tile is followed by its other frames
+    if (texture_id >= globals.animated_tile_start) {
+        texture_id += globals.current_frame % FRAMES_PER_ANIMATION;
+    }
+    let texture_width = globals.tile_width;
+    let texture_height = globals.tile_height;
+    // each tile is followed by transparent padding pixels on both axes
+    let stride_x = texture_width + globals.tile_padding;
+    let stride_y = texture_height + globals.tile_padding;
     // number of columns in the atlas
-    let cols = globals.atlas_size / texture_width; 
-    let pixel_x = f32((texture_id % cols) * texture_width);
-    let pixel_y = f32((texture_id / cols) * texture_height);
+    let cols = globals.atlas_size / stride_x;
+    let pixel_x = f32((texture_id % cols) * stride_x);
+    let pixel_y = f32((texture_id / cols) * stride_y);
 
     switch (v_index % 4u) {
           case 0u: {
@@ -56,23 +76,54 @@ fn calculate_texture_coordinates(v_index: u32, data: u32) -> vec2<f32> {
       }
 }
 
-fn unpack_vertex_data(data: u32) -> vec3<f32> {
-    let x = (data >> 27u) & 0x1Fu;
-    let y = (data >> 18u) & 0x1FFu;
-    let z = (data >> 13u) & 0x1Fu;
+fn unpack_position(bits: u32) -> vec3<f32> {
+    // 10 bits per axis
+    let x = bits & 0x3FFu;
+    let y = (bits >> 10u) & 0x3FFu;
+    let z = (bits >> 20u) & 0x3FFu;
     return vec3<f32>(f32(x), f32(y), f32(z));
 }
 
-fn unpack_normals(data: u32) -> vec3<i32> {
-    let x = (data >> 12u) & 0x1u;
-    let y = (data >> 11u) & 0x1u;
-    let z = (data >> 10u) & 0x1u;
-    // map range [0, 1] to [-1, 1]
-    return vec3<i32>(
-        i32(x) * 2 - 1,
-        i32(y) * 2 - 1,
-        i32(z) * 2 - 1
-    );
+fn unpack_vertex_data(data: vec4<u32>) -> vec3<f32> {
+    // Blended toward the morph target during level of detail transitions
+    return mix(unpack_position(data.x), unpack_position(data.z), bitcast<f32>(data.w));
+}
+
+fn unpack_normals(data: vec4<u32>) -> vec3<i32> {
+    // 3 bits normal index, same order as TerrainVertex::NORMALS
+    let index = (data.y >> 16u) & 0x7u;
+    switch (index) {
+        case 0u: {
+            return vec3<i32>(0, 0, 1);
+        }
+        case 1u: {
+            return vec3<i32>(0, 0, -1);
+        }
+        case 2u: {
+            return vec3<i32>(1, 0, 0);
+        }
+        case 3u: {
+            return vec3<i32>(-1, 0, 0);
+        }
+        case 4u: {
+            return vec3<i32>(0, 1, 0);
+        }
+        default: {
+            return vec3<i32>(0, -1, 0);
+        }
+    }
+}
+
+fn unpack_light(data: vec4<u32>) -> f32 {
+    // 8 bits light level, from 0 to LightMap::MAX_LIGHT
+    let level = (data.y >> 24u) & 0xFFu;
+    return f32(level) / 15.0;
+}
+
+fn unpack_ao(data: vec4<u32>) -> f32 {
+    // 5 bits ambient occlusion, the number of solid blocks around the corner
+    let occlusion = (data.y >> 19u) & 0x1Fu;
+    return 1.0 - f32(min(occlusion, 3u)) * 0.2;
 }
 
 @vertex
@@ -89,6 +140,11 @@ fn vs_main(input: VertexInput) -> VertexOutput {
     output.tex_coords = calculate_texture_coordinates(input.v_index, input.data);
     output.normal = unpack_normals(input.data);
     output.local_pos = local_pos;
+    output.light = unpack_light(input.data);
+    output.ao = unpack_ao(input.data);
+    output.world_pos = world_pos;
+    // Compared before the animation frame is added, so every frame of the tile reflects
+    output.reflective = select(0u, 1u, (input.data.y & 0xFFFFu) == globals.reflective_tile);
     return output;
 }
 
@@ -96,19 +152,75 @@ fn vs_main(input: VertexInput) -> VertexOutput {
 var texture: texture_2d<f32>;
 @group(0) @binding(2)
 var texture_sampler: sampler;
+@group(0) @binding(3)
+var t_cube: texture_cube<f32>;
+@group(0) @binding(4)
+var s_cube: sampler;
+// Sampled with texture_sampler, laid out like the atlas
+@group(0) @binding(5)
+var normal_texture: texture_2d<f32>;
+
+// How much of the environment map reflective blocks show over their texture
+const REFLECTIVITY: f32 = 0.4;
+
+// The view matrix is a rotation followed by a translation, undoing both gives the camera position
+fn camera_position() -> vec3<f32> {
+    let rotation = mat3x3<f32>(globals.view[0].xyz, globals.view[1].xyz, globals.view[2].xyz);
+    return -(transpose(rotation) * globals.view[3].xyz);
+}
+
+// The environment seen in a mirror-like surface, for reflective blocks such as water
+fn environment_reflection(view_dir: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    return textureSample(t_cube, s_cube, reflect(-view_dir, normal)).rgb;
+}
+
+// The direction the texture U coordinate grows along on a face, same corner order as the mesher
+fn face_tangent(normal: vec3<i32>) -> vec3<f32> {
+    if (normal.z > 0) {
+        return vec3<f32>(-1.0, 0.0, 0.0);
+    } else if (normal.z < 0) {
+        return vec3<f32>(1.0, 0.0, 0.0);
+    } else if (normal.x > 0) {
+        return vec3<f32>(0.0, 0.0, 1.0);
+    } else if (normal.x < 0) {
+        return vec3<f32>(0.0, 0.0, -1.0);
+    } else if (normal.y < 0) {
+        return vec3<f32>(0.0, 0.0, 1.0);
+    }
+    return vec3<f32>(1.0, 0.0, 0.0);
+}
+
+// Perturbs the face normal with the normal atlas
+fn mapped_normal(normal: vec3<i32>, tex_coords: vec2<f32>) -> vec3<f32> {
+    let n = vec3<f32>(normal);
+    let t = face_tangent(normal);
+    // The texture V coordinate, like the Y of the normal maps, grows downwards the face
+    let b = cross(n, t);
+    let tbn = mat3x3<f32>(t, b, n);
+    let sampled = textureSample(normal_texture, texture_sampler, tex_coords).xyz * 2.0 - 1.0;
+    return normalize(tbn * sampled);
+}
 
 @fragment
 fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
     let obj_color = textureSample(texture, texture_sampler, input.tex_coords);
+    // Sampled before any early return, derivatives need uniform control flow
+    let normal = mapped_normal(input.normal, input.tex_coords);
+    let view_dir = normalize(camera_position() - input.world_pos);
+    let reflection = environment_reflection(view_dir, normal);
+    let color = mix(obj_color.xyz, reflection, f32(input.reflective) * REFLECTIVITY);
     if (globals.enable_lighting == 0u) {
-        return obj_color;
+        return vec4<f32>(color, obj_color.w);
     }
     let ambient_factor = 0.36;
     let light_color = vec3<f32>(1.0, 1.0, 1.0);
     let ambient = ambient_factor * light_color;
     let light_dir = normalize(globals.sun_pos - input.local_pos);
-    let diff = max(dot(vec3<f32>(input.normal), light_dir), 0.0);
+    let diff = max(dot(normal, light_dir), 0.0);
     let diffuse = diff * light_color;
-    let result = (diffuse + ambient) * obj_color.xyz;
+    // Sunlight and torch light are both stored in the light level,
+    // which darkens the faces they don't reach
+    let light_level = max(input.light, 0.05);
+    let result = (diffuse + ambient) * light_level * input.ao * color;
     return vec4<f32>(result, obj_color.w);
 }
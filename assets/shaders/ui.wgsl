@@ -0,0 +1,36 @@
+// Draws flat HUD elements on top of the scene.
+// Vertex positions are in pixels, with the origin in the top left corner of the screen.
+
+@group(0) @binding(0)
+var<uniform> screen_size: vec4<f32>;
+
+struct VertexInput {
+    @location(0) pos: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    // Pixels to normalized device coordinates, flipping y so it points up
+    let ndc = vec2<f32>(
+        in.pos.x / screen_size.x * 2.0 - 1.0,
+        1.0 - in.pos.y / screen_size.y * 2.0,
+    );
+    out.position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -6,22 +6,158 @@ pub enum BlockId {
     Dirt,
     Grass,
     Stone,
+    Water,
+    Sand,
+    Gravel,
+    Torch,
 }
 
 impl BlockId {
+    /// Every block, in declaration order.
+    pub const ALL: [BlockId; 8] = [
+        BlockId::Air,
+        BlockId::Dirt,
+        BlockId::Grass,
+        BlockId::Stone,
+        BlockId::Water,
+        BlockId::Sand,
+        BlockId::Gravel,
+        BlockId::Torch,
+    ];
+
     pub const fn is_air(self) -> bool {
         matches!(self, BlockId::Air)
     }
+
+    /// Whether entities collide with the block.
+    pub const fn is_solid(self) -> bool {
+        !matches!(self, BlockId::Air | BlockId::Water | BlockId::Torch)
+    }
+
+    /// The light level emitted by the block, 0 for blocks that don't glow.
+    pub const fn light_emission(self) -> u8 {
+        match self {
+            BlockId::Torch => 14,
+            _ => 0,
+        }
+    }
+
+    /// Whether light stops at this block instead of passing through it.
+    pub const fn blocks_light(self) -> bool {
+        !matches!(self, BlockId::Air | BlockId::Torch)
+    }
+
+    /// The human readable name of the block.
+    pub const fn name(self) -> &'static str {
+        match self {
+            BlockId::Air => "Air",
+            BlockId::Dirt => "Dirt",
+            BlockId::Grass => "Grass",
+            BlockId::Stone => "Stone",
+            BlockId::Water => "Water",
+            BlockId::Sand => "Sand",
+            BlockId::Gravel => "Gravel",
+            BlockId::Torch => "Torch",
+        }
+    }
+
+    /// The identifier of the block in commands, the lowercase [`BlockId::name`].
+    pub const fn to_str(self) -> &'static str {
+        match self {
+            BlockId::Air => "air",
+            BlockId::Dirt => "dirt",
+            BlockId::Grass => "grass",
+            BlockId::Stone => "stone",
+            BlockId::Water => "water",
+            BlockId::Sand => "sand",
+            BlockId::Gravel => "gravel",
+            BlockId::Torch => "torch",
+        }
+    }
+
+    /// Looks up a block by name, ignoring case.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|id| id.to_str().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The name given to [`BlockId::from_str`] is not a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownBlockError(pub String);
+
+impl std::fmt::Display for UnknownBlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown block `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownBlockError {}
+
+impl std::str::FromStr for BlockId {
+    type Err = UnknownBlockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BlockId::from_str(s).ok_or_else(|| UnknownBlockError(s.to_owned()))
+    }
 }
 
 impl From<&str> for BlockId {
     fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "air" => BlockId::Air,
-            "dirt" => BlockId::Dirt,
-            "grass" => BlockId::Grass,
-            "stone" => BlockId::Stone,
-            _ => panic!("Unknown block id: {}", s),
+        match BlockId::from_str(s) {
+            Some(id) => id,
+            None => panic!("Unknown block id: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockId, UnknownBlockError};
+
+    #[test]
+    pub fn name_is_const() {
+        const AIR: &str = BlockId::Air.name();
+        assert_eq!(AIR, "Air");
+    }
+
+    #[test]
+    pub fn every_block_has_a_name() {
+        for id in BlockId::ALL {
+            assert!(!id.name().is_empty());
+            // Names can be parsed back
+            assert_eq!(BlockId::from(id.name()), id);
+        }
+    }
+
+    #[test]
+    pub fn parse_ignores_case() {
+        assert_eq!("STONE".parse::<BlockId>(), Ok(BlockId::Stone));
+        assert_eq!("stone".parse::<BlockId>(), Ok(BlockId::Stone));
+        assert_eq!(BlockId::from_str("Dirt"), Some(BlockId::Dirt));
+        assert_eq!(
+            "bedrock".parse::<BlockId>(),
+            Err(UnknownBlockError("bedrock".to_string()))
+        );
+        assert_eq!(BlockId::from_str(""), None);
+    }
+
+    #[test]
+    pub fn to_str_agrees_with_name() {
+        for id in BlockId::ALL {
+            assert!(id.to_str().eq_ignore_ascii_case(id.name()));
+            assert_eq!(BlockId::from_str(id.to_str()), Some(id));
+        }
+    }
+
+    #[test]
+    pub fn only_torches_emit_light() {
+        assert_eq!(BlockId::Torch.light_emission(), 14);
+        assert!(!BlockId::Torch.blocks_light());
+        for id in BlockId::ALL.into_iter().filter(|id| *id != BlockId::Torch) {
+            assert_eq!(id.light_emission(), 0);
         }
     }
 }
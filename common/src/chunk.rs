@@ -1,74 +1,163 @@
-use noise::{NoiseFn, Perlin};
+use noise::NoiseFn;
 use vek::{Vec2, Vec3};
 
-use crate::block::BlockId;
+use crate::{
+    block::BlockId,
+    dir::{Axis, Direction},
+};
 
-pub struct Chunk {
-    blocks: [BlockId; 16 * 256 * 16],
+/// A column of 16x`H`x16 blocks.
+///
+/// The height is a const generic so worlds can trade depth for memory,
+/// [`StandardChunk`] being the height the game is played with.
+#[derive(Clone)]
+pub struct Chunk<const H: usize = 256> {
+    /// Boxed so moving a chunk around only copies a pointer.
+    /// Always holds [`Chunk::VOLUME`] blocks.
+    blocks: Box<[BlockId]>,
 }
 
+/// The chunks of the default world, 256 blocks high.
+pub type StandardChunk = Chunk<256>;
+
 use rayon::{
     iter::{IndexedParallelIterator, IntoParallelRefMutIterator},
     prelude::ParallelIterator,
 };
 
-pub fn compute_height(generator: &noise::BasicMulti<Perlin>, world_x: f64, world_z: f64) -> i32 {
+pub fn compute_height<N: NoiseFn<f64, 2>>(
+    generator: &N,
+    world_x: f64,
+    world_z: f64,
+    min_height: i32,
+    max_height: i32,
+) -> i32 {
     let height = generator.get([world_x, world_z]);
     // Noise values are in range [-1, 1]
     // then adding 1 will transform them to [0, 2]
     // Dividing each of the new values by 2 will re-scale them to the final range [0,1]
-    let height = height + 1.0 / 2.0;
-    // Now we scale it to appropiate chunk height
-    (height * Chunk::SIZE.y as f64) as i32
+    let height = (height.clamp(-1.0, 1.0) + 1.0) / 2.0;
+    // Now we scale it to the requested height range
+    min_height + (height * (max_height - min_height) as f64) as i32
+}
+
+/// Computes the surface height of the terrain column at the world position (`x`, `z`),
+/// without generating its chunk.
+pub fn column_height<N: NoiseFn<f64, 2>>(
+    generator: &N,
+    x: i32,
+    z: i32,
+    min_height: i32,
+    max_height: i32,
+) -> i32 {
+    let noise_x = x as f64 / 330.0;
+    let noise_z = z as f64 / 400.0;
+    compute_height(generator, noise_x, noise_z, min_height, max_height)
 }
 
-impl Chunk {
-    pub const SIZE: Vec3<usize> = Vec3::new(16, 256, 16);
+/// The maximum number of blocks replaced by [`Chunk::flood_fill`].
+pub const MAX_FILL_BLOCKS: usize = 65_536;
+
+/// The most distinct blocks a chunk can have to be encoded by [`Chunk::to_palette`],
+/// as many as a 4-bit index can address.
+pub const MAX_PALETTE_LEN: usize = 16;
+
+impl<const H: usize> Chunk<H> {
+    pub const HEIGHT: usize = H;
+    pub const SIZE: Vec3<usize> = Vec3::new(16, H, 16);
+    /// The number of blocks in a chunk.
+    pub const VOLUME: usize = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
 
     pub fn flat(id: BlockId) -> Self {
         Self {
-            blocks: [id; Self::SIZE.x * Self::SIZE.y * Self::SIZE.z],
+            blocks: Self::filled(id),
         }
     }
 
-    pub fn generate(generator: &noise::BasicMulti<Perlin>, offset: Vec2<i32>) -> Self {
+    /// Allocates the blocks directly on the heap, as a 64KiB array could overflow the stack.
+    fn filled(id: BlockId) -> Box<[BlockId]> {
+        vec![id; Self::VOLUME].into_boxed_slice()
+    }
+
+    /// Generates the terrain of the chunk at `offset` from a 2D height noise.
+    ///
+    /// The surface is placed between `min_height` and `max_height`.
+    pub fn generate<N: NoiseFn<f64, 2> + Sync>(
+        generator: &N,
+        offset: Vec2<i32>,
+        min_height: i32,
+        max_height: i32,
+    ) -> Self {
         let world_x = (offset.x * Self::SIZE.x as i32) as f64;
         let world_z = (offset.y * Self::SIZE.z as i32) as f64;
 
-        let mut blocks = [BlockId::Air; 16 * 256 * 16];
+        let mut blocks = Self::filled(BlockId::Air);
 
         blocks.par_iter_mut().enumerate().for_each(|(id, block)| {
             let x = id % Self::SIZE.x;
             let y = (id / Self::SIZE.x) % Self::SIZE.y;
             let z = (id / (Self::SIZE.x * Self::SIZE.y)) % Self::SIZE.z;
 
-            let noise_x = (world_x + x as f64) / 330.0;
-            let noise_z = (world_z + z as f64) / 400.0;
-            let height = compute_height(generator, noise_x, noise_z);
+            let world_x = world_x as i32 + x as i32;
+            let world_z = world_z as i32 + z as i32;
+            let height = column_height(generator, world_x, world_z, min_height, max_height);
+            let stone_height =
+                Self::stone_height(generator, world_x, world_z, min_height, max_height);
 
-            let offset = 700.0;
-            let noise_x = (world_x + x as f64) / offset;
-            let noise_z = (world_z + z as f64) / offset;
-            let stone_height = compute_height(generator, noise_x, noise_z);
-            let stone_height = ((stone_height as f32) * 0.7) as i32;
+            *block = Self::terrain_block(y as i32, height, stone_height);
+        });
 
-            let y = y as i32;
+        Self { blocks }
+    }
 
-            if y == height {
-                *block = BlockId::Grass
-            } else if y < height && y > stone_height {
-                *block = BlockId::Dirt;
-                if y >= 255 {
-                    *block = BlockId::Grass;
-                }
-            } else if y < stone_height {
-                *block = BlockId::Stone
+    /// Generates the terrain of the single column at the world position (`world_x`, `world_z`),
+    /// from the bottom of the chunk to the top.
+    ///
+    /// Matches the column [`Chunk::generate`] produces at the same position.
+    pub fn generate_column<N: NoiseFn<f64, 2>>(
+        generator: &N,
+        world_x: i32,
+        world_z: i32,
+        min_height: i32,
+        max_height: i32,
+    ) -> Vec<BlockId> {
+        let height = column_height(generator, world_x, world_z, min_height, max_height);
+        let stone_height = Self::stone_height(generator, world_x, world_z, min_height, max_height);
+        (0..H as i32)
+            .map(|y| Self::terrain_block(y, height, stone_height))
+            .collect()
+    }
+
+    /// The height below which the column at (`world_x`, `world_z`) is made of stone.
+    fn stone_height<N: NoiseFn<f64, 2>>(
+        generator: &N,
+        world_x: i32,
+        world_z: i32,
+        min_height: i32,
+        max_height: i32,
+    ) -> i32 {
+        let offset = 700.0;
+        let noise_x = world_x as f64 / offset;
+        let noise_z = world_z as f64 / offset;
+        let stone_height = compute_height(generator, noise_x, noise_z, min_height, max_height);
+        ((stone_height as f32) * 0.7) as i32
+    }
+
+    /// The block at height `y` of a column whose surface is at `height`.
+    fn terrain_block(y: i32, height: i32, stone_height: i32) -> BlockId {
+        if y == height {
+            BlockId::Grass
+        } else if y < height && y > stone_height {
+            if y >= H as i32 - 1 {
+                BlockId::Grass
             } else {
-                *block = BlockId::Air
+                BlockId::Dirt
             }
-        });
-
-        Self { blocks }
+        } else if y < stone_height {
+            BlockId::Stone
+        } else {
+            BlockId::Air
+        }
     }
 
     pub fn index_of(pos: Vec3<i32>) -> Option<usize> {
@@ -88,6 +177,45 @@ impl Chunk {
         Self::index_of(pos).map(|idx| self.blocks[idx])
     }
 
+    /// Replaces the block at `pos` and returns the previous one.
+    ///
+    /// Returns `None` if the position is out of bounds.
+    pub fn set(&mut self, pos: Vec3<i32>, id: BlockId) -> Option<BlockId> {
+        let idx = Self::index_of(pos)?;
+        Some(std::mem::replace(&mut self.blocks[idx], id))
+    }
+
+    /// Replaces every block connected to `start` that has the same id as it with `new_id`.
+    ///
+    /// Blocks are connected through their faces. At most [`MAX_FILL_BLOCKS`] are replaced.
+    /// Returns the number of replaced blocks.
+    pub fn flood_fill(&mut self, start: Vec3<i32>, new_id: BlockId) -> usize {
+        let Some(target) = self.get(start) else {
+            return 0;
+        };
+        if target == new_id {
+            return 0;
+        }
+        // Replacing blocks as they are pushed marks them as visited
+        self.set(start, new_id);
+        let mut count = 1;
+        let mut stack = vec![start];
+        while let Some(pos) = stack.pop() {
+            for dir in Direction::ALL {
+                if count >= MAX_FILL_BLOCKS {
+                    return count;
+                }
+                let next = pos + dir.vec();
+                if self.get(next) == Some(target) {
+                    self.set(next, new_id);
+                    count += 1;
+                    stack.push(next);
+                }
+            }
+        }
+        count
+    }
+
     pub fn within_bounds(pos: Vec3<i32>) -> bool {
         !Self::out_of_bounds(pos)
     }
@@ -99,6 +227,159 @@ impl Chunk {
             || pos.z >= Self::SIZE.z as i32
     }
 
+    /// The blocks of the column at `(x, z)`, from the bottom of the chunk to the top.
+    ///
+    /// Returns `None` if the column is out of bounds.
+    pub fn column(&self, x: i32, z: i32) -> Option<Vec<BlockId>> {
+        (0..Self::SIZE.y as i32)
+            .map(|y| self.get(Vec3::new(x, y, z)))
+            .collect()
+    }
+
+    /// Replaces the blocks of the column at `(x, z)`, starting from the bottom of the chunk.
+    ///
+    /// Blocks past the top of the chunk are ignored.
+    pub fn set_column(&mut self, x: i32, z: i32, column: &[BlockId]) {
+        for (y, id) in column.iter().enumerate() {
+            self.set(Vec3::new(x, y as i32, z), *id);
+        }
+    }
+
+    /// The height of the highest non-air block of the column at `(x, z)`.
+    ///
+    /// Returns `None` if the column is empty or out of bounds.
+    pub fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        (0..Self::SIZE.y as i32)
+            .rev()
+            .find(|y| self.get(Vec3::new(x, *y, z)).is_some_and(|id| !id.is_air()))
+    }
+
+    /// Returns a copy of this chunk rotated 90° around `axis`, through the centre of the chunk.
+    ///
+    /// # Panics
+    ///
+    /// Rotating around [`Axis::X`] or [`Axis::Z`] swaps the height of the chunk with one
+    /// of its sides, so it panics unless the chunk is a cube.
+    pub fn rotate_90(&self, axis: Axis) -> Self {
+        let size = Self::SIZE.map(|x| x as i32);
+        if axis != Axis::Y {
+            assert_eq!(
+                H,
+                Self::SIZE.x,
+                "Only cubic chunks can be rotated around {axis:?}"
+            );
+        }
+        let mut rotated = Self::flat(BlockId::Air);
+        for pos in self.iter() {
+            let Vec3 { x, y, z } = pos;
+            let source = match axis {
+                Axis::X => Vec3::new(x, z, size.y - 1 - y),
+                Axis::Y => Vec3::new(z, y, size.x - 1 - x),
+                Axis::Z => Vec3::new(y, size.x - 1 - x, z),
+            };
+            if let Some(id) = self.get(source) {
+                rotated.set(pos, id);
+            }
+        }
+        rotated
+    }
+
+    /// Returns a copy of this chunk rotated 180° around `axis`.
+    ///
+    /// See [`Chunk::rotate_90`].
+    pub fn rotate_180(&self, axis: Axis) -> Self {
+        self.rotate_90(axis).rotate_90(axis)
+    }
+
+    /// Returns a copy of this chunk rotated 270° around `axis`.
+    ///
+    /// See [`Chunk::rotate_90`].
+    pub fn rotate_270(&self, axis: Axis) -> Self {
+        self.rotate_180(axis).rotate_90(axis)
+    }
+
+    /// Returns a copy of this chunk flipped along `axis`, e.g. left and right are swapped
+    /// by [`Axis::X`].
+    pub fn mirror(&self, axis: Axis) -> Self {
+        let size = Self::SIZE.map(|x| x as i32);
+        let mut mirrored = Self::flat(BlockId::Air);
+        for pos in self.iter() {
+            let mut source = pos;
+            source[axis as usize] = size[axis as usize] - 1 - pos[axis as usize];
+            if let Some(id) = self.get(source) {
+                mirrored.set(pos, id);
+            }
+        }
+        mirrored
+    }
+
+    /// Returns a copy of this chunk turned `rotation` quarter-turns around [`Axis::Y`],
+    /// then mirrored along [`Axis::X`] if `mirror_x` is set.
+    ///
+    /// Gives the 8 orientations a structure can be placed in while staying upright.
+    pub fn oriented(&self, rotation: u8, mirror_x: bool) -> Self {
+        let rotated = match rotation % 4 {
+            0 => self.clone(),
+            1 => self.rotate_90(Axis::Y),
+            2 => self.rotate_180(Axis::Y),
+            _ => self.rotate_270(Axis::Y),
+        };
+        if mirror_x {
+            rotated.mirror(Axis::X)
+        } else {
+            rotated
+        }
+    }
+
+    /// Counts how many blocks of the given type are in this chunk.
+    pub fn count_blocks_of(&self, id: BlockId) -> u32 {
+        self.blocks.iter().filter(|block| **block == id).count() as u32
+    }
+
+    /// Whether every block of this chunk has the same type.
+    pub fn is_uniform(&self) -> bool {
+        let first = self.blocks[0];
+        self.blocks.iter().all(|block| *block == first)
+    }
+
+    /// Encodes the chunk as a palette of its distinct blocks and a 4-bit palette index
+    /// per block, packed two per byte with the first block in the low bits.
+    ///
+    /// Returns `None` if the chunk has more than [`MAX_PALETTE_LEN`] distinct blocks.
+    pub fn to_palette(&self) -> Option<(Vec<BlockId>, Vec<u8>)> {
+        let mut palette = Vec::new();
+        let mut indices = vec![0; Self::VOLUME / 2];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let index = match palette.iter().position(|id| id == block) {
+                Some(index) => index,
+                None if palette.len() == MAX_PALETTE_LEN => return None,
+                None => {
+                    palette.push(*block);
+                    palette.len() - 1
+                },
+            };
+            indices[i / 2] |= (index as u8) << (i % 2 * 4);
+        }
+        Some((palette, indices))
+    }
+
+    /// Decodes a chunk encoded by [`Chunk::to_palette`].
+    ///
+    /// # Panics
+    /// If `indices` does not hold an index for every block,
+    /// or an index is outside of the `palette`.
+    pub fn from_palette(palette: &[BlockId], indices: &[u8]) -> Self {
+        assert_eq!(
+            indices.len(),
+            Self::VOLUME / 2,
+            "Expected two palette indices per byte"
+        );
+        let blocks = (0..Self::VOLUME)
+            .map(|i| palette[((indices[i / 2] >> (i % 2 * 4)) & 0xF) as usize])
+            .collect();
+        Self { blocks }
+    }
+
     pub fn iter(&self) -> ChunkIter {
         ChunkIter {
             index: 0,
@@ -107,7 +388,7 @@ impl Chunk {
     }
 }
 
-pub fn compress(c: &Chunk) -> Vec<(BlockId, u32)> {
+pub fn compress<const H: usize>(c: &Chunk<H>) -> Vec<(BlockId, u32)> {
     let mut compressed = Vec::with_capacity(600);
     let mut current_block = c.blocks[0];
     let mut count = 1;
@@ -126,8 +407,8 @@ pub fn compress(c: &Chunk) -> Vec<(BlockId, u32)> {
     compressed
 }
 
-pub fn decompress(compressed: &[(BlockId, u32)]) -> Chunk {
-    let mut blocks = [BlockId::Air; 16 * 256 * 16];
+pub fn decompress<const H: usize>(compressed: &[(BlockId, u32)]) -> Chunk<H> {
+    let mut blocks = Chunk::<H>::filled(BlockId::Air);
     let mut index = 0;
     for (block, count) in compressed {
         for _ in 0..*count {
@@ -138,6 +419,88 @@ pub fn decompress(compressed: &[(BlockId, u32)]) -> Chunk {
     Chunk { blocks }
 }
 
+/// Chunks stacked on top of each other, for worlds taller than a single chunk.
+#[derive(Clone)]
+pub struct ChunkColumn<const H: usize = 256> {
+    /// From the bottom to the top of the column.
+    pub sections: Vec<Chunk<H>>,
+    /// The vertical chunk position of the bottom section, in chunks.
+    pub world_chunk_y_offset: i32,
+}
+
+impl<const H: usize> ChunkColumn<H> {
+    pub fn new(sections: Vec<Chunk<H>>, world_chunk_y_offset: i32) -> Self {
+        Self {
+            sections,
+            world_chunk_y_offset,
+        }
+    }
+
+    /// A column of `count` sections filled with `id`, starting at the world height 0.
+    pub fn flat(id: BlockId, count: usize) -> Self {
+        Self::new(vec![Chunk::flat(id); count], 0)
+    }
+
+    /// The world height of the bottom of the column.
+    pub fn min_y(&self) -> i32 {
+        self.world_chunk_y_offset * H as i32
+    }
+
+    /// The world height above the top of the column.
+    pub fn max_y(&self) -> i32 {
+        self.min_y() + (self.sections.len() * H) as i32
+    }
+
+    /// Returns the section holding `world_y` and the height within it.
+    fn section_of(&self, world_y: i32) -> Option<(usize, i32)> {
+        let section = world_y.div_euclid(H as i32) - self.world_chunk_y_offset;
+        let section = usize::try_from(section).ok()?;
+        (section < self.sections.len()).then_some((section, world_y.rem_euclid(H as i32)))
+    }
+
+    /// Returns the block at the world height `world_y`, `None` above or below the column
+    /// or if `x` or `z` are outside of the chunk.
+    pub fn get_block(&self, world_y: i32, x: u8, z: u8) -> Option<BlockId> {
+        let (section, y) = self.section_of(world_y)?;
+        self.sections[section].get(Vec3::new(x as i32, y, z as i32))
+    }
+
+    /// Replaces the block at the world height `world_y` and returns the previous one.
+    pub fn set_block(&mut self, world_y: i32, x: u8, z: u8, id: BlockId) -> Option<BlockId> {
+        let (section, y) = self.section_of(world_y)?;
+        self.sections[section].set(Vec3::new(x as i32, y, z as i32), id)
+    }
+
+    /// The section from the world height 0 up to `H`, which is the part of the column that
+    /// gets lit, meshed and sent to clients.
+    pub fn ground(&self) -> Option<&Chunk<H>> {
+        let (section, _) = self.section_of(0)?;
+        Some(&self.sections[section])
+    }
+
+    /// Iterates over every block of the column from the bottom section up,
+    /// with `y` being the world height.
+    pub fn iter_all(&self) -> impl Iterator<Item = (Vec3<i32>, BlockId)> + '_ {
+        self.sections
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, section)| {
+                let min_y = self.min_y() + (i * H) as i32;
+                section.iter().filter_map(move |pos| {
+                    let id = section.get(pos)?;
+                    Some((pos + Vec3::unit_y() * min_y, id))
+                })
+            })
+    }
+}
+
+impl<const H: usize> From<Chunk<H>> for ChunkColumn<H> {
+    /// A column made of a single section starting at the world height 0.
+    fn from(chunk: Chunk<H>) -> Self {
+        Self::new(vec![chunk], 0)
+    }
+}
+
 pub struct ChunkIter {
     index: u32,
     size: Vec3<u32>,
@@ -161,41 +524,437 @@ impl Iterator for ChunkIter {
 
 #[cfg(test)]
 mod tests {
-    use vek::Vec3;
+    use noise::NoiseFn;
+    use vek::{Vec2, Vec3};
 
     use crate::{
         block::BlockId,
-        chunk::{compress, Chunk},
+        chunk::{compress, decompress, Chunk, ChunkColumn, StandardChunk, MAX_PALETTE_LEN},
+        dir::Axis,
     };
 
+    type ShallowChunk = Chunk<64>;
+
+    #[test]
+    pub fn block_array_matches_chunk_size() {
+        assert_eq!(StandardChunk::SIZE.product(), StandardChunk::VOLUME);
+        assert_eq!(
+            StandardChunk::flat(BlockId::Air).blocks.len(),
+            StandardChunk::VOLUME
+        );
+        assert_eq!(ShallowChunk::VOLUME, 16 * 64 * 16);
+        assert_eq!(
+            ShallowChunk::flat(BlockId::Air).blocks.len(),
+            ShallowChunk::VOLUME
+        );
+        // Only the pointer to the blocks is stored inline
+        assert_eq!(
+            std::mem::size_of::<StandardChunk>(),
+            std::mem::size_of::<Box<[BlockId]>>()
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    pub fn bench_allocate_100_chunks() {
+        let start = std::time::Instant::now();
+        let chunks: Vec<_> = (0..100)
+            .map(|_| StandardChunk::flat(BlockId::Stone))
+            .collect();
+        let elapsed = start.elapsed();
+        assert_eq!(chunks.len(), 100);
+        eprintln!("100 chunks: {:?} ({:?}/chunk)", elapsed, elapsed / 100);
+    }
+
+    /// Noise that returns the same value everywhere, giving a flat terrain.
+    struct ConstantNoise(f64);
+
+    impl NoiseFn<f64, 2> for ConstantNoise {
+        fn get(&self, _point: [f64; 2]) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    pub fn surface_height_finds_highest_block() {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        assert_eq!(chunk.surface_height(3, 4), None);
+        chunk.set(Vec3::new(3, 10, 4), BlockId::Stone);
+        chunk.set(Vec3::new(3, 42, 4), BlockId::Grass);
+        assert_eq!(chunk.surface_height(3, 4), Some(42));
+        assert_eq!(chunk.surface_height(4, 4), None);
+        assert_eq!(chunk.surface_height(16, 4), None);
+    }
+
+    #[test]
+    pub fn constant_noise_generates_flat_terrain() {
+        // A noise value of 0 puts the surface halfway through the height range
+        let chunk = StandardChunk::generate(&ConstantNoise(0.0), Vec2::new(3, -7), 0, 256);
+        for x in 0..16 {
+            for z in 0..16 {
+                assert_eq!(chunk.get(Vec3::new(x, 128, z)), Some(BlockId::Grass));
+                assert_eq!(chunk.get(Vec3::new(x, 129, z)), Some(BlockId::Air));
+                assert_eq!(chunk.get(Vec3::new(x, 100, z)), Some(BlockId::Dirt));
+                assert_eq!(chunk.get(Vec3::new(x, 10, z)), Some(BlockId::Stone));
+            }
+        }
+        assert_eq!(chunk.count_blocks_of(BlockId::Grass), 16 * 16);
+    }
+
+    #[test]
+    pub fn noise_range_maps_to_height_range() {
+        for (noise, height) in [(-1.0, 40), (0.0, 80), (1.0, 120), (3.0, 120)] {
+            let chunk = StandardChunk::generate(&ConstantNoise(noise), Vec2::zero(), 40, 120);
+            assert_eq!(chunk.surface_height(0, 0), Some(height));
+        }
+    }
+
+    #[test]
+    pub fn generated_columns_match_the_chunk() {
+        let noise = noise::Perlin::new(88);
+        let offset = Vec2::new(2, -3);
+        let chunk = StandardChunk::generate(&noise, offset, 0, 256);
+        let mut copy = StandardChunk::flat(BlockId::Air);
+        for x in 0..16 {
+            for z in 0..16 {
+                let column = StandardChunk::generate_column(
+                    &noise,
+                    offset.x * 16 + x,
+                    offset.y * 16 + z,
+                    0,
+                    256,
+                );
+                assert_eq!(chunk.column(x, z), Some(column.clone()));
+                copy.set_column(x, z, &column);
+            }
+        }
+        assert_eq!(copy.blocks, chunk.blocks);
+        assert_eq!(chunk.column(16, 0), None);
+    }
+
+    /// A chunk where no rotation maps the blocks onto themselves.
+    fn asymmetric_chunk<const H: usize>() -> Chunk<H> {
+        let mut chunk = Chunk::<H>::flat(BlockId::Air);
+        chunk.set(Vec3::new(0, 0, 0), BlockId::Stone);
+        chunk.set(Vec3::new(1, 0, 0), BlockId::Dirt);
+        chunk.set(Vec3::new(0, 2, 0), BlockId::Grass);
+        chunk.set(Vec3::new(0, 0, 3), BlockId::Sand);
+        chunk
+    }
+
+    #[test]
+    pub fn four_rotations_produce_the_original_chunk() {
+        let chunk = asymmetric_chunk::<16>();
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let rotated = chunk
+                .rotate_90(axis)
+                .rotate_90(axis)
+                .rotate_90(axis)
+                .rotate_90(axis);
+            assert_eq!(rotated.blocks, chunk.blocks, "{axis:?}");
+            assert_eq!(chunk.rotate_270(axis).rotate_90(axis).blocks, chunk.blocks);
+        }
+        let chunk = asymmetric_chunk::<256>();
+        assert_eq!(
+            chunk.rotate_180(Axis::Y).rotate_180(Axis::Y).blocks,
+            chunk.blocks
+        );
+    }
+
+    #[test]
+    pub fn rotated_chunk_differs_from_the_original() {
+        let chunk = asymmetric_chunk::<16>();
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let rotated = chunk.rotate_90(axis);
+            assert_ne!(rotated.blocks, chunk.blocks, "{axis:?}");
+            assert_eq!(
+                rotated.count_blocks_of(BlockId::Air),
+                chunk.count_blocks_of(BlockId::Air)
+            );
+        }
+        // The block at the origin moves to the opposite corner of the side
+        let rotated = asymmetric_chunk::<256>().rotate_90(Axis::Y);
+        assert_eq!(rotated.get(Vec3::new(15, 0, 0)), Some(BlockId::Stone));
+        assert_eq!(rotated.get(Vec3::new(15, 0, 1)), Some(BlockId::Dirt));
+        assert_eq!(rotated.get(Vec3::new(12, 0, 0)), Some(BlockId::Sand));
+    }
+
+    #[test]
+    pub fn mirroring_twice_produces_the_original_chunk() {
+        let chunk = asymmetric_chunk::<256>();
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let mirrored = chunk.mirror(axis);
+            assert_ne!(mirrored.blocks, chunk.blocks, "{axis:?}");
+            assert_eq!(mirrored.mirror(axis).blocks, chunk.blocks, "{axis:?}");
+        }
+        let mirrored = chunk.mirror(Axis::X);
+        assert_eq!(mirrored.get(Vec3::new(15, 0, 0)), Some(BlockId::Stone));
+        assert_eq!(mirrored.get(Vec3::new(14, 0, 0)), Some(BlockId::Dirt));
+        let mirrored = chunk.mirror(Axis::Y);
+        assert_eq!(mirrored.get(Vec3::new(0, 253, 0)), Some(BlockId::Grass));
+    }
+
+    #[test]
+    pub fn orientations_combine_rotation_and_mirror() {
+        let chunk = asymmetric_chunk::<256>();
+        assert_eq!(chunk.oriented(0, false).blocks, chunk.blocks);
+        assert_eq!(chunk.oriented(4, false).blocks, chunk.blocks);
+        assert_eq!(
+            chunk.oriented(1, true).blocks,
+            chunk.rotate_90(Axis::Y).mirror(Axis::X).blocks
+        );
+        // Every orientation of an asymmetric chunk is different
+        let orientations = (0..4)
+            .flat_map(|rotation| [false, true].map(|mirror| chunk.oriented(rotation, mirror)))
+            .collect::<Vec<_>>();
+        for (i, a) in orientations.iter().enumerate() {
+            for b in &orientations[i + 1..] {
+                assert_ne!(a.blocks, b.blocks);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn only_cubic_chunks_rotate_around_horizontal_axes() {
+        asymmetric_chunk::<256>().rotate_90(Axis::X);
+    }
+
     #[test]
     pub fn chunk_iter_works() {
-        let chunk = Chunk::flat(BlockId::Air);
+        let chunk = StandardChunk::flat(BlockId::Air);
         let mut count = 0;
 
         for pos in chunk.iter() {
-            assert!(Chunk::within_bounds(pos));
+            assert!(StandardChunk::within_bounds(pos));
             count += 1;
         }
 
         assert_eq!(count, 16 * 256 * 16);
     }
+    #[test]
+    pub fn flood_fill_replaces_solid_cube() {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    chunk.set(Vec3::new(x, y, z), BlockId::Stone);
+                }
+            }
+        }
+        assert_eq!(chunk.flood_fill(Vec3::new(1, 1, 1), BlockId::Dirt), 64);
+        assert_eq!(
+            chunk
+                .iter()
+                .filter(|p| chunk.get(*p) == Some(BlockId::Dirt))
+                .count(),
+            64
+        );
+        assert_eq!(
+            chunk
+                .iter()
+                .filter(|p| chunk.get(*p) == Some(BlockId::Stone))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    pub fn flood_fill_with_same_id_does_nothing() {
+        let mut chunk = StandardChunk::flat(BlockId::Stone);
+        assert_eq!(chunk.flood_fill(Vec3::zero(), BlockId::Stone), 0);
+        assert_eq!(chunk.flood_fill(Vec3::new(-1, 0, 0), BlockId::Dirt), 0);
+    }
+
+    #[test]
+    pub fn flood_fill_does_not_cross_air() {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        // Two rows of stone separated by an air gap at x = 3
+        for x in 0..3 {
+            chunk.set(Vec3::new(x, 0, 0), BlockId::Stone);
+            chunk.set(Vec3::new(x + 4, 0, 0), BlockId::Stone);
+        }
+        assert_eq!(chunk.flood_fill(Vec3::new(0, 0, 0), BlockId::Dirt), 3);
+        assert_eq!(chunk.get(Vec3::new(4, 0, 0)), Some(BlockId::Stone));
+        assert_eq!(chunk.get(Vec3::new(6, 0, 0)), Some(BlockId::Stone));
+    }
+
     #[test]
     pub fn is_chunk_pos_out_of_bounds() {
-        assert!(Chunk::out_of_bounds(Vec3::new(-1, 0, 0)));
-        assert!(Chunk::out_of_bounds(Vec3::new(0, -1, 0)));
-        assert!(Chunk::out_of_bounds(Vec3::new(0, 0, -1)));
-        assert!(Chunk::out_of_bounds(Vec3::new(16, 0, 0)));
-        assert!(Chunk::out_of_bounds(Vec3::new(0, 256, 0)));
-        assert!(Chunk::out_of_bounds(Vec3::new(0, 0, 16)));
-        assert!(!Chunk::out_of_bounds(Vec3::new(15, 255, 15)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(-1, 0, 0)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(0, -1, 0)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(0, 0, -1)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(16, 0, 0)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(0, 256, 0)));
+        assert!(StandardChunk::out_of_bounds(Vec3::new(0, 0, 16)));
+        assert!(!StandardChunk::out_of_bounds(Vec3::new(15, 255, 15)));
+    }
+
+    #[test]
+    pub fn count_blocks_of_flat_chunk() {
+        assert_eq!(
+            StandardChunk::flat(BlockId::Dirt).count_blocks_of(BlockId::Dirt),
+            16 * 256 * 16
+        );
+        assert_eq!(
+            StandardChunk::flat(BlockId::Air).count_blocks_of(BlockId::Dirt),
+            0
+        );
+    }
+
+    #[test]
+    pub fn layered_chunk_is_not_uniform() {
+        let mut chunk = StandardChunk::flat(BlockId::Dirt);
+        assert!(chunk.is_uniform());
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Vec3::new(x, 4, z), BlockId::Grass);
+            }
+        }
+        assert!(!chunk.is_uniform());
+        assert_eq!(chunk.count_blocks_of(BlockId::Grass), 16 * 16);
     }
 
     #[test]
     pub fn chunk_compression_test() {
-        let chunk = Chunk::flat(BlockId::Dirt);
+        let chunk = StandardChunk::flat(BlockId::Dirt);
         let compressed = compress(&chunk);
         assert_eq!(compressed.len(), 1);
         assert_eq!(compressed[0], (BlockId::Dirt, 16 * 256 * 16));
     }
+
+    #[test]
+    pub fn palette_round_trip() {
+        let mut chunk = StandardChunk::flat(BlockId::Dirt);
+        chunk.set(Vec3::new(1, 0, 0), BlockId::Stone);
+        chunk.set(Vec3::new(15, 255, 15), BlockId::Grass);
+        let (palette, indices) = chunk.to_palette().unwrap();
+        assert_eq!(palette.len(), 3);
+        assert_eq!(
+            StandardChunk::from_palette(&palette, &indices).blocks,
+            chunk.blocks
+        );
+
+        let encoded = std::mem::size_of_val(palette.as_slice()) + indices.len();
+        let dense = std::mem::size_of::<BlockId>() * StandardChunk::VOLUME;
+        assert!(encoded < dense, "{} >= {}", encoded, dense);
+    }
+
+    #[test]
+    pub fn palette_holds_every_block() {
+        assert!(BlockId::ALL.len() <= MAX_PALETTE_LEN);
+        let mut chunk = ShallowChunk::flat(BlockId::Air);
+        for (x, id) in BlockId::ALL.into_iter().enumerate() {
+            chunk.set(Vec3::new(x as i32, 0, 0), id);
+        }
+        let (palette, indices) = chunk.to_palette().unwrap();
+        assert_eq!(palette, BlockId::ALL);
+        assert_eq!(
+            ShallowChunk::from_palette(&palette, &indices).blocks,
+            chunk.blocks
+        );
+    }
+
+    #[test]
+    pub fn shallow_chunk_bounds() {
+        assert!(ShallowChunk::out_of_bounds(Vec3::new(0, 64, 0)));
+        assert!(!ShallowChunk::out_of_bounds(Vec3::new(15, 63, 15)));
+        assert_eq!(
+            ShallowChunk::index_of(Vec3::new(15, 63, 15)),
+            Some(ShallowChunk::VOLUME - 1)
+        );
+        assert_eq!(
+            ShallowChunk::flat(BlockId::Air).iter().count(),
+            16 * 64 * 16
+        );
+        assert_eq!(ShallowChunk::HEIGHT, 64);
+    }
+
+    #[test]
+    pub fn compression_round_trip_for_both_heights() {
+        let mut standard = StandardChunk::flat(BlockId::Air);
+        standard.set(Vec3::new(3, 200, 9), BlockId::Stone);
+        let decompressed: StandardChunk = decompress(&compress(&standard));
+        assert_eq!(decompressed.get(Vec3::new(3, 200, 9)), Some(BlockId::Stone));
+        assert_eq!(decompressed.count_blocks_of(BlockId::Stone), 1);
+
+        let mut shallow = ShallowChunk::flat(BlockId::Dirt);
+        shallow.set(Vec3::new(3, 63, 9), BlockId::Grass);
+        let decompressed: ShallowChunk = decompress(&compress(&shallow));
+        assert_eq!(decompressed.get(Vec3::new(3, 63, 9)), Some(BlockId::Grass));
+        assert_eq!(
+            decompressed.count_blocks_of(BlockId::Dirt),
+            ShallowChunk::VOLUME as u32 - 1
+        );
+    }
+
+    #[test]
+    pub fn shallow_chunk_generation_is_capped_by_height() {
+        let chunk = ShallowChunk::generate(&ConstantNoise(0.0), Vec2::zero(), 0, 64);
+        assert_eq!(chunk.surface_height(0, 0), Some(32));
+        // A surface above the chunk fills it up to the top
+        let chunk = ShallowChunk::generate(&ConstantNoise(1.0), Vec2::zero(), 0, 128);
+        assert_eq!(chunk.surface_height(0, 0), Some(63));
+    }
+
+    #[test]
+    pub fn column_routes_blocks_to_their_section() {
+        let mut column: ChunkColumn = ChunkColumn::flat(BlockId::Air, 2);
+        assert_eq!((column.min_y(), column.max_y()), (0, 512));
+
+        assert_eq!(
+            column.set_block(10, 3, 4, BlockId::Stone),
+            Some(BlockId::Air)
+        );
+        assert_eq!(
+            column.set_block(300, 3, 4, BlockId::Dirt),
+            Some(BlockId::Air)
+        );
+        assert_eq!(column.get_block(10, 3, 4), Some(BlockId::Stone));
+        assert_eq!(column.get_block(300, 3, 4), Some(BlockId::Dirt));
+        assert_eq!(
+            column.sections[0].get(Vec3::new(3, 10, 4)),
+            Some(BlockId::Stone)
+        );
+        assert_eq!(
+            column.sections[1].get(Vec3::new(3, 300 - 256, 4)),
+            Some(BlockId::Dirt)
+        );
+        // Both ends of the column and the seam between the sections
+        assert_eq!(column.get_block(0, 0, 0), Some(BlockId::Air));
+        assert_eq!(column.get_block(255, 0, 0), Some(BlockId::Air));
+        assert_eq!(column.get_block(256, 0, 0), Some(BlockId::Air));
+        assert_eq!(column.get_block(511, 0, 0), Some(BlockId::Air));
+        assert_eq!(column.get_block(512, 0, 0), None);
+        assert_eq!(column.get_block(-1, 0, 0), None);
+        assert_eq!(column.get_block(10, 16, 0), None);
+        assert_eq!(column.set_block(512, 0, 0, BlockId::Stone), None);
+    }
+
+    #[test]
+    pub fn column_below_zero() {
+        let mut column = ChunkColumn::new(vec![ShallowChunk::flat(BlockId::Air); 2], -1);
+        assert_eq!((column.min_y(), column.max_y()), (-64, 64));
+        column.set_block(-1, 0, 0, BlockId::Stone);
+        assert_eq!(
+            column.sections[0].get(Vec3::new(0, 63, 0)),
+            Some(BlockId::Stone)
+        );
+        assert_eq!(column.get_block(-65, 0, 0), None);
+        assert!(std::ptr::eq(column.ground().unwrap(), &column.sections[1]));
+    }
+
+    #[test]
+    pub fn column_iterates_every_section() {
+        let mut column = ChunkColumn::new(vec![ShallowChunk::flat(BlockId::Air); 2], 0);
+        column.set_block(100, 1, 2, BlockId::Stone);
+        let blocks = column.iter_all().collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 2 * ShallowChunk::VOLUME);
+        let solid = blocks
+            .iter()
+            .filter(|(_, id)| *id == BlockId::Stone)
+            .collect::<Vec<_>>();
+        assert_eq!(solid, vec![&(Vec3::new(1, 100, 2), BlockId::Stone)]);
+    }
 }
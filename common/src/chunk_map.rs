@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use vek::Vec2;
+
+/// Values keyed by chunk position, for a world with fixed bounds.
+///
+/// The values are stored in a flat array indexed by the offset from the lowest corner,
+/// so lookups are a bounds check and an index instead of hashing the position.
+/// Positions outside of the bounds never hold a value.
+#[derive(Debug, Clone)]
+pub struct BoundedChunkMap<V> {
+    data: Vec<Option<V>>,
+    min: Vec2<i32>,
+    size: Vec2<u32>,
+}
+
+impl<V> BoundedChunkMap<V> {
+    /// Creates an empty map for the chunks between `min` and `max`, both inclusive.
+    pub fn new(min: Vec2<i32>, max: Vec2<i32>) -> Self {
+        assert!(
+            max.x >= min.x && max.y >= min.y,
+            "The maximum {:?} is lower than the minimum {:?}",
+            max,
+            min
+        );
+        let size = (max - min).map(|x| x as u32 + 1);
+        let mut data = Vec::new();
+        data.resize_with(size.product() as usize, || None);
+        Self { data, min, size }
+    }
+
+    /// Moves the values of `map` into a new map, dropping those outside of the bounds.
+    pub fn from_hash_map(map: HashMap<Vec2<i32>, V>, min: Vec2<i32>, max: Vec2<i32>) -> Self {
+        let mut this = Self::new(min, max);
+        for (pos, value) in map {
+            // Out of bounds values are given back and dropped
+            let _ = this.insert(pos, value);
+        }
+        this
+    }
+
+    pub fn min(&self) -> Vec2<i32> {
+        self.min
+    }
+
+    /// The highest position within the bounds.
+    pub fn max(&self) -> Vec2<i32> {
+        self.min + self.size.map(|x| x as i32 - 1)
+    }
+
+    pub fn contains_pos(&self, pos: Vec2<i32>) -> bool {
+        self.index_of(pos).is_some()
+    }
+
+    fn index_of(&self, pos: Vec2<i32>) -> Option<usize> {
+        // Widened so positions far from the bounds cannot overflow
+        let offset = pos.map(i64::from) - self.min.map(i64::from);
+        let size = self.size.map(i64::from);
+        if offset.x < 0 || offset.y < 0 || offset.x >= size.x || offset.y >= size.y {
+            return None;
+        }
+        Some((offset.y * size.x + offset.x) as usize)
+    }
+
+    fn pos_of(&self, index: usize) -> Vec2<i32> {
+        let index = index as u32;
+        self.min + Vec2::new(index % self.size.x, index / self.size.x).map(|x| x as i32)
+    }
+
+    pub fn get(&self, pos: Vec2<i32>) -> Option<&V> {
+        self.index_of(pos).and_then(|i| self.data[i].as_ref())
+    }
+
+    pub fn get_mut(&mut self, pos: Vec2<i32>) -> Option<&mut V> {
+        self.index_of(pos).and_then(|i| self.data[i].as_mut())
+    }
+
+    /// Inserts a value, returning the previous one.
+    ///
+    /// Fails with the value when `pos` is outside of the bounds.
+    pub fn insert(&mut self, pos: Vec2<i32>, value: V) -> Result<Option<V>, V> {
+        match self.index_of(pos) {
+            Some(i) => Ok(self.data[i].replace(value)),
+            None => Err(value),
+        }
+    }
+
+    pub fn remove(&mut self, pos: Vec2<i32>) -> Option<V> {
+        self.index_of(pos).and_then(|i| self.data[i].take())
+    }
+
+    /// Iterates over the positions holding a value, row by row.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec2<i32>, &V)> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| value.as_ref().map(|value| (self.pos_of(i), value)))
+    }
+
+    /// Number of positions holding a value.
+    pub fn len(&self) -> usize {
+        self.data.iter().filter(|value| value.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(Option::is_none)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use vek::Vec2;
+
+    use super::BoundedChunkMap;
+
+    #[test]
+    pub fn insert_get_remove() {
+        let mut map = BoundedChunkMap::new(Vec2::new(-4, -2), Vec2::new(3, 5));
+        assert_eq!(map.max(), Vec2::new(3, 5));
+        assert!(map.is_empty());
+
+        assert_eq!(map.insert(Vec2::new(-4, -2), 'a'), Ok(None));
+        assert_eq!(map.insert(Vec2::new(3, 5), 'b'), Ok(None));
+        assert_eq!(map.insert(Vec2::new(3, 5), 'c'), Ok(Some('b')));
+        assert_eq!(map.get(Vec2::new(-4, -2)), Some(&'a'));
+        assert_eq!(map.get(Vec2::new(3, 5)), Some(&'c'));
+        assert_eq!(map.get(Vec2::new(0, 0)), None);
+        assert_eq!(map.len(), 2);
+
+        *map.get_mut(Vec2::new(-4, -2)).unwrap() = 'd';
+        assert_eq!(map.remove(Vec2::new(-4, -2)), Some('d'));
+        assert_eq!(map.remove(Vec2::new(-4, -2)), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    pub fn out_of_bounds_positions_hold_nothing() {
+        let mut map = BoundedChunkMap::new(Vec2::new(0, 0), Vec2::new(7, 7));
+        for pos in [
+            Vec2::new(-1, 0),
+            Vec2::new(0, -1),
+            Vec2::new(8, 0),
+            Vec2::new(0, 8),
+            Vec2::new(i32::MIN, i32::MAX),
+        ] {
+            assert!(!map.contains_pos(pos));
+            assert_eq!(map.insert(pos, 1), Err(1));
+            assert_eq!(map.get(pos), None);
+            assert_eq!(map.get_mut(pos), None);
+            assert_eq!(map.remove(pos), None);
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    pub fn iter_returns_positions() {
+        let mut map = BoundedChunkMap::new(Vec2::new(-2, -2), Vec2::new(2, 2));
+        map.insert(Vec2::new(1, -2), 1).unwrap();
+        map.insert(Vec2::new(-2, 2), 2).unwrap();
+        let entries = map.iter().map(|(pos, v)| (pos, *v)).collect::<Vec<_>>();
+        assert_eq!(entries, [(Vec2::new(1, -2), 1), (Vec2::new(-2, 2), 2)]);
+    }
+
+    #[test]
+    pub fn from_hash_map_keeps_values_in_bounds() {
+        let map = HashMap::from([
+            (Vec2::new(0, 0), "origin"),
+            (Vec2::new(3, 3), "corner"),
+            (Vec2::new(4, 0), "outside"),
+        ]);
+        let map = BoundedChunkMap::from_hash_map(map, Vec2::zero(), Vec2::new(3, 3));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(Vec2::new(0, 0)), Some(&"origin"));
+        assert_eq!(map.get(Vec2::new(3, 3)), Some(&"corner"));
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    pub fn bench_lookup_10000_positions() {
+        let max = Vec2::new(127, 127);
+        let mut hash_map = HashMap::new();
+        for x in 0..=max.x {
+            for z in 0..=max.y {
+                hash_map.insert(Vec2::new(x, z), x ^ z);
+            }
+        }
+        let bounded = BoundedChunkMap::from_hash_map(hash_map.clone(), Vec2::zero(), max);
+
+        let mut rng = StdRng::seed_from_u64(88);
+        let positions = (0..10_000)
+            .map(|_| Vec2::new(rng.gen_range(0..=max.x), rng.gen_range(0..=max.y)))
+            .collect::<Vec<_>>();
+
+        let start = std::time::Instant::now();
+        let hash_sum: i64 = positions
+            .iter()
+            .map(|pos| *hash_map.get(pos).unwrap() as i64)
+            .sum();
+        let hash_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let bounded_sum: i64 = positions
+            .iter()
+            .map(|pos| *bounded.get(*pos).unwrap() as i64)
+            .sum();
+        let bounded_time = start.elapsed();
+
+        assert_eq!(hash_sum, bounded_sum);
+        eprintln!(
+            "10000 lookups: HashMap {:?}, BoundedChunkMap {:?}",
+            hash_time, bounded_time
+        );
+    }
+}
@@ -28,3 +28,9 @@ impl Clock {
         1.0 / self.dt.as_secs_f32()
     }
 }
+
+/// How many times per second the server updates the world.
+pub const TICK_RATE: u32 = 20;
+
+/// The time between two server ticks, 50 ms.
+pub const TICK_DURATION: Duration = Duration::from_millis(1000 / TICK_RATE as u64);
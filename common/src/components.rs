@@ -1,4 +1,4 @@
 use vek::Vec3;
 
 #[derive(Debug, Clone, Copy, Default)]
-pub struct Pos(Vec3<f32>);
+pub struct Pos(pub Vec3<f32>);
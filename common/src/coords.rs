@@ -0,0 +1,55 @@
+//! Conversions between block positions and world space.
+//!
+//! A block position (`Vec3<i32>`) names the block whose minimum corner sits at the same
+//! world space coordinates, so block `(0, 0, 0)` fills the cube from `(0.0, 0.0, 0.0)` to
+//! `(1.0, 1.0, 1.0)`. Its centre is therefore at `(0.5, 0.5, 0.5)`.
+//!
+//! Going from world space back to blocks always rounds towards negative infinity,
+//! which keeps negative coordinates in the right block: `-0.5` is inside block `-1`.
+
+use vek::Vec3;
+
+/// The world space centre of the block at `pos`.
+pub fn block_center(pos: Vec3<i32>) -> Vec3<f32> {
+    pos.map(|x| x as f32 + 0.5)
+}
+
+/// The position of the block containing the world space point `pos`.
+pub fn world_to_block(pos: Vec3<f32>) -> Vec3<i32> {
+    pos.map(|x| x.floor() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use super::{block_center, world_to_block};
+
+    #[test]
+    pub fn world_to_block_rounds_down() {
+        assert_eq!(
+            world_to_block(Vec3::new(-0.5, 0.0, 0.0)),
+            Vec3::new(-1, 0, 0)
+        );
+        assert_eq!(
+            world_to_block(Vec3::new(0.99, 1.0, -1.0)),
+            Vec3::new(0, 1, -1)
+        );
+        assert_eq!(
+            world_to_block(Vec3::new(-16.01, 255.5, -0.0)),
+            Vec3::new(-17, 255, 0)
+        );
+    }
+
+    #[test]
+    pub fn block_center_round_trip() {
+        for pos in [
+            Vec3::new(0, 0, 0),
+            Vec3::new(-1, 5, -20),
+            Vec3::new(15, 255, -16),
+        ] {
+            assert_eq!(world_to_block(block_center(pos)), pos);
+        }
+        assert_eq!(block_center(Vec3::new(-1, 0, 2)), Vec3::new(-0.5, 0.5, 2.5));
+    }
+}
@@ -11,6 +11,15 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::North,
+        Direction::South,
+        Direction::East,
+        Direction::West,
+        Direction::Up,
+        Direction::Down,
+    ];
+
     pub fn vec(self) -> Vec3<i32> {
         match self {
             Direction::North => Vec3::unit_z(),
@@ -22,3 +31,11 @@ impl Direction {
         }
     }
 }
+
+/// One of the three axes of the world.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
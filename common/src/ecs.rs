@@ -0,0 +1,250 @@
+//! Fetch helpers for resources that are not always available.
+//!
+//! A system fetching `Read<T, NoDefault>` or `Write<T, NoDefault>` fails to run, with an error,
+//! while the resource does not exist. That is right for resources the game cannot run without,
+//! like the renderer, but not for those created later, e.g. assets loaded in the background.
+//!
+//! [`TryRead`] and [`TryWrite`] store the resource as an `Option<T>` instead,
+//! which defaults to `None`, so the system always runs and can check whether it is there:
+//!
+//! ```ignore
+//! fn system(atlas: TryRead<BlockAtlas>) -> SysResult {
+//!     let Some(atlas) = atlas.inner().as_ref() else {
+//!         return ok();
+//!     };
+//!     // ...
+//! }
+//! ```
+//!
+//! The resource must then be inserted as `Some(value)`.
+//!
+//! Such resources are often the output of an [`AsyncSystem`], which polls a future once per frame
+//! until it completes.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use apecs::{CanFetch, Read, ShouldContinue, Write};
+
+use crate::{state::ShouldContinueExt, SysResult};
+
+/// Read access to a resource that may be missing, see the [module documentation](self).
+pub type TryRead<T> = Read<Option<T>>;
+
+/// Write access to a resource that may be missing, see the [module documentation](self).
+pub type TryWrite<T> = Write<Option<T>>;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Drives a future from a system, polling it once per frame instead of blocking the frame on it.
+///
+/// The future is polled with a waker that does nothing: it makes progress only when polled,
+/// so slow work should run on a runtime, see [`AsyncSystem::spawn`].
+pub struct AsyncSystem<T> {
+    /// `None` once the future completed. The mutex only makes the system `Sync`,
+    /// it is never locked.
+    future: Option<Mutex<BoxFuture<T>>>,
+}
+
+impl<T: Send + 'static> AsyncSystem<T> {
+    /// Polls `future` itself, on the thread running the system.
+    pub fn new<F>(future: F) -> Self
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        Self {
+            future: Some(Mutex::new(Box::pin(future))),
+        }
+    }
+
+    /// Runs `future` on `runtime`, the system only checks whether it is done.
+    pub fn spawn<F>(runtime: &tokio::runtime::Handle, future: F) -> Self
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let task = runtime.spawn(future);
+        Self::new(async move { task.await.expect("Async system task panicked") })
+    }
+
+    /// Polls the future once, returning its output the first time it is ready.
+    pub fn poll(&mut self) -> Option<T> {
+        let future = self
+            .future
+            .as_mut()?
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let waker = noop_waker();
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => {
+                self.future = None;
+                Some(output)
+            },
+            Poll::Pending => None,
+        }
+    }
+
+    /// Whether the future returned its output.
+    pub fn completed(&self) -> bool {
+        self.future.is_none()
+    }
+
+    /// Turns this into a system that polls the future every frame and passes its output
+    /// to `on_complete`, along with the data the system fetched.
+    ///
+    /// The system returns [`ShouldContinue::No`] once the future completed, so it is removed
+    /// from the schedule.
+    pub fn into_system<D, C>(
+        mut self,
+        mut on_complete: C,
+    ) -> impl FnMut(D) -> SysResult + Send + Sync + 'static
+    where
+        D: CanFetch,
+        C: FnMut(T, D) + Send + Sync + 'static,
+    {
+        move |data| {
+            if let Some(output) = self.poll() {
+                on_complete(output, data);
+            }
+            Ok(ShouldContinue::from_bool(!self.completed()))
+        }
+    }
+}
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(std::ptr::null(), &VTABLE),
+        |_| {},
+        |_| {},
+        |_| {},
+    );
+    // SAFETY: every function of the vtable ignores the data pointer
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use apecs::{ok, ShouldContinue};
+
+    use super::{AsyncSystem, TryRead, TryWrite};
+
+    struct Atlas(u32);
+
+    /// 0 until the system runs, then 1 when the resource was missing and 2 when it was found.
+    fn run_with(atlas: Option<Atlas>) -> u8 {
+        let seen = Arc::new(AtomicU8::new(0));
+        let mut world = apecs::World::default();
+        if let Some(atlas) = atlas {
+            world.with_resource(Some(atlas)).unwrap();
+        }
+        let result = seen.clone();
+        world
+            .with_system("try_read", move |atlas: TryRead<Atlas>| {
+                let value = if atlas.inner().is_some() { 2 } else { 1 };
+                seen.store(value, Ordering::SeqCst);
+                ok()
+            })
+            .unwrap();
+        world.tick().unwrap();
+        result.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    pub fn try_read_missing_resource_is_none() {
+        assert_eq!(run_with(None), 1);
+    }
+
+    #[test]
+    pub fn try_read_present_resource_is_some() {
+        assert_eq!(run_with(Some(Atlas(16))), 2);
+    }
+
+    #[test]
+    pub fn try_write_modifies_present_resource() {
+        let mut world = apecs::World::default();
+        world.with_resource(Some(Atlas(16))).unwrap();
+        world
+            .with_system("try_write", |mut atlas: TryWrite<Atlas>| {
+                if let Some(atlas) = atlas.inner_mut().as_mut() {
+                    atlas.0 *= 2;
+                }
+                ok()
+            })
+            .unwrap();
+        world.tick().unwrap();
+        let atlas = world.resource::<Option<Atlas>>().unwrap();
+        assert_eq!(atlas.as_ref().map(|atlas| atlas.0), Some(32));
+    }
+
+    /// Ready on its `polls`th poll.
+    struct CountdownFuture {
+        polls: u32,
+    }
+
+    impl Future for CountdownFuture {
+        type Output = u32;
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<u32> {
+            self.polls -= 1;
+            if self.polls == 0 {
+                Poll::Ready(42)
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    pub fn async_system_completes_on_the_last_poll() {
+        let mut future = AsyncSystem::new(CountdownFuture { polls: 3 });
+        for _ in 0..2 {
+            assert_eq!(future.poll(), None);
+            assert!(!future.completed());
+        }
+        assert_eq!(future.poll(), Some(42));
+        assert!(future.completed());
+        // The output is only returned once
+        assert_eq!(future.poll(), None);
+    }
+
+    #[test]
+    pub fn async_system_quits_once_completed() {
+        let completed = Arc::new(AtomicU8::new(0));
+        let calls = completed.clone();
+        let mut system =
+            AsyncSystem::new(CountdownFuture { polls: 3 }).into_system(move |output, _: ()| {
+                assert_eq!(output, 42);
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+        assert!(matches!(system(()).unwrap(), ShouldContinue::Yes));
+        assert!(matches!(system(()).unwrap(), ShouldContinue::Yes));
+        assert_eq!(completed.load(Ordering::SeqCst), 0);
+        assert!(matches!(system(()).unwrap(), ShouldContinue::No));
+        assert_eq!(completed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    pub fn spawned_futures_run_on_the_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let mut future = AsyncSystem::spawn(runtime.handle(), async { 7 });
+        let output = loop {
+            if let Some(output) = future.poll() {
+                break output;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(output, 7);
+    }
+}
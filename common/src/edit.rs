@@ -0,0 +1,147 @@
+use vek::Vec3;
+
+use crate::{block::BlockId, resources::TerrainMap};
+
+/// The maximum number of edits kept by [`VoxelEditHistory`].
+pub const MAX_HISTORY: usize = 100;
+
+/// A batch of block changes that is undone and redone as a whole.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoxelEdit {
+    /// `(world position, old block, new block)`
+    pub changes: Vec<(Vec3<i32>, BlockId, BlockId)>,
+}
+
+impl VoxelEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, pos: Vec3<i32>, old: BlockId, new: BlockId) {
+        self.changes.push((pos, old, new));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Undo and redo stack of block modifications.
+#[derive(Debug, Default)]
+pub struct VoxelEditHistory {
+    stack: Vec<VoxelEdit>,
+    /// Number of edits currently applied. Everything after it can be redone.
+    cursor: usize,
+}
+
+impl VoxelEditHistory {
+    /// Records an edit that was just applied, discarding the edits that could be redone.
+    pub fn push(&mut self, edit: VoxelEdit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.stack.truncate(self.cursor);
+        self.stack.push(edit);
+        if self.stack.len() > MAX_HISTORY {
+            self.stack.remove(0);
+        }
+        self.cursor = self.stack.len();
+    }
+
+    /// Reverts the last applied edit. Returns false if there is nothing to undo.
+    pub fn undo(&mut self, terrain: &mut TerrainMap) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        // Revert in reverse order in case the same block changed more than once
+        for (pos, old, new) in self.stack[self.cursor].changes.iter().rev() {
+            log::debug!("Undo {:?}: {} -> {}", pos, new.name(), old.name());
+            terrain.set_block(*pos, *old);
+        }
+        true
+    }
+
+    /// Re-applies the last undone edit. Returns false if there is nothing to redo.
+    pub fn redo(&mut self, terrain: &mut TerrainMap) -> bool {
+        let Some(edit) = self.stack.get(self.cursor) else {
+            return false;
+        };
+        for (pos, old, new) in &edit.changes {
+            log::debug!("Redo {:?}: {} -> {}", pos, old.name(), new.name());
+            terrain.set_block(*pos, *new);
+        }
+        self.cursor += 1;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{block::BlockId, chunk::StandardChunk, resources::TerrainMap};
+
+    use super::{VoxelEdit, VoxelEditHistory, MAX_HISTORY};
+
+    fn place(terrain: &mut TerrainMap, pos: Vec3<i32>, id: BlockId) -> VoxelEdit {
+        let old = terrain.set_block(pos, id).unwrap();
+        let mut edit = VoxelEdit::new();
+        edit.push(pos, old, id);
+        edit
+    }
+
+    #[test]
+    pub fn undo_then_redo_restores_state() {
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::zero(), StandardChunk::flat(BlockId::Air).into());
+        let mut history = VoxelEditHistory::default();
+
+        let a = Vec3::new(1, 2, 3);
+        let b = Vec3::new(4, 5, 6);
+        history.push(place(&mut terrain, a, BlockId::Stone));
+        history.push(place(&mut terrain, b, BlockId::Dirt));
+
+        assert!(history.undo(&mut terrain));
+        assert_eq!(terrain.get_block(b), Some(BlockId::Air));
+        assert!(history.undo(&mut terrain));
+        assert_eq!(terrain.get_block(a), Some(BlockId::Air));
+        assert!(!history.undo(&mut terrain));
+
+        assert!(history.redo(&mut terrain));
+        assert!(history.redo(&mut terrain));
+        assert!(!history.redo(&mut terrain));
+        assert_eq!(terrain.get_block(a), Some(BlockId::Stone));
+        assert_eq!(terrain.get_block(b), Some(BlockId::Dirt));
+    }
+
+    #[test]
+    pub fn history_evicts_oldest_edit() {
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::zero(), StandardChunk::flat(BlockId::Air).into());
+        let mut history = VoxelEditHistory::default();
+
+        for i in 0..=MAX_HISTORY as i32 {
+            let pos = Vec3::new(i % 16, i / 16, 0);
+            history.push(place(&mut terrain, pos, BlockId::Stone));
+        }
+        assert_eq!(history.len(), MAX_HISTORY);
+
+        while history.undo(&mut terrain) {}
+        // The first edit can no longer be undone
+        assert_eq!(terrain.get_block(Vec3::zero()), Some(BlockId::Stone));
+        assert_eq!(terrain.get_block(Vec3::new(1, 0, 0)), Some(BlockId::Air));
+    }
+}
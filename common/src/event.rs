@@ -1,6 +1,7 @@
 use apecs::{ok, Write};
+use vek::Vec3;
 
-use crate::SysResult;
+use crate::{block::BlockId, resources::Weather, SysResult};
 
 /// Represents a type that can be stored in an [`Events<E>`] resource
 pub trait Event: Send + Sync + 'static {}
@@ -24,6 +25,24 @@ impl<T: Event> Events<T> {
         self.events.clear();
     }
 
+    /// Moves the events out, in the order they were sent.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Moves the events to the end of `target`, in the order they were sent.
+    pub fn drain_into(&mut self, target: &mut Vec<T>) {
+        target.append(&mut self.events);
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
     // this should be called once per frame
     pub fn update(&mut self) {
         self.events.clear();
@@ -36,8 +55,46 @@ impl<T: Event> Default for Events<T> {
     }
 }
 
+/// Sent whenever a block in the world is placed or removed.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockUpdateEvent {
+    pub world_pos: Vec3<i32>,
+    pub old_id: BlockId,
+    pub new_id: BlockId,
+}
+
+/// Sent when the weather of the world changes.
+#[derive(Debug, Clone, Copy)]
+pub struct WeatherChangedEvent {
+    pub old: Weather,
+    pub new: Weather,
+}
+
 /// A generic update system for events
 pub fn event_update_system<E: Event>(mut events: Write<Events<E>>) -> SysResult {
     events.update();
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Events;
+
+    #[test]
+    pub fn drained_events_are_moved_out() {
+        let mut events = Events::default();
+        events.send(String::from("first"));
+        events.send(String::from("second"));
+        assert_eq!(events.len(), 2);
+
+        let mut target = vec![String::from("old")];
+        events.drain_into(&mut target);
+        assert_eq!(events.len(), 0);
+        assert!(events.is_empty());
+        assert_eq!(target, ["old", "first", "second"]);
+
+        events.send(String::from("third"));
+        assert_eq!(events.drain().collect::<Vec<_>>(), ["third"]);
+        assert!(events.is_empty());
+    }
+}
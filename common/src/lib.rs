@@ -1,9 +1,14 @@
 pub mod block;
 pub mod chunk;
+pub mod chunk_map;
 pub mod clock;
 pub mod components;
+pub mod coords;
 pub mod dir;
+pub mod ecs;
+pub mod edit;
 pub mod event;
+pub mod light;
 pub mod net;
 pub mod resources;
 pub mod state;
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, VecDeque};
+
+use vek::Vec3;
+
+use crate::{block::BlockId, chunk::Chunk, dir::Direction};
+
+/// The brightest light level, that of the sky.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Stores the light level of every block in a chunk `H` blocks high.
+pub struct LightMap<const H: usize = 256> {
+    levels: Box<[u8]>,
+}
+
+impl<const H: usize> Default for LightMap<H> {
+    fn default() -> Self {
+        Self {
+            levels: vec![0; Chunk::<H>::VOLUME].into_boxed_slice(),
+        }
+    }
+}
+
+impl<const H: usize> LightMap<H> {
+    /// Recomputes the sunlight of every column in the chunk, then spreads the light
+    /// of the blocks that emit some.
+    ///
+    /// Sunlight travels straight down from the sky until it reaches the first solid block.
+    /// That block is lit as well since its top face is exposed to the sky.
+    pub fn propagate_sunlight(&mut self, chunk: &Chunk<H>) {
+        self.propagate_light(chunk, &[None; 4]);
+    }
+
+    /// Like [`LightMap::propagate_sunlight`], with the light of the emitting blocks of the
+    /// `neighbors` spreading across the chunk borders too.
+    ///
+    /// The neighbours are in the order of the first four [`Direction::ALL`]:
+    /// north, south, east and west.
+    pub fn propagate_light(&mut self, chunk: &Chunk<H>, neighbors: &[Option<&Chunk<H>>; 4]) {
+        for x in 0..Chunk::<H>::SIZE.x as i32 {
+            for z in 0..Chunk::<H>::SIZE.z as i32 {
+                let mut light = MAX_LIGHT;
+                for y in (0..Chunk::<H>::SIZE.y as i32).rev() {
+                    let pos = Vec3::new(x, y, z);
+                    if let Some(idx) = Chunk::<H>::index_of(pos) {
+                        self.levels[idx] = light;
+                    }
+                    if chunk.get(pos).is_some_and(|id| id.blocks_light()) {
+                        light = 0;
+                    }
+                }
+            }
+        }
+        self.propagate_block_light(chunk, neighbors);
+    }
+
+    /// Spreads the light of emitting blocks, such as torches, in every direction.
+    ///
+    /// The light loses one level per block travelled and stops at blocks that block light,
+    /// which are still lit so their faces towards the light source are visible.
+    /// A block keeps the brightest of its sunlight and the lights reaching it.
+    fn propagate_block_light(&mut self, chunk: &Chunk<H>, neighbors: &[Option<&Chunk<H>>; 4]) {
+        let size = Chunk::<H>::SIZE.map(|x| x as i32);
+        // Where the blocks of each neighbour are, relative to the chunk
+        let offsets = [
+            Vec3::new(0, 0, size.z),
+            Vec3::new(0, 0, -size.z),
+            Vec3::new(size.x, 0, 0),
+            Vec3::new(-size.x, 0, 0),
+        ];
+        let block_at = |pos: Vec3<i32>| -> Option<BlockId> {
+            let neighbor = match pos {
+                pos if pos.z >= size.z => 0,
+                pos if pos.z < 0 => 1,
+                pos if pos.x >= size.x => 2,
+                pos if pos.x < 0 => 3,
+                pos => return chunk.get(pos),
+            };
+            neighbors[neighbor]?.get(pos - offsets[neighbor])
+        };
+        // The light travelling through the neighbours, which is not stored
+        let mut outside = HashMap::new();
+
+        let mut queue = VecDeque::new();
+        let sources = neighbors
+            .iter()
+            .zip(offsets)
+            .filter_map(|(neighbor, offset)| neighbor.map(|neighbor| (neighbor, offset)))
+            .chain([(chunk, Vec3::zero())]);
+        for (source, offset) in sources {
+            for pos in source.iter() {
+                let Some(emission) = source.get(pos).map(|id| id.light_emission()) else {
+                    continue;
+                };
+                if emission > 0 {
+                    self.raise(&mut outside, pos + offset, emission);
+                    queue.push_back((pos + offset, emission));
+                }
+            }
+        }
+
+        while let Some((pos, light)) = queue.pop_front() {
+            if light <= 1 {
+                continue;
+            }
+            for dir in Direction::ALL {
+                let next = pos + dir.vec();
+                let Some(id) = block_at(next) else {
+                    continue;
+                };
+                if self.raise(&mut outside, next, light - 1) && !id.blocks_light() {
+                    queue.push_back((next, light - 1));
+                }
+            }
+        }
+    }
+
+    /// Raises the light at `pos` to `light`, returning whether it was darker.
+    ///
+    /// Positions outside of the chunk are looked up in `outside`.
+    fn raise(&mut self, outside: &mut HashMap<Vec3<i32>, u8>, pos: Vec3<i32>, light: u8) -> bool {
+        let level = match Chunk::<H>::index_of(pos) {
+            Some(idx) => &mut self.levels[idx],
+            None => outside.entry(pos).or_insert(0),
+        };
+        if *level >= light {
+            return false;
+        }
+        *level = light;
+        true
+    }
+
+    pub fn get(&self, pos: Vec3<i32>) -> Option<u8> {
+        Chunk::<H>::index_of(pos).map(|idx| self.levels[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use crate::{
+        block::BlockId,
+        chunk::{Chunk, StandardChunk},
+    };
+
+    use super::{LightMap, MAX_LIGHT};
+
+    /// An empty chunk under a stone roof, so no sunlight reaches inside.
+    fn dark_chunk<const H: usize>() -> Chunk<H> {
+        let mut chunk = Chunk::<H>::flat(BlockId::Air);
+        let top = H as i32 - 1;
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Vec3::new(x, top, z), BlockId::Stone);
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    pub fn torch_lights_neighbours() {
+        let mut chunk: StandardChunk = dark_chunk();
+        chunk.set(Vec3::new(8, 8, 8), BlockId::Torch);
+        let mut lights: LightMap = LightMap::default();
+        lights.propagate_sunlight(&chunk);
+
+        assert_eq!(lights.get(Vec3::new(8, 8, 8)), Some(14));
+        assert_eq!(lights.get(Vec3::new(9, 8, 8)), Some(13));
+        assert_eq!(lights.get(Vec3::new(8, 8, 12)), Some(10));
+        assert_eq!(lights.get(Vec3::new(8, 30, 8)), Some(0));
+    }
+
+    #[test]
+    pub fn torch_light_stops_at_solid_blocks() {
+        let mut chunk: StandardChunk = dark_chunk();
+        chunk.set(Vec3::new(8, 8, 8), BlockId::Torch);
+        chunk.set(Vec3::new(9, 8, 8), BlockId::Stone);
+        let mut lights: LightMap = LightMap::default();
+        lights.propagate_sunlight(&chunk);
+
+        // The stone is lit but the light goes around it
+        assert_eq!(lights.get(Vec3::new(9, 8, 8)), Some(13));
+        assert_eq!(lights.get(Vec3::new(10, 8, 8)), Some(10));
+    }
+
+    #[test]
+    pub fn torch_lights_across_chunk_borders() {
+        let mut west: StandardChunk = dark_chunk();
+        west.set(Vec3::new(15, 8, 8), BlockId::Torch);
+        let east: StandardChunk = dark_chunk();
+
+        let mut lights: LightMap = LightMap::default();
+        lights.propagate_light(&east, &[None, None, None, Some(&west)]);
+        assert_eq!(lights.get(Vec3::new(0, 8, 8)), Some(13));
+        assert_eq!(lights.get(Vec3::new(3, 8, 8)), Some(10));
+        assert_eq!(lights.get(Vec3::new(0, 8, 10)), Some(11));
+
+        // Only lit by the neighbours it is given
+        lights.propagate_sunlight(&east);
+        assert_eq!(lights.get(Vec3::new(0, 8, 8)), Some(0));
+    }
+
+    #[test]
+    pub fn sunlight_is_brighter_than_torches() {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(8, 8, 8), BlockId::Torch);
+        let mut lights: LightMap = LightMap::default();
+        lights.propagate_sunlight(&chunk);
+        assert_eq!(lights.get(Vec3::new(9, 8, 8)), Some(MAX_LIGHT));
+    }
+
+    #[test]
+    pub fn shallow_chunks_are_lit() {
+        let mut chunk: Chunk<64> = dark_chunk();
+        chunk.set(Vec3::new(8, 62, 8), BlockId::Torch);
+        let mut lights = LightMap::<64>::default();
+        lights.propagate_sunlight(&chunk);
+        assert_eq!(lights.get(Vec3::new(9, 62, 8)), Some(13));
+        assert_eq!(lights.get(Vec3::new(8, 61, 8)), Some(13));
+        assert_eq!(lights.get(Vec3::new(8, 64, 8)), None);
+
+        let mut lights = LightMap::<64>::default();
+        lights.propagate_sunlight(&Chunk::<64>::flat(BlockId::Air));
+        assert_eq!(lights.get(Vec3::new(0, 0, 0)), Some(MAX_LIGHT));
+    }
+}
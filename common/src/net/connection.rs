@@ -70,11 +70,24 @@ impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
         }
     }
 
+    /// The underlying socket, to send raw bytes such as a message broadcast to many clients.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// The local address the underlying socket is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetworkError> {
+        self.socket
+            .local_addr()
+            .map_err(|e| NetworkError::IOError(e.kind()))
+    }
+
     fn bind(addr: SocketAddr) -> Result<UdpSocket, NetworkError> {
         socket::bind_udp_socket(addr).map_err(|_| NetworkError::SocketBindError)
     }
 
-    fn serialize(packet: &S) -> Vec<u8> {
+    /// Encodes a packet the way it is sent, e.g. to broadcast it with [`Connection::socket`].
+    pub fn serialize(packet: &S) -> Vec<u8> {
         let writer = bincode::serialize(packet).expect("Failed to serialize packet");
         lz4_compress::compress(&writer)
     }
@@ -1,11 +1,73 @@
 use serde::{Deserialize, Serialize};
-use vek::Vec2;
+use vek::{Vec2, Vec3};
 
-use crate::{block::BlockId, uid::Uid};
+use crate::block::BlockId;
+
+/// Bumped whenever the packets change in a way older clients can't understand.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The first packet a client sends to join a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub version: u32,
+    /// UTF-8 encoded, padded with zeros.
+    pub player_name: [u8; 32],
+}
+
+impl ClientHello {
+    /// Creates a hello for the current protocol version.
+    ///
+    /// Names longer than 32 bytes are truncated.
+    pub fn new(name: &str) -> Self {
+        let mut player_name = [0; 32];
+        let mut len = name.len().min(player_name.len());
+        // Don't cut a character in half
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        player_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self {
+            version: PROTOCOL_VERSION,
+            player_name,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let len = self
+            .player_name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.player_name.len());
+        String::from_utf8_lossy(&self.player_name[..len]).into_owned()
+    }
+}
+
+/// The answer of the server to a [`ClientHello`] it accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServerWelcome {
+    pub player_id: u64,
+    pub spawn_pos: Vec3<f32>,
+}
+
+/// The state of a player at the end of a server tick.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    /// The server tick this snapshot was taken at.
+    pub tick: u64,
+    pub pos: Vec3<f32>,
+}
+
+impl PlayerState {
+    /// The position between this snapshot and the `next` one,
+    /// `alpha` being the fraction of a tick elapsed since `self`.
+    pub fn interpolate(&self, next: &PlayerState, alpha: f32) -> Vec3<f32> {
+        Vec3::lerp(self.pos, next.pos, alpha)
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientPacket {
-    Connect,
+    Hello(ClientHello),
     Disconnect,
     Ping(PingPacket),
     ChunkRequest(Vec2<i32>),
@@ -13,14 +75,17 @@ pub enum ClientPacket {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerPacket {
-    ClientSync {
-        uid: Uid,
+    Welcome(ServerWelcome),
+    /// The client uses a different protocol version and was not accepted.
+    VersionMismatch {
+        server_version: u32,
     },
     Ping(PingPacket),
     ChunkUpdate {
         pos: Vec2<i32>,
         data: Vec<(BlockId, u32)>,
     },
+    PlayerState(PlayerState),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,3 +93,35 @@ pub enum PingPacket {
     Ping,
     Pong,
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use super::{ClientHello, PlayerState};
+
+    #[test]
+    pub fn player_name_round_trip() {
+        assert_eq!(ClientHello::new("Steve").name(), "Steve");
+        let long = "a".repeat(40);
+        assert_eq!(ClientHello::new(&long).name(), "a".repeat(32));
+        // 'é' is two bytes and would be split at byte 32
+        let name = format!("{}é", "a".repeat(31));
+        assert_eq!(ClientHello::new(&name).name(), "a".repeat(31));
+    }
+
+    #[test]
+    pub fn interpolate_player_state() {
+        let prev = PlayerState {
+            tick: 1,
+            pos: Vec3::new(0.0, 10.0, 0.0),
+        };
+        let next = PlayerState {
+            tick: 2,
+            pos: Vec3::new(4.0, 10.0, -2.0),
+        };
+        assert_eq!(prev.interpolate(&next, 0.0), prev.pos);
+        assert_eq!(prev.interpolate(&next, 0.5), Vec3::new(2.0, 10.0, -1.0));
+        assert_eq!(prev.interpolate(&next, 1.0), next.pos);
+    }
+}
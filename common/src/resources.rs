@@ -1,21 +1,308 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
-use vek::Vec2;
+use serde::{Deserialize, Serialize};
+use vek::{Vec2, Vec3};
 
-use crate::{chunk::Chunk, uid::Uid};
+use crate::{
+    block::BlockId,
+    chunk::{Chunk, ChunkColumn, StandardChunk},
+    coords,
+    dir::Direction,
+    light::LightMap,
+    uid::Uid,
+};
 
 /// This resource stores the time passed since the previous tick
 #[derive(Default)]
 pub struct DeltaTime(pub f32);
 
+/// Whether the game is paused, e.g. because the window lost focus.
+///
+/// While paused [`DeltaTime`] is left untouched and the simulation systems don't run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Paused(pub bool);
+
 /// This is the time passed since the game started
 #[derive(Default)]
 pub struct ProgramTime(pub f64);
 
+/// The simulated time of day
+pub struct WorldTime {
+    pub ticks: u64,
+    pub ticks_per_day: u64,
+}
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self {
+            ticks: 0,
+            ticks_per_day: 24000,
+        }
+    }
+}
+
+impl WorldTime {
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// How far into the current day we are, in the range [0, 1).
+    ///
+    /// 0 is midnight and 0.5 is noon.
+    pub fn day_fraction(&self) -> f32 {
+        (self.ticks % self.ticks_per_day) as f32 / self.ticks_per_day as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Weather {
+    #[default]
+    Clear,
+    Rain,
+    Snow,
+}
+
+/// The weather currently active in the world
+#[derive(Debug, Default)]
+pub struct CurrentWeather(pub Weather);
+
+/// Time spent in each system stage during the last tick, in microseconds
+#[derive(Default)]
+pub struct PerformanceStats {
+    pub stage_times: HashMap<&'static str, u64>,
+    /// GPU time of the main render pass, if the GPU supports timestamp queries
+    pub gpu_frame_time_us: Option<f32>,
+    /// Number of chunks with a terrain mesh
+    pub terrain_chunks: usize,
+    /// Number of vertices of all the terrain meshes
+    pub terrain_vertices: usize,
+    pub average_vertices_per_chunk: f32,
+}
+
+impl PerformanceStats {
+    pub fn record(&mut self, stage: &'static str, elapsed: Duration) {
+        self.stage_times.insert(stage, elapsed.as_micros() as u64);
+    }
+}
+
 #[derive(Default)]
 pub struct TerrainMap {
-    pub chunks: HashMap<Vec2<i32>, Chunk>,
+    pub chunks: HashMap<Vec2<i32>, ChunkColumn>,
     pub pending_chunks: HashSet<Vec2<i32>>,
+    pub lights: HashMap<Vec2<i32>, LightMap>,
+    /// Chunks that changed since they were last meshed
+    pub dirty: HashSet<Vec2<i32>>,
+}
+
+impl TerrainMap {
+    /// Iterates over the chunk columns whose Chebyshev distance to `centre` is at most `radius`,
+    /// i.e. the square of `2 * radius + 1` chunks around it.
+    pub fn columns_in_radius(centre: Vec2<i32>, radius: u32) -> impl Iterator<Item = Vec2<i32>> {
+        let radius = radius as i32;
+        (-radius..=radius)
+            .flat_map(move |dx| (-radius..=radius).map(move |dz| centre + Vec2::new(dx, dz)))
+    }
+
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Number of blocks stored by the loaded chunks, air included.
+    pub fn total_block_count(&self) -> u64 {
+        self.section_count() as u64 * StandardChunk::VOLUME as u64
+    }
+
+    fn section_count(&self) -> usize {
+        self.chunks.values().map(|column| column.sections.len()).sum()
+    }
+
+    /// Rough estimate of the memory used by the loaded chunks and their light maps, in bytes.
+    pub fn memory_estimate_bytes(&self) -> u64 {
+        let chunk =
+            std::mem::size_of::<Chunk>() + StandardChunk::VOLUME * std::mem::size_of::<BlockId>();
+        let lights = std::mem::size_of::<LightMap>() + StandardChunk::VOLUME;
+        (self.section_count() * chunk + self.lights.len() * lights) as u64
+    }
+
+    /// Returns the position of the chunk that contains the given world block position.
+    pub fn chunk_pos(world_pos: Vec3<i32>) -> Vec2<i32> {
+        Vec2::new(
+            world_pos.x.div_euclid(StandardChunk::SIZE.x as i32),
+            world_pos.z.div_euclid(StandardChunk::SIZE.z as i32),
+        )
+    }
+
+    /// Maps a world block position to the local position inside its chunk.
+    ///
+    /// Euclidean modulo is used so negative world positions map to the right local block.
+    pub fn local_pos(world_pos: Vec3<i32>) -> Vec3<i32> {
+        Vec3::new(
+            world_pos.x.rem_euclid(StandardChunk::SIZE.x as i32),
+            world_pos.y,
+            world_pos.z.rem_euclid(StandardChunk::SIZE.z as i32),
+        )
+    }
+
+    /// Returns the block at the given world position, or `None` if its chunk is not loaded.
+    pub fn get_block(&self, world_pos: Vec3<i32>) -> Option<BlockId> {
+        let column = self.chunks.get(&Self::chunk_pos(world_pos))?;
+        let local = Self::local_pos(world_pos);
+        column.get_block(local.y, local.x as u8, local.z as u8)
+    }
+
+    /// Replaces the block at the given world position and returns the previous one.
+    ///
+    /// The chunk containing the block is marked dirty so it gets re-meshed.
+    pub fn set_block(&mut self, world_pos: Vec3<i32>, id: BlockId) -> Option<BlockId> {
+        let chunk_pos = Self::chunk_pos(world_pos);
+        let column = self.chunks.get_mut(&chunk_pos)?;
+        let local = Self::local_pos(world_pos);
+        let old = column.set_block(local.y, local.x as u8, local.z as u8, id)?;
+        self.dirty.insert(chunk_pos);
+        Some(old)
+    }
+
+    /// Returns the loaded non-air blocks overlapping the box from `min` to `max`,
+    /// in world space.
+    ///
+    /// Only blocks intersecting the inside of the box are returned, so a box ending exactly
+    /// on a block boundary doesn't include the blocks it touches. Blocks of unloaded chunks
+    /// are skipped.
+    pub fn query_aabb(&self, min: Vec3<f32>, max: Vec3<f32>) -> Vec<(Vec3<i32>, BlockId)> {
+        let start = coords::world_to_block(min);
+        let end = max.map(|x| x.ceil() as i32);
+        let mut blocks = Vec::new();
+        for x in start.x..end.x {
+            for y in start.y..end.y {
+                for z in start.z..end.z {
+                    let pos = Vec3::new(x, y, z);
+                    match self.get_block(pos) {
+                        Some(id) if !id.is_air() => blocks.push((pos, id)),
+                        _ => {},
+                    }
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Returns the positions of the blocks overlapping the box from `min` to `max`
+    /// that entities collide with.
+    ///
+    /// See [`TerrainMap::query_aabb`].
+    pub fn query_aabb_solid(&self, min: Vec3<f32>, max: Vec3<f32>) -> Vec<Vec3<i32>> {
+        self.query_aabb(min, max)
+            .into_iter()
+            .filter(|(_, id)| id.is_solid())
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
+    /// Returns the light level at the given world position,
+    /// or `None` if its chunk has not been lit yet.
+    pub fn light_at(&self, world_pos: Vec3<i32>) -> Option<u8> {
+        let light = self.lights.get(&Self::chunk_pos(world_pos))?;
+        light.get(Self::local_pos(world_pos))
+    }
+
+    /// Recomputes the lighting of a loaded chunk, including the light its loaded
+    /// neighbours spread across the borders, and marks it dirty.
+    pub fn relight(&mut self, chunk_pos: Vec2<i32>) {
+        let Some(chunk) = self.chunks.get(&chunk_pos).and_then(ChunkColumn::ground) else {
+            return;
+        };
+        let neighbors = Self::neighbors(chunk_pos)
+            .map(|pos| self.chunks.get(&pos).and_then(ChunkColumn::ground));
+        self.lights
+            .entry(chunk_pos)
+            .or_default()
+            .propagate_light(chunk, &neighbors);
+        self.dirty.insert(chunk_pos);
+    }
+
+    /// Re-lights a chunk and its loaded neighbours, which its light may reach.
+    pub fn relight_around(&mut self, chunk_pos: Vec2<i32>) {
+        self.relight(chunk_pos);
+        for pos in Self::neighbors(chunk_pos) {
+            self.relight(pos);
+        }
+    }
+
+    /// The positions of the chunks next to `chunk_pos`, north, south, east and west.
+    fn neighbors(chunk_pos: Vec2<i32>) -> [Vec2<i32>; 4] {
+        [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ]
+        .map(|dir| chunk_pos + Vec2::new(dir.vec().x, dir.vec().z))
+    }
+
+    /// Casts a ray through the loaded terrain and returns the first solid block it hits
+    /// along with the normal of the face the ray entered through.
+    ///
+    /// The ray is traversed one block at a time (DDA), so it crosses chunk borders seamlessly.
+    /// Returns `None` if nothing is hit within `max_steps` blocks.
+    pub fn ray_to_block(
+        &self,
+        origin: Vec3<f32>,
+        direction: Vec3<f32>,
+        max_steps: u32,
+    ) -> Option<(Vec3<i32>, Vec3<i32>)> {
+        if direction.magnitude_squared() == 0.0 {
+            return None;
+        }
+        let dir = direction.normalized();
+        let mut pos = coords::world_to_block(origin);
+        let step = dir.map(|x| {
+            if x > 0.0 {
+                1
+            } else if x < 0.0 {
+                -1
+            } else {
+                0
+            }
+        });
+        // How far along the ray we have to move to cross a whole block on each axis
+        let t_delta = dir.map(|x| if x != 0.0 { (1.0 / x).abs() } else { f32::INFINITY });
+        // How far along the ray the next block boundary is on each axis
+        let mut t_max = Vec3::<f32>::zero();
+        for axis in 0..3 {
+            t_max[axis] = match step[axis] {
+                1 => (pos[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis],
+                -1 => (origin[axis] - pos[axis] as f32) * t_delta[axis],
+                _ => f32::INFINITY,
+            };
+        }
+
+        let mut normal = Vec3::zero();
+        for _ in 0..max_steps {
+            if self.get_block(pos).is_some_and(BlockId::is_solid) {
+                return Some((pos, normal));
+            }
+            // Step into the next block along the axis with the closest boundary
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z {
+                    0
+                } else {
+                    2
+                }
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = Vec3::zero();
+            normal[axis] = -step[axis];
+        }
+        None
+    }
 }
 
 #[derive(Default)]
@@ -69,3 +356,141 @@ impl Default for TerrainConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::BlockId,
+        chunk::{ChunkColumn, StandardChunk},
+        resources::TerrainMap,
+    };
+
+    #[test]
+    pub fn columns_in_radius() {
+        let columns = TerrainMap::columns_in_radius(Vec2::zero(), 0).collect::<Vec<_>>();
+        assert_eq!(columns, vec![Vec2::zero()]);
+        assert_eq!(TerrainMap::columns_in_radius(Vec2::zero(), 1).count(), 9);
+
+        let centre = Vec2::new(5, 5);
+        let columns = TerrainMap::columns_in_radius(centre, 2).collect::<Vec<_>>();
+        assert_eq!(columns.len(), 25);
+        for pos in &columns {
+            assert!((3..=7).contains(&pos.x) && (3..=7).contains(&pos.y));
+        }
+        let unique = columns.iter().collect::<std::collections::HashSet<_>>();
+        assert_eq!(unique.len(), 25);
+    }
+
+    #[test]
+    pub fn loaded_chunks_are_counted() {
+        let mut terrain = TerrainMap::default();
+        assert_eq!(terrain.loaded_chunk_count(), 0);
+        assert_eq!(terrain.memory_estimate_bytes(), 0);
+        for x in 0..4 {
+            terrain
+                .chunks
+                .insert(Vec2::new(x, 0), StandardChunk::flat(BlockId::Air).into());
+        }
+        assert_eq!(terrain.loaded_chunk_count(), 4);
+        assert_eq!(terrain.total_block_count(), 4 * 16 * 256 * 16);
+        assert!(terrain.memory_estimate_bytes() >= terrain.total_block_count());
+    }
+
+    #[test]
+    pub fn blocks_above_the_first_section_are_stored() {
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::new(-1, 0), ChunkColumn::flat(BlockId::Air, 2));
+        assert_eq!(terrain.total_block_count(), 2 * 16 * 256 * 16);
+
+        let pos = Vec3::new(-3, 300, 5);
+        assert_eq!(terrain.set_block(pos, BlockId::Stone), Some(BlockId::Air));
+        assert_eq!(terrain.get_block(pos), Some(BlockId::Stone));
+        assert_eq!(terrain.get_block(pos - Vec3::unit_y() * 256), Some(BlockId::Air));
+        assert_eq!(terrain.get_block(Vec3::new(-3, 512, 5)), None);
+        assert!(terrain.dirty.contains(&Vec2::new(-1, 0)));
+        let column = &terrain.chunks[&Vec2::new(-1, 0)];
+        assert_eq!(
+            column.sections[1].get(Vec3::new(13, 300 - 256, 5)),
+            Some(BlockId::Stone)
+        );
+    }
+
+    fn flat_terrain(chunk_pos: Vec2<i32>, surface: i32) -> TerrainMap {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        for pos in chunk.iter() {
+            if pos.y <= surface {
+                chunk.set(pos, BlockId::Stone);
+            }
+        }
+        let mut terrain = TerrainMap::default();
+        terrain.chunks.insert(chunk_pos, chunk.into());
+        terrain
+    }
+
+    #[test]
+    pub fn ray_hits_flat_surface() {
+        let terrain = flat_terrain(Vec2::zero(), 4);
+        let hit = terrain.ray_to_block(Vec3::new(8.0, 10.0, 8.0), -Vec3::unit_y(), 10);
+        assert_eq!(hit, Some((Vec3::new(8, 4, 8), Vec3::unit_y())));
+    }
+
+    #[test]
+    pub fn ray_passes_through_water() {
+        let mut terrain = flat_terrain(Vec2::zero(), 4);
+        terrain.set_block(Vec3::new(8, 5, 8), BlockId::Water);
+        terrain.set_block(Vec3::new(8, 6, 8), BlockId::Water);
+        let hit = terrain.ray_to_block(Vec3::new(8.0, 10.0, 8.0), -Vec3::unit_y(), 10);
+        assert_eq!(hit, Some((Vec3::new(8, 4, 8), Vec3::unit_y())));
+    }
+
+    #[test]
+    pub fn ray_hits_in_negative_chunk() {
+        let terrain = flat_terrain(Vec2::new(-1, -1), 4);
+        let hit = terrain.ray_to_block(Vec3::new(-8.5, 10.0, -8.5), -Vec3::unit_y(), 10);
+        assert_eq!(hit, Some((Vec3::new(-9, 4, -9), Vec3::unit_y())));
+    }
+
+    #[test]
+    pub fn aabb_query_finds_solid_blocks() {
+        let mut terrain = flat_terrain(Vec2::zero(), 4);
+        terrain.set_block(Vec3::new(8, 5, 8), BlockId::Water);
+        // The 3x3x3 blocks from (7, 3, 7) to (9, 5, 9)
+        let min = Vec3::new(7.0, 3.0, 7.0);
+        let max = Vec3::new(10.0, 6.0, 10.0);
+
+        let mut expected = Vec::new();
+        for x in 7..10 {
+            for y in 3..5 {
+                for z in 7..10 {
+                    expected.push(Vec3::new(x, y, z));
+                }
+            }
+        }
+        assert_eq!(terrain.query_aabb_solid(min, max), expected);
+
+        let blocks = terrain.query_aabb(min, max);
+        assert_eq!(blocks.len(), expected.len() + 1);
+        assert!(blocks.contains(&(Vec3::new(8, 5, 8), BlockId::Water)));
+    }
+
+    #[test]
+    pub fn aabb_query_skips_unloaded_chunks() {
+        let terrain = flat_terrain(Vec2::zero(), 4);
+        let blocks = terrain.query_aabb_solid(Vec3::new(-1.5, 4.5, 0.5), Vec3::new(0.5, 5.0, 1.0));
+        assert_eq!(blocks, vec![Vec3::new(0, 4, 0)]);
+        assert!(terrain
+            .query_aabb(Vec3::new(-8.0, 0.0, -8.0), Vec3::new(-1.0, 8.0, -1.0))
+            .is_empty());
+    }
+
+    #[test]
+    pub fn ray_gives_up_after_max_steps() {
+        let terrain = flat_terrain(Vec2::zero(), 4);
+        let hit = terrain.ray_to_block(Vec3::new(8.0, 100.0, 8.0), -Vec3::unit_y(), 10);
+        assert_eq!(hit, None);
+    }
+}
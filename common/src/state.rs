@@ -1,8 +1,13 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use apecs::{CanFetch, ShouldContinue, Write};
 
 use crate::{
     event::{Event, Events},
-    resources::{DeltaTime, EntityMap, GameMode, Ping, ProgramTime, TerrainMap},
+    resources::{
+        DeltaTime, EntityMap, GameMode, Paused, PerformanceStats, Ping, ProgramTime, TerrainMap,
+    },
+    SysResult,
 };
 
 pub struct State {
@@ -28,13 +33,16 @@ impl State {
             .with_default_resource::<TerrainMap>()?
             .with_default_resource::<EntityMap>()?
             .with_default_resource::<Ping>()?
+            .with_default_resource::<Paused>()?
             .with_resource(mode)?;
 
         Ok(Self { world })
     }
 
     pub fn tick(&mut self, dt: Duration) {
-        self.resource_mut::<DeltaTime>().0 = dt.as_secs_f32();
+        if !self.resource::<Paused>().0 {
+            self.resource_mut::<DeltaTime>().0 = dt.as_secs_f32();
+        }
         self.resource_mut::<ProgramTime>().0 += dt.as_secs_f64();
 
         if let Err(e) = self.world.tick() {
@@ -101,3 +109,121 @@ pub fn print_system_schedule(world: &mut apecs::World) {
         log::debug!("{}: {:?}", i, system);
     }
 }
+
+/// Wraps a system so the time it takes to run is written to [`PerformanceStats`] under `stage`.
+pub fn timed<T, F>(
+    stage: &'static str,
+    mut system: F,
+) -> impl FnMut((T, Write<PerformanceStats>)) -> SysResult + Send + Sync + 'static
+where
+    T: CanFetch,
+    F: FnMut(T) -> SysResult + Send + Sync + 'static,
+{
+    move |(data, mut stats)| {
+        let start = Instant::now();
+        let result = system(data);
+        stats.record(stage, start.elapsed());
+        result
+    }
+}
+
+/// Helpers to build the [`ShouldContinue`] value returned by systems.
+pub trait ShouldContinueExt: Sized {
+    /// `Yes` when `b` is true, `No` otherwise.
+    fn from_bool(b: bool) -> Self;
+
+    /// `No` when `condition` is true, `Yes` otherwise.
+    ///
+    /// Returning `No` only unschedules the system that returned it, it doesn't exit the game.
+    fn stop_if(condition: bool) -> Self {
+        Self::from_bool(!condition)
+    }
+
+    /// `No` if either of the two is `No`.
+    fn and(self, other: Self) -> Self;
+}
+
+impl ShouldContinueExt for ShouldContinue {
+    fn from_bool(b: bool) -> Self {
+        if b {
+            ShouldContinue::Yes
+        } else {
+            ShouldContinue::No
+        }
+    }
+
+    fn and(self, other: Self) -> Self {
+        Self::from_bool(matches!(self, ShouldContinue::Yes) && matches!(other, ShouldContinue::Yes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apecs::{ok, Read, ShouldContinue};
+
+    use crate::resources::{DeltaTime, PerformanceStats};
+
+    use super::{timed, ShouldContinueExt};
+
+    #[test]
+    pub fn should_continue_and() {
+        let yes = || ShouldContinue::Yes;
+        let no = || ShouldContinue::No;
+        assert!(matches!(yes().and(yes()), ShouldContinue::Yes));
+        assert!(matches!(yes().and(no()), ShouldContinue::No));
+        assert!(matches!(no().and(yes()), ShouldContinue::No));
+        assert!(matches!(no().and(no()), ShouldContinue::No));
+    }
+
+    #[test]
+    pub fn should_continue_stop_if() {
+        assert!(matches!(ShouldContinue::stop_if(true), ShouldContinue::No));
+        assert!(matches!(
+            ShouldContinue::stop_if(false),
+            ShouldContinue::Yes
+        ));
+        assert!(matches!(
+            ShouldContinue::from_bool(true),
+            ShouldContinue::Yes
+        ));
+        assert!(matches!(
+            ShouldContinue::from_bool(false),
+            ShouldContinue::No
+        ));
+    }
+
+    #[test]
+    pub fn timed_systems_record_their_stage() {
+        const FIRST: &str = "first";
+        const SECOND: &str = "second";
+        let mut world = apecs::World::default();
+        world
+            .with_system(FIRST, timed(FIRST, |_: Read<DeltaTime>| ok()))
+            .unwrap()
+            .with_system_with_dependencies(
+                SECOND,
+                timed(SECOND, |_: Read<DeltaTime>| ok()),
+                &[FIRST],
+                &[],
+            )
+            .unwrap();
+        for _ in 0..10 {
+            world.tick().unwrap();
+        }
+        let mut registered = world
+            .get_sync_schedule_names()
+            .into_iter()
+            .flatten()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        registered.sort_unstable();
+        let stats = world.resource::<PerformanceStats>().unwrap();
+        let mut recorded = stats
+            .stage_times
+            .keys()
+            .map(|stage| stage.to_string())
+            .collect::<Vec<_>>();
+        recorded.sort_unstable();
+        assert_eq!(recorded, registered);
+    }
+}
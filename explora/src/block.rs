@@ -7,10 +7,34 @@ use common::block::BlockId;
 use log::info;
 use serde::{Deserialize, Serialize};
 
+use crate::render::atlas::BlockAtlas;
+
+/// How a block is turned into geometry by the mesher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MeshType {
+    /// A solid cube, whose hidden faces are culled.
+    #[default]
+    Cube,
+    /// Two crossed quads, used for vegetation such as grass and flowers.
+    Cross,
+    /// The block is not rendered.
+    None,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlockDescriptor {
     pub name: String,
     pub textures: Textures,
+    #[serde(default)]
+    pub mesh_type: MeshType,
+    /// Number of frames stacked vertically in the textures of an animated block.
+    /// 0 and 1 mean the block is not animated.
+    #[serde(default)]
+    pub animation_frames: u8,
+    /// Whether the block mirrors the environment map, like water.
+    #[serde(default)]
+    pub reflective: bool,
 }
 
 impl BlockDescriptor {
@@ -93,7 +117,45 @@ impl BlockMap {
         self.blocks.get(&id)
     }
 
+    /// Whether the block fully hides the faces of the blocks next to it.
+    pub fn occludes(&self, id: BlockId) -> bool {
+        !id.is_air()
+            && self
+                .get(id)
+                .is_none_or(|block| block.mesh_type == MeshType::Cube)
+    }
+
     pub fn textures(&self) -> &[String] {
         &self.textures
     }
+
+    /// The tile the terrain shader draws reflections on.
+    ///
+    /// The shader only knows about one reflective tile, the top texture of the first
+    /// reflective block found.
+    pub fn reflective_tile(&self, atlas: &BlockAtlas) -> Option<u16> {
+        let block = self.blocks.values().find(|block| block.reflective)?;
+        let (top, _, _) = block.textures();
+        Some(atlas.get_texture_id(top))
+    }
+
+    /// Warns about the blocks whose textures do not have the number of frames they declare.
+    pub fn check_animations(&self, atlas: &BlockAtlas) {
+        for block in self.blocks.values() {
+            let expected = block.animation_frames.max(1) as u32;
+            let (top, side, bottom) = block.textures();
+            for texture in [top, side, bottom] {
+                let frames = atlas.frame_count(texture);
+                if frames != expected {
+                    log::warn!(
+                        "Block {} declares {} animation frames but its texture `{}` has {}",
+                        block.name,
+                        expected,
+                        texture,
+                        frames
+                    );
+                }
+            }
+        }
+    }
 }
@@ -1,7 +1,8 @@
-use vek::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use vek::{Mat4, Vec2, Vec3, Vec4};
 
-const Z_NEAR: f32 = 0.1;
-const Z_FAR: f32 = 1000.0;
+pub const Z_NEAR: f32 = 0.1;
+pub const Z_FAR: f32 = 1000.0;
 
 pub struct Plane {
     pub normal: Vec3<f32>,
@@ -15,6 +16,72 @@ impl Plane {
             distance: point.dot(normal),
         }
     }
+
+    /// The plane `a*x + b*y + c*z + d = 0`, with the normal towards the positive side.
+    pub fn from_coefficients(coefficients: Vec4<f32>) -> Self {
+        let normal = coefficients.xyz();
+        let length = normal.magnitude();
+        Self {
+            normal: normal / length,
+            distance: -coefficients.w / length,
+        }
+    }
+
+    /// The distance of `point` to the plane, negative behind it.
+    pub fn signed_distance(&self, point: Vec3<f32>) -> f32 {
+        self.normal.dot(point) - self.distance
+    }
+}
+
+/// Where a box is relative to a [`Frustum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrustumTest {
+    Inside,
+    Outside,
+    Intersects,
+}
+
+/// The volume seen by a camera, bounded by six planes facing inwards.
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view-projection matrix with the Gribb-Hartmann method.
+    ///
+    /// The matrix must map the depth to -1..1, like [`Mat4::perspective_lh_no`].
+    pub fn from_view_proj(view_proj: Mat4<f32>) -> Self {
+        let [x, y, z, w] = view_proj.into_row_arrays().map(Vec4::from);
+        Self {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z].map(Plane::from_coefficients),
+        }
+    }
+
+    /// Tests the axis-aligned box between `min` and `max` against every plane.
+    pub fn test_aabb(&self, min: Vec3<f32>, max: Vec3<f32>) -> FrustumTest {
+        let mut result = FrustumTest::Inside;
+        for plane in &self.planes {
+            // The corners furthest along and against the normal
+            let furthest = Vec3::new(
+                if plane.normal.x >= 0.0 { max.x } else { min.x },
+                if plane.normal.y >= 0.0 { max.y } else { min.y },
+                if plane.normal.z >= 0.0 { max.z } else { min.z },
+            );
+            let nearest = Vec3::new(
+                if plane.normal.x >= 0.0 { min.x } else { max.x },
+                if plane.normal.y >= 0.0 { min.y } else { max.y },
+                if plane.normal.z >= 0.0 { min.z } else { max.z },
+            );
+            if plane.signed_distance(furthest) < 0.0 {
+                return FrustumTest::Outside;
+            }
+            if plane.signed_distance(nearest) < 0.0 {
+                result = FrustumTest::Intersects;
+            }
+        }
+        result
+    }
 }
 
 pub struct Matrices {
@@ -22,7 +89,19 @@ pub struct Matrices {
     pub proj: Mat4<f32>,
 }
 
+#[derive(Debug)]
+pub enum CameraDeserialiseError {
+    Json(serde_json::Error),
+}
+
+impl From<serde_json::Error> for CameraDeserialiseError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
 /// Represents a camera in 3D space.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Camera {
     /// The position of the camera in world space.
     pos: Vec3<f32>,
@@ -36,29 +115,52 @@ pub struct Camera {
     /// The pitch is how much we are looking up or down.
     /// The yaw is how much we are looking left or right.
     rot: Vec2<f32>,
+    /// Derived from the fov and aspect ratio, so it is rebuilt after loading.
+    #[serde(skip)]
     proj: Mat4<f32>,
 }
 
 impl Default for Camera {
     fn default() -> Self {
-        Self {
+        let mut camera = Self {
             pos: Vec3::new(0.0, 257.0, 0.0),
             aspect: 1.0,
             fov: 70.0,
             rot: Vec2::new(-46.0, 0.0),
             proj: Mat4::identity(),
-        }
+        };
+        // Culling with an identity projection would hide the terrain until the first resize
+        camera.rebuild_projection();
+        camera
     }
 }
 impl Camera {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Camera is always serializable")
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, CameraDeserialiseError> {
+        let mut camera = serde_json::from_str::<Camera>(s)?;
+        camera.rebuild_projection();
+        Ok(camera)
+    }
+
     pub fn compute_matrices(&mut self) -> Matrices {
-        let view = Mat4::look_at_lh(self.pos, self.pos + self.forward(), Vec3::unit_y());
         Matrices {
-            view,
+            view: self.view(),
             proj: self.proj,
         }
     }
 
+    /// The planes bounding what the camera sees, in world space.
+    pub fn compute_view_frustum(&self) -> Frustum {
+        Frustum::from_view_proj(self.proj * self.view())
+    }
+
+    fn view(&self) -> Mat4<f32> {
+        Mat4::look_at_lh(self.pos, self.pos + self.forward(), Vec3::unit_y())
+    }
+
     pub fn move_by(&mut self, dx: f32, dy: f32, dz: f32) {
         self.pos += dz * self.forward_xz() + -dx * self.right() + Vec3::unit_y() * dy;
     }
@@ -124,7 +226,80 @@ impl Camera {
         self.pos
     }
 
+    pub fn set_pos(&mut self, pos: Vec3<f32>) {
+        self.pos = pos;
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.rot.x
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.rot.y
+    }
+
+    pub fn set_rotation(&mut self, yaw: f32, pitch: f32) {
+        self.rot = Vec2::new(yaw, pitch);
+    }
+
     fn rebuild_projection(&mut self) {
         self.proj = Mat4::perspective_lh_no(self.fov.to_radians(), self.aspect, Z_NEAR, Z_FAR)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use super::{Camera, FrustumTest};
+
+    #[test]
+    pub fn frustum_culls_boxes_behind_the_camera() {
+        let mut camera = Camera::default();
+        camera.set_pos(Vec3::zero());
+        camera.set_rotation(0.0, 0.0);
+        camera.set_aspect_ratio(16.0 / 9.0);
+        let frustum = camera.compute_view_frustum();
+
+        let ahead = camera.forward() * 10.0;
+        assert_eq!(
+            frustum.test_aabb(ahead - 0.5, ahead + 0.5),
+            FrustumTest::Inside
+        );
+        let behind = camera.forward() * -1000.0;
+        assert_eq!(
+            frustum.test_aabb(behind - 0.5, behind + 0.5),
+            FrustumTest::Outside
+        );
+        // Crosses the near plane
+        assert_eq!(
+            frustum.test_aabb(Vec3::broadcast(-5.0), Vec3::broadcast(5.0)),
+            FrustumTest::Intersects
+        );
+        let beyond_far_plane = camera.forward() * 2000.0;
+        assert_eq!(
+            frustum.test_aabb(beyond_far_plane - 0.5, beyond_far_plane + 0.5),
+            FrustumTest::Outside
+        );
+    }
+
+    #[test]
+    pub fn json_round_trip() {
+        let mut camera = Camera::default();
+        camera.set_pos(Vec3::new(-12.5, 90.0, 33.0));
+        camera.set_rotation(1.25, -0.4);
+        camera.set_fov(85.0);
+        camera.set_aspect_ratio(16.0 / 9.0);
+
+        let mut loaded = Camera::from_json(&camera.to_json()).unwrap();
+        let expected = camera.compute_matrices();
+        let actual = loaded.compute_matrices();
+        assert_eq!(actual.view, expected.view);
+        assert_eq!(actual.proj, expected.proj);
+    }
+
+    #[test]
+    pub fn invalid_json_is_an_error() {
+        assert!(Camera::from_json("{\"pos\": 3}").is_err());
+    }
+}
@@ -1,5 +1,32 @@
+use common::net::error::NetworkError;
+
 #[derive(Debug)]
 pub enum Error {
-    ServerTimeout,
+    Connect(ConnectError),
     Other(String),
 }
+
+/// Reasons the handshake with a server can fail.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The server did not answer within the timeout.
+    Timeout,
+    /// The server runs a different protocol version.
+    VersionMismatch {
+        client: u32,
+        server: u32,
+    },
+    Network(NetworkError),
+}
+
+impl From<NetworkError> for ConnectError {
+    fn from(value: NetworkError) -> Self {
+        Self::Network(value)
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(value: ConnectError) -> Self {
+        Self::Connect(value)
+    }
+}
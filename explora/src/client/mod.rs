@@ -1,20 +1,67 @@
 pub mod error;
 
-use std::{io::ErrorKind, net::SocketAddr, time::Duration};
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 use common::{
+    clock::TICK_DURATION,
     components::Pos,
     net::{
         connection::Connection,
         error::NetworkError,
-        packet::{ClientPacket, PingPacket, ServerPacket},
+        packet::{
+            ClientHello, ClientPacket, PingPacket, PlayerState, ServerPacket, ServerWelcome,
+            PROTOCOL_VERSION,
+        },
     },
-    resources::{Ping, ProgramTime, TerrainConfig, TerrainMap},
+    resources::{Ping, ProgramTime, TerrainMap},
     state::State,
+    uid::Uid,
 };
 use log::info;
-
-use self::error::Error;
+use vek::Vec3;
+
+use self::error::{ConnectError, Error};
+
+/// How long to wait for the server to answer our hello.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Performs the handshake with the server at `addr`.
+///
+/// Sends a [`ClientHello`] and waits up to 5 seconds for the [`ServerWelcome`].
+pub fn connect(
+    addr: SocketAddr,
+    name: &str,
+) -> Result<(Connection<ClientPacket, ServerPacket>, ServerWelcome), ConnectError> {
+    let connection: Connection<ClientPacket, ServerPacket> = Connection::connect(addr)?;
+    info!("Connecting to {}", addr);
+    connection.send(ClientPacket::Hello(ClientHello::new(name)))?;
+    let instant = std::time::Instant::now();
+
+    loop {
+        match connection.recv() {
+            Ok((packet, _)) => match packet {
+                ServerPacket::Welcome(welcome) => return Ok((connection, welcome)),
+                ServerPacket::VersionMismatch { server_version } => {
+                    return Err(ConnectError::VersionMismatch {
+                        client: PROTOCOL_VERSION,
+                        server: server_version,
+                    });
+                },
+                _ => (),
+            },
+            Err(NetworkError::IOError(ErrorKind::WouldBlock)) => {
+                if instant.elapsed() > CONNECT_TIMEOUT {
+                    return Err(ConnectError::Timeout);
+                }
+            },
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
 
 pub struct Client {
     connection: Connection<ClientPacket, ServerPacket>,
@@ -23,45 +70,23 @@ pub struct Client {
     last_ping_time: f64,
     packet_count: usize,
     last_chunk_request_time: f64,
+    /// The entity id of the player.
+    player: usize,
+    /// The two latest snapshots of the player sent by the server, oldest first,
+    /// and when the latest one arrived.
+    player_snapshots: Option<(PlayerState, PlayerState, Instant)>,
 }
 
 impl Client {
-    pub fn new(host: SocketAddr) -> Result<Self, Error> {
-        let connection: Connection<ClientPacket, ServerPacket> = Connection::connect(host).unwrap();
-        info!("Connecting to {}", host);
-        connection.send(ClientPacket::Connect).unwrap();
+    pub fn new(host: SocketAddr, name: &str) -> Result<Self, Error> {
+        let (connection, welcome) = connect(host, name)?;
+        log::info!("Joined to game with uid {}", welcome.player_id);
         let mut state = State::client().expect("Failed to create client state");
-        let instant = std::time::Instant::now();
-
-        loop {
-            match connection.recv() {
-                Ok((packet, addr)) => {
-                    log::info!("Received packet from {}: {:?}", addr, packet);
-                    match packet {
-                        ServerPacket::ClientSync { uid } => {
-                            log::info!("Joined to game with uid {}", uid);
-                            let entity = state.ecs_mut().entity();
-                            entity.with_bundle((Pos::default(), uid));
-                            break;
-                        },
-                        ServerPacket::Ping(_) => {},
-                        _ => (),
-                    }
-                },
-                // TODO: return errors instead of panicking
-                Err(NetworkError::IOError(ErrorKind::WouldBlock)) => {
-                    if instant.elapsed() > Duration::from_secs(5) {
-                        return Err(Error::ServerTimeout);
-                    }
-                },
-                Err(err) => {
-                    return Err(Error::Other(format!(
-                        "Failed to receive initial sync packet: {:?}",
-                        err
-                    )));
-                },
-            }
-        }
+        let player = state
+            .ecs_mut()
+            .entity()
+            .with_bundle((Pos(welcome.spawn_pos), Uid(welcome.player_id)))
+            .id();
 
         Ok(Self {
             connection,
@@ -69,6 +94,8 @@ impl Client {
             last_ping_time: 0.0,
             packet_count: 0,
             last_chunk_request_time: 0.0,
+            player,
+            player_snapshots: None,
         })
     }
 
@@ -93,17 +120,22 @@ impl Client {
                         self.state.program_time() - self.last_ping_time;
                 },
                 ServerPacket::ChunkUpdate { pos, data } => {
-                    let chunk = common::chunk::decompress(&data);
                     let terrain = self.state.resource_mut::<TerrainMap>();
-                    let old = terrain.chunks.insert(pos, chunk);
-                    if let Some(old) = old {
-                        log::warn!("Overwriting chunk at {:?} with new chunk", pos);
+                    // Changed chunks are sent to every client, even those that didn't load them
+                    let requested = terrain.pending_chunks.remove(&pos);
+                    if requested || terrain.chunks.contains_key(&pos) {
+                        let chunk = common::chunk::decompress(&data);
+                        terrain.chunks.insert(pos, chunk.into());
+                        terrain.relight_around(pos);
                     }
-                    terrain.pending_chunks.remove(&pos);
                 },
+                ServerPacket::PlayerState(state) => self.push_player_snapshot(state),
                 _ => (),
             }
         }
+        if let Some(pos) = self.interpolated_player_pos() {
+            self.state.ecs_mut().insert_component(self.player, Pos(pos));
+        }
         // this may run multiple times until the chunk arrives
         // so we'll throttle the chunk requests.
         if self.state.program_time() - self.last_chunk_request_time < 0.1 {
@@ -120,6 +152,26 @@ impl Client {
         }
     }
 
+    fn push_player_snapshot(&mut self, state: PlayerState) {
+        let prev = match self.player_snapshots {
+            // Snapshots can arrive out of order
+            Some((_, latest, _)) if state.tick <= latest.tick => return,
+            Some((_, latest, _)) => latest,
+            None => state,
+        };
+        self.player_snapshots = Some((prev, state, Instant::now()));
+    }
+
+    /// The position of the player between the two latest server snapshots.
+    ///
+    /// The server ticks at a fixed rate, so we render one tick behind it and move
+    /// from the previous snapshot to the latest one during the tick following its arrival.
+    pub fn interpolated_player_pos(&self) -> Option<Vec3<f32>> {
+        let (prev, latest, received) = self.player_snapshots?;
+        let alpha = received.elapsed().as_secs_f32() / TICK_DURATION.as_secs_f32();
+        Some(prev.interpolate(&latest, alpha.min(1.0)))
+    }
+
     pub fn send_packet(&self, packet: ClientPacket) {
         if let Err(e) = self.connection.send(packet) {
             log::error!("Failed to send packet: {:?}", e);
@@ -1,3 +1,5 @@
+use crate::{client::error::Error as ClientError, render::error::RenderError, save::SaveError};
+
 #[derive(Debug)]
 pub enum Error {
     Window(winit::error::OsError),
@@ -8,3 +10,84 @@ impl From<winit::error::OsError> for Error {
         Self::Window(value)
     }
 }
+
+/// Every error that can stop the game.
+#[derive(Debug)]
+pub enum AppError {
+    Window(Error),
+    Render(RenderError),
+    Save(SaveError),
+    Network(std::io::Error),
+    Client(ClientError),
+    EventLoop(winit::error::EventLoopError),
+    Ecs(apecs::anyhow::Error),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Window(Error::Window(e)) => write!(f, "Failed to create the window: {}", e),
+            AppError::Render(e) => write!(f, "Failed to render: {}", e),
+            AppError::Save(e) => write!(f, "Failed to access the save: {:?}", e),
+            AppError::Network(e) => write!(f, "Network error: {}", e),
+            AppError::Client(e) => write!(f, "Failed to join the server: {:?}", e),
+            AppError::EventLoop(e) => write!(f, "The event loop stopped: {}", e),
+            AppError::Ecs(e) => write!(f, "Failed to set up the game: {}", e),
+        }
+    }
+}
+
+impl From<Error> for AppError {
+    fn from(value: Error) -> Self {
+        Self::Window(value)
+    }
+}
+
+impl From<RenderError> for AppError {
+    fn from(value: RenderError) -> Self {
+        Self::Render(value)
+    }
+}
+
+impl From<SaveError> for AppError {
+    fn from(value: SaveError) -> Self {
+        Self::Save(value)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Network(value)
+    }
+}
+
+impl From<ClientError> for AppError {
+    fn from(value: ClientError) -> Self {
+        Self::Client(value)
+    }
+}
+
+impl From<winit::error::EventLoopError> for AppError {
+    fn from(value: winit::error::EventLoopError) -> Self {
+        Self::EventLoop(value)
+    }
+}
+
+impl From<apecs::anyhow::Error> for AppError {
+    fn from(value: apecs::anyhow::Error) -> Self {
+        Self::Ecs(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::error::RenderError;
+
+    use super::AppError;
+
+    #[test]
+    pub fn render_errors_are_readable() {
+        let message = AppError::Render(RenderError::AdapterNotFound).to_string();
+        assert!(message.to_lowercase().contains("adapter"), "{}", message);
+    }
+}
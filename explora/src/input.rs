@@ -1,8 +1,13 @@
-use apecs::{ok, Write};
-use common::SysResult;
+use std::{collections::HashMap, path::Path};
+
+use apecs::{ok, Read, Write};
+use common::{event::Events, resources::Paused, SysResult};
+use serde::{Deserialize, Serialize};
 use vek::{Vec2, Vec3};
 
-#[derive(Debug, Clone, Copy)]
+use crate::window::WindowEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GameInput {
     MoveForward,
     MoveBackward,
@@ -12,6 +17,113 @@ pub enum GameInput {
     Sneak,
     ToggleWireframe,
     ToggleCursor,
+    Quit,
+    Undo,
+    Redo,
+    /// Writes the block atlas with its tile ids to disk, in debug builds.
+    DumpAtlas,
+    TogglePause,
+    /// Saves the last frame to the pictures folder.
+    Screenshot,
+}
+
+impl GameInput {
+    /// Every input, in declaration order.
+    pub const ALL: [GameInput; 14] = [
+        GameInput::MoveForward,
+        GameInput::MoveBackward,
+        GameInput::MoveLeft,
+        GameInput::MoveRight,
+        GameInput::Jump,
+        GameInput::Sneak,
+        GameInput::ToggleWireframe,
+        GameInput::ToggleCursor,
+        GameInput::Quit,
+        GameInput::Undo,
+        GameInput::Redo,
+        GameInput::DumpAtlas,
+        GameInput::TogglePause,
+        GameInput::Screenshot,
+    ];
+
+    /// Finds the input bound to `key`.
+    ///
+    /// If several inputs share the key, the first one in declaration order is returned.
+    pub fn from_key(key: Key, map: &KeyMap) -> Option<GameInput> {
+        Self::ALL
+            .into_iter()
+            .find(|input| map.get(*input) == Some(key))
+    }
+}
+
+pub const KEYBINDINGS_PATH: &str = "keybindings.toml";
+
+/// The key bound to each [`GameInput`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: HashMap<GameInput, Key>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = HashMap::from([
+            (GameInput::MoveForward, Key::KeyW),
+            (GameInput::MoveBackward, Key::KeyS),
+            (GameInput::MoveLeft, Key::KeyA),
+            (GameInput::MoveRight, Key::KeyD),
+            (GameInput::Jump, Key::Space),
+            (GameInput::Sneak, Key::ShiftLeft),
+            (GameInput::ToggleCursor, Key::Period),
+            (GameInput::ToggleWireframe, Key::F11),
+            (GameInput::Quit, Key::Escape),
+            (GameInput::Undo, Key::KeyZ),
+            (GameInput::Redo, Key::KeyY),
+            (GameInput::DumpAtlas, Key::F10),
+            (GameInput::TogglePause, Key::KeyP),
+            (GameInput::Screenshot, Key::F12),
+        ]);
+        Self { bindings }
+    }
+}
+
+impl KeyMap {
+    /// Loads the default bindings, replaced by the ones found in the TOML file at `path`.
+    ///
+    /// The file maps input names to key codes, e.g. `Jump = "KeyZ"`.
+    /// If it is missing or invalid the default bindings are used.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let mut map = Self::default();
+        let Ok(file) = std::fs::read_to_string(path) else {
+            log::info!(
+                "No key bindings found at `{}`, using defaults",
+                path.display()
+            );
+            return map;
+        };
+        let overrides = match toml::from_str::<HashMap<String, Key>>(&file) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                log::error!("Failed to parse `{}`: {}", path.display(), e);
+                return map;
+            },
+        };
+        for (name, key) in overrides {
+            match toml::Value::String(name.clone()).try_into::<GameInput>() {
+                Ok(input) => map.bind(input, key),
+                Err(_) => log::warn!("Unknown input `{}` in `{}`", name, path.display()),
+            }
+        }
+        map
+    }
+
+    pub fn get(&self, input: GameInput) -> Option<Key> {
+        self.bindings.get(&input).copied()
+    }
+
+    pub fn bind(&mut self, input: GameInput, key: Key) {
+        self.bindings.insert(input, key);
+    }
 }
 
 /// Input struct that holds the state of the keyboard and mouse.
@@ -20,22 +132,28 @@ pub struct Input {
     pub just_pressed: [bool; 256],
     pub buttons: [bool; 128],
     pub cursor_delta: Vec2<f32>,
+    pub key_map: KeyMap,
 }
 
 impl Default for Input {
     fn default() -> Self {
+        Self::new(KeyMap::default())
+    }
+}
+
+pub type Key = winit::keyboard::KeyCode;
+
+impl Input {
+    pub fn new(key_map: KeyMap) -> Self {
         Self {
             pressed: [false; 256],
             just_pressed: [false; 256],
             buttons: [false; 128],
             cursor_delta: Vec2::zero(),
+            key_map,
         }
     }
-}
-
-pub type Key = winit::keyboard::KeyCode;
 
-impl Input {
     pub fn press(&mut self, input: Key) {
         if !self.pressed[input as usize] {
             self.just_pressed[input as usize] = true;
@@ -43,7 +161,7 @@ impl Input {
         self.pressed[input as usize] = true;
     }
 
-    pub const fn move_direction(&self) -> Vec3<f32> {
+    pub fn move_direction(&self) -> Vec3<f32> {
         vek::Vec3::new(
             (self.pressed(GameInput::MoveRight) as i32 - self.pressed(GameInput::MoveLeft) as i32)
                 as f32,
@@ -53,20 +171,28 @@ impl Input {
         )
     }
 
-    pub const fn pressed(&self, input: GameInput) -> bool {
-        match key_mapping(input) {
-            Some(key) => self.pressed[key as usize],
+    pub fn pressed(&self, input: GameInput) -> bool {
+        match self.key_map.get(input) {
+            Some(key) => self.pressed[key as usize] && self.modifier_held(input),
             None => false,
         }
     }
 
-    pub const fn just_pressed(&self, input: GameInput) -> bool {
-        match key_mapping(input) {
-            Some(key) => self.just_pressed[key as usize],
+    pub fn just_pressed(&self, input: GameInput) -> bool {
+        match self.key_map.get(input) {
+            Some(key) => self.just_pressed[key as usize] && self.modifier_held(input),
             None => false,
         }
     }
 
+    /// Whether the modifier key the input needs, if any, is held down.
+    const fn modifier_held(&self, input: GameInput) -> bool {
+        match modifier_mapping(input) {
+            Some(key) => self.pressed[key as usize],
+            None => true,
+        }
+    }
+
     pub fn release(&mut self, input: Key) {
         self.pressed[input as usize] = false;
     }
@@ -91,20 +217,64 @@ impl Input {
     }
 }
 
-const fn key_mapping(key: GameInput) -> Option<Key> {
+/// The key that must be held together with the mapped key.
+const fn modifier_mapping(key: GameInput) -> Option<Key> {
     match key {
-        GameInput::MoveForward => Some(Key::KeyW),
-        GameInput::MoveBackward => Some(Key::KeyS),
-        GameInput::MoveLeft => Some(Key::KeyA),
-        GameInput::MoveRight => Some(Key::KeyD),
-        GameInput::Jump => Some(Key::Space),
-        GameInput::Sneak => Some(Key::ShiftLeft),
-        GameInput::ToggleCursor => Some(Key::Period),
-        GameInput::ToggleWireframe => Some(Key::F12),
+        GameInput::Undo | GameInput::Redo => Some(Key::ControlLeft),
+        _ => None,
     }
 }
 
-pub fn input_system(mut input: Write<Input>) -> SysResult {
+pub const INPUT_SYSTEM: &str = "input";
+
+/// Pauses the game when the window loses focus and resumes it when it gets it back.
+///
+/// [`GameInput::TogglePause`] pauses or resumes the game manually.
+pub fn input_system(
+    (mut input, events, mut paused): (Write<Input>, Read<Events<WindowEvent>>, Write<Paused>),
+) -> SysResult {
+    for event in &events.events {
+        if let WindowEvent::Focused(focused) = event {
+            paused.0 = !focused;
+        }
+    }
+    if input.just_pressed(GameInput::TogglePause) {
+        paused.0 = !paused.0;
+    }
     input.update();
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GameInput, Input, Key, KeyMap};
+
+    #[test]
+    pub fn rebound_key_triggers_input() {
+        let mut key_map = KeyMap::default();
+        key_map.bind(GameInput::Jump, Key::KeyZ);
+        assert_eq!(
+            GameInput::from_key(Key::KeyZ, &key_map),
+            Some(GameInput::Jump)
+        );
+        assert_eq!(GameInput::from_key(Key::Space, &key_map), None);
+
+        let mut input = Input::new(key_map);
+        input.press(Key::Space);
+        assert!(!input.pressed(GameInput::Jump));
+        input.press(Key::KeyZ);
+        assert!(input.pressed(GameInput::Jump));
+        assert!(input.just_pressed(GameInput::Jump));
+    }
+
+    #[test]
+    pub fn overrides_keep_other_defaults() {
+        let path = std::env::temp_dir().join("explora_keybindings_test.toml");
+        std::fs::write(&path, "Jump = \"KeyZ\"\n").unwrap();
+        let key_map = KeyMap::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(key_map.get(GameInput::Jump), Some(Key::KeyZ));
+        assert_eq!(key_map.get(GameInput::MoveForward), Some(Key::KeyW));
+    }
+}
@@ -6,9 +6,12 @@ pub mod input;
 pub mod mesh;
 pub mod render;
 pub mod run;
+pub mod save;
 pub mod scene;
+pub mod screenshot;
 pub mod settings;
 pub mod singleplayer;
+pub mod sky;
 pub mod terrain;
 pub mod ui;
 pub mod window;
@@ -1,75 +1,127 @@
-use common::{clock::Clock, resources::GameMode};
-use explora::render::Renderer;
-use explora::settings::GameplaySettings;
+use std::path::PathBuf;
+
+use apecs::{ok, NoDefault, Read, ShouldContinue};
+
+use common::{clock::Clock, ecs::TryRead, resources::GameMode, state::timed};
+use explora::render::{atlas::BlockAtlas, error::RenderError, Renderer};
+use explora::settings::{self, GameplaySettings};
 use explora::terrain;
 use explora::{
     block::BlockMap,
     client::Client,
-    input::{self, Input},
+    error::AppError,
+    input::{self, Input, KeyMap},
     scene,
+    screenshot::{self, ScreenshotSavedEvent},
     singleplayer::Singleplayer,
     ui::EguiInput,
     window::{Window, WindowEvent},
 };
-fn main() -> apecs::anyhow::Result<()> {
+fn main() {
     common::init_logger("wgpu=warn,naga=error,apecs=warn");
 
-    let (window, event_loop) = Window::new().unwrap_or_else(|error| match error {
-        explora::error::Error::Window(e) => panic!("{:?}", e),
-    });
+    if let Err(error) = run() {
+        let hint = match &error {
+            AppError::Window(_) | AppError::EventLoop(_) => {
+                "Make sure a display is available to open the game window."
+            },
+            AppError::Render(_) => "Make sure your graphics drivers are up to date.",
+            AppError::Save(_) => "The save file may be corrupted or in use.",
+            AppError::Network(_) | AppError::Client(_) => {
+                "Make sure the server is running and reachable."
+            },
+            AppError::Ecs(_) => "This is a bug, please report it.",
+        };
+        eprintln!("Error: {}\n{}", error, hint);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), AppError> {
+    let (window, event_loop) = Window::new()?;
     let singleplayer = Singleplayer::init();
     let addr = singleplayer.wait_for_init();
-    let mut client = match Client::new(addr) {
-        Ok(t) => t,
-        Err(err) => {
-            log::error!("{:?}", err);
-            // TODO: if we cannot connect to the server create a single-player game
-            panic!();
-        },
-    };
+    // TODO: if we cannot connect to the server create a single-player game
+    let mut client = Client::new(addr, "Player")?;
     initialize_ecs(&mut client, window)?;
     // TODO: change this. this should NOT be here
     *client.state_mut().resource_mut::<GameMode>() = GameMode::Singleplayer;
-    explora::run::run(event_loop, client);
-    Ok(())
+    explora::run::run(event_loop, client, Some(singleplayer))
 }
 
-fn initialize_ecs(client: &mut Client, window: Window) -> apecs::anyhow::Result<()> {
+fn initialize_ecs(client: &mut Client, window: Window) -> Result<(), AppError> {
     let block_map = BlockMap::load_blocks("assets/blocks", "assets/textures/blocks");
-    let render_plugin = Renderer::initialize(window.platform(), block_map.textures()).unwrap();
+    let settings = GameplaySettings::load(settings::SETTINGS_PATH);
+    // The block textures are loaded in the background while the game starts
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .build()
+        .map_err(RenderError::from)?;
+    let render_plugin = Renderer::initialize_loading_atlas(
+        window.platform(),
+        runtime.handle(),
+        PathBuf::from("assets/textures/blocks"),
+        settings.anti_aliasing,
+    )?;
 
     client
         .state_mut()
         .ecs_mut()
         .with_resource(block_map)?
+        .with_resource(runtime)?
         .with_default_resource::<Clock>()?
-        .with_default_resource::<Input>()?
+        .with_resource(Input::new(KeyMap::load(input::KEYBINDINGS_PATH)))?
         .with_default_resource::<EguiInput>()?
-        .with_default_resource::<GameplaySettings>()?
+        .with_resource(settings)?
         .with_resource(window)?
         .with_plugin(render_plugin)?
         .with_system(
-            explora::terrain::CHUNK_LOAD_SYSTEM,
-            explora::terrain::chunk_load_system,
+            terrain::CHUNK_LOAD_SYSTEM,
+            timed(terrain::CHUNK_LOAD_SYSTEM, terrain::chunk_load_system),
         )?
         .with_system_with_dependencies(
-            explora::terrain::TERRAIN_CHUNK_MESH_SYSTEM,
-            explora::terrain::terrain_chunk_mesh,
+            terrain::TERRAIN_CHUNK_MESH_SYSTEM,
+            timed(
+                terrain::TERRAIN_CHUNK_MESH_SYSTEM,
+                terrain::terrain_chunk_mesh,
+            ),
             &[terrain::CHUNK_LOAD_SYSTEM],
             &[],
         )?
+        // Not timed: the debug UI reads the PerformanceStats the timed stages write
         .with_system_with_dependencies(
             explora::render::SYSTEM_STAGE_UI_DRAW_WIDGETS,
             explora::ui::ui_debug_render_system,
             &[],
             &[],
         )?
+        .with_system(
+            "check_animations",
+            |(blocks, atlas): (Read<BlockMap, NoDefault>, TryRead<BlockAtlas>)| {
+                let Some(atlas) = atlas.inner().as_ref() else {
+                    return ok();
+                };
+                blocks.check_animations(atlas);
+                // Only needed once
+                Ok(ShouldContinue::No)
+            },
+        )?
+        .with_system("screenshot_saved", screenshot::screenshot_saved_system)?
         .with_system_barrier()
-        .with_system("scene_update", scene::scene_update_system)?
+        .with_system(
+            scene::SCENE_UPDATE_SYSTEM,
+            timed(scene::SCENE_UPDATE_SYSTEM, scene::scene_update_system),
+        )?
+        .with_system("window_title", explora::window::window_title_system)?
         .with_system_barrier()
-        .with_system("input", input::input_system)?;
+        .with_system(
+            input::INPUT_SYSTEM,
+            timed(input::INPUT_SYSTEM, input::input_system),
+        )?;
 
     client.state_mut().with_event::<WindowEvent>("window_event");
+    client
+        .state_mut()
+        .with_event::<ScreenshotSavedEvent>("screenshot_saved_event");
     common::state::print_system_schedule(client.state_mut().ecs_mut());
     Ok(())
 }
@@ -1,197 +1,957 @@
-use common::{chunk::Chunk, dir::Direction, resources::TerrainMap};
+use std::collections::HashMap;
+
+use common::{
+    block::BlockId,
+    chunk::{Chunk, ChunkColumn},
+    dir::Direction,
+    light::{self, LightMap},
+    resources::TerrainMap,
+};
 use vek::{Vec2, Vec3};
 
 use crate::{
-    block::BlockMap,
+    block::{BlockMap, MeshType},
     render::{atlas::BlockAtlas, vertex::TerrainVertex},
 };
 
-pub fn create_chunk_mesh(
-    chunk: &Chunk,
-    chunk_pos: Vec2<i32>,
-    terrain_map: &TerrainMap,
-    block_map: &BlockMap,
-    block_atlas: &BlockAtlas,
-) -> Vec<TerrainVertex> {
-    
-    let mut vertices = Vec::with_capacity(3000);
+/// Keeps the last mesh computed for each chunk so the GPU buffers can be
+/// recreated without running the mesher again, e.g. after the renderer restarts.
+#[derive(Default)]
+pub struct ChunkMeshCache {
+    meshes: HashMap<Vec2<i32>, Vec<TerrainVertex>>,
+}
 
-    for pos in chunk.iter() {
-        let origin = pos.map(|x| x as u32);
-        let render_quad = |direction: Direction| {
-            let dir = direction.vec(); // The direction of the face we are checking for render
-            let adjacent_pos = pos + dir; // The pos of the adjacent block
-
-            if Chunk::out_of_bounds(adjacent_pos) {
-                // If the adjacent block is out of bounds
-                // it means we are at the edge of the chunk
-                if matches!(direction, Direction::Up) || matches!(direction, Direction::Down) {
-                    // If the direction is up or down we can render the quad
-                    // Since we have no chunks above or below
-                    return true;
-                }
+impl ChunkMeshCache {
+    /// Returns the cached mesh of the chunk, computing it with `mesher` if there is none.
+    pub fn get_or_insert_with<F>(&mut self, pos: Vec2<i32>, mesher: F) -> &[TerrainVertex]
+    where
+        F: FnOnce() -> Vec<TerrainVertex>,
+    {
+        self.meshes.entry(pos).or_insert_with(mesher)
+    }
 
-                // Now we have to check if there is a chunk adjacent to this one
-                let neighbor_chunk_dir = Vec2::new(chunk_pos.x + dir.x, chunk_pos.y + dir.z);
-
-                let Some(neighbor_chunk) = terrain_map.chunks.get(&(neighbor_chunk_dir)) else {
-                    // If there is no adjacent chunk we have to render the quad
-                    // because it is a border of the chunk
-                    return true;
-                };
-
-                // map out of bound adj block pos to neighbor local pos
-                let neighbor_block_in_border = Vec3::new(
-                    if adjacent_pos.x < 0 {
-                        Chunk::SIZE.x as i32 - 1
-                    } else if adjacent_pos.x >= Chunk::SIZE.x as i32 {
-                        0
-                    } else {
-                        adjacent_pos.x
-                    },
-                    adjacent_pos.y,
-                    if adjacent_pos.z < 0 {
-                        Chunk::SIZE.z as i32 - 1
-                    } else if adjacent_pos.z >= Chunk::SIZE.z as i32 {
-                        0
-                    } else {
-                        adjacent_pos.z
-                    },
-                );
-                // Check if the adjacent block is air or not in the map
-                return match neighbor_chunk.get(neighbor_block_in_border) {
-                    Some(id) => id.is_air(),
-                    None => true,
-                };
+    pub fn get(&self, pos: Vec2<i32>) -> Option<&[TerrainVertex]> {
+        self.meshes.get(&pos).map(Vec::as_slice)
+    }
+
+    /// Drops the mesh of a chunk that changed, so the next call re-meshes it.
+    pub fn invalidate(&mut self, pos: Vec2<i32>) {
+        self.meshes.remove(&pos);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec2<i32>, &Vec<TerrainVertex>)> {
+        self.meshes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+}
+
+/// A face of the terrain mesh, independent of the vertex format used to upload it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quad {
+    /// Corners in counter-clockwise order as seen from the normal.
+    pub positions: [Vec3<f32>; 4],
+    pub normal: Vec3<f32>,
+    pub tile_id: u32,
+    /// Ambient occlusion of each corner.
+    pub ao: [u8; 4],
+    /// Light level of each corner.
+    pub light: [u8; 4],
+}
+
+impl Quad {
+    /// Creates a fully lit quad without ambient occlusion.
+    pub fn new(corners: [Vec3<u32>; 4], tile_id: u16, normal: Vec3<i32>) -> Self {
+        Self {
+            positions: corners.map(|corner| corner.map(|x| x as f32)),
+            normal: normal.map(|x| x as f32),
+            tile_id: tile_id as u32,
+            ao: [0; 4],
+            light: [light::MAX_LIGHT; 4],
+        }
+    }
+
+    /// Sets the light level of every corner.
+    pub fn with_light(mut self, light: u8) -> Self {
+        self.light = [light; 4];
+        self
+    }
+}
+
+/// Converts quads to packed terrain vertices, four per quad.
+pub fn quads_to_vertices(quads: &[Quad]) -> Vec<TerrainVertex> {
+    let mut vertices = Vec::with_capacity(quads.len() * 4);
+    for quad in quads {
+        let normal = TerrainVertex::normal_index(quad.normal.map(|x| x.round() as i32));
+        for i in 0..4 {
+            vertices.push(TerrainVertex::new(
+                quad.positions[i],
+                quad.tile_id as u16,
+                normal,
+                quad.ao[i],
+                quad.light[i],
+            ));
+        }
+    }
+    vertices
+}
+
+/// Creates the mesh of a vegetation block: two quads crossing diagonally through the block,
+/// each one double-faced so it can be seen from both sides.
+pub fn create_cross_mesh(texture_id: u16, pos: Vec3<i32>) -> Vec<Quad> {
+    let origin = pos.map(|x| x as u32);
+    let mut quads = Vec::with_capacity(4);
+    // Each face is listed bottom-left, bottom-right, top-right, top-left as seen from its normal
+    let faces = [
+        (
+            [
+                Vec3::zero(),
+                Vec3::new(1, 0, 1),
+                Vec3::one(),
+                Vec3::unit_y(),
+            ],
+            Vec3::new(1, 0, -1),
+        ),
+        (
+            [
+                Vec3::new(1, 0, 1),
+                Vec3::zero(),
+                Vec3::unit_y(),
+                Vec3::one(),
+            ],
+            Vec3::new(-1, 0, 1),
+        ),
+        (
+            [
+                Vec3::unit_x(),
+                Vec3::unit_z(),
+                Vec3::new(0, 1, 1),
+                Vec3::new(1, 1, 0),
+            ],
+            Vec3::new(1, 0, 1),
+        ),
+        (
+            [
+                Vec3::unit_z(),
+                Vec3::unit_x(),
+                Vec3::new(1, 1, 0),
+                Vec3::new(0, 1, 1),
+            ],
+            Vec3::new(-1, 0, -1),
+        ),
+    ];
+    for (corners, normal) in faces {
+        quads.push(Quad::new(
+            corners.map(|corner| origin + corner),
+            texture_id,
+            normal,
+        ));
+    }
+    quads
+}
+
+/// The chunks next to the one being meshed, used to cull the faces on its borders.
+#[derive(Clone, Copy)]
+pub struct BorderBlocks<'a, const H: usize = 256> {
+    pub north: Option<&'a Chunk<H>>,
+    pub south: Option<&'a Chunk<H>>,
+    pub east: Option<&'a Chunk<H>>,
+    pub west: Option<&'a Chunk<H>>,
+}
+
+impl<'a, const H: usize> Default for BorderBlocks<'a, H> {
+    fn default() -> Self {
+        Self {
+            north: None,
+            south: None,
+            east: None,
+            west: None,
+        }
+    }
+}
+
+impl<'a> BorderBlocks<'a> {
+    /// Collects the loaded neighbours of the chunk at `pos`.
+    pub fn from_terrain(terrain: &'a TerrainMap, pos: Vec2<i32>) -> Self {
+        let get = |dir: Direction| {
+            let dir = dir.vec();
+            terrain
+                .chunks
+                .get(&(pos + Vec2::new(dir.x, dir.z)))
+                .and_then(ChunkColumn::ground)
+        };
+        Self {
+            north: get(Direction::North),
+            south: get(Direction::South),
+            east: get(Direction::East),
+            west: get(Direction::West),
+        }
+    }
+}
+
+impl<'a, const H: usize> BorderBlocks<'a, H> {
+    pub fn get(&self, direction: Direction) -> Option<&'a Chunk<H>> {
+        match direction {
+            Direction::North => self.north,
+            Direction::South => self.south,
+            Direction::East => self.east,
+            Direction::West => self.west,
+            Direction::Up | Direction::Down => None,
+        }
+    }
+}
+
+/// Counters collected while meshing a chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    /// Blocks that produced at least one face.
+    pub blocks: usize,
+    pub faces: usize,
+}
+
+/// Creates the mesh of a chunk. Only the chunk and the block data are required,
+/// everything else is opt-in:
+///
+/// - without [`MeshBuilder::with_neighbors`] every face on the chunk borders is culled,
+///   as if no neighbour was loaded.
+/// - without [`MeshBuilder::with_light`] every face is fully lit.
+/// - without [`MeshBuilder::with_ao`] the ambient occlusion pass is skipped.
+#[derive(Clone, Copy)]
+pub struct MeshBuilder<'a, const H: usize = 256> {
+    chunk: &'a Chunk<H>,
+    block_map: &'a BlockMap,
+    atlas: &'a BlockAtlas,
+    border: BorderBlocks<'a, H>,
+    lights: Option<&'a LightMap<H>>,
+    ao: bool,
+}
+
+impl<'a, const H: usize> MeshBuilder<'a, H> {
+    pub fn new(chunk: &'a Chunk<H>, block_map: &'a BlockMap, atlas: &'a BlockAtlas) -> Self {
+        Self {
+            chunk,
+            block_map,
+            atlas,
+            border: BorderBlocks::default(),
+            lights: None,
+            ao: false,
+        }
+    }
+
+    /// Uses the blocks of the neighbours to cull the faces on the chunk borders.
+    ///
+    /// Border faces towards a neighbour that isn't loaded are not rendered: the chunk
+    /// has to be meshed again once the neighbour is available.
+    pub fn with_neighbors(mut self, border: BorderBlocks<'a, H>) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Lights each face by the block in front of it.
+    pub fn with_light(mut self, lights: &'a LightMap<H>) -> Self {
+        self.lights = Some(lights);
+        self
+    }
+
+    /// Darkens the corners of cube faces next to other solid blocks.
+    pub fn with_ao(mut self, ao: bool) -> Self {
+        self.ao = ao;
+        self
+    }
+
+    /// Meshes the chunk into packed vertices, four per face.
+    pub fn build(&self) -> (Vec<TerrainVertex>, MeshStats) {
+        let (quads, stats) = self.mesh();
+        (quads_to_vertices(&quads), stats)
+    }
+
+    /// Meshes the chunk into quads, before they are packed into vertices.
+    pub fn build_quads(&self) -> Vec<Quad> {
+        self.mesh().0
+    }
+
+    fn mesh(&self) -> (Vec<Quad>, MeshStats) {
+        let Self {
+            chunk,
+            block_map,
+            atlas: block_atlas,
+            border,
+            lights,
+            ao,
+        } = *self;
+        // Sized up front so building the mesh allocates only once
+        let mut quads = Vec::with_capacity(count_visible_faces(chunk, &border, block_map) as usize);
+        let mut stats = MeshStats::default();
+
+        for pos in chunk.iter() {
+            let origin = pos.map(|x| x as u32);
+            let render_quad =
+                |direction: Direction| face_visible(chunk, &border, block_map, pos, direction);
+
+            let id = match chunk.get(pos) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            if id.is_air() {
+                continue;
+            }
+
+            let Some(block) = block_map.get(id) else {
+                log::error!("Block with id: {:?} not found", id);
+                continue;
+            };
+
+            let (top, side, bottom) = block.textures();
+            let top = block_atlas.get_texture_id(top);
+            let side = block_atlas.get_texture_id(side);
+            let bottom = block_atlas.get_texture_id(bottom);
+
+            let light_at = |pos: Vec3<i32>| match lights {
+                Some(lights) => lights.get(pos),
+                None => Some(light::MAX_LIGHT),
+            };
+            let own_light = light_at(pos).unwrap_or(light::MAX_LIGHT);
+
+            match block.mesh_type {
+                MeshType::Cube => {},
+                MeshType::Cross => {
+                    quads.extend(
+                        create_cross_mesh(side, pos)
+                            .into_iter()
+                            .map(|quad| quad.with_light(own_light)),
+                    );
+                    continue;
+                },
+                MeshType::None => continue,
+            }
+            let first_face = quads.len();
+
+            // North
+            if render_quad(Direction::North) {
+                let normal = Direction::North.vec();
+                quads.push(Quad::new(
+                    [
+                        origin + Vec3::unit_x() + Vec3::unit_z(),
+                        origin + Vec3::unit_z(),
+                        origin + Vec3::unit_z() + Vec3::unit_y(),
+                        origin + Vec3::unit_z() + Vec3::unit_x() + Vec3::unit_y(),
+                    ],
+                    side,
+                    normal,
+                ));
+            }
+
+            // South
+            if render_quad(Direction::South) {
+                let normal = Direction::South.vec();
+
+                quads.push(Quad::new(
+                    [
+                        origin,
+                        origin + Vec3::unit_x(),
+                        origin + Vec3::unit_x() + Vec3::unit_y(),
+                        origin + Vec3::unit_y(),
+                    ],
+                    side,
+                    normal,
+                ));
             }
-            // The adjacent block is within the bounds of this chunk
-            // render only if the adjacent block is not there e.g air or not in the map
-            match chunk.get(adjacent_pos) {
-                Some(id) => id.is_air(),
-                None => true,
+
+            // East
+            if render_quad(Direction::East) {
+                let normal = Direction::East.vec();
+                quads.push(Quad::new(
+                    [
+                        origin + Vec3::unit_x(),
+                        origin + Vec3::unit_x() + Vec3::unit_z(),
+                        origin + Vec3::unit_x() + Vec3::unit_z() + Vec3::unit_y(),
+                        origin + Vec3::unit_x() + Vec3::unit_y(),
+                    ],
+                    side,
+                    normal,
+                ));
+            }
+
+            // West
+            if render_quad(Direction::West) {
+                let normal = Direction::West.vec();
+                quads.push(Quad::new(
+                    [
+                        origin + Vec3::unit_z(),
+                        origin,
+                        origin + Vec3::unit_y(),
+                        origin + Vec3::unit_z() + Vec3::unit_y(),
+                    ],
+                    side,
+                    normal,
+                ));
+            }
+            // Bottom
+            if render_quad(Direction::Down) {
+                let normal = Direction::Down.vec();
+                quads.push(Quad::new(
+                    [
+                        origin,
+                        origin + Vec3::unit_z(),
+                        origin + Vec3::unit_x() + Vec3::unit_z(),
+                        origin + Vec3::unit_x(),
+                    ],
+                    bottom,
+                    normal,
+                ));
+            }
+
+            // Top
+            if render_quad(Direction::Up) {
+                let normal = Direction::Up.vec();
+                quads.push(Quad::new(
+                    [
+                        origin + Vec3::unit_y(),
+                        origin + Vec3::unit_y() + Vec3::unit_x(),
+                        origin + Vec3::unit_y() + Vec3::unit_x() + Vec3::unit_z(),
+                        origin + Vec3::unit_y() + Vec3::unit_z(),
+                    ],
+                    top,
+                    normal,
+                ));
             }
+
+            for quad in &mut quads[first_face..] {
+                let normal = quad.normal.map(|x| x.round() as i32);
+                // Faces on the chunk borders use the light of the block itself
+                let light = light_at(pos + normal).unwrap_or(own_light);
+                quad.light = [light; 4];
+                if ao {
+                    quad.ao = corner_occlusion(quad, pos, |pos| {
+                        neighbor_block(chunk, &border, pos).is_some_and(|id| block_map.occludes(id))
+                    });
+                }
+            }
+            if quads.len() > first_face {
+                stats.blocks += 1;
+            }
+        }
+        stats.faces = quads.len();
+        (quads, stats)
+    }
+}
+
+/// Counts the faces [`MeshBuilder`] produces for `chunk`, without building them.
+///
+/// Each face becomes four vertices, so this gives the size of the vertex buffer up front.
+pub fn count_visible_faces<const H: usize>(
+    chunk: &Chunk<H>,
+    border: &BorderBlocks<H>,
+    blocks: &BlockMap,
+) -> u32 {
+    let mut faces = 0;
+    for pos in chunk.iter() {
+        let Some(id) = chunk.get(pos).filter(|id| !id.is_air()) else {
+            continue;
+        };
+        match blocks.get(id).map(|block| block.mesh_type) {
+            Some(MeshType::Cube) => {
+                faces += Direction::ALL
+                    .into_iter()
+                    .filter(|direction| face_visible(chunk, border, blocks, pos, *direction))
+                    .count() as u32;
+            },
+            // Two double-faced quads
+            Some(MeshType::Cross) => faces += 4,
+            Some(MeshType::None) | None => {},
+        }
+    }
+    faces
+}
+
+/// Whether the face of the block at `pos` towards `direction` can be seen.
+fn face_visible<const H: usize>(
+    chunk: &Chunk<H>,
+    border: &BorderBlocks<H>,
+    block_map: &BlockMap,
+    pos: Vec3<i32>,
+    direction: Direction,
+) -> bool {
+    let dir = direction.vec(); // The direction of the face we are checking for render
+    let adjacent_pos = pos + dir; // The pos of the adjacent block
+
+    if Chunk::<H>::out_of_bounds(adjacent_pos) {
+        // If the adjacent block is out of bounds
+        // it means we are at the edge of the chunk
+        if matches!(direction, Direction::Up) || matches!(direction, Direction::Down) {
+            // If the direction is up or down we can render the quad
+            // Since we have no chunks above or below
+            return true;
+        }
+
+        let Some(neighbor_chunk) = border.get(direction) else {
+            // We can't know if the face is hidden until the neighbor is loaded
+            return false;
         };
 
-        let id = match chunk.get(pos) {
-            Some(id) => id,
-            None => continue,
+        // map out of bound adj block pos to neighbor local pos
+        let neighbor_block_in_border = Vec3::new(
+            if adjacent_pos.x < 0 {
+                Chunk::<H>::SIZE.x as i32 - 1
+            } else if adjacent_pos.x >= Chunk::<H>::SIZE.x as i32 {
+                0
+            } else {
+                adjacent_pos.x
+            },
+            adjacent_pos.y,
+            if adjacent_pos.z < 0 {
+                Chunk::<H>::SIZE.z as i32 - 1
+            } else if adjacent_pos.z >= Chunk::<H>::SIZE.z as i32 {
+                0
+            } else {
+                adjacent_pos.z
+            },
+        );
+        // Check if the adjacent block is air or not in the map
+        return match neighbor_chunk.get(neighbor_block_in_border) {
+            Some(id) => !block_map.occludes(id),
+            None => true,
         };
+    }
+    // The adjacent block is within the bounds of this chunk
+    // render only if the adjacent block is not there e.g air or not in the map
+    match chunk.get(adjacent_pos) {
+        Some(id) => !block_map.occludes(id),
+        None => true,
+    }
+}
 
-        if id.is_air() {
-            continue;
+/// Returns the block at `pos`, looking into the neighbours for positions just outside the chunk.
+fn neighbor_block<const H: usize>(
+    chunk: &Chunk<H>,
+    border: &BorderBlocks<H>,
+    pos: Vec3<i32>,
+) -> Option<BlockId> {
+    if !Chunk::<H>::out_of_bounds(pos) {
+        return chunk.get(pos);
+    }
+    let size = Chunk::<H>::SIZE.map(|x| x as i32);
+    let direction = match (pos.x, pos.z) {
+        (x, z) if x < 0 && (0..size.z).contains(&z) => Direction::West,
+        (x, z) if x >= size.x && (0..size.z).contains(&z) => Direction::East,
+        (x, z) if z < 0 && (0..size.x).contains(&x) => Direction::South,
+        (x, z) if z >= size.z && (0..size.x).contains(&x) => Direction::North,
+        // Above or below the chunk, or diagonal to it
+        _ => return None,
+    };
+    let local = Vec3::new(pos.x.rem_euclid(size.x), pos.y, pos.z.rem_euclid(size.z));
+    border.get(direction)?.get(local)
+}
+
+/// Computes the ambient occlusion of each corner of a cube face of the block at `pos`,
+/// from 0 for an open corner to 3 for a corner enclosed by solid blocks.
+fn corner_occlusion(quad: &Quad, pos: Vec3<i32>, occludes: impl Fn(Vec3<i32>) -> bool) -> [u8; 4] {
+    let normal = quad.normal.map(|x| x.round() as i32);
+    let front = pos + normal;
+    let tangents: Vec<usize> = (0..3).filter(|axis| normal[*axis] == 0).collect();
+    quad.positions.map(|corner| {
+        let local = corner.map(|x| x as i32) - pos;
+        // Move towards the corner along each axis of the face
+        let [side1, side2] = [tangents[0], tangents[1]].map(|axis| {
+            let mut offset = Vec3::zero();
+            offset[axis] = if local[axis] > 0 { 1 } else { -1 };
+            offset
+        });
+        let side1_solid = occludes(front + side1);
+        let side2_solid = occludes(front + side2);
+        if side1_solid && side2_solid {
+            // The corner block can't be seen between the two sides
+            return 3;
         }
+        side1_solid as u8 + side2_solid as u8 + occludes(front + side1 + side2) as u8
+    })
+}
 
-        let Some(block) = block_map.get(id) else {
-            log::error!("Block with id: {:?} not found", id);
-            continue;
+#[cfg(test)]
+mod tests {
+    use common::{
+        block::BlockId,
+        chunk::{Chunk, StandardChunk},
+        dir::Direction,
+        light::LightMap,
+    };
+    use vek::{Vec2, Vec3};
+
+    use crate::{block::BlockMap, render::atlas::BlockAtlas};
+
+    use super::{
+        count_visible_faces, create_cross_mesh, quads_to_vertices, BorderBlocks, ChunkMeshCache,
+        MeshBuilder, MeshStats, Quad,
+    };
+
+    fn blocks() -> (BlockMap, BlockAtlas) {
+        let block_map = BlockMap::load_blocks(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/blocks"),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/textures/blocks"),
+        );
+        let atlas = BlockAtlas::create(block_map.textures()).unwrap();
+        (block_map, atlas)
+    }
+
+    fn count_faces(quads: &[Quad], direction: Direction) -> usize {
+        let normal = direction.vec().map(|x| x as f32);
+        quads.iter().filter(|quad| quad.normal == normal).count()
+    }
+
+    #[test]
+    pub fn solid_neighbor_hides_border_faces() {
+        let (block_map, atlas) = blocks();
+        let chunk = StandardChunk::flat(BlockId::Stone);
+        let solid = StandardChunk::flat(BlockId::Stone);
+        let air = StandardChunk::flat(BlockId::Air);
+
+        let border = BorderBlocks {
+            north: Some(&solid),
+            ..Default::default()
         };
+        let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+            .with_neighbors(border)
+            .build_quads();
+        assert_eq!(count_faces(&quads, Direction::North), 0);
 
-        let (top, side, bottom) = block.textures();
-        let top = block_atlas.get_texture_id(top);
-        let side = block_atlas.get_texture_id(side);
-        let bottom = block_atlas.get_texture_id(bottom);
+        let border = BorderBlocks {
+            north: Some(&air),
+            ..Default::default()
+        };
+        let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+            .with_neighbors(border)
+            .build_quads();
+        assert_eq!(
+            count_faces(&quads, Direction::North),
+            StandardChunk::SIZE.x * StandardChunk::SIZE.y
+        );
+        // Faces towards missing neighbors are culled
+        assert_eq!(count_faces(&quads, Direction::South), 0);
+        assert_eq!(count_faces(&quads, Direction::East), 0);
+        assert_eq!(count_faces(&quads, Direction::West), 0);
+    }
 
-        // North
-        if render_quad(Direction::North) {
-            let normal = Direction::North.vec();
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
+    #[test]
+    pub fn shallow_chunks_are_meshed() {
+        let (block_map, atlas) = blocks();
+        let chunk = Chunk::<64>::flat(BlockId::Stone);
+        let air = Chunk::<64>::flat(BlockId::Air);
+
+        let border = BorderBlocks {
+            north: Some(&air),
+            ..Default::default()
+        };
+        let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+            .with_neighbors(border)
+            .build_quads();
+        assert_eq!(count_faces(&quads, Direction::North), 16 * 64);
+        assert_eq!(count_faces(&quads, Direction::Up), 16 * 16);
+        assert!(quads
+            .iter()
+            .all(|quad| quad.positions.iter().all(|p| p.y <= 64.0)));
+    }
+
+    #[test]
+    pub fn cached_chunks_are_meshed_once() {
+        let mut cache = ChunkMeshCache::default();
+        let mut calls = 0;
+        let pos = Vec2::new(2, -3);
+        for _ in 0..2 {
+            let mesh = cache.get_or_insert_with(pos, || {
+                calls += 1;
+                quads_to_vertices(&create_cross_mesh(0, Vec3::zero()))
+            });
+            assert_eq!(mesh.len(), 16);
         }
+        assert_eq!(calls, 1);
 
-        // South
-        if render_quad(Direction::South) {
-            let normal = Direction::South.vec();
+        // Invalidated chunks are meshed again
+        cache.invalidate(pos);
+        cache.get_or_insert_with(pos, || {
+            calls += 1;
+            Vec::new()
+        });
+        assert_eq!(calls, 2);
+    }
 
-            vertices.push(TerrainVertex::new(origin, side, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), side, normal));
+    #[test]
+    pub fn quad_converts_to_four_vertices() {
+        let quad = Quad {
+            positions: [
+                Vec3::new(1.0, 2.0, 3.0),
+                Vec3::new(2.0, 2.0, 3.0),
+                Vec3::new(2.0, 3.0, 3.0),
+                Vec3::new(1.0, 3.0, 3.0),
+            ],
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            tile_id: 7,
+            ao: [0, 10, 20, 31],
+            light: [15, 14, 13, 12],
+        };
+        let vertices = quads_to_vertices(std::slice::from_ref(&quad));
+        assert_eq!(vertices.len(), 4);
+        for (i, vertex) in vertices.iter().enumerate() {
+            assert_eq!(vertex.position(), quad.positions[i].map(|x| x as u32));
+            assert_eq!(vertex.tile(), 7);
+            assert_eq!(vertex.ao(), quad.ao[i]);
+            assert_eq!(vertex.light(), quad.light[i]);
         }
+    }
 
-        // East
-        if render_quad(Direction::East) {
-            let normal = Direction::East.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
+    #[test]
+    pub fn cross_mesh_is_double_faced() {
+        let vertices = quads_to_vertices(&create_cross_mesh(0, Vec3::new(3, 10, 5)));
+        assert_eq!(vertices.len(), 16);
+
+        let normals = vertices
+            .chunks(4)
+            .map(|quad| {
+                let p = quad
+                    .iter()
+                    .map(|v| v.position().map(|x| x as i32))
+                    .collect::<Vec<_>>();
+                (p[1] - p[0]).cross(p[2] - p[0])
+            })
+            .collect::<Vec<_>>();
+        for normal in &normals {
+            // Every quad stands upright and faces away from its plane
+            assert_eq!(normal.y, 0);
+            assert_ne!(*normal, Vec3::zero());
         }
+        // Each plane is visible from both sides
+        assert_eq!(normals[0], -normals[1]);
+        assert_eq!(normals[2], -normals[3]);
+    }
 
-        // West
-        if render_quad(Direction::West) {
-            let normal = Direction::West.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), side, normal));
-            vertices.push(TerrainVertex::new(origin, side, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
+    #[test]
+    pub fn faces_are_lit_by_the_block_in_front() {
+        let (block_map, atlas) = blocks();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        // A roof keeps the sunlight out
+        for x in 0..StandardChunk::SIZE.x as i32 {
+            for z in 0..StandardChunk::SIZE.z as i32 {
+                chunk.set(
+                    Vec3::new(x, StandardChunk::SIZE.y as i32 - 1, z),
+                    BlockId::Stone,
+                );
+            }
         }
-        // Bottom
-        if render_quad(Direction::Down) {
-            let normal = Direction::Down.vec();
-            vertices.push(TerrainVertex::new(origin, bottom, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), bottom, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                bottom,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), bottom, normal));
+        chunk.set(Vec3::new(8, 8, 8), BlockId::Stone);
+        chunk.set(Vec3::new(9, 8, 8), BlockId::Torch);
+        let mut lights: LightMap = LightMap::default();
+        lights.propagate_sunlight(&chunk);
+
+        let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+            .with_light(&lights)
+            .build_quads();
+        let face_light = |direction: Direction| {
+            let normal = direction.vec().map(|x| x as f32);
+            quads
+                .iter()
+                .find(|quad| quad.normal == normal && quad.positions[0].y == 8.0)
+                .map(|quad| quad.light)
+                .unwrap()
+        };
+        // Facing the torch
+        assert_eq!(face_light(Direction::East), [14; 4]);
+        // The light has to go around the stone to reach the other side
+        assert_eq!(face_light(Direction::West), [10; 4]);
+    }
+
+    #[test]
+    pub fn builder_defaults_to_lit_faces_without_ao() {
+        let (block_map, atlas) = blocks();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(3, 10, 5), BlockId::Stone);
+
+        let (vertices, stats) = MeshBuilder::new(&chunk, &block_map, &atlas).build();
+        assert_eq!(
+            stats,
+            MeshStats {
+                blocks: 1,
+                faces: 6
+            }
+        );
+        assert_eq!(vertices.len(), 24);
+        for vertex in &vertices {
+            assert_eq!(vertex.light(), 15);
+            assert_eq!(vertex.ao(), 0);
         }
+        // The same faces a plain cube is made of
+        let stone = atlas.get_texture_id(block_map.get(BlockId::Stone).unwrap().textures().0);
+        let origin = Vec3::new(3, 10, 5);
+        for vertex in vertices.chunks(4) {
+            assert_eq!(vertex[0].tile(), stone);
+            for corner in vertex {
+                let offset = corner.position().map(|x| x as i32) - origin;
+                assert!(offset.iter().all(|x| (0..=1).contains(x)));
+            }
+        }
+    }
 
-        // Top
-        if render_quad(Direction::Up) {
-            let normal = Direction::Up.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), top, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_x(),
-                top,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_x() + Vec3::unit_z(),
-                top,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_z(),
-                top,
-                normal,
-            ));
+    #[test]
+    pub fn ao_darkens_corners_next_to_solid_blocks() {
+        let (block_map, atlas) = blocks();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(8, 10, 8), BlockId::Stone);
+        // A wall on top of the east side of the block
+        chunk.set(Vec3::new(9, 11, 8), BlockId::Stone);
+        chunk.set(Vec3::new(9, 11, 9), BlockId::Stone);
+
+        let top_ao = |ao: bool| {
+            let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+                .with_ao(ao)
+                .build_quads();
+            let top = quads
+                .iter()
+                .find(|quad| quad.normal == Vec3::unit_y() && quad.positions[0].y == 11.0)
+                .unwrap();
+            top.positions
+                .iter()
+                .zip(top.ao)
+                .map(|(corner, ao)| (corner.map(|x| x as i32), ao))
+                .collect::<Vec<_>>()
+        };
+        for (_, ao) in top_ao(false) {
+            assert_eq!(ao, 0);
+        }
+        for (corner, ao) in top_ao(true) {
+            let expected = match (corner.x, corner.z) {
+                // Next to the wall, with the corner block behind it too
+                (9, 9) => 2,
+                (9, 8) => 1,
+                _ => 0,
+            };
+            assert_eq!(ao, expected, "corner {:?}", corner);
+        }
+    }
+
+    #[test]
+    pub fn visible_faces_match_the_mesh() {
+        let (block_map, atlas) = blocks();
+        let air = StandardChunk::flat(BlockId::Air);
+        let solid = StandardChunk::flat(BlockId::Stone);
+        let mut scattered = StandardChunk::flat(BlockId::Air);
+        for (i, id) in BlockId::ALL.into_iter().enumerate() {
+            let i = i as i32;
+            scattered.set(Vec3::new(i * 2, i, 15 - i), id);
+            scattered.set(Vec3::new(0, i, 0), id);
+            scattered.set(Vec3::new(15, 200 + i, i), id);
+        }
+        let mut hills = StandardChunk::flat(BlockId::Air);
+        for pos in hills.iter() {
+            if pos.y < 40 + pos.x + pos.z % 3 {
+                hills.set(pos, BlockId::Dirt);
+            }
+        }
+
+        let borders = [
+            BorderBlocks::default(),
+            BorderBlocks {
+                north: Some(&air),
+                east: Some(&solid),
+                ..Default::default()
+            },
+            BorderBlocks {
+                north: Some(&air),
+                south: Some(&air),
+                east: Some(&air),
+                west: Some(&hills),
+            },
+        ];
+        for chunk in [&air, &solid, &scattered, &hills] {
+            for border in borders {
+                let (vertices, _) = MeshBuilder::new(chunk, &block_map, &atlas)
+                    .with_neighbors(border)
+                    .build();
+                assert_eq!(
+                    count_visible_faces(chunk, &border, &block_map) as usize,
+                    vertices.len() / 4
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn builder_meshes_a_hand_checked_column() {
+        let (block_map, atlas) = blocks();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(15, 9, 5), BlockId::Stone);
+        chunk.set(Vec3::new(15, 10, 5), BlockId::Grass);
+        let solid = StandardChunk::flat(BlockId::Stone);
+        // Hides the east faces of both blocks
+        let border = BorderBlocks {
+            east: Some(&solid),
+            ..Default::default()
+        };
+        let quads = MeshBuilder::new(&chunk, &block_map, &atlas)
+            .with_neighbors(border)
+            .build_quads();
+
+        let quad = |corners: [[u32; 3]; 4], texture: &str, direction: Direction| {
+            Quad::new(
+                corners.map(Vec3::from),
+                atlas.get_texture_id(texture),
+                direction.vec(),
+            )
+        };
+        let stone = [
+            quad(
+                [[16, 9, 6], [15, 9, 6], [15, 10, 6], [16, 10, 6]],
+                "stone",
+                Direction::North,
+            ),
+            quad(
+                [[15, 9, 5], [16, 9, 5], [16, 10, 5], [15, 10, 5]],
+                "stone",
+                Direction::South,
+            ),
+            quad(
+                [[15, 9, 6], [15, 9, 5], [15, 10, 5], [15, 10, 6]],
+                "stone",
+                Direction::West,
+            ),
+            quad(
+                [[15, 9, 5], [15, 9, 6], [16, 9, 6], [16, 9, 5]],
+                "stone",
+                Direction::Down,
+            ),
+        ];
+        let grass = [
+            quad(
+                [[16, 10, 6], [15, 10, 6], [15, 11, 6], [16, 11, 6]],
+                "grass_side",
+                Direction::North,
+            ),
+            quad(
+                [[15, 10, 5], [16, 10, 5], [16, 11, 5], [15, 11, 5]],
+                "grass_side",
+                Direction::South,
+            ),
+            quad(
+                [[15, 10, 6], [15, 10, 5], [15, 11, 5], [15, 11, 6]],
+                "grass_side",
+                Direction::West,
+            ),
+            quad(
+                [[15, 11, 5], [16, 11, 5], [16, 11, 6], [15, 11, 6]],
+                "grass_top",
+                Direction::Up,
+            ),
+        ];
+        // Each block is meshed on its own, in the order the chunk is iterated
+        let (first, second) = quads.split_at(4);
+        if first[0].tile_id == stone[0].tile_id {
+            assert_eq!((first, second), (&stone[..], &grass[..]));
+        } else {
+            assert_eq!((first, second), (&grass[..], &stone[..]));
         }
     }
-    vertices
 }
@@ -1,76 +1,378 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Write, path::Path};
 
-use image::{GenericImage, RgbaImage};
+use image::{GenericImage, GrayImage, Rgba, RgbaImage};
+use vek::{Vec2, Vec3};
 
-use super::texture::Texture;
+use super::{format_label, texture::Texture};
+
+/// Number of atlas tiles used by every animated texture.
+///
+/// Must match `FRAMES_PER_ANIMATION` in the terrain shader.
+pub const FRAMES_PER_ANIMATION: u32 = 4;
+
+/// How steep the bumps of the normal atlas are.
+pub const NORMAL_MAP_STRENGTH: f32 = 2.0;
+
+/// The colour of a normal pointing straight out of the surface in a normal map.
+const FLAT_NORMAL: Rgba<u8> = Rgba([128, 128, 255, 255]);
+
+#[derive(Debug)]
+pub enum AtlasError {
+    /// A texture could not be read from disk.
+    Io(String, std::io::Error),
+    /// A texture could not be decoded.
+    Image(String, image::ImageError),
+    /// A texture is not in a format the image decoder supports.
+    UnsupportedFormat(String),
+    /// The atlas could not be written to disk.
+    Save(image::ImageError),
+    /// A texture does not have the same size as the atlas tiles.
+    TileSizeMismatch(String),
+    /// There were no textures to pack.
+    Empty,
+    /// A texture pack archive could not be extracted.
+    Zip(String, zip::result::ZipError),
+}
+
+impl AtlasError {
+    fn from_image(path: &str, error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(e) => AtlasError::Io(path.to_owned(), e),
+            image::ImageError::Unsupported(_) => AtlasError::UnsupportedFormat(path.to_owned()),
+            e => AtlasError::Image(path.to_owned(), e),
+        }
+    }
+}
+
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::Io(path, e) => {
+                write!(f, "Failed to read texture: {}. Path: {}", e, path)
+            },
+            AtlasError::Image(path, e) => {
+                write!(f, "Failed to load texture: {}. Path: {}", e, path)
+            },
+            AtlasError::UnsupportedFormat(path) => {
+                write!(f, "Unsupported texture format. Path: {}", path)
+            },
+            AtlasError::Save(e) => write!(f, "Failed to save atlas: {}", e),
+            AtlasError::TileSizeMismatch(path) => {
+                write!(f, "All textures must be the same size. Path: {}", path)
+            },
+            AtlasError::Empty => write!(f, "No textures to pack"),
+            AtlasError::Zip(path, e) => {
+                write!(f, "Failed to extract texture pack: {}. Path: {}", e, path)
+            },
+        }
+    }
+}
 
 pub struct BlockAtlas {
     pub buffer: RgbaImage,
     pub tiles: HashMap<String, u16>,
-    pub tile_size: u32,
+    /// The width and height of a tile, without its padding.
+    pub tile_size: Vec2<u32>,
     pub atlas_size: u32,
+    /// Transparent pixels between each tile.
+    pub padding: u32,
+    /// The pixel offset of each tile, indexed by tile id.
+    pub tile_offsets: Vec<Vec2<u32>>,
+    /// Tiles from this id on are animated, each one followed by its other frames.
+    pub animated_tile_start: u16,
+    /// Number of frames of the animated textures.
+    pub frame_counts: HashMap<String, u32>,
 }
 
 impl BlockAtlas {
-    pub fn create(textures: &[String]) -> std::io::Result<Self> {
-        let mut texture_data = Vec::new();
-        let (mut last_width, mut last_height) = (0, 0);
+    /// Creates an atlas using the size of the first texture as the tile size.
+    pub fn create(textures: &[String]) -> Result<Self, AtlasError> {
+        let first = textures.first().ok_or(AtlasError::Empty)?;
+        let (width, height) =
+            image::image_dimensions(first).map_err(|e| AtlasError::from_image(first, e))?;
+        let (tile_w, tile_h) = tile_size_of(width, height);
+        Self::create_pow2(textures, tile_w, tile_h, 0)
+    }
+
+    /// Creates an atlas whose size is rounded up to the next power of two,
+    /// which some samplers require for mipmapping.
+    ///
+    /// `padding` transparent pixels are added after each tile to avoid
+    /// sampling the neighbour tiles.
+    pub fn create_pow2(
+        textures: &[String],
+        tile_w: u32,
+        tile_h: u32,
+        padding: u32,
+    ) -> Result<Self, AtlasError> {
+        let mut images = Vec::with_capacity(textures.len());
         for path in textures {
-            let image = match image::open(path) {
-                Ok(image) => image,
-                Err(e) => panic!("Failed to load texture: {}. Path: {}", e, path),
-            };
-
-            if last_width != 0
-                && last_height != 0
-                && (image.width() != last_width || image.height() != last_height)
-            {
-                panic!("All textures must be the same size");
+            let image = image::open(path)
+                .map_err(|e| AtlasError::from_image(path, e))?
+                .to_rgba8();
+            let filename = path
+                .split('/')
+                .next_back()
+                .unwrap()
+                .split('.')
+                .next()
+                .unwrap();
+            images.push((filename.to_owned(), image));
+        }
+        let atlas = Self::pack(images, tile_w, tile_h, padding)?;
+        // Dump the packed atlas next to the binary while developing; a failed dump isn't fatal
+        #[cfg(debug_assertions)]
+        let _ = atlas.save("atlas.png");
+        Ok(atlas)
+    }
+
+    /// Loads every PNG file of a directory into an atlas, without blocking on the file system.
+    ///
+    /// The files are read and decoded concurrently, decoding on the blocking thread pool.
+    /// Tiles are ordered by file name and use the size of the first texture.
+    /// Must be polled from within a tokio runtime.
+    pub async fn load_from_directory_async(path: &Path) -> Result<Self, AtlasError> {
+        let display = path.display().to_string();
+        let mut entries = tokio::fs::read_dir(path)
+            .await
+            .map_err(|e| AtlasError::Io(display.clone(), e))?;
+        let mut paths = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| AtlasError::Io(display.clone(), e))?
+        {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "png") {
+                paths.push(path);
             }
+        }
+        paths.sort();
+
+        let tasks = paths
+            .into_iter()
+            .map(|path| {
+                tokio::spawn(async move {
+                    let name = path.display().to_string();
+                    let bytes = tokio::fs::read(&path)
+                        .await
+                        .map_err(|e| AtlasError::Io(name.clone(), e))?;
+                    let image = tokio::task::spawn_blocking(move || {
+                        image::load_from_memory(&bytes)
+                            .map(|image| image.to_rgba8())
+                            .map_err(|e| AtlasError::from_image(&name, e))
+                    })
+                    .await
+                    .expect("Texture decoding panicked")?;
+                    let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+                    Ok::<_, AtlasError>((stem, image))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut images = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            images.push(task.await.expect("Texture loading panicked")?);
+        }
+        let (width, height) = images.first().ok_or(AtlasError::Empty)?.1.dimensions();
+        let (tile_w, tile_h) = tile_size_of(width, height);
+        Self::pack(images, tile_w, tile_h, 0)
+    }
+
+    /// Blocks on [`BlockAtlas::load_from_directory_async`] for callers outside of a tokio runtime.
+    pub fn load_from_directory(path: &Path) -> Result<Self, AtlasError> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .build()
+            .map_err(|e| AtlasError::Io(path.display().to_string(), e))?;
+        runtime.block_on(Self::load_from_directory_async(path))
+    }
 
-            last_width = image.width();
-            last_height = image.height();
+    /// Loads a texture pack, a ZIP archive with the PNG textures at its root.
+    ///
+    /// The archive is extracted to a temporary directory which is then loaded like
+    /// [`BlockAtlas::load_from_directory`].
+    pub fn load_from_zip(path: &Path) -> Result<Self, AtlasError> {
+        let display = path.display().to_string();
+        let file = std::fs::File::open(path).map_err(|e| AtlasError::Io(display.clone(), e))?;
+        let mut archive =
+            zip::ZipArchive::new(file).map_err(|e| AtlasError::Zip(display.clone(), e))?;
 
-            texture_data.push(image);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = std::env::temp_dir().join(format!("explora_texture_pack_{}", stem));
+        // Leftovers of a previous extraction would end up in the atlas
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).map_err(|e| AtlasError::Io(display.clone(), e))?;
+        }
+        archive
+            .extract(&dir)
+            .map_err(|e| AtlasError::Zip(display, e))?;
+
+        let atlas = Self::load_from_directory(&dir);
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            log::warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+        atlas
+    }
+
+    /// Writes the atlas image to `path`, mostly useful for debugging.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), AtlasError> {
+        self.buffer.save(path).map_err(AtlasError::Save)
+    }
+
+    /// Packs the textures into a grid of tiles.
+    ///
+    /// A texture as high as several tiles is an animation, with its frames stacked vertically.
+    /// Animated textures are placed after the static ones and always take
+    /// [`FRAMES_PER_ANIMATION`] tiles, looping over their frames to fill them.
+    fn pack(
+        images: Vec<(String, RgbaImage)>,
+        tile_w: u32,
+        tile_h: u32,
+        padding: u32,
+    ) -> Result<Self, AtlasError> {
+        if images.is_empty() {
+            return Err(AtlasError::Empty);
+        }
+        let mut frames = Vec::with_capacity(images.len());
+        let mut animations = Vec::new();
+        for (name, image) in images {
+            if image.width() != tile_w || image.height() % tile_h != 0 {
+                return Err(AtlasError::TileSizeMismatch(name));
+            }
+            match image.height() / tile_h {
+                1 => frames.push((Some(name), image)),
+                count => animations.push((name, image, count)),
+            }
+        }
+        let animated_tile_start = frames.len() as u16;
+        let mut frame_counts = HashMap::new();
+        for (name, image, count) in animations {
+            for i in 0..FRAMES_PER_ANIMATION {
+                let y = (i % count) * tile_h;
+                let frame = image::imageops::crop_imm(&image, 0, y, tile_w, tile_h).to_image();
+                // Only the first frame is looked up by name
+                frames.push(((i == 0).then(|| name.clone()), frame));
+            }
+            frame_counts.insert(name, count);
         }
 
-        let cols = (textures.len() as f32).sqrt().ceil() as u32;
-        let rows = cols;
+        let stride = Vec2::new(tile_w + padding, tile_h + padding);
+        // Start with a square grid and round it up to a power of two
+        let cols = (frames.len() as f32).sqrt().ceil() as u32;
+        let atlas_size = (cols * stride.x).max(cols * stride.y).next_power_of_two();
+        // Then fit as many tiles per row as the final size allows,
+        // so the shader can compute the columns from the atlas size.
+        let cols = atlas_size / stride.x;
 
-        let mut atlas = RgbaImage::new(cols * last_width, rows * last_height);
+        let mut atlas = RgbaImage::new(atlas_size, atlas_size);
         let mut tiles = HashMap::new();
+        let mut tile_offsets = Vec::with_capacity(frames.len());
 
         // Write the atlas
-        for (i, image) in texture_data.iter().enumerate() {
-            let x = (i as u32 % cols) * last_width;
-            let y = (i as u32 / rows) * last_height;
-
-            let filename = textures[i]
-                .split('/')
-                .last()
-                .unwrap()
-                .split('.')
-                .next()
-                .unwrap();
+        for (i, (name, image)) in frames.iter().enumerate() {
+            let offset = Vec2::new((i as u32 % cols) * stride.x, (i as u32 / cols) * stride.y);
 
-            tiles.insert(filename.to_owned(), i as u16);
+            if let Some(name) = name {
+                tiles.insert(name.clone(), i as u16);
+            }
+            tile_offsets.push(offset);
 
             atlas
-                .copy_from(image, x, y)
+                .copy_from(image, offset.x, offset.y)
                 .expect("Failed to copy texture to atlas");
         }
 
-        atlas.save("atlas.png").expect("Failed to save atlas");
         Ok(Self {
-            tile_size: last_width,
-            atlas_size: cols * last_width,
+            tile_size: Vec2::new(tile_w, tile_h),
+            atlas_size,
             buffer: atlas,
             tiles,
+            padding,
+            tile_offsets,
+            animated_tile_start,
+            frame_counts,
         })
     }
 
     pub fn create_texture_handle(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
-        Texture::new(device, queue, self.buffer.clone())
+        Texture::new_with_label(
+            device,
+            queue,
+            self.buffer.clone(),
+            &format_label("texture", "block_atlas", 0),
+        )
+    }
+
+    /// Computes the normal map of a surface whose height is given by `heightmap`,
+    /// the brighter the higher.
+    ///
+    /// The slope at each pixel is found with a Sobel filter and scaled by `strength`.
+    /// The normal, in the frame of the texture with X right and Y down, is stored as
+    /// `(normal + 1) / 2` in the RGB channels.
+    pub fn generate_normals_from_heightmap(heightmap: &GrayImage, strength: f32) -> RgbaImage {
+        let (width, height) = heightmap.dimensions();
+        // Pixels outside of the image repeat the closest edge
+        let height_at = |x: i64, y: i64| {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let y = y.clamp(0, height as i64 - 1) as u32;
+            heightmap.get_pixel(x, y).0[0] as f32 / 255.0
+        };
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            let dx = (height_at(x + 1, y - 1)
+                + 2.0 * height_at(x + 1, y)
+                + height_at(x + 1, y + 1))
+                - (height_at(x - 1, y - 1) + 2.0 * height_at(x - 1, y) + height_at(x - 1, y + 1));
+            let dy = (height_at(x - 1, y + 1)
+                + 2.0 * height_at(x, y + 1)
+                + height_at(x + 1, y + 1))
+                - (height_at(x - 1, y - 1) + 2.0 * height_at(x, y - 1) + height_at(x + 1, y - 1));
+            // The surface faces away from the slope
+            let normal = Vec3::new(-dx * strength, -dy * strength, 1.0).normalized();
+            let [r, g, b] = normal
+                .map(|x| ((x * 0.5 + 0.5) * 255.0).round() as u8)
+                .into_array();
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// Creates the normal maps of every tile, laid out like the colour atlas.
+    ///
+    /// Block textures have no height data, so their brightness is used instead.
+    pub fn normal_atlas(&self, strength: f32) -> RgbaImage {
+        let mut normals = RgbaImage::from_pixel(self.atlas_size, self.atlas_size, FLAT_NORMAL);
+        for offset in &self.tile_offsets {
+            let tile = image::imageops::crop_imm(
+                &self.buffer,
+                offset.x,
+                offset.y,
+                self.tile_size.x,
+                self.tile_size.y,
+            )
+            .to_image();
+            let heightmap = image::DynamicImage::ImageRgba8(tile).to_luma8();
+            normals
+                .copy_from(
+                    &Self::generate_normals_from_heightmap(&heightmap, strength),
+                    offset.x,
+                    offset.y,
+                )
+                .expect("Failed to copy normal map to atlas");
+        }
+        normals
+    }
+
+    pub fn create_normal_texture_handle(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Texture {
+        Texture::normal_map(
+            device,
+            queue,
+            self.normal_atlas(NORMAL_MAP_STRENGTH),
+            &format_label("texture", "block_normal_atlas", 0),
+        )
     }
 
     pub fn get_texture_id(&self, texture: &str) -> u16 {
@@ -79,4 +381,451 @@ impl BlockAtlas {
             None => panic!("Texture with name: {:?} not found. Make sure your texture is in assets/textures and is a png file", texture),
         }
     }
+
+    /// Number of animation frames of a texture, 1 if it is not animated.
+    pub fn frame_count(&self, texture: &str) -> u32 {
+        self.frame_counts.get(texture).copied().unwrap_or(1)
+    }
+
+    /// The tile drawn for `id` at `frame`, computed the same way as in the terrain shader.
+    pub fn frame_tile(&self, id: u16, frame: u32) -> u16 {
+        if id >= self.animated_tile_start {
+            id + (frame % FRAMES_PER_ANIMATION) as u16
+        } else {
+            id
+        }
+    }
+
+    /// Returns the UV rectangle `(min, max)` of a tile, excluding its padding.
+    pub fn tile_uv(&self, id: u16) -> Option<(Vec2<f32>, Vec2<f32>)> {
+        let offset = *self.tile_offsets.get(id as usize)?;
+        let size = self.atlas_size as f32;
+        let min = offset.map(|x| x as f32 / size);
+        let max = (offset + self.tile_size).map(|x| x as f32 / size);
+        Some((min, max))
+    }
+}
+
+/// The tile size of a texture, which is a vertical strip of square frames when it is animated.
+fn tile_size_of(width: u32, height: u32) -> (u32, u32) {
+    if height > width && height.is_multiple_of(width) {
+        (width, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Lists every tile of the atlas with its texture name and UV rectangle, ordered by tile id.
+pub fn atlas_diagnostics(atlas: &BlockAtlas) -> String {
+    let mut tiles = atlas.tiles.iter().collect::<Vec<_>>();
+    tiles.sort_by_key(|(_, id)| **id);
+    let mut out = format!(
+        "Atlas {}x{}, {} tiles of {}x{}px, {}px padding\n",
+        atlas.atlas_size,
+        atlas.atlas_size,
+        tiles.len(),
+        atlas.tile_size.x,
+        atlas.tile_size.y,
+        atlas.padding
+    );
+    for (name, id) in tiles {
+        let Some((min, max)) = atlas.tile_uv(*id) else {
+            continue;
+        };
+        writeln!(
+            out,
+            "{:>4} {:<24} uv_min: ({:.4}, {:.4}) uv_max: ({:.4}, {:.4})",
+            id, name, min.x, min.y, max.x, max.y
+        )
+        .expect("Writing to a String can't fail");
+    }
+    out
+}
+
+/// 3x5 pixel glyphs of the digits, one row per entry with the leftmost pixel in the highest bit.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Draws `n` at `(x, y)` with the [`DIGITS`] glyphs, clipped to the image.
+fn draw_number(image: &mut RgbaImage, n: u16, x: u32, y: u32, color: Rgba<u8>) {
+    for (i, digit) in n.to_string().bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let left = x + i as u32 * 4;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                let (px, py) = (left + col, y + row as u32);
+                if bits & (0b100 >> col) != 0 && px < image.width() && py < image.height() {
+                    image.put_pixel(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+impl BlockAtlas {
+    /// Writes the atlas with the tile grid and the id of each tile drawn over it.
+    pub fn dump_to_png(&self, path: &Path) -> std::io::Result<()> {
+        let mut image = self.buffer.clone();
+        let grid = Rgba([255, 0, 255, 255]);
+        for offset in &self.tile_offsets {
+            let end = (*offset + self.tile_size).map(|x| x.min(self.atlas_size) - 1);
+            for x in offset.x..=end.x {
+                image.put_pixel(x, offset.y, grid);
+                image.put_pixel(x, end.y, grid);
+            }
+            for y in offset.y..=end.y {
+                image.put_pixel(offset.x, y, grid);
+                image.put_pixel(end.x, y, grid);
+            }
+        }
+        for id in self.tiles.values() {
+            let offset = self.tile_offsets[*id as usize];
+            draw_number(&mut image, *id, offset.x + 2, offset.y + 2, Rgba([255; 4]));
+        }
+        image.save(path).map_err(|e| match e {
+            image::ImageError::IoError(e) => e,
+            e => std::io::Error::other(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgba, RgbaImage};
+    use vek::{Vec2, Vec3};
+
+    use std::path::Path;
+
+    use super::{atlas_diagnostics, AtlasError, BlockAtlas, FLAT_NORMAL, FRAMES_PER_ANIMATION};
+
+    const TEXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/textures/blocks");
+
+    fn sorted_pngs(dir: &Path) -> Vec<String> {
+        let mut paths = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    fn solid_tiles(count: u8) -> Vec<(String, RgbaImage)> {
+        (0..count)
+            .map(|i| {
+                let color = Rgba([i * 40 + 10, 255 - i * 40, i, 255]);
+                (format!("tile{}", i), RgbaImage::from_pixel(16, 16, color))
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn missing_texture_is_io_error() {
+        let result = BlockAtlas::create(&["does/not/exist.png".to_string()]);
+        assert!(matches!(result, Err(AtlasError::Io(..))));
+    }
+
+    #[test]
+    pub fn save_round_trip() {
+        let pixels = [
+            Rgba([255, 0, 0, 255]),
+            Rgba([0, 255, 0, 128]),
+            Rgba([0, 0, 255, 0]),
+            Rgba([10, 20, 30, 40]),
+        ];
+        let image = RgbaImage::from_fn(2, 2, |x, y| pixels[(y * 2 + x) as usize]);
+        let atlas = BlockAtlas::pack(vec![("tile".to_string(), image.clone())], 2, 2, 0).unwrap();
+        assert_eq!(atlas.atlas_size, 2);
+
+        let path = std::env::temp_dir().join("explora_atlas_round_trip.png");
+        atlas.save(&path).unwrap();
+        let read = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, image);
+    }
+
+    #[test]
+    pub fn atlas_size_is_power_of_two() {
+        let atlas = BlockAtlas::pack(solid_tiles(5), 16, 16, 0).unwrap();
+        assert_eq!(atlas.buffer.dimensions(), (64, 64));
+        assert_eq!(atlas.atlas_size, 64);
+    }
+
+    #[test]
+    pub fn rectangular_tiles_use_both_strides() {
+        let tiles = (0..5u8)
+            .map(|i| {
+                let color = Rgba([i * 40 + 10, 0, 0, 255]);
+                (format!("tile{}", i), RgbaImage::from_pixel(8, 4, color))
+            })
+            .collect::<Vec<_>>();
+        let atlas = BlockAtlas::pack(tiles.clone(), 8, 4, 1).unwrap();
+        assert_eq!(atlas.tile_size, Vec2::new(8, 4));
+        let size = atlas.atlas_size as f32;
+        // The columns are spaced by the padded width and the rows by the padded height
+        let cols = atlas.atlas_size / 9;
+        for (name, image) in &tiles {
+            let id = atlas.get_texture_id(name);
+            let (min, max) = atlas.tile_uv(id).unwrap();
+            let offset = Vec2::new(id as u32 % cols * 9, id as u32 / cols * 5);
+            assert_eq!(min, offset.map(|x| x as f32 / size));
+            assert_eq!(max - min, Vec2::new(8.0, 4.0) / size);
+            assert_eq!(
+                atlas.buffer.get_pixel(offset.x + 7, offset.y + 3),
+                image.get_pixel(0, 0)
+            );
+        }
+    }
+
+    #[test]
+    pub fn padded_tiles_do_not_bleed() {
+        let tiles = solid_tiles(5);
+        let atlas = BlockAtlas::pack(tiles.clone(), 16, 16, 1).unwrap();
+        assert!(atlas.atlas_size.is_power_of_two());
+
+        for (name, image) in &tiles {
+            let id = atlas.get_texture_id(name);
+            let (min, max) = atlas.tile_uv(id).unwrap();
+            let min = min.map(|x| (x * atlas.atlas_size as f32).round() as u32);
+            let max = max.map(|x| (x * atlas.atlas_size as f32).round() as u32);
+            // Everything inside the UV rectangle belongs to this tile
+            for x in min.x..max.x {
+                for y in min.y..max.y {
+                    assert_eq!(atlas.buffer.get_pixel(x, y), image.get_pixel(0, 0));
+                }
+            }
+            // And the pixel right after it is transparent padding
+            if max.x < atlas.atlas_size {
+                assert_eq!(atlas.buffer.get_pixel(max.x, min.y)[3], 0);
+            }
+        }
+    }
+
+    #[test]
+    pub fn diagnostics_list_every_texture_once() {
+        let atlas = BlockAtlas::pack(solid_tiles(5), 16, 16, 1).unwrap();
+        let diagnostics = atlas_diagnostics(&atlas);
+        for name in atlas.tiles.keys() {
+            assert_eq!(diagnostics.matches(name.as_str()).count(), 1, "{}", name);
+        }
+        // A header and one line per tile
+        assert_eq!(diagnostics.lines().count(), 6);
+    }
+
+    #[test]
+    pub fn dump_draws_tile_grid() {
+        let atlas = BlockAtlas::pack(solid_tiles(2), 16, 16, 0).unwrap();
+        let path = std::env::temp_dir().join("explora_atlas_dump.png");
+        atlas.dump_to_png(&path).unwrap();
+        let dump = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dump.dimensions(), atlas.buffer.dimensions());
+        assert_eq!(*dump.get_pixel(0, 0), Rgba([255, 0, 255, 255]));
+        // The inside of the tile is left untouched
+        assert_eq!(dump.get_pixel(8, 12), atlas.buffer.get_pixel(8, 12));
+    }
+
+    #[test]
+    pub fn async_and_sync_atlases_match() {
+        let dir = Path::new(TEXTURES);
+        let sync = BlockAtlas::pack(
+            sorted_pngs(dir)
+                .iter()
+                .map(|path| {
+                    let name = Path::new(path).file_stem().unwrap().to_string_lossy();
+                    (name.into_owned(), image::open(path).unwrap().to_rgba8())
+                })
+                .collect(),
+            16,
+            16,
+            0,
+        )
+        .unwrap();
+        let loaded = BlockAtlas::load_from_directory(dir).unwrap();
+        assert_eq!(loaded.tiles, sync.tiles);
+        assert_eq!(loaded.tile_offsets, sync.tile_offsets);
+        assert!(loaded.buffer == sync.buffer);
+    }
+
+    /// A strip of `frames` tiles of different colours.
+    fn animated_tile(frames: u8) -> RgbaImage {
+        RgbaImage::from_fn(16, 16 * frames as u32, |_, y| {
+            Rgba([0, 0, 100 + (y / 16) as u8 * 40, 255])
+        })
+    }
+
+    #[test]
+    pub fn animated_tiles_cycle_through_frames() {
+        let mut images = solid_tiles(2);
+        images.insert(0, ("water".to_string(), animated_tile(4)));
+        let atlas = BlockAtlas::pack(images, 16, 16, 0).unwrap();
+        assert_eq!(atlas.animated_tile_start, 2);
+        assert_eq!(atlas.tile_offsets.len(), 2 + FRAMES_PER_ANIMATION as usize);
+        assert_eq!(atlas.frame_count("water"), 4);
+        assert_eq!(atlas.frame_count("tile0"), 1);
+
+        let water = atlas.get_texture_id("water");
+        let uvs = (0..4)
+            .map(|frame| atlas.tile_uv(atlas.frame_tile(water, frame)).unwrap())
+            .collect::<Vec<_>>();
+        for (i, a) in uvs.iter().enumerate() {
+            for b in &uvs[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        // Frames are packed in order
+        for frame in 0..4 {
+            let offset = atlas.tile_offsets[atlas.frame_tile(water, frame) as usize];
+            let pixel = atlas.buffer.get_pixel(offset.x, offset.y);
+            assert_eq!(pixel.0[2], 100 + frame as u8 * 40);
+        }
+
+        let stone = atlas.get_texture_id("tile1");
+        let uv = atlas.tile_uv(stone);
+        for frame in 0..4 {
+            assert_eq!(atlas.tile_uv(atlas.frame_tile(stone, frame)), uv);
+        }
+    }
+
+    #[test]
+    pub fn short_animations_loop() {
+        let atlas =
+            BlockAtlas::pack(vec![("lava".to_string(), animated_tile(2))], 16, 16, 0).unwrap();
+        let lava = atlas.get_texture_id("lava");
+        let first = atlas.tile_offsets[atlas.frame_tile(lava, 0) as usize];
+        let third = atlas.tile_offsets[atlas.frame_tile(lava, 2) as usize];
+        assert_ne!(first, third);
+        assert_eq!(
+            atlas.buffer.get_pixel(first.x, first.y),
+            atlas.buffer.get_pixel(third.x, third.y)
+        );
+    }
+
+    #[test]
+    pub fn load_from_zip_matches_directory() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join("explora_texture_pack_test.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for (name, image) in solid_tiles(3) {
+            let mut bytes = Vec::new();
+            image
+                .write_to(
+                    &mut std::io::Cursor::new(&mut bytes),
+                    image::ImageOutputFormat::Png,
+                )
+                .unwrap();
+            writer
+                .start_file(format!("{}.png", name), Default::default())
+                .unwrap();
+            writer.write_all(&bytes).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let atlas = BlockAtlas::load_from_zip(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let packed = BlockAtlas::pack(solid_tiles(3), 16, 16, 0).unwrap();
+        assert_eq!(atlas.tiles, packed.tiles);
+        assert_eq!(atlas.buffer, packed.buffer);
+    }
+
+    #[test]
+    pub fn invalid_zip_is_zip_error() {
+        let path = std::env::temp_dir().join("explora_texture_pack_invalid.zip");
+        std::fs::write(&path, b"not a zip archive").unwrap();
+        let result = BlockAtlas::load_from_zip(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(AtlasError::Zip(..))));
+    }
+
+    #[test]
+    pub fn missing_directory_is_io_error() {
+        let result = BlockAtlas::load_from_directory(Path::new("does/not/exist"));
+        assert!(matches!(result, Err(AtlasError::Io(..))));
+    }
+
+    /// Compares loading 64 textures one after the other and concurrently.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    pub fn bench_load_64_textures() {
+        let dir = std::env::temp_dir().join("explora_atlas_bench");
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 0..64u8 {
+            let image = RgbaImage::from_pixel(16, 16, Rgba([i * 4, 255 - i * 4, i, 255]));
+            image.save(dir.join(format!("tile{:02}.png", i))).unwrap();
+        }
+
+        let start = std::time::Instant::now();
+        let images = sorted_pngs(&dir)
+            .iter()
+            .map(|path| (path.clone(), image::open(path).unwrap().to_rgba8()))
+            .collect::<Vec<_>>();
+        let serial = start.elapsed();
+        BlockAtlas::pack(images, 16, 16, 0).unwrap();
+
+        let start = std::time::Instant::now();
+        BlockAtlas::load_from_directory(&dir).unwrap();
+        let concurrent = start.elapsed();
+        std::fs::remove_dir_all(&dir).unwrap();
+        eprintln!(
+            "64 textures: serial {:?}, concurrent {:?}",
+            serial, concurrent
+        );
+    }
+
+    fn decode_normal(pixel: &Rgba<u8>) -> Vec3<f32> {
+        Vec3::new(pixel.0[0], pixel.0[1], pixel.0[2]).map(|x| x as f32 / 255.0 * 2.0 - 1.0)
+    }
+
+    #[test]
+    pub fn flat_heightmaps_have_flat_normals() {
+        let heightmap = GrayImage::from_pixel(8, 8, Luma([90]));
+        let normals = BlockAtlas::generate_normals_from_heightmap(&heightmap, 4.0);
+        assert!(normals.pixels().all(|pixel| *pixel == FLAT_NORMAL));
+    }
+
+    #[test]
+    pub fn sobel_normals_round_trip() {
+        // Rises by 10 every pixel to the right
+        let heightmap = GrayImage::from_fn(8, 8, |x, _| Luma([x as u8 * 10]));
+        let strength = 2.0;
+        let normals = BlockAtlas::generate_normals_from_heightmap(&heightmap, strength);
+
+        // The Sobel kernel weighs the slope of the three rows by 1, 2 and 1, over two pixels
+        let slope = 10.0 / 255.0 * 2.0 * 4.0;
+        let expected = Vec3::new(-slope * strength, 0.0, 1.0).normalized();
+        for x in 1..7 {
+            let normal = decode_normal(normals.get_pixel(x, 4));
+            assert!(
+                (normal - expected).magnitude() < 0.01,
+                "{:?} != {:?}",
+                normal,
+                expected
+            );
+        }
+        // Facing away from the slope, towards the left
+        assert!(decode_normal(normals.get_pixel(4, 0)).x < 0.0);
+    }
+
+    #[test]
+    pub fn normal_atlas_matches_the_colour_atlas_layout() {
+        let atlas = BlockAtlas::pack(solid_tiles(3), 16, 16, 2).unwrap();
+        let normals = atlas.normal_atlas(2.0);
+        assert_eq!(normals.dimensions(), atlas.buffer.dimensions());
+        // Solid colours have no bumps
+        assert!(normals.pixels().all(|pixel| *pixel == FLAT_NORMAL));
+    }
 }
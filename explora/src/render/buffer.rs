@@ -1,5 +1,7 @@
 use wgpu::util::DeviceExt;
 
+use super::{format_label, wait_idle};
+
 /// Represents a GPU buffer.
 ///
 /// It is a wrapper around [wgpu::Buffer].
@@ -17,12 +19,65 @@ pub struct Buffer<T: Copy + bytemuck::Pod> {
 }
 
 impl<T: Copy + bytemuck::Pod> Buffer<T> {
+    const ELEMENT_SIZE: u64 = std::mem::size_of::<T>() as u64;
+
     /// Creates a new [Buffer].
     ///
     /// The buffer is initialized with the given data.
     pub fn new(device: &wgpu::Device, usage: wgpu::BufferUsages, data: &[T]) -> Self {
+        Self::create(device, usage, data, None)
+    }
+
+    /// Creates a new [Buffer] with a debug label.
+    pub fn new_with_label(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsages,
+        data: &[T],
+        label: &str,
+    ) -> Self {
+        Self::create(device, usage, data, Some(label))
+    }
+
+    /// Creates a storage buffer holding `data`, e.g. the input of a compute shader.
+    ///
+    /// It can be written to from the CPU and copied from, to read it back.
+    pub fn new_storage(device: &wgpu::Device, data: &[T], label: &str) -> Self {
+        Self::create(
+            device,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            data,
+            Some(label),
+        )
+    }
+
+    /// Creates a zeroed storage buffer of `capacity` elements for a compute shader to write to.
+    ///
+    /// Storage buffers can only be mapped with [wgpu::Features::MAPPABLE_PRIMARY_BUFFERS],
+    /// so `MAP_READ` is only added when the device has it. Otherwise the results are read
+    /// by copying them to a staging buffer, as [Buffer::read_back] does.
+    pub fn new_read_storage(device: &wgpu::Device, capacity: usize, label: &str) -> Self {
+        let mut usage = wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC;
+        if device
+            .features()
+            .contains(wgpu::Features::MAPPABLE_PRIMARY_BUFFERS)
+        {
+            usage |= wgpu::BufferUsages::MAP_READ;
+        }
+        Self::create(device, usage, &vec![T::zeroed(); capacity], Some(label))
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsages,
+        data: &[T],
+        label: Option<&str>,
+    ) -> Self {
         let descriptor = wgpu::util::BufferInitDescriptor {
-            label: None,
+            label,
             contents: bytemuck::cast_slice(data),
             usage,
         };
@@ -44,18 +99,744 @@ impl<T: Copy + bytemuck::Pod> Buffer<T> {
         queue.write_buffer(&self.buf, 0, bytemuck::cast_slice(data))
     }
 
+    /// Writes `data` starting `offset_elements` elements into the buffer.
+    ///
+    /// Panics if the data does not fit in the buffer.
+    pub fn write_at_offset(&self, queue: &wgpu::Queue, offset_elements: u64, data: &[T]) {
+        assert!(
+            offset_elements + data.len() as u64 <= self.capacity(),
+            "Writing {} elements at offset {} overflows a buffer of {} elements",
+            data.len(),
+            offset_elements,
+            self.capacity()
+        );
+        if data.is_empty() {
+            return;
+        }
+        queue.write_buffer(
+            &self.buf,
+            offset_elements * Self::ELEMENT_SIZE,
+            bytemuck::cast_slice(data),
+        )
+    }
+
+    /// Records a GPU side copy of `count` elements from this buffer into `dst`.
+    ///
+    /// This buffer needs [wgpu::BufferUsages::COPY_SRC] and `dst` needs [wgpu::BufferUsages::COPY_DST].
+    /// Panics if either range is out of bounds.
+    pub fn copy_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        dst: &Buffer<T>,
+        src_offset: u64,
+        dst_offset: u64,
+        count: u64,
+    ) {
+        assert!(
+            src_offset + count <= self.capacity(),
+            "Copy source range is out of bounds"
+        );
+        assert!(
+            dst_offset + count <= dst.capacity(),
+            "Copy destination range is out of bounds"
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.buf,
+            src_offset * Self::ELEMENT_SIZE,
+            &dst.buf,
+            dst_offset * Self::ELEMENT_SIZE,
+            count * Self::ELEMENT_SIZE,
+        );
+    }
+
+    /// The number of elements the buffer can hold.
+    pub fn capacity(&self) -> u64 {
+        self.buf.size() / Self::ELEMENT_SIZE
+    }
+
     /// Gives you the whole buffer slice.
     pub fn slice(&self) -> wgpu::BufferSlice<'_> {
         self.buf.slice(..)
     }
 
-    pub fn as_entire_binding(&self) -> wgpu::BindingResource {
+    pub fn as_entire_binding(&self) -> wgpu::BindingResource<'_> {
         self.buf.as_entire_binding()
     }
 
+    /// The usage flags the buffer was created with.
+    pub fn usage(&self) -> wgpu::BufferUsages {
+        self.buf.usage()
+    }
+
+    /// Creates a new buffer with the same length, usage and contents.
+    ///
+    /// The contents are read back through a staging buffer and uploaded again,
+    /// so this buffer must have been created with [wgpu::BufferUsages::COPY_SRC].
+    /// This blocks until the GPU has finished copying.
+    pub fn clone_to(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let data = self.read_staged(device, queue);
+        Self::create(
+            device,
+            self.usage(),
+            &data,
+            Some(&format_label("buffer", "clone", 0)),
+        )
+    }
+
+    /// Reads the contents of the buffer back to the CPU.
+    ///
+    /// The buffer must have been created with [wgpu::BufferUsages::COPY_SRC].
+    /// This blocks until the GPU has finished copying, so it is only meant for debugging.
+    #[cfg(any(test, debug_assertions))]
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<T> {
+        self.read_staged(device, queue)
+    }
+
+    fn read_staged(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<T> {
+        let size = self.buf.size();
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format_label("buffer", "readback_staging", 0)),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&format_label("command_encoder", "readback", 0)),
+        });
+        encoder.copy_buffer_to_buffer(&self.buf, 0, &staging, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        wait_idle(device);
+        rx.recv()
+            .expect("Staging buffer was dropped while mapping")
+            .expect("Failed to map staging buffer");
+
+        let data = {
+            let view = slice.get_mapped_range();
+            // The buffer may be padded to the copy alignment
+            let bytes = self.len as usize * std::mem::size_of::<T>();
+            bytemuck::cast_slice::<u8, T>(&view[..bytes]).to_vec()
+        };
+        staging.unmap();
+        data
+    }
+
     /// Gives you the length of the buffer.
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u32 {
         self.len
     }
 }
+
+/// Hands out the slots of a [`GpuBufferPool`], reusing freed slots first.
+struct SlotAllocator {
+    /// Freed slot indices, the next one to be handed out is at the end.
+    free: Vec<u32>,
+    /// The slots from this index on were never handed out.
+    next: u32,
+    slot_count: u32,
+}
+
+impl SlotAllocator {
+    fn new(slots: u32) -> Self {
+        Self::with_used(slots, 0)
+    }
+
+    /// An allocator whose first `used` slots are already handed out.
+    fn with_used(slots: u32, used: u32) -> Self {
+        Self {
+            free: Vec::new(),
+            next: used,
+            slot_count: slots,
+        }
+    }
+
+    fn allocate(&mut self) -> Option<u32> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        if self.next == self.slot_count {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    fn free(&mut self, slot: u32) {
+        debug_assert!(
+            slot < self.next && !self.free.contains(&slot),
+            "Slot {} freed twice",
+            slot
+        );
+        self.free.push(slot);
+    }
+
+    fn available(&self) -> usize {
+        self.free.len() + (self.slot_count - self.next) as usize
+    }
+
+    fn freed(&self) -> usize {
+        self.free.len()
+    }
+
+    /// The number of slots that were handed out at least once.
+    fn peak(&self) -> u32 {
+        self.next
+    }
+
+    fn slot_count(&self) -> u32 {
+        self.slot_count
+    }
+}
+
+/// The share of free slots above which [`GpuBufferPool::should_defragment`] is true.
+pub const DEFRAGMENT_THRESHOLD: f32 = 0.2;
+
+/// A handle to a slot of a [`GpuBufferPool`].
+///
+/// It can't be cloned so a slot can only be freed once.
+#[derive(Debug)]
+pub struct PoolSlot<T> {
+    index: u32,
+    /// The number of elements written to the slot.
+    len: u32,
+    phantom: std::marker::PhantomData<T>,
+}
+
+impl<T> PoolSlot<T> {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+}
+
+/// One large GPU buffer split into fixed-size slots.
+///
+/// Allocating a slot only writes to the buffer, which is much cheaper than
+/// creating a new buffer for each chunk.
+pub struct GpuBufferPool<T: Copy + bytemuck::Pod> {
+    buffer: Buffer<T>,
+    /// The number of elements of each slot.
+    slot_size: u32,
+    slots: SlotAllocator,
+}
+
+impl<T: Copy + bytemuck::Pod> GpuBufferPool<T> {
+    /// Allocates `slot_count` slots of `slot_size` elements.
+    ///
+    /// `COPY_DST` is added to the usages since the slots are filled with writes,
+    /// and `COPY_SRC` so they can be moved by [`GpuBufferPool::defragment`].
+    pub fn new(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsages,
+        slot_size: u32,
+        slot_count: u32,
+    ) -> Self {
+        let data = vec![T::zeroed(); slot_size as usize * slot_count as usize];
+        Self {
+            buffer: Buffer::new_with_label(
+                device,
+                usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                &data,
+                &format_label("buffer", "pool", 0),
+            ),
+            slot_size,
+            slots: SlotAllocator::new(slot_count),
+        }
+    }
+
+    /// Writes `data` to a free slot.
+    ///
+    /// Returns `None` if the pool is full or the data is larger than a slot.
+    pub fn allocate(&mut self, queue: &wgpu::Queue, data: &[T]) -> Option<PoolSlot<T>> {
+        if data.len() > self.slot_size as usize {
+            return None;
+        }
+        let index = self.slots.allocate()?;
+        self.buffer.write_at_offset(queue, self.offset(index), data);
+        Some(PoolSlot {
+            index,
+            len: data.len() as u32,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Copies `len` elements of `src` from `src_offset` on to a free slot, on the GPU.
+    ///
+    /// Returns `None` if the pool is full or the data is larger than a slot.
+    pub fn allocate_copy(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &Buffer<T>,
+        src_offset: u64,
+        len: u32,
+    ) -> Option<PoolSlot<T>> {
+        if len > self.slot_size {
+            return None;
+        }
+        let index = self.slots.allocate()?;
+        src.copy_to(
+            encoder,
+            &self.buffer,
+            src_offset,
+            self.offset(index),
+            len as u64,
+        );
+        Some(PoolSlot {
+            index,
+            len,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Overwrites the data of `slot`, which must not grow.
+    pub fn write(&self, queue: &wgpu::Queue, slot: &PoolSlot<T>, data: &[T]) {
+        assert!(
+            data.len() <= slot.len as usize,
+            "The data doesn't fit in the slot"
+        );
+        self.buffer
+            .write_at_offset(queue, self.offset(slot.index), data);
+    }
+
+    /// Gives the slot back to the pool. Its contents are left as is until it is reused.
+    pub fn free(&mut self, slot: PoolSlot<T>) {
+        self.slots.free(slot.index);
+    }
+
+    /// The part of the buffer holding the data of `slot`.
+    pub fn slice(&self, slot: &PoolSlot<T>) -> wgpu::BufferSlice<'_> {
+        let element_size = Buffer::<T>::ELEMENT_SIZE;
+        let start = self.offset(slot.index) * element_size;
+        let end = start + slot.len as u64 * element_size;
+        self.buffer.buf.slice(start..end)
+    }
+
+    /// The number of slots that can still be allocated.
+    pub fn available(&self) -> usize {
+        self.slots.available()
+    }
+
+    pub fn slot_size(&self) -> u32 {
+        self.slot_size
+    }
+
+    /// The buffer holding every slot.
+    pub fn buffer(&self) -> &Buffer<T> {
+        &self.buffer
+    }
+
+    /// The offset of the first element of `slot` in [`GpuBufferPool::buffer`].
+    pub fn slot_offset(&self, slot: &PoolSlot<T>) -> u64 {
+        self.offset(slot.index)
+    }
+
+    /// The number of slots the buffer is split into, allocated or not.
+    pub fn slot_count(&self) -> u32 {
+        self.slots.slot_count()
+    }
+
+    /// The share of the slots that were used and freed since, from 0.0 to 1.0.
+    ///
+    /// Slots that were never allocated don't count, so a new pool is not fragmented.
+    pub fn fragmentation_ratio(&self) -> f32 {
+        match self.slots.peak() {
+            0 => 0.0,
+            peak => self.slots.freed() as f32 / peak as f32,
+        }
+    }
+
+    /// Whether enough slots were freed for [`GpuBufferPool::defragment`] to be worth it.
+    pub fn should_defragment(&self) -> bool {
+        self.fragmentation_ratio() > DEFRAGMENT_THRESHOLD
+    }
+
+    /// Moves the data of `slots` to the front of a new buffer of `slot_count` slots,
+    /// releasing the memory of the freed ones. The slot handles are updated in place.
+    ///
+    /// `slots` must be every slot still allocated from this pool.
+    /// Panics if they don't fit in `slot_count` slots.
+    pub fn defragment(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        slots: &mut [&mut PoolSlot<T>],
+        slot_count: u32,
+    ) {
+        let used = slots.len() as u32;
+        assert!(used <= slot_count, "The slots don't fit in the new pool");
+        debug_assert_eq!(
+            self.available() as u32,
+            self.slot_count() - used,
+            "Every allocated slot must be moved"
+        );
+        let data = vec![T::zeroed(); self.slot_size as usize * slot_count as usize];
+        let buffer = Buffer::new_with_label(
+            device,
+            self.buffer.usage(),
+            &data,
+            &format_label("buffer", "pool", 0),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&format_label("command_encoder", "pool_defragment", 0)),
+        });
+        for (index, slot) in slots.iter_mut().enumerate() {
+            let index = index as u32;
+            self.buffer.copy_to(
+                &mut encoder,
+                &buffer,
+                self.offset(slot.index),
+                self.offset(index),
+                slot.len as u64,
+            );
+            slot.index = index;
+        }
+        queue.submit(Some(encoder.finish()));
+
+        // wgpu keeps the old buffer alive until the copies are done
+        self.buffer = buffer;
+        self.slots = SlotAllocator::with_used(slot_count, used);
+    }
+
+    fn offset(&self, index: u32) -> u64 {
+        index as u64 * self.slot_size as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::test_device::device;
+
+    use super::{Buffer, GpuBufferPool, SlotAllocator, DEFRAGMENT_THRESHOLD};
+
+    #[test]
+    pub fn new_creates_buffer_of_correct_size() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let data = [[1.0f32, 2.0, 3.0]; 5];
+        let buffer = Buffer::new(&device, wgpu::BufferUsages::VERTEX, &data);
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(
+            buffer.buf.size(),
+            std::mem::size_of_val(&data) as wgpu::BufferAddress
+        );
+    }
+
+    #[test]
+    pub fn labelled_buffer_holds_its_data() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let buffer = Buffer::new_with_label(
+            &device,
+            wgpu::BufferUsages::COPY_SRC,
+            &[4u32, 3, 2, 1],
+            "Labelled Buffer",
+        );
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.read_back(&device, &queue), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    pub fn storage_buffer_has_the_size_of_its_data() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let data = [1u32, 2, 3, 4, 5, 6, 7];
+        let buffer = Buffer::new_storage(&device, &data, "storage");
+        assert_eq!(buffer.len(), 7);
+        assert_eq!(buffer.buf.size(), 7 * 4);
+        assert!(buffer.usage().contains(
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC
+        ));
+    }
+
+    #[test]
+    pub fn read_storage_buffer_is_zeroed() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let buffer = Buffer::<u32>::new_read_storage(&device, 16, "read_storage");
+        assert_eq!(buffer.capacity(), 16);
+        // The default device descriptor enables no optional features
+        assert!(!buffer.usage().contains(wgpu::BufferUsages::MAP_READ));
+        assert_eq!(buffer.read_back(&device, &queue), [0; 16]);
+    }
+
+    #[test]
+    pub fn clone_to_copies_length_usage_and_contents() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let usage = wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST;
+        let original = Buffer::new(&device, usage, &[1u32, 2, 3, 4]);
+        let clone = original.clone_to(&device, &queue);
+        assert_eq!(clone.len(), original.len());
+        assert_eq!(clone.usage(), original.usage());
+        assert_eq!(clone.read_back(&device, &queue), [1, 2, 3, 4]);
+
+        // The clone is a separate allocation
+        clone.write(&queue, &[9; 4]);
+        assert_eq!(clone.read_back(&device, &queue), [9; 4]);
+        assert_eq!(original.read_back(&device, &queue), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    pub fn write_does_not_panic() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let buffer = Buffer::new(&device, wgpu::BufferUsages::COPY_DST, &[0u32; 8]);
+        buffer.write(&queue, &[7; 8]);
+        // Empty writes are skipped
+        buffer.write(&queue, &[]);
+        queue.submit(None);
+        device.poll(wgpu::Maintain::Wait);
+        assert_eq!(buffer.len(), 8);
+    }
+
+    #[test]
+    pub fn as_entire_binding_can_be_bound() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let buffer = Buffer::new(&device, wgpu::BufferUsages::UNIFORM, &[[0.0f32; 4]]);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn read_back_returns_written_data() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let buffer = Buffer::new(
+            &device,
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            &[0u32; 4],
+        );
+        buffer.write(&queue, &[1, 2, 3, 4]);
+        assert_eq!(buffer.read_back(&device, &queue), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn write_at_offset_updates_range() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let buffer = Buffer::new(
+            &device,
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            &[0u32; 4],
+        );
+        assert_eq!(buffer.capacity(), 4);
+        buffer.write_at_offset(&queue, 2, &[7, 8]);
+        assert_eq!(buffer.read_back(&device, &queue), vec![0, 0, 7, 8]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn copy_to_copies_range() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let usage = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        let src = Buffer::new(&device, usage, &[1u32, 2, 3, 4]);
+        let dst = Buffer::new(&device, usage, &[0u32; 4]);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        src.copy_to(&mut encoder, &dst, 1, 2, 2);
+        queue.submit(Some(encoder.finish()));
+        assert_eq!(dst.read_back(&device, &queue), vec![0, 0, 2, 3]);
+    }
+
+    #[test]
+    pub fn freed_slots_are_reused() {
+        let mut slots = SlotAllocator::new(4);
+        let all: Vec<_> = (0..4).map(|_| slots.allocate().unwrap()).collect();
+        assert_eq!(all, vec![0, 1, 2, 3]);
+        assert_eq!(slots.allocate(), None);
+
+        // Free every other slot, fragmenting the pool
+        slots.free(1);
+        slots.free(3);
+        assert_eq!(slots.available(), 2);
+        let mut reused = vec![slots.allocate().unwrap(), slots.allocate().unwrap()];
+        reused.sort();
+        assert_eq!(reused, vec![1, 3]);
+        assert_eq!(slots.allocate(), None);
+
+        // Once everything is freed the whole pool is usable again
+        for slot in all {
+            slots.free(slot);
+        }
+        assert_eq!(slots.available(), 4);
+        assert_eq!((0..4).filter_map(|_| slots.allocate()).count(), 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn pool_writes_to_slot() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let mut pool = GpuBufferPool::<u32>::new(&device, wgpu::BufferUsages::COPY_SRC, 2, 3);
+        let first = pool.allocate(&queue, &[1, 2]).unwrap();
+        let second = pool.allocate(&queue, &[3]).unwrap();
+        assert_eq!(second.len(), 1);
+        // Too large for a slot
+        assert!(pool.allocate(&queue, &[0; 3]).is_none());
+        assert_eq!(
+            pool.buffer.read_back(&device, &queue),
+            vec![1, 2, 3, 0, 0, 0]
+        );
+
+        pool.free(first);
+        let third = pool.allocate(&queue, &[4, 5]).unwrap();
+        assert_eq!(third.index(), 0);
+        assert_eq!(
+            pool.buffer.read_back(&device, &queue),
+            vec![4, 5, 3, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    pub fn defragment_releases_freed_slots() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let mut pool = GpuBufferPool::<u32>::new(&device, wgpu::BufferUsages::VERTEX, 2, 10);
+        // Capacity that was never used is not fragmentation
+        assert_eq!(pool.fragmentation_ratio(), 0.0);
+        let slots: Vec<_> = (0..10)
+            .map(|i| pool.allocate(&queue, &[i, i + 100]).unwrap())
+            .collect();
+        assert_eq!(pool.fragmentation_ratio(), 0.0);
+        assert!(!pool.should_defragment());
+
+        // Unload 60% of the chunks
+        let mut kept = Vec::new();
+        for slot in slots {
+            if slot.index() % 5 < 2 {
+                kept.push(slot);
+            } else {
+                pool.free(slot);
+            }
+        }
+        assert_eq!(pool.fragmentation_ratio(), 0.6);
+        assert!(pool.should_defragment());
+
+        pool.defragment(&device, &queue, &mut kept.iter_mut().collect::<Vec<_>>(), 5);
+        assert_eq!(pool.slot_count(), 5);
+        assert!(pool.fragmentation_ratio() <= DEFRAGMENT_THRESHOLD);
+        assert_eq!(
+            kept.iter().map(|slot| slot.index()).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            pool.buffer.read_back(&device, &queue),
+            vec![0, 100, 1, 101, 5, 105, 6, 106, 0, 0]
+        );
+
+        // The remaining free slot is handed out next
+        let slot = pool.allocate(&queue, &[7]).unwrap();
+        assert_eq!(slot.index(), 4);
+        assert!(pool.allocate(&queue, &[8]).is_none());
+    }
+
+    /// Compares the allocation latency of a pool with one buffer per chunk.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    pub fn bench_allocate_1000_chunks() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping benchmark");
+            return;
+        };
+        const CHUNKS: u32 = 1000;
+        let mesh = vec![0u32; 4096];
+
+        let mut pool = GpuBufferPool::new(
+            &device,
+            wgpu::BufferUsages::VERTEX,
+            mesh.len() as u32,
+            CHUNKS,
+        );
+        let start = std::time::Instant::now();
+        let slots: Vec<_> = (0..CHUNKS)
+            .map(|_| pool.allocate(&queue, &mesh).unwrap())
+            .collect();
+        queue.submit(None);
+        device.poll(wgpu::Maintain::Wait);
+        let pooled = start.elapsed();
+        assert_eq!(slots.len(), CHUNKS as usize);
+
+        let start = std::time::Instant::now();
+        let buffers: Vec<_> = (0..CHUNKS)
+            .map(|_| Buffer::new(&device, wgpu::BufferUsages::VERTEX, &mesh))
+            .collect();
+        device.poll(wgpu::Maintain::Wait);
+        let dedicated = start.elapsed();
+        assert_eq!(buffers.len(), CHUNKS as usize);
+
+        eprintln!(
+            "{} chunks: pooled {:?} ({:?}/chunk), dedicated {:?} ({:?}/chunk)",
+            CHUNKS,
+            pooled,
+            pooled / CHUNKS,
+            dedicated,
+            dedicated / CHUNKS
+        );
+    }
+}
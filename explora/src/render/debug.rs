@@ -0,0 +1,299 @@
+//! Debug lines drawn on top of the terrain, e.g. to visualise raycasts and bounding boxes.
+//!
+//! Only compiled in debug builds.
+use apecs::{anyhow::Result, *};
+use common::resources::DeltaTime;
+use vek::Vec3;
+
+use crate::render::{
+    buffer::Buffer, format_label, texture, CommandEncoder, RenderTexture, Renderer, Vertex,
+};
+
+/// A line segment in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+    pub start: Vec3<f32>,
+    pub end: Vec3<f32>,
+    pub color: [f32; 4],
+    /// Seconds the line stays visible. A line with no lifetime is drawn for a single frame.
+    pub lifetime: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct DebugLineVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl Vertex for DebugLineVertex {
+    // Every pair of vertices is a line
+    const INDEX_BUFFER: Option<wgpu::IndexFormat> = None;
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: Self::STRIDE,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+/// The lines to draw, filled by any system during the frame.
+#[derive(Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+}
+
+impl DebugDraw {
+    /// Draws a line for the current frame.
+    pub fn line(&mut self, start: Vec3<f32>, end: Vec3<f32>, color: [f32; 4]) {
+        self.push(DebugLine {
+            start,
+            end,
+            color,
+            lifetime: 0.0,
+        });
+    }
+
+    /// Draws the 12 edges of the box between `min` and `max` for the current frame.
+    pub fn aabb(&mut self, min: Vec3<f32>, max: Vec3<f32>, color: [f32; 4]) {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        // Corners are connected when they differ by exactly one axis
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    pub fn push(&mut self, line: DebugLine) {
+        self.lines.push(line);
+    }
+
+    pub fn lines(&self) -> &[DebugLine] {
+        &self.lines
+    }
+
+    /// Two vertices per line, for a line list.
+    pub fn vertices(&self) -> Vec<DebugLineVertex> {
+        self.lines
+            .iter()
+            .flat_map(|line| {
+                [line.start, line.end].map(|pos| DebugLineVertex {
+                    pos: pos.into_array(),
+                    color: line.color,
+                })
+            })
+            .collect()
+    }
+
+    /// Ages the lines by `dt` seconds and removes the expired ones.
+    pub fn tick(&mut self, dt: f32) {
+        self.lines.retain_mut(|line| {
+            line.lifetime -= dt;
+            line.lifetime > 0.0
+        });
+    }
+}
+
+pub struct DebugLinePipeline {
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugLinePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        common_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "debug_line", 0)),
+            bind_group_layouts: &[common_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "debug_line", 0)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[DebugLineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Hidden behind the terrain, without hiding anything themselves
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+}
+
+#[derive(CanFetch)]
+pub struct DebugDrawSystem {
+    encoder: Write<Option<CommandEncoder>>,
+    texture: Write<Option<RenderTexture>>,
+    renderer: Read<Renderer, NoDefault>,
+    debug_draw: Write<DebugDraw>,
+    dt: Read<DeltaTime>,
+}
+
+/// Draws the debug lines in their own pass, after the terrain.
+pub fn debug_draw_system(mut system: DebugDrawSystem) -> Result<ShouldContinue> {
+    let vertices = system.debug_draw.vertices();
+    let dt = system.dt.0;
+    system.debug_draw.tick(dt);
+    if vertices.is_empty() {
+        return ok();
+    }
+    let (Some(encoder), Some(texture)) = (
+        system.encoder.inner_mut().as_mut(),
+        system.texture.inner_mut().as_mut(),
+    ) else {
+        return ok();
+    };
+    let renderer = &system.renderer;
+    let buffer = Buffer::new_with_label(
+        &renderer.device,
+        wgpu::BufferUsages::VERTEX,
+        &vertices,
+        &format_label("buffer", "debug_line_vertex", 0),
+    );
+
+    let mut render_pass = encoder
+        .encoder
+        .begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "debug_line", 0)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: renderer.world_color_view(&texture.surface_tex_view),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &renderer.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    render_pass.set_pipeline(&renderer.debug_line_pipeline.pipeline);
+    render_pass.set_bind_group(0, &renderer.core_bind_group, &[]);
+    render_pass.set_vertex_buffer(0, buffer.slice());
+    render_pass.draw(0..buffer.len(), 0..1);
+    ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use crate::render::{create_common_bind_group_layout, test_device::device};
+
+    use super::{DebugDraw, DebugLine, DebugLinePipeline};
+
+    const SHADER: &str = include_str!("../../../assets/shaders/debug_line.wgsl");
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    #[test]
+    pub fn aabb_has_twelve_edges() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.aabb(Vec3::zero(), Vec3::one(), RED);
+        assert_eq!(debug_draw.vertices().len(), 24);
+        // Every edge is one unit long along a single axis
+        for line in debug_draw.lines() {
+            let delta = line.end - line.start;
+            assert_eq!(delta.sum(), 1.0);
+            assert_eq!(delta.map(|x| (x != 0.0) as u32).sum(), 1);
+        }
+    }
+
+    #[test]
+    pub fn lines_expire_after_their_lifetime() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.line(Vec3::zero(), Vec3::unit_x(), RED);
+        debug_draw.push(DebugLine {
+            start: Vec3::zero(),
+            end: Vec3::unit_y(),
+            color: RED,
+            lifetime: 1.0,
+        });
+        debug_draw.tick(0.5);
+        assert_eq!(debug_draw.lines().len(), 1);
+        debug_draw.tick(0.5);
+        assert!(debug_draw.lines().is_empty());
+    }
+
+    #[test]
+    pub fn debug_line_pipeline_compiles() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let layout = create_common_bind_group_layout(&device);
+        DebugLinePipeline::new(
+            &device,
+            &layout,
+            &shader,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            1,
+        );
+        let error = pollster::block_on(device.pop_error_scope());
+        assert!(error.is_none(), "{:?}", error);
+    }
+}
@@ -1,8 +1,42 @@
+use super::atlas::AtlasError;
+
 #[derive(Debug)]
 pub enum RenderError {
     DeviceRequest(wgpu::RequestDeviceError),
     AdapterNotFound,
     SurfaceError(wgpu::CreateSurfaceError),
+    Atlas(AtlasError),
+    Io(std::io::Error),
+    /// A texture file could not be decoded.
+    Texture(String, image::ImageError),
+    /// A shader failed to compile or a pipeline could not be built from it.
+    Shader(String),
+    UnknownPipeline(String),
+    Pipeline(PipelineError),
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// The device could not allocate the pipeline anymore.
+    DeviceLost,
+    /// The bind group layouts don't match the ones declared by the shader.
+    BindGroupLayoutMismatch(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::DeviceRequest(e) => write!(f, "Failed to request a device: {}", e),
+            RenderError::AdapterNotFound => write!(f, "No compatible graphics adapter found"),
+            RenderError::SurfaceError(e) => write!(f, "Failed to create the surface: {}", e),
+            RenderError::Atlas(e) => write!(f, "{}", e),
+            RenderError::Io(e) => write!(f, "{}", e),
+            RenderError::Texture(path, e) => write!(f, "Failed to load texture `{}`: {}", path, e),
+            RenderError::Shader(e) => write!(f, "Shader error: {}", e),
+            RenderError::UnknownPipeline(name) => write!(f, "Unknown pipeline `{}`", name),
+            RenderError::Pipeline(e) => write!(f, "Pipeline error: {:?}", e),
+        }
+    }
 }
 
 impl From<wgpu::RequestDeviceError> for RenderError {
@@ -16,3 +50,21 @@ impl From<wgpu::CreateSurfaceError> for RenderError {
         Self::SurfaceError(value)
     }
 }
+
+impl From<AtlasError> for RenderError {
+    fn from(value: AtlasError) -> Self {
+        Self::Atlas(value)
+    }
+}
+
+impl From<std::io::Error> for RenderError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<PipelineError> for RenderError {
+    fn from(value: PipelineError) -> Self {
+        Self::Pipeline(value)
+    }
+}
@@ -1,24 +1,91 @@
 pub mod atlas;
 pub mod buffer;
+#[cfg(debug_assertions)]
+pub mod debug;
 pub mod error;
 pub mod pipeline;
 pub mod resources;
+#[cfg(test)]
+pub(crate) mod test_device;
 pub mod texture;
+pub mod timestamp;
 pub mod ui;
 pub mod vertex;
 
 use atlas::BlockAtlas;
 use buffer::Buffer;
-use resources::{EguiContext, TerrainRender};
-use texture::Texture;
-use vek::{Mat4, Vec3};
+use common::state::timed;
+use pipeline::{
+    FxaaPipeline, HighlightPipeline, LinearDepthPipeline, MultisampleResolvePipeline,
+    PipelineConfig, PipelineRegistry, UiPipeline,
+};
+use resources::{BlockHighlight, EguiContext, HudRender, TerrainRender};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use texture::{Texture, TextureCache};
+use timestamp::TimestampQueries;
+use vek::{Mat4, Vec2, Vec3};
 
 pub const SYSTEM_STAGE_PRE_RENDER: &str = "pre_render";
 pub const SYSTEM_STAGE_RENDER: &str = "render";
 pub const SYSTEM_STAGE_UI_DRAW_WIDGETS: &str = "ui_draw_widgets";
+#[cfg(debug_assertions)]
+pub const SYSTEM_STAGE_DEBUG_DRAW: &str = "debug_draw";
 pub const SYSTEM_STAGE_UI_RENDER: &str = "ui_render";
 pub const SYSTEM_STAGE_POST_RENDER: &str = "post_render";
 
+/// Features that are used when available, without being required to run.
+///
+/// - `POLYGON_MODE_LINE` for the wireframe view.
+/// - `TIMESTAMP_QUERY` to measure the GPU frame time.
+/// - `MAPPABLE_PRIMARY_BUFFERS` to map the output of compute shaders without a staging buffer.
+const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE
+    .union(wgpu::Features::TIMESTAMP_QUERY)
+    .union(wgpu::Features::MAPPABLE_PRIMARY_BUFFERS);
+
+/// Graphics debuggers may cut labels longer than this.
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// Formats the debug label of a GPU object as `"{category}/{name}[{index}]"`,
+/// e.g. `"buffer/terrain_index[0]"`.
+///
+/// `index` tells apart objects created with the same name, and is 0 for unique objects.
+pub fn format_label(category: &str, name: &str, index: u32) -> String {
+    let label = format!("{}/{}[{}]", category, name, index);
+    debug_assert!(
+        label.len() <= MAX_LABEL_LEN,
+        "Debug label `{}` is longer than {} bytes",
+        label,
+        MAX_LABEL_LEN
+    );
+    label
+}
+
+/// Hands out the labels of GPU objects created many times, such as chunk buffers,
+/// numbering them in creation order.
+#[derive(Debug, Default)]
+pub struct DebugLabelRegistry {
+    counters: HashMap<(&'static str, &'static str), u32>,
+}
+
+impl DebugLabelRegistry {
+    pub fn next(&mut self, category: &'static str, name: &'static str) -> String {
+        let index = self.next_index(category, name);
+        format_label(category, name, index)
+    }
+
+    /// Returns the index of the next object, for objects whose labels are formatted elsewhere.
+    pub fn next_index(&mut self, category: &'static str, name: &'static str) -> u32 {
+        let counter = self.counters.entry((category, name)).or_default();
+        let index = *counter;
+        *counter += 1;
+        index
+    }
+}
+
 pub trait Vertex: bytemuck::Pod {
     const STRIDE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
 
@@ -47,8 +114,19 @@ pub struct Uniforms {
     pub sun_pos: [f32; 3],
     pub enable_lighting: u32,
     pub atlas_size: u32,
-    pub tile_size: u32,
-    _padding: [f32; 2], // UNUSED for now
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tile_padding: u32,
+    /// Advanced over time to animate the tiles from `animated_tile_start` on.
+    pub current_frame: u32,
+    pub animated_tile_start: u32,
+    /// Clip planes of the camera projection.
+    pub near: f32,
+    pub far: f32,
+    /// The tile of the reflective blocks, which mirror the environment map.
+    pub reflective_tile: u32,
+    // WGSL rounds the size of uniform structs up to 16 bytes
+    _padding: [u32; 3],
 }
 
 impl Uniforms {
@@ -58,7 +136,8 @@ impl Uniforms {
         sun_pos: Vec3<f32>,
         lighting: u32,
         atlas_size: u32,
-        tile_size: u32,
+        tile_size: Vec2<u32>,
+        tile_padding: u32,
     ) -> Self {
         Self {
             view: view.into_col_arrays(),
@@ -66,20 +145,132 @@ impl Uniforms {
             sun_pos: sun_pos.into_array(),
             enable_lighting: lighting,
             atlas_size,
-            tile_size,
-            _padding: [0.0; 2],
+            tile_width: tile_size.x,
+            tile_height: tile_size.y,
+            tile_padding,
+            current_frame: 0,
+            // Nothing is animated until an atlas says so
+            animated_tile_start: u32::MAX,
+            near: camera::Z_NEAR,
+            far: camera::Z_FAR,
+            reflective_tile: u32::MAX,
+            _padding: [0; 3],
         }
     }
 }
 impl Default for Uniforms {
     fn default() -> Self {
-        Self::new(Mat4::identity(), Mat4::identity(), Vec3::zero(), 1, 0, 0)
+        Self::new(
+            Mat4::identity(),
+            Mat4::identity(),
+            Vec3::zero(),
+            1,
+            0,
+            Vec2::zero(),
+            0,
+        )
     }
 }
 
-pub struct Pipelines {
-    pub terrain: pipeline::TerrainPipeline,
-    pub terrain_wireframe: pipeline::TerrainPipeline,
+/// The state of the frame being rendered, for debugging.
+///
+/// Updated by the pre-render stage once the frame has been acquired.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderStats {
+    /// The number of frames rendered so far, usable to seed per-frame randomness.
+    pub frame_number: u64,
+    pub surface_width: u32,
+    pub surface_height: u32,
+    pub surface_format: wgpu::TextureFormat,
+    pub last_present_mode: wgpu::PresentMode,
+}
+
+impl RenderStats {
+    pub fn new(config: &wgpu::SurfaceConfiguration) -> Self {
+        Self {
+            frame_number: 0,
+            surface_width: config.width,
+            surface_height: config.height,
+            surface_format: config.format,
+            last_present_mode: config.present_mode,
+        }
+    }
+
+    /// Counts a new frame rendered to a surface configured with `config`.
+    pub fn record_frame(&mut self, config: &wgpu::SurfaceConfiguration) {
+        *self = Self {
+            frame_number: self.frame_number + 1,
+            ..Self::new(config)
+        };
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.surface_width as f32 / self.surface_height.max(1) as f32
+    }
+
+    /// Whether the surface has a floating point format, able to hold colours brighter than white.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.surface_format,
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+        )
+    }
+}
+
+/// The passes smoothing the edges of the world in one of the [`AntiAliasingMode`]s.
+struct AntiAliasing {
+    /// The mode the device supports out of the requested one.
+    mode: AntiAliasingMode,
+    /// Only used when the world is drawn with more than one sample per pixel.
+    msaa: Option<MultisampleResolvePipeline>,
+    /// Only used when the world is anti-aliased with FXAA.
+    fxaa: Option<FxaaPipeline>,
+}
+
+impl AntiAliasing {
+    /// `mode` must be supported by the device, see [`supported_sample_count`].
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        mode: AntiAliasingMode,
+    ) -> Self {
+        let sample_count = mode.sample_count();
+        Self {
+            mode,
+            msaa: (sample_count > 1)
+                .then(|| MultisampleResolvePipeline::new(device, config, sample_count)),
+            fxaa: mode
+                .is_post_process()
+                .then(|| FxaaPipeline::new(device, config)),
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if let Some(msaa) = &mut self.msaa {
+            msaa.resize(device, width, height);
+        }
+        if let Some(fxaa) = &mut self.fxaa {
+            fxaa.resize(device, width, height);
+        }
+    }
+
+    fn world_color_view<'a>(&'a self, surface: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        if let Some(msaa) = &self.msaa {
+            msaa.view()
+        } else if let Some(fxaa) = &self.fxaa {
+            fxaa.view()
+        } else {
+            surface
+        }
+    }
+
+    fn run(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        if let Some(msaa) = &self.msaa {
+            msaa.resolve(encoder, target);
+        } else if let Some(fxaa) = &self.fxaa {
+            fxaa.run(encoder, target);
+        }
+    }
 }
 
 pub struct Renderer {
@@ -87,15 +278,45 @@ pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    pipelines: Pipelines,
+    pipelines: PipelineRegistry,
     uniforms_buffer: Buffer<Uniforms>,
     terrain_index_buffer: Buffer<u32>,
     core_bind_group: wgpu::BindGroup,
+    atlas_texture: Texture,
+    /// The normal maps of the block textures, laid out like the atlas.
+    normal_atlas: Texture,
+    /// The cube map reflected by shiny surfaces.
+    environment_map: Texture,
     depth_texture: Texture,
+    /// A copy of the last presented frame, for screenshots.
+    /// Only available if the surface can be copied from.
+    last_frame: Option<wgpu::Texture>,
+    anti_aliasing: AntiAliasing,
+    /// Only created while the depth of field is enabled.
+    linear_depth: Option<LinearDepthPipeline>,
     egui_renderer: egui_wgpu::Renderer,
+    ui_pipeline: UiPipeline,
+    /// Rebuilt when the surface is resized, to stay centered.
+    crosshair: ui::Crosshair,
+    highlight_pipeline: HighlightPipeline,
+    block_highlight: BlockHighlight,
     // For debugging
     pub graphics_backend: String,
+    common_bind_group_layout: wgpu::BindGroupLayout,
     chunk_pos_bind_group_layout: wgpu::BindGroupLayout,
+    clear_color: wgpu::Color,
+    /// Only available if the adapter supports timestamp queries.
+    timestamps: Option<TimestampQueries>,
+    /// The graphics APIs the device was picked from.
+    backends: wgpu::Backends,
+    /// Set by [`Renderer::request_backend`], applied before the next frame.
+    pending_backends: Option<wgpu::Backends>,
+    /// Incremented every time the device is recreated, see [`Renderer::device_generation`].
+    device_generation: u32,
+    labels: DebugLabelRegistry,
+    texture_cache: TextureCache,
+    #[cfg(debug_assertions)]
+    debug_line_pipeline: debug::DebugLinePipeline,
 }
 
 impl Renderer {
@@ -103,21 +324,73 @@ impl Renderer {
         window: &winit::window::Window,
         textures: &[String],
     ) -> Result<apecs::Plugin, error::RenderError> {
-        let backends = std::env::var("WGPU_BACKEND")
-            .ok()
-            .and_then(|env| match env.to_lowercase().as_str() {
-                "vulkan" => Some(wgpu::Backends::VULKAN),
-                "metal" => Some(wgpu::Backends::METAL),
-                "dx12" => Some(wgpu::Backends::DX12),
-                "dx11" => Some(wgpu::Backends::DX11),
-                "opengl" => Some(wgpu::Backends::GL),
-                "primary" => Some(wgpu::Backends::PRIMARY),
-                "secondary" => Some(wgpu::Backends::SECONDARY),
-                "all" => Some(wgpu::Backends::all()),
-                _ => None,
-            })
-            .unwrap_or(wgpu::Backends::PRIMARY);
+        let block_atlas = BlockAtlas::create(textures)?;
+        Self::initialize_with_atlas(window, block_atlas, AntiAliasingMode::None)
+    }
+
+    /// Like [`Renderer::initialize`], with an atlas that was already loaded.
+    ///
+    /// The world is anti-aliased with `anti_aliasing`, or not at all if the graphics device
+    /// does not support it.
+    pub fn initialize_with_atlas(
+        window: &winit::window::Window,
+        block_atlas: BlockAtlas,
+        anti_aliasing: AntiAliasingMode,
+    ) -> Result<apecs::Plugin, error::RenderError> {
+        let this = Self::new(
+            window,
+            Some(&block_atlas),
+            anti_aliasing,
+            backends_from_env(),
+        )?;
+        Ok(Self::initialize_ecs_plugin(this, Some(block_atlas)))
+    }
 
+    /// Like [`Renderer::initialize_with_atlas`], without waiting for the atlas to be loaded.
+    ///
+    /// The atlas is loaded from the `path` directory on `runtime` while the game starts,
+    /// the terrain is meshed once it is available. If it fails to load, the error is stored
+    /// in the `Option<RenderError>` resource and [`run`](crate::run::run) stops the game with it.
+    pub fn initialize_loading_atlas(
+        window: &winit::window::Window,
+        runtime: &tokio::runtime::Handle,
+        path: PathBuf,
+        anti_aliasing: AntiAliasingMode,
+    ) -> Result<apecs::Plugin, error::RenderError> {
+        let this = Self::new(window, None, anti_aliasing, backends_from_env())?;
+        let loading = AsyncSystem::spawn(runtime, async move {
+            BlockAtlas::load_from_directory_async(&path).await
+        });
+        let load_atlas = loading.into_system(
+            |result,
+             (mut renderer, mut atlas, mut failure): (
+                Write<Renderer, NoDefault>,
+                TryWrite<BlockAtlas>,
+                TryWrite<error::RenderError>,
+            )| match result {
+                Ok(loaded) => {
+                    log::info!("Loaded the block atlas");
+                    renderer.set_block_atlas(&loaded);
+                    *atlas.inner_mut() = Some(loaded);
+                },
+                // The game can't run without its textures
+                Err(e) => *failure.inner_mut() = Some(e.into()),
+            },
+        );
+        Ok(Self::initialize_ecs_plugin(this, None).with_system(
+            "block_atlas_load",
+            load_atlas,
+            &[],
+            &[SYSTEM_STAGE_PRE_RENDER],
+        ))
+    }
+
+    fn new(
+        window: &winit::window::Window,
+        block_atlas: Option<&BlockAtlas>,
+        anti_aliasing: AntiAliasingMode,
+        backends: wgpu::Backends,
+    ) -> Result<Self, error::RenderError> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends,
             flags: wgpu::InstanceFlags::default(),
@@ -145,7 +418,8 @@ impl Renderer {
 
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
-                features: wgpu::Features::POLYGON_MODE_LINE,
+                // Every feature is optional, so only request the ones the adapter has
+                features: adapter.features() & OPTIONAL_FEATURES,
                 limits: wgpu::Limits::default(),
                 label: None,
             },
@@ -162,8 +436,11 @@ impl Renderer {
             .unwrap_or(surface_caps.formats[0]);
 
         let size = window.inner_size();
+        // Frames can only be captured if they can be copied out of the surface
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -172,119 +449,100 @@ impl Renderer {
             view_formats: Vec::with_capacity(0),
         };
         surface.configure(&device, &config);
+        let last_frame = create_frame_capture(&device, &config);
+
+        let sample_count =
+            supported_sample_count(&adapter, surface_format, anti_aliasing.sample_count());
+        let anti_aliasing = AntiAliasing::new(
+            &device,
+            &config,
+            anti_aliasing.with_sample_count(sample_count),
+        );
 
         let shader = device
             .create_shader_module(wgpu::include_wgsl!("../../../assets/shaders/terrain.wgsl"));
 
-        let uniforms_buffer = Buffer::new(
+        let uniforms_buffer = Buffer::new_with_label(
             &device,
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             &[Uniforms::default()],
+            &format_label("buffer", "uniforms", 0),
         );
 
-        let block_atlas = match BlockAtlas::create(textures) {
-            Ok(atlas) => atlas,
-            Err(err) => {
-                panic!("Failed to create block atlas: {}", err);
-                // TODO: return custom error? (e.g RendererError::BlockAtlasCreationFailed)
-            },
-        };
+        let common_bind_group_layout = create_common_bind_group_layout(&device);
 
-        let common_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Common Bind Group Layout"),
-                entries: &[
-                    // Globals
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Atlas Texture
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    // Atlas Texture Sampler
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+        // Nothing is textured until the atlas is loaded
+        let (atlas_image, normal_atlas) = match block_atlas {
+            Some(atlas) => (
+                atlas.create_texture_handle(&device, &queue),
+                atlas.create_normal_texture_handle(&device, &queue),
+            ),
+            None => (
+                Texture::new(&device, &queue, image::RgbaImage::new(1, 1)),
+                // Points straight out of the surface
+                Texture::from_bytes(
+                    &device,
+                    &queue,
+                    &[128, 128, 255, 255],
+                    1,
+                    1,
+                    wgpu::TextureFormat::Rgba8Unorm,
+                ),
+            ),
+        };
 
-        let atlas_image = block_atlas.create_texture_handle(&device, &queue);
+        // The default clear colour until an environment map is loaded
+        let environment_map = Texture::solid_cube(&device, &queue, [26, 51, 77, 255]);
+        let common_bind_group = create_common_bind_group(
+            &device,
+            &common_bind_group_layout,
+            &uniforms_buffer,
+            &atlas_image,
+            &normal_atlas,
+            &environment_map,
+        );
 
-        let common_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Common Bind Group"),
-            layout: &common_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniforms_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&atlas_image.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&atlas_image.sampler),
-                },
-            ],
-        });
+        let chunk_pos_bind_group_layout = create_chunk_pos_bind_group_layout(&device);
 
-        let chunk_pos_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Chunk Pos Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
+        let bind_group_layouts = [&common_bind_group_layout, &chunk_pos_bind_group_layout];
+        let mut pipelines = PipelineRegistry::with_sample_count(sample_count);
+        pipelines.register_terrain_pipelines(&device, &bind_group_layouts, &shader, &config);
 
-        let pipelines = Pipelines {
-            terrain: pipeline::TerrainPipeline::new(
-                &device,
-                &[&common_bind_group_layout, &chunk_pos_bind_group_layout],
-                &shader,
-                &config,
-                false,
-            ),
-            terrain_wireframe: pipeline::TerrainPipeline::new(
+        let depth_texture =
+            Texture::depth(&device, config.width, config.height, sample_count, false);
+        let terrain_index_buffer = compute_terrain_indices(&device, 5000);
+        let egui_renderer = egui_wgpu::Renderer::new(&device, surface_format, None, 1);
+        let ui_shader =
+            device.create_shader_module(wgpu::include_wgsl!("../../../assets/shaders/ui.wgsl"));
+        let ui_pipeline = UiPipeline::new(&device, &ui_shader, surface_format);
+        let crosshair = ui::Crosshair::new(&device, config.width, config.height);
+        #[cfg(debug_assertions)]
+        let debug_line_pipeline = {
+            let shader = device.create_shader_module(wgpu::include_wgsl!(
+                "../../../assets/shaders/debug_line.wgsl"
+            ));
+            debug::DebugLinePipeline::new(
                 &device,
-                &[&common_bind_group_layout, &chunk_pos_bind_group_layout],
+                &common_bind_group_layout,
                 &shader,
-                &config,
-                true,
-            ),
+                surface_format,
+                sample_count,
+            )
         };
-
-        let depth_texture = Texture::depth(&device, config.width, config.height);
-        let terrain_index_buffer = compute_terrain_indices(&device, 5000);
-        let egui_renderer = egui_wgpu::Renderer::new(&device, surface_format, None, 1);
+        let highlight_shader = device.create_shader_module(wgpu::include_wgsl!(
+            "../../../assets/shaders/highlight.wgsl"
+        ));
+        let highlight_pipeline = HighlightPipeline::new(
+            &device,
+            &common_bind_group_layout,
+            &highlight_shader,
+            surface_format,
+            sample_count,
+        );
         let graphics_backend = format!("{:?}", adapter_info.backend);
+        let timestamps = TimestampQueries::new(&device, &queue);
 
-        let this = Self {
+        Ok(Self {
             surface,
             device,
             queue,
@@ -292,47 +550,147 @@ impl Renderer {
             terrain_index_buffer,
             uniforms_buffer,
             core_bind_group: common_bind_group,
+            atlas_texture: atlas_image,
+            normal_atlas,
+            environment_map,
             pipelines,
             depth_texture,
+            last_frame,
+            anti_aliasing,
+            linear_depth: None,
             egui_renderer,
+            ui_pipeline,
+            crosshair,
+            highlight_pipeline,
+            block_highlight: BlockHighlight::default(),
             graphics_backend,
+            common_bind_group_layout,
             chunk_pos_bind_group_layout,
-        };
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            timestamps,
+            backends,
+            pending_backends: None,
+            device_generation: 0,
+            labels: DebugLabelRegistry::default(),
+            texture_cache: TextureCache::default(),
+            #[cfg(debug_assertions)]
+            debug_line_pipeline,
+        })
+    }
+
+    /// Creates every GPU resource again on a new device, e.g. after the device was lost.
+    ///
+    /// Buffers created by the old device, such as the chunk meshes, are invalid afterwards
+    /// and must be recreated by the caller.
+    pub fn recreate(
+        &mut self,
+        window: &Window,
+        atlas: &BlockAtlas,
+    ) -> Result<(), error::RenderError> {
+        log::error!("Graphics device lost, recreating the renderer");
+        self.change_backend(window, atlas, self.backends)
+    }
 
-        Ok(Self::initialize_ecs_plugin(this, block_atlas))
+    /// Creates every GPU resource again on a device of one of the `backends`,
+    /// e.g. to move from Vulkan to DirectX 12.
+    ///
+    /// The old device is only dropped once the new one is ready, so the renderer is left
+    /// untouched if it fails. Otherwise buffers created by the old device, such as the chunk
+    /// meshes, are invalid afterwards and must be recreated by the caller.
+    pub fn change_backend(
+        &mut self,
+        window: &Window,
+        atlas: &BlockAtlas,
+        backends: wgpu::Backends,
+    ) -> Result<(), error::RenderError> {
+        let mut renderer = Self::new(
+            window.platform(),
+            Some(atlas),
+            self.anti_aliasing.mode,
+            backends,
+        )?;
+        renderer.clear_color = self.clear_color;
+        renderer.device_generation = self.device_generation + 1;
+        *self = renderer;
+        log::info!("Renderer recreated on {}", self.graphics_backend);
+        Ok(())
     }
 
-    fn initialize_ecs_plugin(self, atlas: BlockAtlas) -> apecs::Plugin {
-        apecs::Plugin::default()
+    /// Changes every time the device is recreated. The textures registered with
+    /// [`Renderer::register_ui_texture`] before that are gone and must be registered again,
+    /// without freeing the old ids which may now belong to other textures.
+    pub fn device_generation(&self) -> u32 {
+        self.device_generation
+    }
+
+    /// Switches to one of `backends` before the next frame, see [`Renderer::change_backend`].
+    pub fn request_backend(&mut self, backends: wgpu::Backends) {
+        self.pending_backends = Some(backends);
+    }
+
+    fn initialize_ecs_plugin(self, atlas: Option<BlockAtlas>) -> apecs::Plugin {
+        let stats = RenderStats::new(&self.config);
+        let plugin = apecs::Plugin::default()
+            .with_resource(move |_: ()| Ok(stats))
             .with_resource(|_: ()| Ok(self))
             .with_resource(|_: ()| Ok(Uniforms::default()))
             .with_resource(|_: ()| Ok(TerrainRender::default()))
             .with_resource(|_: ()| Ok(EguiContext::default()))
+            .with_resource(|_: ()| Ok(HudRender::default()))
             .with_resource(|_: ()| Ok(atlas))
+            .with_resource(|_: ()| Ok(None::<error::RenderError>))
             .with_system(
                 SYSTEM_STAGE_PRE_RENDER,
-                pre_render_system,
+                timed(SYSTEM_STAGE_PRE_RENDER, pre_render_system),
                 &[SYSTEM_STAGE_RENDER],
                 &[],
             )
+            .with_system(
+                "morph_target",
+                morph_target_system,
+                &[SYSTEM_STAGE_RENDER],
+                &[],
+            )
+            .with_system(
+                "gpu_timings",
+                gpu_timings_system,
+                &[],
+                &[SYSTEM_STAGE_PRE_RENDER],
+            )
             .with_system(
                 SYSTEM_STAGE_RENDER,
-                render_system,
+                timed(SYSTEM_STAGE_RENDER, render_system),
                 &[SYSTEM_STAGE_UI_DRAW_WIDGETS],
                 &[SYSTEM_STAGE_PRE_RENDER],
             )
             .with_system(
                 SYSTEM_STAGE_UI_RENDER,
-                ui::ui_render_system,
+                timed(SYSTEM_STAGE_UI_RENDER, ui::ui_render_system),
                 &[],
                 &[SYSTEM_STAGE_UI_DRAW_WIDGETS],
             )
             .with_system(
                 SYSTEM_STAGE_POST_RENDER,
-                post_render_system,
+                timed(SYSTEM_STAGE_POST_RENDER, post_render_system),
                 &[],
                 &[SYSTEM_STAGE_UI_RENDER],
-            )
+            );
+        // The debug lines go on top of the terrain and below the UI
+        #[cfg(debug_assertions)]
+        let plugin = plugin
+            .with_resource(|_: ()| Ok(debug::DebugDraw::default()))
+            .with_system(
+                SYSTEM_STAGE_DEBUG_DRAW,
+                debug::debug_draw_system,
+                &[SYSTEM_STAGE_UI_RENDER],
+                &[SYSTEM_STAGE_RENDER],
+            );
+        plugin
     }
 
     pub fn resize(&mut self, new_width: u32, new_height: u32) {
@@ -344,17 +702,323 @@ impl Renderer {
         }
         self.config.width = new_width;
         self.config.height = new_height;
-        self.depth_texture = Texture::depth(&self.device, new_width, new_height);
+        self.depth_texture = Texture::depth(
+            &self.device,
+            new_width,
+            new_height,
+            self.sample_count(),
+            self.linear_depth.is_some(),
+        );
+        self.last_frame = create_frame_capture(&self.device, &self.config);
+        self.crosshair.resize(&self.device, new_width, new_height);
+        self.anti_aliasing
+            .resize(&self.device, new_width, new_height);
+        if let Some(linear_depth) = &mut self.linear_depth {
+            linear_depth.resize(
+                &self.device,
+                &self.uniforms_buffer,
+                &self.depth_texture.view,
+                new_width,
+                new_height,
+            );
+        }
         self.surface.configure(&self.device, &self.config);
     }
 
+    pub fn width(&self) -> u32 {
+        self.config.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.config.height
+    }
+
+    /// Reads back the last frame captured for a screenshot, see [`Renderer::screenshot_region`].
+    pub fn screenshot(&self) -> image::RgbaImage {
+        self.screenshot_region(0, 0, self.width(), self.height())
+    }
+
+    /// Reads back a rectangle of the last frame captured for a screenshot, clamped to
+    /// the frame bounds. Frames are only captured when a [`Screenshots::request`] is pending.
+    ///
+    /// The image is empty if the surface of this device can't be copied from.
+    pub fn screenshot_region(&self, x: u32, y: u32, width: u32, height: u32) -> image::RgbaImage {
+        let Some(frame) = &self.last_frame else {
+            log::warn!(
+                "Screenshots are not supported by the {} surface",
+                self.graphics_backend
+            );
+            return image::RgbaImage::new(0, 0);
+        };
+        read_texture_region(&self.device, &self.queue, frame, x, y, width, height)
+    }
+
+    /// Blocks until the GPU has finished all the submitted work.
+    ///
+    /// Stalls the frame, so it is only meant to synchronise with the GPU in tests.
+    #[cfg(any(test, feature = "debug_sync"))]
+    pub fn wait_idle(&self) {
+        wait_idle(&self.device);
+    }
+
+    /// Samples per pixel of the world passes, 1 when the world is not anti-aliased.
+    pub fn sample_count(&self) -> u32 {
+        self.pipelines.sample_count()
+    }
+
+    pub fn anti_aliasing_mode(&self) -> AntiAliasingMode {
+        self.anti_aliasing.mode
+    }
+
+    /// The view the world passes draw to: the multisampled or FXAA texture when anti-aliasing
+    /// is on, otherwise the `surface` view directly.
+    fn world_color_view<'a>(&'a self, surface: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        self.anti_aliasing.world_color_view(surface)
+    }
+
+    /// Resolves the multisampled world or applies FXAA to it, writing the result to `target`
+    /// so the UI can be drawn on top of it.
+    ///
+    /// Does nothing when anti-aliasing is off, as the world was drawn to `target` directly.
+    pub fn anti_aliasing_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) {
+        self.anti_aliasing.run(encoder, target);
+    }
+
+    /// Draws a crosshair in the middle of `view`, on top of what it already contains.
+    pub fn draw_crosshair(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.ui_pipeline
+            .write_screen_size(&self.queue, self.config.width, self.config.height);
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "crosshair", 0)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.ui_pipeline.pipeline);
+        pass.set_bind_group(0, &self.ui_pipeline.bind_group, &[]);
+        self.crosshair.draw(&mut pass);
+    }
+
+    /// Writes the distance from the camera of every pixel of the world to
+    /// [`Renderer::linear_depth_view`], for the depth of field.
+    ///
+    /// Must be recorded after the world passes, which write the depth buffer.
+    pub fn linearize_depth_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(linear_depth) = &self.linear_depth {
+            linear_depth.run(encoder);
+        }
+    }
+
+    /// The output of [`Renderer::linearize_depth_pass`], an `Rgba32Float` texture with the
+    /// linear depth in the red channel. Only available while the depth of field is enabled.
+    pub fn linear_depth_view(&self) -> Option<&wgpu::TextureView> {
+        self.linear_depth.as_ref().map(LinearDepthPipeline::view)
+    }
+
+    /// Creates the linear depth pass the first time the depth of field is enabled,
+    /// and drops it again when it is disabled.
+    pub fn set_depth_of_field(&mut self, enabled: bool) {
+        if enabled == self.linear_depth.is_some() {
+            return;
+        }
+        // The linear depth pass reads the depth texture
+        self.depth_texture = Texture::depth(
+            &self.device,
+            self.config.width,
+            self.config.height,
+            self.sample_count(),
+            enabled,
+        );
+        if !enabled {
+            self.linear_depth = None;
+        } else {
+            self.linear_depth = Some(LinearDepthPipeline::new(
+                &self.device,
+                &self.uniforms_buffer,
+                &self.depth_texture.view,
+                self.config.width,
+                self.config.height,
+                self.sample_count(),
+            ));
+        }
+    }
+
+    /// Replaces the environment map with a cube map made of `faces`,
+    /// in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn set_environment_map(&mut self, faces: [image::DynamicImage; 6]) {
+        self.environment_map = Texture::cube(&self.device, &self.queue, faces);
+        self.update_common_bind_group();
+    }
+
+    /// Replaces the block textures with the ones of `atlas`.
+    pub fn set_block_atlas(&mut self, atlas: &BlockAtlas) {
+        self.atlas_texture = atlas.create_texture_handle(&self.device, &self.queue);
+        self.normal_atlas = atlas.create_normal_texture_handle(&self.device, &self.queue);
+        self.update_common_bind_group();
+    }
+
+    /// Binds the current textures, after one of them was replaced.
+    fn update_common_bind_group(&mut self) {
+        self.core_bind_group = create_common_bind_group(
+            &self.device,
+            &self.common_bind_group_layout,
+            &self.uniforms_buffer,
+            &self.atlas_texture,
+            &self.normal_atlas,
+            &self.environment_map,
+        );
+    }
+
+    pub fn environment_map(&self) -> &Texture {
+        &self.environment_map
+    }
+
+    /// Adds a pipeline that render systems can look up by `name`.
+    ///
+    /// `config` is used to build it again when its shader is reloaded.
+    pub fn register_pipeline(
+        &mut self,
+        name: &str,
+        config: PipelineConfig,
+        pipeline: wgpu::RenderPipeline,
+    ) {
+        self.pipelines.register(name, config, pipeline);
+    }
+
+    /// Builds a pipeline from `config` and the WGSL shader at `shader_path` and registers
+    /// it as `name`. Set [`TerrainRender::custom_pipeline`] to draw the terrain with it.
+    pub fn add_custom_pipeline(
+        &mut self,
+        name: &str,
+        config: PipelineConfig,
+        shader_path: &Path,
+    ) -> Result<(), error::RenderError> {
+        let source = std::fs::read_to_string(shader_path)?;
+        let _old = self.pipelines.add_custom(
+            name,
+            config,
+            &self.device,
+            &[
+                &self.common_bind_group_layout,
+                &self.chunk_pos_bind_group_layout,
+            ],
+            &source,
+            &self.config,
+        )?;
+        log::info!("Added pipeline {} from {}", name, shader_path.display());
+        Ok(())
+    }
+
+    /// Rebuilds the pipeline registered as `name` with the WGSL shader at `shader_path`,
+    /// leaving every other pipeline untouched.
+    ///
+    /// If the shader fails to compile the current pipeline is kept.
+    pub fn reload_pipeline(
+        &mut self,
+        name: &str,
+        shader_path: &Path,
+    ) -> Result<(), error::RenderError> {
+        let source = std::fs::read_to_string(shader_path)?;
+        // wgpu keeps the old pipeline alive until the frames using it are done
+        let _old = self.pipelines.reload(
+            name,
+            &self.device,
+            &[
+                &self.common_bind_group_layout,
+                &self.chunk_pos_bind_group_layout,
+            ],
+            &source,
+            &self.config,
+        )?;
+        log::info!("Reloaded pipeline {} from {}", name, shader_path.display());
+        Ok(())
+    }
+
+    /// The features enabled on the device.
+    pub fn device_features(&self) -> wgpu::Features {
+        self.device.features()
+    }
+
+    /// Whether every feature in `feature` is enabled on the device.
+    pub fn supports(&self, feature: wgpu::Features) -> bool {
+        self.device_features().contains(feature)
+    }
+
+    /// Sets the colour the frame is cleared with, which is what you see as the sky.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    /// The GPU time of the last measured render pass, if timestamp queries are supported.
+    pub fn gpu_frame_time_us(&self) -> Option<f32> {
+        self.timestamps.as_ref()?.frame_time_us()
+    }
+
+    /// Highlights a face of a block in the next frame, replacing any other highlight.
+    ///
+    /// `face` is the normal of the face, pointing away from the block.
+    pub fn highlight_block_face(
+        &mut self,
+        chunk: Vec2<i32>,
+        local_pos: Vec3<i32>,
+        face: Vec3<i32>,
+    ) {
+        self.block_highlight
+            .set(&self.device, &self.queue, chunk, local_pos, face);
+    }
+
+    pub fn clear_block_highlight(&mut self) {
+        self.block_highlight.clear();
+    }
+
     pub fn write_uniforms(&mut self, uniforms: Uniforms) {
         self.uniforms_buffer.write(&self.queue, &[uniforms]);
     }
 
     pub fn create_vertex_buffer<T: Vertex>(&mut self, data: &[T]) -> Buffer<T> {
         self.check_index_buffer::<T>(data.len());
-        Buffer::new(&self.device, wgpu::BufferUsages::VERTEX, data)
+        // Writable so level of detail transitions can blend the vertices in place,
+        // and copyable so defragmenting the terrain can move them
+        Buffer::new_with_label(
+            &self.device,
+            wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            data,
+            &self.labels.next("buffer", "vertex"),
+        )
+    }
+
+    /// Creates a storage buffer holding `data`, to be read by compute shaders.
+    pub fn create_storage_buffer<T: bytemuck::Pod>(&self, data: &[T]) -> Buffer<T> {
+        Buffer::new_storage(&self.device, data, &format_label("buffer", "storage", 0))
+    }
+
+    /// Creates a zeroed storage buffer of `capacity` elements for compute shaders to write to.
+    ///
+    /// See [`Buffer::new_read_storage`] for how to read it back.
+    pub fn create_read_storage_buffer<T: bytemuck::Pod>(&self, capacity: usize) -> Buffer<T> {
+        Buffer::new_read_storage(
+            &self.device,
+            capacity,
+            &format_label("buffer", "read_storage", 0),
+        )
     }
 
     pub fn create_terrain_chunk_mesh(
@@ -362,14 +1026,40 @@ impl Renderer {
         chunk_pos: ChunkPos,
         buf: Buffer<TerrainVertex>,
     ) -> TerrainChunkMesh {
+        let index = self.labels.next_index("bind_group", "chunk_pos");
         TerrainChunkMesh::new(
             &self.device,
             &self.chunk_pos_bind_group_layout,
             chunk_pos,
             buf,
+            index,
         )
     }
 
+    pub fn create_texture_from_image(&mut self, image: image::RgbaImage) -> Texture {
+        let label = self.labels.next("texture", "image");
+        Texture::new_with_label(&self.device, &self.queue, image, &label)
+    }
+
+    /// Loads the texture at `path`, or shares the one already loaded from the same file.
+    pub fn get_or_load_texture(&mut self, path: &Path) -> Result<Arc<Texture>, error::RenderError> {
+        self.texture_cache
+            .get_or_load(&self.device, &self.queue, path)
+    }
+
+    /// Makes a texture available to egui, e.g. to show it with an [`egui::Image`].
+    pub fn register_ui_texture(&mut self, texture: &Texture) -> egui::TextureId {
+        self.egui_renderer.register_native_texture(
+            &self.device,
+            &texture.view,
+            wgpu::FilterMode::Nearest,
+        )
+    }
+
+    pub fn free_ui_texture(&mut self, id: egui::TextureId) {
+        self.egui_renderer.free_texture(&id);
+    }
+
     pub fn update_ui_texture(
         &mut self,
         id: egui::TextureId,
@@ -400,31 +1090,45 @@ impl Renderer {
             Some(wgpu::IndexFormat::Uint16) => {
                 // TODO: create u16 index buffer
             },
-            Some(wgpu::IndexFormat::Uint32) => {
-                if self.terrain_index_buffer.len() < vertex_length as u32 {
-                    if len > u32::MAX as usize {
-                        panic!(
-                            "Too many vertices for {} using u32 index buffer. Count: {}",
-                            core::any::type_name::<V>(),
-                            len
-                        );
-                    }
-                    log::info!(
-                        "Recreating index buffer for {}, with {} vertices",
+            Some(wgpu::IndexFormat::Uint32)
+                if self.terrain_index_buffer.len() < vertex_length as u32 =>
+            {
+                if len > u32::MAX as usize {
+                    panic!(
+                        "Too many vertices for {} using u32 index buffer. Count: {}",
                         core::any::type_name::<V>(),
                         len
                     );
-                    self.terrain_index_buffer = compute_terrain_indices(&self.device, len);
                 }
+                log::info!(
+                    "Recreating index buffer for {}, with {} vertices",
+                    core::any::type_name::<V>(),
+                    len
+                );
+                self.terrain_index_buffer = compute_terrain_indices(&self.device, len);
             },
 
-            None => (),
+            Some(wgpu::IndexFormat::Uint32) | None => (),
         }
     }
 }
 
 use apecs::*;
 
+use common::{
+    ecs::{AsyncSystem, TryRead, TryWrite},
+    event::Events,
+    resources::{DeltaTime, PerformanceStats},
+};
+
+use crate::{
+    camera::{self, Camera, FrustumTest},
+    mesh::ChunkMeshCache,
+    screenshot::Screenshots,
+    settings::{AntiAliasingMode, GameplaySettings},
+    window::{Window, WindowEvent},
+};
+
 use self::{resources::TerrainChunkMesh, vertex::TerrainVertex};
 
 struct RenderTexture {
@@ -440,11 +1144,59 @@ struct CommandEncoder {
 struct PreRenderSystem {
     encoder: Write<Option<CommandEncoder>>,
     texture: Write<Option<RenderTexture>>,
-    renderer: Read<Renderer, NoDefault>,
+    renderer: Write<Renderer, NoDefault>,
+    events: Read<Events<WindowEvent>>,
+    window: Read<Window, NoDefault>,
+    atlas: TryRead<BlockAtlas>,
+    mesh_cache: Read<ChunkMeshCache>,
+    terrain_render: Write<TerrainRender>,
+    stats: Write<RenderStats, NoDefault>,
+    settings: Read<GameplaySettings>,
+    egui_context: Read<EguiContext>,
+}
+
+/// The size of the last resize event, the earlier ones are already outdated.
+fn latest_resize(events: &Events<WindowEvent>) -> Option<Vec2<u32>> {
+    events.events.iter().rev().find_map(|event| match event {
+        WindowEvent::Resize(size) => Some(*size),
+        _ => None,
+    })
 }
 
 fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<ShouldContinue> {
-    let renderer = system.renderer;
+    // Resize before acquiring the frame so the swapchain and depth texture
+    // already match the window when the render pass begins.
+    if let Some(size) = latest_resize(&system.events) {
+        system.renderer.resize(size.x, size.y);
+    }
+    // Pick up the GPU timings of a previous frame
+    let Renderer {
+        timestamps, device, ..
+    } = &mut *system.renderer;
+    if let Some(timestamps) = timestamps {
+        timestamps.read(device);
+    }
+    let mut renderer = system.renderer;
+    if let Some(backends) = renderer.pending_backends.take() {
+        let Some(atlas) = system.atlas.inner().as_ref() else {
+            log::error!("Can't change the graphics backend before the block atlas is loaded");
+            return ok();
+        };
+        match renderer.change_backend(&system.window, atlas, backends) {
+            Ok(()) => {
+                // The chunk meshes and the UI textures belong to the old device
+                crate::terrain::restore_chunk_meshes(
+                    &mut renderer,
+                    &system.mesh_cache,
+                    &mut system.terrain_render,
+                );
+                system.egui_context.reupload_textures();
+                return ok();
+            },
+            Err(err) => log::error!("Failed to change the graphics backend: {:?}", err),
+        }
+    }
+    renderer.set_depth_of_field(system.settings.depth_of_field_enabled);
     let surface = match renderer.surface.get_current_texture() {
         Ok(t) => t,
         Err(err) => {
@@ -454,10 +1206,20 @@ fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<Shoul
                     return ok();
                 },
                 wgpu::SurfaceError::Lost => {
-                    log::warn!("Swapchain is lost, recreating...");
-                    renderer
-                        .surface
-                        .configure(&renderer.device, &renderer.config);
+                    let Some(atlas) = system.atlas.inner().as_ref() else {
+                        log::error!("Graphics device lost before the block atlas was loaded");
+                        return ok();
+                    };
+                    renderer.recreate(&system.window, atlas).map_err(|err| {
+                        anyhow::anyhow!("Failed to recreate the renderer: {:?}", err)
+                    })?;
+                    // The chunk meshes and the UI textures belong to the old device
+                    crate::terrain::restore_chunk_meshes(
+                        &mut renderer,
+                        &system.mesh_cache,
+                        &mut system.terrain_render,
+                    );
+                    system.egui_context.reupload_textures();
                     return ok();
                 },
                 wgpu::SurfaceError::OutOfMemory => {
@@ -466,6 +1228,7 @@ fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<Shoul
             }
         },
     };
+    system.stats.record_frame(&renderer.config);
     let view = surface
         .texture
         .create_view(&wgpu::TextureViewDescriptor::default());
@@ -473,7 +1236,7 @@ fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<Shoul
     let encoder = renderer
         .device
         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
+            label: Some(&format_label("command_encoder", "render", 0)),
         });
 
     let texture = RenderTexture {
@@ -491,9 +1254,37 @@ fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<Shoul
 #[derive(CanFetch)]
 struct RenderSystem {
     renderer: Read<Renderer, NoDefault>,
+    camera: Read<Camera>,
     terrain: Write<TerrainRender>,
     texture: Write<Option<RenderTexture>>,
     encoder: Write<Option<CommandEncoder>>,
+    settings: Read<GameplaySettings>,
+}
+
+/// Draws the given terrain chunks with the pipeline that is currently bound
+fn draw_terrain_chunks<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    renderer: &'a Renderer,
+    terrain: &'a TerrainRender,
+    chunks: impl IntoIterator<Item = &'a TerrainChunkMesh>,
+) {
+    render_pass.set_bind_group(0, &renderer.core_bind_group, &[]);
+    render_pass.set_index_buffer(
+        renderer.terrain_index_buffer.slice(),
+        wgpu::IndexFormat::Uint32,
+    );
+
+    for terrain_data in chunks {
+        render_pass.set_bind_group(1, &terrain_data.chunk_pos_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, terrain.vertex_slice(terrain_data));
+        render_pass.draw_indexed(0..terrain_data.vertices.len() / 4 * 6, 0, 0..1);
+    }
+}
+
+/// Whether the terrain depth is drawn in a prepass before the main pass.
+fn use_depth_prepass(settings: &GameplaySettings, terrain: &TerrainRender) -> bool {
+    // The prepass does not help the wireframe view, which has no fragment cost to save
+    settings.depth_prepass_enabled && !terrain.wireframe && !terrain.chunks.is_empty()
 }
 
 /// Sets up the main render pass and draws the terrain
@@ -503,50 +1294,139 @@ fn render_system(mut system: RenderSystem) -> apecs::anyhow::Result<ShouldContin
     let texture = system.texture.inner_mut().as_mut().unwrap();
     let encoder = &mut system.encoder.inner_mut().as_mut().unwrap().encoder;
 
+    let frustum = system.camera.compute_view_frustum();
+    let visible = |pos: Vec2<i32>| {
+        let (min, max) = TerrainRender::chunk_bounds(pos);
+        frustum.test_aabb(min, max) != FrustumTest::Outside
+    };
+    let visible_chunks = system
+        .terrain
+        .chunks
+        .iter()
+        .filter(|(pos, _)| visible(**pos))
+        .map(|(_, mesh)| mesh)
+        .collect::<Vec<_>>();
+
+    let depth_prepass = use_depth_prepass(&system.settings, &system.terrain);
+
+    if depth_prepass {
+        let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "depth_prepass", 0)),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &renderer.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some(pipeline) = renderer.pipelines.get(pipeline::DEPTH_PREPASS_PIPELINE) {
+            prepass.set_pipeline(pipeline);
+            draw_terrain_chunks(
+                &mut prepass,
+                renderer,
+                &system.terrain,
+                visible_chunks.iter().copied(),
+            );
+        }
+    }
+
     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        label: Some("Render Pass"),
+        label: Some(&format_label("render_pass", "terrain", 0)),
         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-            view: &texture.surface_tex_view,
+            view: renderer.world_color_view(&texture.surface_tex_view),
             resolve_target: None,
             ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color {
-                    r: 0.1,
-                    g: 0.2,
-                    b: 0.3,
-                    a: 1.0,
-                }),
+                load: wgpu::LoadOp::Clear(renderer.clear_color),
                 store: wgpu::StoreOp::Store,
             },
         })],
         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
             view: &system.renderer.depth_texture.view,
             depth_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Clear(1.0),
+                load: if depth_prepass {
+                    wgpu::LoadOp::Load
+                } else {
+                    wgpu::LoadOp::Clear(1.0)
+                },
                 store: wgpu::StoreOp::Store,
             }),
             stencil_ops: None,
         }),
         occlusion_query_set: None,
-        timestamp_writes: None,
+        timestamp_writes: renderer
+            .timestamps
+            .as_ref()
+            .map(TimestampQueries::pass_writes),
     });
 
     if !system.terrain.chunks.is_empty() {
-        if system.terrain.wireframe {
-            render_pass.set_pipeline(&renderer.pipelines.terrain_wireframe.pipeline);
+        let name = if let Some(custom) = &system.terrain.custom_pipeline {
+            custom.as_str()
+        } else if system.terrain.wireframe {
+            pipeline::TERRAIN_WIREFRAME_PIPELINE
+        } else if depth_prepass {
+            pipeline::TERRAIN_PREPASSED_PIPELINE
         } else {
-            render_pass.set_pipeline(&renderer.pipelines.terrain.pipeline);
+            pipeline::TERRAIN_PIPELINE
+        };
+        match renderer.pipelines.get(name) {
+            Some(pipeline) => {
+                render_pass.set_pipeline(pipeline);
+                draw_terrain_chunks(
+                    &mut render_pass,
+                    renderer,
+                    &system.terrain,
+                    visible_chunks.iter().copied(),
+                );
+                // Transparent geometry must be blended over everything behind it
+                let order = system
+                    .terrain
+                    .sorted_transparent_chunks(system.camera.pos());
+                let transparent = order
+                    .iter()
+                    .filter(|pos| visible(**pos))
+                    .filter_map(|pos| system.terrain.transparent_chunks.get(pos));
+                draw_terrain_chunks(&mut render_pass, renderer, &system.terrain, transparent);
+            },
+            None => log::error!("Pipeline {} is not registered", name),
         }
-        render_pass.set_bind_group(0, &renderer.core_bind_group, &[]);
-        render_pass.set_index_buffer(
-            renderer.terrain_index_buffer.slice(),
-            wgpu::IndexFormat::Uint32,
-        );
+    }
+    drop(render_pass);
 
-        for terrain_data in system.terrain.chunks.values() {
-            render_pass.set_bind_group(1, &terrain_data.chunk_pos_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, terrain_data.vertex_buffer.slice());
-            render_pass.draw_indexed(0..terrain_data.vertex_buffer.len() / 4 * 6, 0, 0..1);
-        }
+    if let Some(highlight) = renderer.block_highlight.buffer() {
+        let mut highlight_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "block_highlight", 0)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: renderer.world_color_view(&texture.surface_tex_view),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &renderer.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        highlight_pass.set_pipeline(&renderer.highlight_pipeline.pipeline);
+        highlight_pass.set_bind_group(0, &renderer.core_bind_group, &[]);
+        highlight_pass.set_vertex_buffer(0, highlight.slice());
+        highlight_pass.draw(0..highlight.len(), 0..1);
+    }
+    if system.settings.depth_of_field_enabled {
+        renderer.linearize_depth_pass(encoder);
     }
     ok()
 }
@@ -555,7 +1435,8 @@ fn render_system(mut system: RenderSystem) -> apecs::anyhow::Result<ShouldContin
 struct PostRenderSystem {
     texture: Write<Option<RenderTexture>>,
     command_encoder: Write<Option<CommandEncoder>>,
-    renderer: Read<Renderer, NoDefault>,
+    renderer: Write<Renderer, NoDefault>,
+    screenshots: Write<Screenshots>,
 }
 
 fn post_render_system(mut system: PostRenderSystem) -> apecs::anyhow::Result<ShouldContinue> {
@@ -566,13 +1447,116 @@ fn post_render_system(mut system: PostRenderSystem) -> apecs::anyhow::Result<Sho
 
     if let (Some(texture), Some(command_encoder)) = (texture, command_encoder) {
         let texture = texture.surface_tex;
-        let command_encoder = command_encoder.encoder;
+        let mut command_encoder = command_encoder.encoder;
+        if let Some(timestamps) = &system.renderer.timestamps {
+            timestamps.resolve(&mut command_encoder);
+        }
+        let screenshot = system.screenshots.take_request();
+        if let (Some(_), Some(frame)) = (&screenshot, &system.renderer.last_frame) {
+            command_encoder.copy_texture_to_texture(
+                texture.texture.as_image_copy(),
+                frame.as_image_copy(),
+                frame.size(),
+            );
+        }
         system.renderer.queue.submit(Some(command_encoder.finish()));
+        if let Some(path) = screenshot {
+            system.screenshots.save(system.renderer.screenshot(), path);
+        }
+        // The scene highlights the targeted block again every frame
+        system.renderer.clear_block_highlight();
+        if let Some(timestamps) = &mut system.renderer.timestamps {
+            timestamps.map();
+        }
         texture.present();
     }
     ok()
 }
 
+#[derive(CanFetch)]
+struct GpuTimingsSystem {
+    renderer: Read<Renderer, NoDefault>,
+    terrain: Read<TerrainRender>,
+    stats: Write<PerformanceStats>,
+}
+
+/// Copies the GPU timings read in the pre-render stage and the terrain mesh statistics
+/// to [`PerformanceStats`]
+fn gpu_timings_system(mut system: GpuTimingsSystem) -> apecs::anyhow::Result<ShouldContinue> {
+    system.stats.gpu_frame_time_us = system.renderer.gpu_frame_time_us();
+    system.stats.terrain_chunks = system.terrain.chunk_count();
+    system.stats.terrain_vertices = system.terrain.total_vertex_count();
+    system.stats.average_vertices_per_chunk = system.terrain.average_vertices_per_chunk();
+    ok()
+}
+
+#[derive(CanFetch)]
+struct MorphTargetSystem {
+    renderer: Read<Renderer, NoDefault>,
+    terrain: Write<TerrainRender>,
+    delta: Read<DeltaTime>,
+}
+
+/// Advances the level of detail transitions of the terrain before it is drawn
+fn morph_target_system(mut system: MorphTargetSystem) -> apecs::anyhow::Result<ShouldContinue> {
+    system
+        .terrain
+        .update_morph(&system.renderer.queue, system.delta.0);
+    ok()
+}
+
+/// Parses a graphics API name as accepted by the `WGPU_BACKEND` environment variable.
+pub fn parse_backends(name: &str) -> Option<wgpu::Backends> {
+    match name.to_lowercase().as_str() {
+        "vulkan" => Some(wgpu::Backends::VULKAN),
+        "metal" => Some(wgpu::Backends::METAL),
+        "dx12" => Some(wgpu::Backends::DX12),
+        "dx11" => Some(wgpu::Backends::DX11),
+        "opengl" => Some(wgpu::Backends::GL),
+        "primary" => Some(wgpu::Backends::PRIMARY),
+        "secondary" => Some(wgpu::Backends::SECONDARY),
+        "all" => Some(wgpu::Backends::all()),
+        _ => None,
+    }
+}
+
+/// The backends picked with the `WGPU_BACKEND` environment variable, the primary ones by default.
+fn backends_from_env() -> wgpu::Backends {
+    std::env::var("WGPU_BACKEND")
+        .ok()
+        .and_then(|env| parse_backends(&env))
+        .unwrap_or(wgpu::Backends::PRIMARY)
+}
+
+/// Returns `requested` if both the colour and depth formats can be multisampled that much,
+/// otherwise 1.
+fn supported_sample_count(
+    adapter: &wgpu::Adapter,
+    format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let supported = |count: u32| {
+        [format, Texture::DEPTH_FORMAT].iter().all(|format| {
+            adapter
+                .get_texture_format_features(*format)
+                .flags
+                .sample_count_supported(count)
+        })
+    };
+    let count = [8, 4, 2, 1]
+        .into_iter()
+        .find(|&count| count <= requested && (count == 1 || supported(count)))
+        .unwrap_or(1);
+    if count < requested {
+        log::warn!(
+            "{}x anti-aliasing is not supported by this device, using {}x instead",
+            requested,
+            count
+        );
+    }
+    count
+}
+
 fn compute_terrain_indices(device: &wgpu::Device, vert_length: usize) -> Buffer<u32> {
     assert!(vert_length <= u32::MAX as usize);
     let indices = [0, 1, 2, 2, 3, 0]
@@ -584,5 +1568,527 @@ fn compute_terrain_indices(device: &wgpu::Device, vert_length: usize) -> Buffer<
         .map(|(i, b)| (i / 6 * 4 + b) as u32)
         .collect::<Vec<_>>();
 
-    Buffer::new(device, wgpu::BufferUsages::INDEX, &indices)
+    Buffer::new_with_label(
+        device,
+        wgpu::BufferUsages::INDEX,
+        &indices,
+        &format_label("buffer", "terrain_index", 0),
+    )
+}
+
+pub(crate) fn create_common_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format_label("bind_group_layout", "common", 0)),
+        entries: &[
+            // Globals
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Atlas Texture
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            // Atlas Texture Sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Environment Cube Map
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            // Environment Cube Map Sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Normal Atlas Texture, sampled with the atlas sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn create_common_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniforms: &Buffer<Uniforms>,
+    atlas: &Texture,
+    normal_atlas: &Texture,
+    environment_map: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format_label("bind_group", "common", 0)),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&atlas.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&environment_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&environment_map.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&normal_atlas.view),
+            },
+        ],
+    })
+}
+
+pub(crate) fn create_chunk_pos_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format_label("bind_group_layout", "chunk_pos", 0)),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+/// Creates the texture the frames are copied to before being presented,
+/// if the surface allows copying from it.
+fn create_frame_capture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> Option<wgpu::Texture> {
+    if !config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+        return None;
+    }
+    Some(device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(&format_label("texture", "last_frame", 0)),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    }))
+}
+
+/// Blocks until `device` has finished all the work submitted to it, and runs the callbacks
+/// of the buffers it mapped.
+pub(crate) fn wait_idle(device: &wgpu::Device) {
+    device.poll(wgpu::Maintain::Wait);
+}
+
+/// Reads a rectangle of an 8-bit RGBA or BGRA `texture` back from the GPU.
+///
+/// The rectangle is clamped to the texture bounds.
+fn read_texture_region(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> image::RgbaImage {
+    let x = x.min(texture.width());
+    let y = y.min(texture.height());
+    let width = width.min(texture.width() - x);
+    let height = height.min(texture.height() - y);
+    if width == 0 || height == 0 {
+        return image::RgbaImage::new(width, height);
+    }
+
+    // Rows of the staging buffer must be aligned
+    let unpadded_row = width * 4;
+    let padded_row = unpadded_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format_label("buffer", "screenshot_staging", 0)),
+        size: (padded_row * height) as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some(&format_label("command_encoder", "screenshot", 0)),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = tx.send(result);
+    });
+    wait_idle(device);
+    rx.recv()
+        .expect("Staging buffer was dropped while mapping")
+        .expect("Failed to map staging buffer");
+
+    let mut pixels = Vec::with_capacity((unpadded_row * height) as usize);
+    {
+        let view = slice.get_mapped_range();
+        for row in view.chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+    }
+    staging.unmap();
+    if matches!(
+        texture.format(),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    ) {
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    image::RgbaImage::from_raw(width, height, pixels).expect("Pixels don't fill the image")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_chunk_pos_bind_group_layout, create_common_bind_group_layout, format_label,
+        latest_resize, parse_backends,
+        pipeline::{PipelineRegistry, TERRAIN_PIPELINE},
+        read_texture_region,
+        resources::{TerrainChunkMesh, TerrainRender},
+        test_device::device,
+        texture::Texture,
+        use_depth_prepass,
+        vertex::TerrainVertex,
+        wait_idle, AntiAliasing, ChunkPos, DebugLabelRegistry, RenderStats,
+    };
+    use crate::{
+        render::buffer::Buffer,
+        settings::{AntiAliasingMode, GameplaySettings},
+        window::WindowEvent,
+    };
+    use common::event::Events;
+    use vek::{Vec2, Vec3};
+
+    fn surface_config(format: wgpu::TextureFormat) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: 1920,
+            height: 1080,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        }
+    }
+
+    #[test]
+    pub fn depth_prepass_needs_solid_terrain() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let settings = GameplaySettings {
+            depth_prepass_enabled: true,
+            ..Default::default()
+        };
+        let mut terrain = TerrainRender::default();
+        assert!(!use_depth_prepass(&settings, &terrain));
+
+        let layout = create_chunk_pos_bind_group_layout(&device);
+        let vertices = vec![TerrainVertex::pack(Vec3::zero(), 0, 0, 0, 0); 4];
+        let buffer = Buffer::new(&device, wgpu::BufferUsages::VERTEX, &vertices);
+        let mesh = TerrainChunkMesh::new(&device, &layout, ChunkPos::new(0, 0), buffer, 0);
+        terrain.chunks.insert(Vec2::zero(), mesh);
+        assert!(use_depth_prepass(&settings, &terrain));
+
+        terrain.wireframe = true;
+        assert!(!use_depth_prepass(&settings, &terrain));
+        terrain.wireframe = false;
+        let disabled = GameplaySettings {
+            depth_prepass_enabled: false,
+            ..Default::default()
+        };
+        assert!(!use_depth_prepass(&disabled, &terrain));
+    }
+
+    #[test]
+    pub fn only_the_latest_resize_is_applied() {
+        let mut events = Events::default();
+        assert_eq!(latest_resize(&events), None);
+        events.send(WindowEvent::Resize(Vec2::new(800, 600)));
+        events.send(WindowEvent::Focused(true));
+        events.send(WindowEvent::Resize(Vec2::new(1280, 720)));
+        events.send(WindowEvent::CursorMove(Vec2::zero()));
+        assert_eq!(latest_resize(&events), Some(Vec2::new(1280, 720)));
+    }
+
+    #[test]
+    pub fn render_stats_count_frames() {
+        let mut config = surface_config(wgpu::TextureFormat::Bgra8UnormSrgb);
+        let mut stats = RenderStats::new(&config);
+        assert_eq!(stats.frame_number, 0);
+        config.width = 800;
+        config.height = 600;
+        config.present_mode = wgpu::PresentMode::Mailbox;
+        for _ in 0..5 {
+            stats.record_frame(&config);
+        }
+        assert_eq!(stats.frame_number, 5);
+        assert_eq!((stats.surface_width, stats.surface_height), (800, 600));
+        assert_eq!(stats.last_present_mode, wgpu::PresentMode::Mailbox);
+        assert_eq!(stats.aspect_ratio(), 800.0 / 600.0);
+    }
+
+    #[test]
+    pub fn float_surfaces_are_hdr() {
+        let sdr = RenderStats::new(&surface_config(wgpu::TextureFormat::Bgra8UnormSrgb));
+        assert!(!sdr.is_hdr());
+        let hdr = RenderStats::new(&surface_config(wgpu::TextureFormat::Rgba16Float));
+        assert!(hdr.is_hdr());
+    }
+
+    #[test]
+    pub fn label_format() {
+        assert_eq!(
+            format_label("buffer", "terrain_index", 0),
+            "buffer/terrain_index[0]"
+        );
+    }
+
+    #[test]
+    pub fn registry_numbers_labels_per_name() {
+        let mut labels = DebugLabelRegistry::default();
+        assert_eq!(labels.next("buffer", "vertex"), "buffer/vertex[0]");
+        assert_eq!(labels.next("buffer", "vertex"), "buffer/vertex[1]");
+        assert_eq!(labels.next("texture", "vertex"), "texture/vertex[0]");
+        assert_eq!(labels.next_index("buffer", "vertex"), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    pub fn long_labels_are_rejected() {
+        format_label("buffer", &"a".repeat(64), 0);
+    }
+
+    #[test]
+    pub fn backend_names_are_case_insensitive() {
+        assert_eq!(parse_backends("Vulkan"), Some(wgpu::Backends::VULKAN));
+        assert_eq!(parse_backends("DX12"), Some(wgpu::Backends::DX12));
+        assert_eq!(parse_backends("opengl"), Some(wgpu::Backends::GL));
+        assert_eq!(parse_backends("glide"), None);
+    }
+
+    #[test]
+    pub fn waiting_twice_does_not_block() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        queue.submit(None);
+        wait_idle(&device);
+        // Nothing is left to wait for the second time
+        wait_idle(&device);
+    }
+
+    #[test]
+    pub fn texture_regions_are_read_back() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let image =
+            image::RgbaImage::from_fn(32, 24, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+        let size = wgpu::Extent3d {
+            width: image.width(),
+            height: image.height(),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            &image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(image.width() * 4),
+                rows_per_image: None,
+            },
+            size,
+        );
+
+        let full = read_texture_region(&device, &queue, &texture, 0, 0, 32, 24);
+        assert_eq!(full, image);
+
+        let region = read_texture_region(&device, &queue, &texture, 10, 10, 5, 5);
+        assert_eq!(region.dimensions(), (5, 5));
+        assert_eq!(
+            region,
+            image::imageops::crop_imm(&image, 10, 10, 5, 5).to_image()
+        );
+
+        // Clamped to the texture bounds
+        let corner = read_texture_region(&device, &queue, &texture, 30, 20, 5, 5);
+        assert_eq!(corner.dimensions(), (2, 4));
+    }
+
+    #[test]
+    pub fn anti_aliasing_can_be_reconfigured() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let config = wgpu::SurfaceConfiguration {
+            width: 64,
+            height: 4,
+            ..surface_config(wgpu::TextureFormat::Rgba8Unorm)
+        };
+        let surface = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let surface_view = surface.create_view(&wgpu::TextureViewDescriptor::default());
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let shader = device
+            .create_shader_module(wgpu::include_wgsl!("../../../assets/shaders/terrain.wgsl"));
+
+        // Rebuilt the way the renderer is when the backend changes
+        for mode in [
+            AntiAliasingMode::Msaa4x,
+            AntiAliasingMode::None,
+            AntiAliasingMode::Fxaa,
+            AntiAliasingMode::None,
+        ] {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let anti_aliasing = AntiAliasing::new(&device, &config, mode);
+            let sample_count = mode.sample_count();
+            let mut pipelines = PipelineRegistry::with_sample_count(sample_count);
+            pipelines.register_terrain_pipelines(&device, &[&common, &chunk_pos], &shader, &config);
+            let depth = Texture::depth(&device, config.width, config.height, sample_count, false);
+
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: anti_aliasing.world_color_view(&surface_view),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            // Fails if the pipeline and the attachments disagree on the sample count
+            pass.set_pipeline(pipelines.get(TERRAIN_PIPELINE).unwrap());
+            drop(pass);
+            anti_aliasing.run(&mut encoder, &surface_view);
+            queue.submit(Some(encoder.finish()));
+
+            let error = pollster::block_on(device.pop_error_scope());
+            assert!(error.is_none(), "{:?}: {:?}", mode, error);
+            let frame = read_texture_region(&device, &queue, &surface, 0, 0, 64, 4);
+            assert!(
+                frame.pixels().all(|pixel| pixel.0 == [0, 255, 0, 255]),
+                "{:?} left artifacts",
+                mode
+            );
+        }
+    }
 }
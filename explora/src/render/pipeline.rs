@@ -1,38 +1,508 @@
-use crate::render::{texture, vertex::TerrainVertex, Vertex};
+use std::{collections::HashMap, sync::Arc};
 
+use crate::render::{
+    buffer::Buffer,
+    error::{PipelineError, RenderError},
+    format_label, texture,
+    vertex::{HighlightVertex, TerrainVertex, UiVertex},
+    Uniforms, Vertex,
+};
+
+pub const TERRAIN_PIPELINE: &str = "terrain";
+pub const TERRAIN_WIREFRAME_PIPELINE: &str = "terrain_wireframe";
+/// The terrain pipeline used after the depth prepass.
+pub const TERRAIN_PREPASSED_PIPELINE: &str = "terrain_prepassed";
+pub const DEPTH_PREPASS_PIPELINE: &str = "depth_prepass";
+
+/// Describes how to build a pipeline, so it can be built again from a new shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineConfig {
+    Terrain {
+        wireframe: bool,
+        depth_prepass: bool,
+    },
+    DepthPrepass,
+}
+
+impl PipelineConfig {
+    pub fn build(
+        self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        match self {
+            PipelineConfig::Terrain {
+                wireframe,
+                depth_prepass,
+            } => create_terrain_pipeline(
+                device,
+                bind_group_layouts,
+                shader,
+                config.format,
+                wireframe,
+                depth_prepass,
+                sample_count,
+            ),
+            PipelineConfig::DepthPrepass => {
+                DepthPrepassPipeline::new(device, bind_group_layouts, shader, sample_count).pipeline
+            },
+        }
+    }
+}
+
+struct RegisteredPipeline {
+    config: PipelineConfig,
+    pipeline: wgpu::RenderPipeline,
+}
+
+/// Render pipelines looked up by name.
+pub struct PipelineRegistry {
+    pipelines: HashMap<String, RegisteredPipeline>,
+    /// Samples per pixel of the targets the pipelines draw to.
+    sample_count: u32,
+}
+
+impl Default for PipelineRegistry {
+    fn default() -> Self {
+        Self::with_sample_count(1)
+    }
+}
+
+impl PipelineRegistry {
+    /// Creates a registry whose pipelines draw to targets with `sample_count` samples per pixel.
+    pub fn with_sample_count(sample_count: u32) -> Self {
+        Self {
+            pipelines: HashMap::new(),
+            sample_count,
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Adds a pipeline, returning the one previously registered under the same name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        config: PipelineConfig,
+        pipeline: wgpu::RenderPipeline,
+    ) -> Option<wgpu::RenderPipeline> {
+        self.pipelines
+            .insert(name.to_owned(), RegisteredPipeline { config, pipeline })
+            .map(|old| old.pipeline)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(name).map(|p| &p.pipeline)
+    }
+
+    pub fn config(&self, name: &str) -> Option<PipelineConfig> {
+        self.pipelines.get(name).map(|p| p.config)
+    }
+
+    /// Builds and registers every pipeline used to draw the terrain.
+    ///
+    /// The wireframe pipeline needs [`wgpu::Features::POLYGON_MODE_LINE`] and is skipped
+    /// when the device does not have it.
+    pub fn register_terrain_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        let wireframe = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        if !wireframe {
+            log::warn!("Wireframe rendering is not supported by this device");
+        }
+        for (name, pipeline_config) in [
+            (
+                TERRAIN_PIPELINE,
+                PipelineConfig::Terrain {
+                    wireframe: false,
+                    depth_prepass: false,
+                },
+            ),
+            (
+                TERRAIN_WIREFRAME_PIPELINE,
+                PipelineConfig::Terrain {
+                    wireframe: true,
+                    depth_prepass: false,
+                },
+            ),
+            (
+                TERRAIN_PREPASSED_PIPELINE,
+                PipelineConfig::Terrain {
+                    wireframe: false,
+                    depth_prepass: true,
+                },
+            ),
+            (DEPTH_PREPASS_PIPELINE, PipelineConfig::DepthPrepass),
+        ] {
+            if name == TERRAIN_WIREFRAME_PIPELINE && !wireframe {
+                continue;
+            }
+            let pipeline = pipeline_config.build(
+                device,
+                bind_group_layouts,
+                shader,
+                config,
+                self.sample_count,
+            );
+            self.register(name, pipeline_config, pipeline);
+        }
+    }
+
+    /// Builds the pipeline registered as `name` again from the WGSL `source`.
+    ///
+    /// Returns the replaced pipeline, so the caller decides when it is dropped.
+    /// If the shader is invalid the registry is left untouched.
+    pub fn reload(
+        &mut self,
+        name: &str,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        source: &str,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<wgpu::RenderPipeline, RenderError> {
+        let pipeline_config = self
+            .config(name)
+            .ok_or_else(|| RenderError::UnknownPipeline(name.to_owned()))?;
+        let pipeline = self.build_from_source(
+            name,
+            pipeline_config,
+            device,
+            bind_group_layouts,
+            source,
+            config,
+        )?;
+
+        let old = self
+            .register(name, pipeline_config, pipeline)
+            .expect("The pipeline was registered");
+        Ok(old)
+    }
+
+    /// Builds a pipeline from `pipeline_config` and the WGSL `source` and registers it
+    /// as `name`, so it can be drawn with instead of one of the built-in pipelines.
+    ///
+    /// Returns the pipeline previously registered under the same name, if any.
+    /// If the shader is invalid the registry is left untouched.
+    pub fn add_custom(
+        &mut self,
+        name: &str,
+        pipeline_config: PipelineConfig,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        source: &str,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<Option<wgpu::RenderPipeline>, RenderError> {
+        let pipeline = self.build_from_source(
+            name,
+            pipeline_config,
+            device,
+            bind_group_layouts,
+            source,
+            config,
+        )?;
+        Ok(self.register(name, pipeline_config, pipeline))
+    }
+
+    fn build_from_source(
+        &self,
+        name: &str,
+        pipeline_config: PipelineConfig,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        source: &str,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<wgpu::RenderPipeline, RenderError> {
+        // Catch shader errors instead of letting wgpu panic
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = pipeline_config.build(
+            device,
+            bind_group_layouts,
+            &shader,
+            config,
+            self.sample_count,
+        );
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(RenderError::Shader(error.to_string()));
+        }
+        Ok(pipeline)
+    }
+}
+
+/// The terrain pipeline together with everything needed to build it again.
+///
+/// Keeping the compiled shader around means the pipeline can be rebuilt for new
+/// bind group layouts without reading the shader source from disk.
 pub struct TerrainPipeline {
     pub pipeline: wgpu::RenderPipeline,
+    shader: Arc<wgpu::ShaderModule>,
+    config: PipelineConfig,
+    format: wgpu::TextureFormat,
+    bind_group_count: usize,
 }
 
 impl TerrainPipeline {
     pub fn new(
         device: &wgpu::Device,
         bind_group_layouts: &[&wgpu::BindGroupLayout],
-        shader: &wgpu::ShaderModule,
+        shader: Arc<wgpu::ShaderModule>,
         config: &wgpu::SurfaceConfiguration,
         wireframe: bool,
+        depth_prepass: bool,
     ) -> Self {
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts,
-                push_constant_ranges: &[],
-            });
+        let pipeline = create_terrain_pipeline(
+            device,
+            bind_group_layouts,
+            &shader,
+            config.format,
+            wireframe,
+            depth_prepass,
+            1,
+        );
+        Self {
+            pipeline,
+            shader,
+            config: PipelineConfig::Terrain {
+                wireframe,
+                depth_prepass,
+            },
+            format: config.format,
+            bind_group_count: bind_group_layouts.len(),
+        }
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&render_pipeline_layout),
+    /// Recreates the render pipeline for new bind group layouts, reusing the cached shader.
+    ///
+    /// The shader declares a fixed number of bind groups, so the number of layouts must not change.
+    /// On error the current pipeline is kept.
+    pub fn rebuild_with_layouts(
+        &mut self,
+        device: &wgpu::Device,
+        new_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Result<(), PipelineError> {
+        if new_layouts.len() != self.bind_group_count {
+            return Err(PipelineError::BindGroupLayoutMismatch(format!(
+                "expected {} bind group layouts, got {}",
+                self.bind_group_count,
+                new_layouts.len()
+            )));
+        }
+        let PipelineConfig::Terrain {
+            wireframe,
+            depth_prepass,
+        } = self.config
+        else {
+            unreachable!("TerrainPipeline is always built from a terrain config")
+        };
+
+        device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = create_terrain_pipeline(
+            device,
+            new_layouts,
+            &self.shader,
+            self.format,
+            wireframe,
+            depth_prepass,
+            1,
+        );
+        let validation = pollster::block_on(device.pop_error_scope());
+        let out_of_memory = pollster::block_on(device.pop_error_scope());
+        if let Some(error) = validation {
+            return Err(PipelineError::BindGroupLayoutMismatch(error.to_string()));
+        }
+        if out_of_memory.is_some() {
+            // A device that can no longer allocate resources is not coming back
+            return Err(PipelineError::DeviceLost);
+        }
+        self.pipeline = pipeline;
+        Ok(())
+    }
+
+    pub fn config(&self) -> PipelineConfig {
+        self.config
+    }
+}
+
+fn create_terrain_pipeline(
+    device: &wgpu::Device,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    wireframe: bool,
+    depth_prepass: bool,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format_label("pipeline_layout", "terrain", 0)),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format_label(
+            "pipeline",
+            if wireframe {
+                TERRAIN_WIREFRAME_PIPELINE
+            } else {
+                TERRAIN_PIPELINE
+            },
+            0,
+        )),
+        layout: Some(&render_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[TerrainVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::all(),
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: if wireframe {
+                wgpu::PolygonMode::Line
+            } else {
+                wgpu::PolygonMode::Fill
+            },
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            // When the depth buffer was already filled by the prepass
+            // only the closest fragments pass and there is nothing left to write.
+            depth_write_enabled: !depth_prepass,
+            depth_compare: if depth_prepass {
+                wgpu::CompareFunction::LessEqual
+            } else {
+                wgpu::CompareFunction::Less
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// Renders the terrain into the depth buffer only.
+///
+/// Running it before the main pass means the fragment shader only runs once per pixel.
+pub struct DepthPrepassPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl DepthPrepassPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "depth_prepass", 0)),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "depth_prepass", 0)),
+            layout: Some(&layout),
             vertex: wgpu::VertexState {
                 module: shader,
                 entry_point: "vs_main",
                 buffers: &[TerrainVertex::desc()],
             },
+            // No colour writes
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+}
+
+/// Blends the highlight of the targeted block face over the terrain.
+pub struct HighlightPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl HighlightPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        common_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "highlight", 0)),
+            bind_group_layouts: &[common_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "highlight", 0)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[HighlightVertex::desc()],
+            },
             fragment: Some(wgpu::FragmentState {
                 module: shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::all(),
                 })],
             }),
@@ -40,22 +510,106 @@ impl TerrainPipeline {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: if wireframe {
-                    wgpu::PolygonMode::Line
-                } else {
-                    wgpu::PolygonMode::Fill
-                },
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
+            // Hidden by the blocks in front of it, without hiding the ones behind
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+}
+
+/// Draws HUD elements that egui is not suited for, e.g. a fullscreen vignette.
+pub struct UiPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+    screen_size_buffer: Buffer<[f32; 4]>,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl UiPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format_label("bind_group_layout", "ui", 0)),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let screen_size_buffer = Buffer::new_with_label(
+            device,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            &[[1.0; 4]],
+            &format_label("buffer", "ui_screen_size", 0),
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format_label("bind_group", "ui", 0)),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_size_buffer.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "ui", 0)),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "ui", 0)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[UiVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // HUD elements are always facing the screen
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Drawn on top of everything
+            depth_stencil: None,
             multisample: wgpu::MultisampleState {
                 count: 1,
                 mask: !0,
@@ -64,7 +618,1099 @@ impl TerrainPipeline {
             multiview: None,
         });
         Self {
-            pipeline: render_pipeline,
+            pipeline,
+            screen_size_buffer,
+            bind_group,
+        }
+    }
+
+    /// Updates the screen size used to convert pixels to normalized device coordinates.
+    pub fn write_screen_size(&self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.screen_size_buffer
+            .write(queue, &[[width as f32, height as f32, 0.0, 0.0]]);
+    }
+}
+
+/// Anti-aliases the world with a multisampled colour texture.
+///
+/// The world passes draw into the multisampled texture, which is then resolved to the
+/// swapchain in its own pass. The UI is drawn on the resolved image afterwards, with one sample
+/// per pixel, so it stays sharp and its pipelines don't need to know about multisampling.
+pub struct MultisampleResolvePipeline {
+    view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+}
+
+impl MultisampleResolvePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
+        let view = Self::create_view(
+            device,
+            config.format,
+            config.width,
+            config.height,
+            sample_count,
+        );
+        Self {
+            view,
+            format: config.format,
+            sample_count,
         }
     }
+
+    fn create_view(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> wgpu::TextureView {
+        let label = format_label("texture", "multisampled_color", 0);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Recreates the multisampled texture with the new size of the swapchain.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.view = Self::create_view(device, self.format, width, height, self.sample_count);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// The texture the world is drawn to.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Resolves the multisampled texture to `target`, usually the swapchain view.
+    ///
+    /// The samples are not needed once resolved, so they are discarded.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "multisample_resolve", 0)),
+            color_attachments: &[Some(self.resolve_attachment(target))],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+
+    fn resolve_attachment<'a>(
+        &'a self,
+        target: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        wgpu::RenderPassColorAttachment {
+            view: &self.view,
+            resolve_target: Some(target),
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Discard,
+            },
+        }
+    }
+}
+
+/// Anti-aliases the world in screen space with FXAA, which is cheaper than multisampling
+/// complex scenes.
+///
+/// The world passes draw into a colour texture with one sample per pixel, which is then
+/// drawn to the swapchain with its edges smoothed. Like with [`MultisampleResolvePipeline`],
+/// the UI is drawn afterwards so it stays sharp.
+pub struct FxaaPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl FxaaPipeline {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader =
+            device.create_shader_module(wgpu::include_wgsl!("../../../assets/shaders/fxaa.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format_label("bind_group_layout", "fxaa", 0)),
+            entries: &[
+                // The world
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "fxaa", 0)),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "fxaa", 0)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    // Every pixel of the world is replaced
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        // Edges are blended by sampling between pixels
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format_label("sampler", "fxaa", 0)),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let texture = Self::create_texture(device, config.format, config.width, config.height);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &view, &sampler);
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            format: config.format,
+            texture,
+            view,
+            bind_group,
+        }
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format_label("texture", "fxaa_input", 0)),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format_label("bind_group", "fxaa", 0)),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreates the texture the world is drawn to with the new size of the swapchain.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = Self::create_texture(device, self.format, width, height);
+        self.view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.view, &self.sampler);
+    }
+
+    /// The texture the world is drawn to.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// The texture the world is drawn to, e.g. to upload an image to it.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// Draws the anti-aliased world to `target`, usually the swapchain view.
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "fxaa", 0)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Converts the depth buffer to linear distances from the camera, stored in a colour texture
+/// that post-processing passes such as depth of field can sample.
+pub struct LinearDepthPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sample_count: u32,
+}
+
+impl LinearDepthPipeline {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    /// `depth` must have `sample_count` samples per pixel, the clip planes are read from `uniforms`.
+    pub fn new(
+        device: &wgpu::Device,
+        uniforms: &Buffer<Uniforms>,
+        depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!(
+            "../../../assets/shaders/linear_depth.wgsl"
+        ));
+        let multisampled = sample_count > 1;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format_label("bind_group_layout", "linear_depth", 0)),
+            entries: &[
+                // Globals
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Depth texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: Self::depth_binding(multisampled),
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Not a depth binding, which the GL backend can't load from
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format_label("pipeline_layout", "linear_depth", 0)),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format_label("pipeline", "linear_depth", 0)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: if multisampled {
+                    "fs_main_multisampled"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Self::FORMAT,
+                    // Float textures can't be blended
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, uniforms, depth, multisampled);
+        let texture = Self::create_texture(device, width, height);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            texture,
+            view,
+            sample_count,
+        }
+    }
+
+    fn depth_binding(multisampled: bool) -> u32 {
+        if multisampled {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniforms: &Buffer<Uniforms>,
+        depth: &wgpu::TextureView,
+        multisampled: bool,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format_label("bind_group", "linear_depth", 0)),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: Self::depth_binding(multisampled),
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        })
+    }
+
+    fn create_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format_label("texture", "linear_depth", 0)),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    /// Recreates the output texture with the new size of the swapchain, reading from the
+    /// new `depth` texture.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        uniforms: &Buffer<Uniforms>,
+        depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            uniforms,
+            depth,
+            self.sample_count > 1,
+        );
+        self.texture = Self::create_texture(device, width, height);
+        self.view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+    }
+
+    /// The linear depth, in blocks from the camera, is stored in the red channel.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Draws the linear depth of the whole screen. The depth texture must not be
+    /// used as an attachment of a pass that is still recording.
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format_label("render_pass", "linear_depth", 0)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{
+        create_chunk_pos_bind_group_layout, create_common_bind_group_layout, test_device::device,
+    };
+
+    use std::sync::Arc;
+
+    use crate::{
+        camera,
+        render::{
+            buffer::Buffer,
+            error::{PipelineError, RenderError},
+            texture::Texture,
+            Uniforms,
+        },
+    };
+
+    use super::{
+        FxaaPipeline, HighlightPipeline, LinearDepthPipeline, MultisampleResolvePipeline,
+        PipelineConfig, PipelineRegistry, TerrainPipeline, UiPipeline, DEPTH_PREPASS_PIPELINE,
+        TERRAIN_PIPELINE, TERRAIN_PREPASSED_PIPELINE, TERRAIN_WIREFRAME_PIPELINE,
+    };
+
+    const SHADER: &str = include_str!("../../../assets/shaders/terrain.wgsl");
+    const UI_SHADER: &str = include_str!("../../../assets/shaders/ui.wgsl");
+    const HIGHLIGHT_SHADER: &str = include_str!("../../../assets/shaders/highlight.wgsl");
+
+    #[test]
+    pub fn reload_replaces_registered_pipeline() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let layouts = [&common, &chunk_pos];
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let config = PipelineConfig::Terrain {
+            wireframe: false,
+            depth_prepass: false,
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let mut registry = PipelineRegistry::default();
+        let pipeline = config.build(&device, &layouts, &shader, &surface_config, 1);
+        assert!(registry
+            .register(TERRAIN_PIPELINE, config, pipeline)
+            .is_none());
+
+        // The old pipeline is handed back instead of being dropped
+        let old = registry.reload(TERRAIN_PIPELINE, &device, &layouts, SHADER, &surface_config);
+        assert!(old.is_ok());
+        assert!(registry.get(TERRAIN_PIPELINE).is_some());
+        assert_eq!(registry.config(TERRAIN_PIPELINE), Some(config));
+        drop(old);
+    }
+
+    #[test]
+    pub fn custom_pipelines_are_registered_by_name() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let layouts = [&common, &chunk_pos];
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let solid = PipelineConfig::Terrain {
+            wireframe: false,
+            depth_prepass: false,
+        };
+        // Fill mode only, the wireframe view needs a device feature
+        let prepassed = PipelineConfig::Terrain {
+            wireframe: false,
+            depth_prepass: true,
+        };
+
+        let mut registry = PipelineRegistry::default();
+        let old = registry.add_custom("toon", solid, &device, &layouts, SHADER, &surface_config);
+        assert!(matches!(old, Ok(None)));
+        let old = registry.add_custom(
+            "outline",
+            prepassed,
+            &device,
+            &layouts,
+            SHADER,
+            &surface_config,
+        );
+        assert!(matches!(old, Ok(None)));
+
+        assert!(registry.get("toon").is_some());
+        assert!(registry.get("outline").is_some());
+        assert_eq!(registry.config("toon"), Some(solid));
+        assert_eq!(registry.config("outline"), Some(prepassed));
+
+        // An invalid shader leaves the registry untouched
+        let result = registry.add_custom("broken", solid, &device, &layouts, "fn", &surface_config);
+        assert!(matches!(result, Err(RenderError::Shader(_))));
+        assert!(registry.get("broken").is_none());
+    }
+
+    #[test]
+    pub fn reload_unknown_pipeline_fails() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let mut registry = PipelineRegistry::default();
+        assert!(registry
+            .reload("missing", &device, &[], SHADER, &surface_config)
+            .is_err());
+    }
+
+    #[test]
+    pub fn rebuild_with_same_layouts_reuses_shader() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let layouts = [&common, &chunk_pos];
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let shader = Arc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        }));
+
+        let mut pipeline = TerrainPipeline::new(
+            &device,
+            &layouts,
+            Arc::clone(&shader),
+            &surface_config,
+            false,
+            false,
+        );
+        assert!(pipeline.rebuild_with_layouts(&device, &layouts).is_ok());
+        // The cached module was used, no new shader was created
+        assert!(Arc::ptr_eq(&pipeline.shader, &shader));
+        assert_eq!(
+            pipeline.config(),
+            PipelineConfig::Terrain {
+                wireframe: false,
+                depth_prepass: false,
+            }
+        );
+
+        assert!(matches!(
+            pipeline.rebuild_with_layouts(&device, &[&common]),
+            Err(PipelineError::BindGroupLayoutMismatch(_))
+        ));
+    }
+
+    #[test]
+    pub fn ui_pipeline_compiles() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(UI_SHADER.into()),
+        });
+        UiPipeline::new(&device, &shader, wgpu::TextureFormat::Bgra8UnormSrgb);
+        let error = pollster::block_on(device.pop_error_scope());
+        assert!(error.is_none(), "{:?}", error);
+    }
+
+    #[test]
+    pub fn highlight_pipeline_compiles() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(HIGHLIGHT_SHADER.into()),
+        });
+        let layout = create_common_bind_group_layout(&device);
+        HighlightPipeline::new(
+            &device,
+            &layout,
+            &shader,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            1,
+        );
+        let error = pollster::block_on(device.pop_error_scope());
+        assert!(error.is_none(), "{:?}", error);
+    }
+
+    #[test]
+    pub fn wireframe_is_skipped_without_feature() {
+        // The default device descriptor enables no optional features
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let mut registry = PipelineRegistry::default();
+        registry.register_terrain_pipelines(
+            &device,
+            &[&common, &chunk_pos],
+            &shader,
+            &surface_config,
+        );
+
+        assert!(registry.get(TERRAIN_PIPELINE).is_some());
+        assert!(registry.get(TERRAIN_WIREFRAME_PIPELINE).is_none());
+    }
+
+    #[test]
+    pub fn depth_prepass_pipelines_are_registered() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let common = create_common_bind_group_layout(&device);
+        let chunk_pos = create_chunk_pos_bind_group_layout(&device);
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let mut registry = PipelineRegistry::default();
+        registry.register_terrain_pipelines(
+            &device,
+            &[&common, &chunk_pos],
+            &shader,
+            &surface_config,
+        );
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+
+        assert!(registry.get(DEPTH_PREPASS_PIPELINE).is_some());
+        assert_eq!(
+            registry.config(DEPTH_PREPASS_PIPELINE),
+            Some(PipelineConfig::DepthPrepass)
+        );
+        // The main pass only tests against the depth the prepass wrote
+        assert_eq!(
+            registry.config(TERRAIN_PREPASSED_PIPELINE),
+            Some(PipelineConfig::Terrain {
+                wireframe: false,
+                depth_prepass: true,
+            })
+        );
+    }
+
+    /// A 64x1 surface, so a row is exactly the 256 bytes copies to buffers are aligned to.
+    fn msaa_surface(device: &wgpu::Device) -> (wgpu::SurfaceConfiguration, wgpu::Texture) {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            width: 64,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let surface = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        (config, surface)
+    }
+
+    #[test]
+    pub fn msaa_resolves_to_the_swapchain_view() {
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let (config, surface) = msaa_surface(&device);
+        let surface_view = surface.create_view(&wgpu::TextureViewDescriptor::default());
+        let msaa = MultisampleResolvePipeline::new(&device, &config, 4);
+
+        // The samples are read from the multisampled texture and written to the swapchain,
+        // where the UI is drawn afterwards
+        let attachment = msaa.resolve_attachment(&surface_view);
+        assert!(std::ptr::eq(attachment.view, msaa.view()));
+        assert!(std::ptr::eq(
+            attachment.resolve_target.unwrap(),
+            &surface_view
+        ));
+        assert!(!std::ptr::eq(msaa.view(), &surface_view));
+        assert_eq!(msaa.sample_count(), 4);
+    }
+
+    #[test]
+    pub fn resolve_copies_the_world_to_the_target() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let (config, surface) = msaa_surface(&device);
+        let surface_view = surface.create_view(&wgpu::TextureViewDescriptor::default());
+        let msaa = MultisampleResolvePipeline::new(&device, &config, 4);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        // Stands in for the world passes
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: msaa.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::RED),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        msaa.resolve(&mut encoder, &surface_view);
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 256,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            surface.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(256),
+                    rows_per_image: None,
+                },
+            },
+            surface.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let pixels = slice.get_mapped_range();
+        assert!(pixels.chunks(4).all(|pixel| pixel == [255, 0, 0, 255]));
+    }
+
+    /// Uploads a 64x64 `image` as the world and reads back what FXAA draws to the surface.
+    fn run_fxaa(device: &wgpu::Device, queue: &wgpu::Queue, image: &[[u8; 4]]) -> Vec<[u8; 4]> {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            width: 64,
+            height: 64,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let fxaa = FxaaPipeline::new(device, &config);
+        let world = fxaa.texture();
+        queue.write_texture(
+            world.as_image_copy(),
+            bytemuck::cast_slice(image),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(256),
+                rows_per_image: None,
+            },
+            world.size(),
+        );
+        let surface = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: world.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let surface_view = surface.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        fxaa.run(&mut encoder, &surface_view);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 256 * 64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            surface.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(256),
+                    rows_per_image: None,
+                },
+            },
+            surface.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let pixels = bytemuck::cast_slice::<u8, [u8; 4]>(&slice.get_mapped_range()).to_vec();
+        pixels
+    }
+
+    #[test]
+    pub fn fxaa_keeps_flat_colours() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let image = vec![[200, 100, 50, 255]; 64 * 64];
+        assert_eq!(run_fxaa(&device, &queue, &image), image);
+    }
+
+    #[test]
+    pub fn fxaa_smooths_edges() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        // A white triangle under a shallow staircase edge
+        let image = (0..64 * 64)
+            .map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                if y > x / 4 + 16 {
+                    [255; 4]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            })
+            .collect::<Vec<_>>();
+        let smoothed = run_fxaa(&device, &queue, &image);
+        let blended = smoothed
+            .iter()
+            .filter(|pixel| pixel[0] != 0 && pixel[0] != 255)
+            .count();
+        assert!(blended > 0, "No pixel of the edge was blended");
+        // Far from the edge nothing changes
+        assert_eq!(smoothed[0], image[0]);
+        assert_eq!(smoothed[64 * 64 - 1], image[64 * 64 - 1]);
+    }
+
+    /// Clears a depth texture to the far plane and converts it to linear depth.
+    fn linearize_cleared_depth(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sample_count: u32,
+    ) -> LinearDepthPipeline {
+        let uniforms = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[Uniforms::default()]);
+        let depth = Texture::depth(device, 4, 4, sample_count, true);
+        let linear_depth =
+            LinearDepthPipeline::new(device, &uniforms, &depth.view, 4, 4, sample_count);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        // Stands in for the world passes
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        linear_depth.run(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        linear_depth
+    }
+
+    #[test]
+    pub fn far_plane_is_linearized_to_the_far_distance() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let linear_depth = linearize_cleared_depth(&device, &queue, 1);
+        let texture = &linear_depth.texture;
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 256 * 4,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(256),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let pixels = slice.get_mapped_range();
+        let depth: &[f32] = bytemuck::cast_slice(&pixels[..16]);
+        assert!((depth[0] - camera::Z_FAR).abs() < 1.0, "{}", depth[0]);
+    }
+
+    #[test]
+    pub fn multisampled_depth_is_linearized() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        linearize_cleared_depth(&device, &queue, 4);
+        device.poll(wgpu::Maintain::Wait);
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+    }
 }
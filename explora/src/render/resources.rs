@@ -1,21 +1,325 @@
 use std::collections::HashMap;
 
-use vek::Vec2;
+use vek::{Rgba, Vec2, Vec3};
 
-use crate::render::{buffer::Buffer, vertex::TerrainVertex};
+use common::chunk::StandardChunk;
 
-use super::ChunkPos;
+use crate::render::{
+    buffer::{Buffer, GpuBufferPool, PoolSlot, DEFRAGMENT_THRESHOLD},
+    vertex::{HighlightVertex, TerrainVertex, UiVertex},
+};
+
+use super::{format_label, ChunkPos, Renderer};
 
 #[derive(Default)]
 pub struct TerrainRender {
     pub chunks: HashMap<Vec2<i32>, TerrainChunkMesh>,
+    /// The see-through geometry of each chunk, e.g. water and glass.
+    ///
+    /// It is drawn after the opaque chunks, from back to front.
+    pub transparent_chunks: HashMap<Vec2<i32>, TerrainChunkMesh>,
     pub wireframe: bool,
+    /// The name of a registered pipeline to draw the terrain with instead of the
+    /// built-in ones, see [`Renderer::add_custom_pipeline`](super::Renderer::add_custom_pipeline).
+    pub custom_pipeline: Option<String>,
+    /// How far the chunks with a [`MorphTarget`] are in their level of detail transition,
+    /// from 0.0 for the old mesh to 1.0 for the new one.
+    pub morph_factor: f32,
+    /// Set by the `/defrag` command, see [`TerrainRender::defragment`].
+    pub defragment_requested: bool,
+    /// Holds the vertices of the chunks packed by the last defragmentation.
+    vertex_pool: Option<GpuBufferPool<TerrainVertex>>,
+    /// The most chunk meshes held at once since the last defragmentation.
+    peak_meshes: usize,
+    /// The chunks removed since the last defragmentation.
+    unloaded_chunks: u32,
+}
+
+impl TerrainRender {
+    /// Seconds a level of detail transition takes.
+    pub const MORPH_DURATION: f32 = 0.5;
+    /// The number of unloaded chunks after which the terrain is defragmented.
+    pub const AUTO_DEFRAGMENT_UNLOADS: u32 = 50;
+
+    /// Adds the opaque mesh of the chunk at `pos`, replacing its previous one.
+    pub fn insert_chunk(&mut self, pos: Vec2<i32>, mesh: TerrainChunkMesh) {
+        if let Some(old) = self.chunks.insert(pos, mesh) {
+            self.release(old);
+        }
+        self.peak_meshes = self.peak_meshes.max(self.mesh_count());
+    }
+
+    /// Drops the meshes of the chunk at `pos`, e.g. when it is unloaded.
+    pub fn remove_chunk(&mut self, pos: Vec2<i32>) {
+        let opaque = self.chunks.remove(&pos);
+        let transparent = self.transparent_chunks.remove(&pos);
+        if opaque.is_none() && transparent.is_none() {
+            return;
+        }
+        for mesh in opaque.into_iter().chain(transparent) {
+            self.release(mesh);
+        }
+        self.unloaded_chunks += 1;
+    }
+
+    /// Drops every chunk mesh along with the memory they were packed in.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.transparent_chunks.clear();
+        self.vertex_pool = None;
+        self.peak_meshes = 0;
+    }
+
+    fn release(&mut self, mesh: TerrainChunkMesh) {
+        if let (ChunkVertices::Pooled(slot), Some(pool)) = (mesh.vertices, &mut self.vertex_pool) {
+            pool.free(slot);
+        }
+    }
+
+    fn mesh_count(&self) -> usize {
+        self.chunks.len() + self.transparent_chunks.len()
+    }
+
+    /// The part of a buffer holding the vertices of `mesh`.
+    pub fn vertex_slice<'a>(&'a self, mesh: &'a TerrainChunkMesh) -> wgpu::BufferSlice<'a> {
+        match &mesh.vertices {
+            ChunkVertices::Buffer(buffer) => buffer.slice(),
+            ChunkVertices::Pooled(slot) => self
+                .vertex_pool
+                .as_ref()
+                .expect("Pooled vertices without a pool")
+                .slice(slot),
+        }
+    }
+
+    /// The number of GPU buffers holding chunk vertices.
+    pub fn vertex_allocation_count(&self) -> usize {
+        let buffers = self
+            .chunks
+            .values()
+            .chain(self.transparent_chunks.values())
+            .filter(|mesh| matches!(mesh.vertices, ChunkVertices::Buffer(_)))
+            .count();
+        buffers + self.vertex_pool.is_some() as usize
+    }
+
+    /// The share of the chunk meshes held since the last defragmentation that were dropped
+    /// since, from 0.0 to 1.0.
+    pub fn fragmentation_ratio(&self) -> f32 {
+        match self.peak_meshes {
+            0 => 0.0,
+            peak => 1.0 - self.mesh_count() as f32 / peak as f32,
+        }
+    }
+
+    /// Whether [`TerrainRender::defragment`] was requested or enough chunks were unloaded.
+    pub fn should_defragment(&self) -> bool {
+        self.defragment_requested || self.unloaded_chunks >= Self::AUTO_DEFRAGMENT_UNLOADS
+    }
+
+    /// Packs the vertices of every chunk next to each other in a new buffer, releasing the
+    /// memory of the unloaded chunks.
+    ///
+    /// Nothing is moved unless the [`TerrainRender::fragmentation_ratio`] is above
+    /// [`DEFRAGMENT_THRESHOLD`]. Returns whether the vertices were moved.
+    pub fn defragment(&mut self, renderer: &Renderer) -> bool {
+        self.defragment_on(&renderer.device, &renderer.queue)
+    }
+
+    fn defragment_on(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.defragment_requested = false;
+        self.unloaded_chunks = 0;
+        if self.fragmentation_ratio() <= DEFRAGMENT_THRESHOLD {
+            return false;
+        }
+        let old_pool = self.vertex_pool.take();
+        let meshes: Vec<_> = self
+            .chunks
+            .values_mut()
+            .chain(self.transparent_chunks.values_mut())
+            .collect();
+        self.peak_meshes = meshes.len();
+        if meshes.is_empty() {
+            return true;
+        }
+        let slot_size = meshes.iter().map(|mesh| mesh.vertices.len()).max();
+        let mut pool = GpuBufferPool::new(
+            device,
+            wgpu::BufferUsages::VERTEX,
+            slot_size.unwrap_or_default(),
+            meshes.len() as u32,
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&format_label("command_encoder", "terrain_defragment", 0)),
+        });
+        for mesh in meshes {
+            let (src, offset) = match &mesh.vertices {
+                ChunkVertices::Buffer(buffer) => (buffer, 0),
+                ChunkVertices::Pooled(slot) => {
+                    let old_pool = old_pool.as_ref().expect("Pooled vertices without a pool");
+                    (old_pool.buffer(), old_pool.slot_offset(slot))
+                },
+            };
+            let slot = pool
+                .allocate_copy(&mut encoder, src, offset, mesh.vertices.len())
+                .expect("The pool has a slot for each chunk");
+            mesh.vertices = ChunkVertices::Pooled(slot);
+        }
+        queue.submit(Some(encoder.finish()));
+        // wgpu keeps the old buffers alive until the copies are done
+        self.vertex_pool = Some(pool);
+        true
+    }
+
+    /// Starts blending the opaque mesh of the chunk at `pos` between two levels of detail.
+    ///
+    /// Returns `false` if the chunk has no mesh or its vertex count does not match.
+    /// The chunk's vertex buffer is rewritten every frame, so it needs
+    /// [`wgpu::BufferUsages::COPY_DST`].
+    pub fn start_morph(&mut self, pos: Vec2<i32>, morph: MorphTarget) -> bool {
+        let Some(mesh) = self.chunks.get_mut(&pos) else {
+            return false;
+        };
+        if mesh.vertices.len() as usize != morph.old.len() {
+            return false;
+        }
+        mesh.morph = Some(morph);
+        self.morph_factor = 0.0;
+        true
+    }
+
+    /// Moves [`TerrainRender::morph_factor`] `dt` seconds toward 1.0 and writes the blended
+    /// vertices of the morphing chunks.
+    ///
+    /// Once the transition is over the chunks keep their new mesh and the factor goes back to 0.0.
+    pub fn update_morph(&mut self, queue: &wgpu::Queue, dt: f32) {
+        if self.chunks.values().all(|mesh| mesh.morph.is_none()) {
+            self.morph_factor = 0.0;
+            return;
+        }
+        self.morph_factor = (self.morph_factor + dt / Self::MORPH_DURATION).min(1.0);
+        let done = self.morph_factor >= 1.0;
+        for mesh in self.chunks.values_mut() {
+            let Some(morph) = &mesh.morph else {
+                continue;
+            };
+            let vertices = morph.blend(self.morph_factor);
+            match &mesh.vertices {
+                ChunkVertices::Buffer(buffer) => buffer.write(queue, &vertices),
+                ChunkVertices::Pooled(slot) => {
+                    if let Some(pool) = &self.vertex_pool {
+                        pool.write(queue, slot, &vertices);
+                    }
+                },
+            }
+            if done {
+                mesh.morph = None;
+            }
+        }
+    }
+
+    /// The number of chunks with opaque geometry.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The number of vertices of the opaque geometry of every chunk.
+    pub fn total_vertex_count(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|mesh| mesh.vertices.len() as usize)
+            .sum()
+    }
+
+    pub fn average_vertices_per_chunk(&self) -> f32 {
+        match self.chunk_count() {
+            0 => 0.0,
+            count => self.total_vertex_count() as f32 / count as f32,
+        }
+    }
+
+    /// The world space box around the chunk at `pos`.
+    pub fn chunk_bounds(pos: Vec2<i32>) -> (Vec3<f32>, Vec3<f32>) {
+        let min = Vec3::new(pos.x * CHUNK_WIDTH, 0, pos.y * CHUNK_WIDTH);
+        let size = common::chunk::StandardChunk::SIZE.map(|x| x as i32);
+        (min.map(|x| x as f32), (min + size).map(|x| x as f32))
+    }
+
+    /// Returns the chunks with transparent geometry, furthest from the camera first.
+    ///
+    /// Chunks at the same distance are ordered by their position so the order is stable.
+    pub fn sorted_transparent_chunks(&self, camera_pos: Vec3<f32>) -> Vec<Vec2<i32>> {
+        back_to_front(
+            self.transparent_chunks.keys().copied().collect(),
+            camera_pos,
+        )
+    }
+}
+
+fn back_to_front(mut chunks: Vec<Vec2<i32>>, camera_pos: Vec3<f32>) -> Vec<Vec2<i32>> {
+    let camera = Vec2::new(camera_pos.x, camera_pos.z);
+    // Squared distances sort the same way and avoid the square root
+    let distance = |pos: &Vec2<i32>| {
+        let center = pos.map(|x| (x * CHUNK_WIDTH) as f32 + CHUNK_WIDTH as f32 / 2.0);
+        center.distance_squared(camera)
+    };
+    chunks.sort_by(|a, b| {
+        distance(b)
+            .total_cmp(&distance(a))
+            .then_with(|| (a.x, a.y).cmp(&(b.x, b.y)))
+    });
+    chunks
+}
+
+/// The horizontal size of a chunk in blocks.
+const CHUNK_WIDTH: i32 = common::chunk::StandardChunk::SIZE.x as i32;
+
+/// The same chunk mesh in two levels of detail, with one vertex of `new` for each one of `old`.
+pub struct MorphTarget {
+    pub old: Vec<TerrainVertex>,
+    pub new: Vec<TerrainVertex>,
+}
+
+impl MorphTarget {
+    /// `None` if the meshes don't have as many vertices.
+    pub fn new(old: Vec<TerrainVertex>, new: Vec<TerrainVertex>) -> Option<Self> {
+        (old.len() == new.len()).then_some(Self { old, new })
+    }
+
+    /// The vertices `factor` of the way from the old mesh to the new one.
+    pub fn blend(&self, factor: f32) -> Vec<TerrainVertex> {
+        self.old
+            .iter()
+            .zip(&self.new)
+            .map(|(old, new)| TerrainVertex::interpolate(*old, *new, factor))
+            .collect()
+    }
+}
+
+/// Where the vertices of a [`TerrainChunkMesh`] are stored.
+pub enum ChunkVertices {
+    /// A buffer of their own, as created when the chunk is meshed.
+    Buffer(Buffer<TerrainVertex>),
+    /// A slot of the buffer [`TerrainRender::defragment`] packed them in.
+    Pooled(PoolSlot<TerrainVertex>),
+}
+
+impl ChunkVertices {
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u32 {
+        match self {
+            ChunkVertices::Buffer(buffer) => buffer.len(),
+            ChunkVertices::Pooled(slot) => slot.len(),
+        }
+    }
 }
 
 pub struct TerrainChunkMesh {
-    pub vertex_buffer: Buffer<TerrainVertex>,
+    pub vertices: ChunkVertices,
     pub chunk_pos_buffer: Buffer<ChunkPos>,
     pub chunk_pos_bind_group: wgpu::BindGroup,
+    /// Set during a level of detail transition, see [`TerrainRender::start_morph`].
+    pub morph: Option<MorphTarget>,
 }
 
 impl TerrainChunkMesh {
@@ -24,15 +328,17 @@ impl TerrainChunkMesh {
         layout: &wgpu::BindGroupLayout,
         chunk_pos: ChunkPos,
         vertex_buffer: Buffer<TerrainVertex>,
+        label_index: u32,
     ) -> Self {
-        let chunk_pos_buffer = Buffer::new(
+        let chunk_pos_buffer = Buffer::new_with_label(
             device,
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             &[chunk_pos],
+            &format_label("buffer", "chunk_pos", label_index),
         );
 
         let chunk_pos_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Chunk Pos Bind Group"),
+            label: Some(&format_label("bind_group", "chunk_pos", label_index)),
             layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -41,9 +347,10 @@ impl TerrainChunkMesh {
         });
 
         Self {
-            vertex_buffer,
+            vertices: ChunkVertices::Buffer(vertex_buffer),
             chunk_pos_buffer,
             chunk_pos_bind_group,
+            morph: None,
         }
     }
 }
@@ -59,9 +366,400 @@ impl EguiContext {
     pub fn get_mut(&mut self) -> &mut egui::Context {
         &mut self.0
     }
+
+    /// Sends every texture egui manages again with the next frame, for a new egui renderer
+    /// that has none of them yet.
+    ///
+    /// Must only be called after the first frame, once the fonts are loaded.
+    pub fn reupload_textures(&self) {
+        // Images are loaded again when they are next shown
+        self.0.forget_all_images();
+        let font_image = self.0.fonts(|fonts| fonts.image());
+        self.0.tex_manager().write().set(
+            egui::TextureId::default(),
+            egui::epaint::ImageDelta::full(
+                font_image,
+                egui::epaint::TextureAtlas::texture_options(),
+            ),
+        );
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct EguiSettings {
     pub scale_factor: f32,
 }
+
+/// How much larger than a block the highlight is, so it does not z-fight with the face.
+const HIGHLIGHT_SCALE: f32 = 1.01;
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.9, 0.1, 0.35];
+
+/// The two triangles covering the `face` of a block, slightly scaled up from the block centre.
+///
+/// `face` is the normal of the face, pointing away from the block.
+pub fn block_face_quad(
+    chunk: Vec2<i32>,
+    local_pos: Vec3<i32>,
+    face: Vec3<i32>,
+) -> [HighlightVertex; 6] {
+    let size = StandardChunk::SIZE.map(|x| x as i32);
+    let world_pos = Vec3::new(
+        chunk.x * size.x + local_pos.x,
+        local_pos.y,
+        chunk.y * size.z + local_pos.z,
+    );
+    let half = HIGHLIGHT_SCALE / 2.0;
+    let normal = face.map(|x| x as f32);
+    let centre = world_pos.map(|x| x as f32) + 0.5 + normal * half;
+    // The two axes the face spans
+    let u = Vec3::new(normal.y.abs(), normal.z.abs(), normal.x.abs()) * half;
+    let v = Vec3::new(normal.z.abs(), normal.x.abs(), normal.y.abs()) * half;
+    let corners = [
+        centre - u - v,
+        centre + u - v,
+        centre + u + v,
+        centre - u + v,
+    ];
+    [0, 1, 2, 2, 3, 0].map(|i| HighlightVertex::new(corners[i], HIGHLIGHT_COLOR))
+}
+
+/// The highlighted face of the targeted block.
+///
+/// The quad buffer is kept between frames and rewritten, but the highlight is only drawn
+/// in the frames [`BlockHighlight::set`] was called for.
+#[derive(Default)]
+pub struct BlockHighlight {
+    buffer: Option<Buffer<HighlightVertex>>,
+    visible: bool,
+}
+
+impl BlockHighlight {
+    pub fn set(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunk: Vec2<i32>,
+        local_pos: Vec3<i32>,
+        face: Vec3<i32>,
+    ) {
+        let quad = block_face_quad(chunk, local_pos, face);
+        match &self.buffer {
+            Some(buffer) => buffer.write(queue, &quad),
+            None => {
+                self.buffer = Some(Buffer::new_with_label(
+                    device,
+                    wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    &quad,
+                    &format_label("buffer", "block_highlight", 0),
+                ))
+            },
+        }
+        self.visible = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.visible = false;
+    }
+
+    /// The quad to draw this frame, if any.
+    pub fn buffer(&self) -> Option<&Buffer<HighlightVertex>> {
+        self.buffer.as_ref().filter(|_| self.visible)
+    }
+}
+
+/// HUD elements drawn with the UI pipeline, below the egui windows.
+///
+/// The vertices are cleared after every frame.
+#[derive(Default)]
+pub struct HudRender {
+    pub vertices: Vec<UiVertex>,
+}
+
+impl HudRender {
+    /// Adds a rectangle between the pixel positions `min` and `max`.
+    pub fn push_rect(&mut self, min: Vec2<f32>, max: Vec2<f32>, color: Rgba<f32>) {
+        let color = color.into_array();
+        let corners = [
+            (Vec2::new(min.x, min.y), Vec2::new(0.0, 0.0)),
+            (Vec2::new(min.x, max.y), Vec2::new(0.0, 1.0)),
+            (Vec2::new(max.x, max.y), Vec2::new(1.0, 1.0)),
+            (Vec2::new(max.x, min.y), Vec2::new(1.0, 0.0)),
+        ];
+        for i in [0, 1, 2, 2, 3, 0] {
+            let (pos, uv) = corners[i];
+            self.vertices.push(UiVertex::new(pos, uv, color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::render::{buffer::Buffer, test_device::device, vertex::TerrainVertex, ChunkPos};
+
+    use super::{
+        back_to_front, block_face_quad, BlockHighlight, ChunkVertices, EguiContext, MorphTarget,
+        TerrainChunkMesh, TerrainRender,
+    };
+
+    fn read_vertices(
+        terrain: &TerrainRender,
+        pos: Vec2<i32>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<TerrainVertex> {
+        match &terrain.chunks[&pos].vertices {
+            ChunkVertices::Buffer(buffer) => buffer.read_back(device, queue),
+            ChunkVertices::Pooled(slot) => {
+                let pool = terrain.vertex_pool.as_ref().unwrap();
+                let start = pool.slot_offset(slot) as usize;
+                pool.buffer().read_back(device, queue)[start..start + slot.len() as usize].to_vec()
+            },
+        }
+    }
+
+    #[test]
+    pub fn mesh_statistics() {
+        assert_eq!(TerrainRender::default().average_vertices_per_chunk(), 0.0);
+        let Some((device, _)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let layout = crate::render::create_chunk_pos_bind_group_layout(&device);
+        let mut terrain = TerrainRender::default();
+        for (i, len) in [4, 8, 12].into_iter().enumerate() {
+            let vertices = vec![TerrainVertex::pack(Vec3::zero(), 0, 0, 0, 0); len];
+            let buffer = Buffer::new(&device, wgpu::BufferUsages::VERTEX, &vertices);
+            let mesh =
+                TerrainChunkMesh::new(&device, &layout, ChunkPos::new(i as i32, 0), buffer, 0);
+            terrain.chunks.insert(Vec2::new(i as i32, 0), mesh);
+        }
+        assert_eq!(terrain.chunk_count(), 3);
+        assert_eq!(terrain.total_vertex_count(), 24);
+        assert_eq!(terrain.average_vertices_per_chunk(), 8.0);
+    }
+
+    #[test]
+    pub fn morph_blends_between_levels_of_detail() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let layout = crate::render::create_chunk_pos_bind_group_layout(&device);
+        let old = vec![TerrainVertex::pack(Vec3::new(0, 10, 0), 1, 4, 0, 0); 4];
+        let new = vec![TerrainVertex::pack(Vec3::new(0, 14, 0), 1, 4, 0, 0); 4];
+        let buffer = Buffer::new(
+            &device,
+            wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            &old,
+        );
+        let mut terrain = TerrainRender::default();
+        let mesh = TerrainChunkMesh::new(&device, &layout, ChunkPos::new(0, 0), buffer, 0);
+        terrain.chunks.insert(Vec2::zero(), mesh);
+        assert!(MorphTarget::new(old.clone(), vec![]).is_none());
+        let morph = MorphTarget::new(old, new.clone()).unwrap();
+        assert!(!terrain.start_morph(Vec2::unit_x(), MorphTarget::new(vec![], vec![]).unwrap()));
+        assert!(terrain.start_morph(Vec2::zero(), morph));
+
+        terrain.update_morph(&queue, TerrainRender::MORPH_DURATION / 4.0);
+        assert_eq!(terrain.morph_factor, 0.25);
+        let vertices = read_vertices(&terrain, Vec2::zero(), &device, &queue);
+        assert!(vertices
+            .iter()
+            .all(|v| v.morphed_position() == Vec3::new(0.0, 11.0, 0.0)));
+
+        terrain.update_morph(&queue, TerrainRender::MORPH_DURATION);
+        assert_eq!(terrain.morph_factor, 1.0);
+        let mesh = &terrain.chunks[&Vec2::zero()];
+        assert!(mesh.morph.is_none());
+        assert_eq!(read_vertices(&terrain, Vec2::zero(), &device, &queue), new);
+        // Ready for the next transition
+        terrain.update_morph(&queue, 0.1);
+        assert_eq!(terrain.morph_factor, 0.0);
+    }
+
+    #[test]
+    pub fn defragment_packs_the_remaining_chunks() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let layout = crate::render::create_chunk_pos_bind_group_layout(&device);
+        let mut terrain = TerrainRender::default();
+        let vertices = |x: i32| vec![TerrainVertex::pack(Vec3::new(0, x as u16, 0), 1, 0, 0, 0); 4];
+        for x in 0..10 {
+            let buffer = Buffer::new(
+                &device,
+                wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+                &vertices(x),
+            );
+            let mesh = TerrainChunkMesh::new(&device, &layout, ChunkPos::new(x, 0), buffer, 0);
+            terrain.insert_chunk(Vec2::new(x, 0), mesh);
+        }
+        assert_eq!(terrain.fragmentation_ratio(), 0.0);
+        assert!(!terrain.defragment_on(&device, &queue));
+
+        // Unload 60% of the chunks
+        for x in 4..10 {
+            terrain.remove_chunk(Vec2::new(x, 0));
+        }
+        assert_eq!(terrain.fragmentation_ratio(), 0.6);
+        assert_eq!(terrain.vertex_allocation_count(), 4);
+        assert!(terrain.defragment_on(&device, &queue));
+        assert_eq!(terrain.vertex_allocation_count(), 1);
+        assert_eq!(terrain.fragmentation_ratio(), 0.0);
+        for x in 0..4 {
+            let pos = Vec2::new(x, 0);
+            assert!(matches!(
+                terrain.chunks[&pos].vertices,
+                ChunkVertices::Pooled(_)
+            ));
+            assert_eq!(read_vertices(&terrain, pos, &device, &queue), vertices(x));
+        }
+
+        // Packed chunks can be moved again
+        terrain.remove_chunk(Vec2::new(0, 0));
+        assert_eq!(terrain.fragmentation_ratio(), 0.25);
+        assert!(terrain.defragment_on(&device, &queue));
+        assert_eq!(terrain.vertex_allocation_count(), 1);
+        assert_eq!(
+            read_vertices(&terrain, Vec2::new(3, 0), &device, &queue),
+            vertices(3)
+        );
+    }
+
+    #[test]
+    pub fn defragment_is_triggered_by_unloads_or_request() {
+        let mut terrain = TerrainRender::default();
+        assert!(!terrain.should_defragment());
+        terrain.defragment_requested = true;
+        assert!(terrain.should_defragment());
+
+        terrain.defragment_requested = false;
+        terrain.unloaded_chunks = TerrainRender::AUTO_DEFRAGMENT_UNLOADS - 1;
+        // Chunks that were never loaded don't count
+        terrain.remove_chunk(Vec2::zero());
+        assert!(!terrain.should_defragment());
+        terrain.unloaded_chunks += 1;
+        assert!(terrain.should_defragment());
+    }
+
+    #[test]
+    pub fn furthest_chunks_come_first() {
+        // The camera sits in the middle of chunk (0, 0)
+        let camera = Vec3::new(8.0, 100.0, 8.0);
+        let chunks = vec![Vec2::new(1, 0), Vec2::new(0, 3), Vec2::new(-2, 0)];
+        assert_eq!(
+            back_to_front(chunks, camera),
+            vec![Vec2::new(0, 3), Vec2::new(-2, 0), Vec2::new(1, 0)]
+        );
+    }
+
+    #[test]
+    pub fn ties_are_ordered_by_position() {
+        let camera = Vec3::new(8.0, 100.0, 8.0);
+        let chunks = vec![
+            Vec2::new(0, 1),
+            Vec2::new(1, 0),
+            Vec2::new(0, -1),
+            Vec2::new(-1, 0),
+        ];
+        assert_eq!(
+            back_to_front(chunks, camera),
+            vec![
+                Vec2::new(-1, 0),
+                Vec2::new(0, -1),
+                Vec2::new(0, 1),
+                Vec2::new(1, 0)
+            ]
+        );
+    }
+
+    #[test]
+    pub fn face_quad_covers_face() {
+        // The top face of the block at (1, 64, 2) in chunk (-1, 3)
+        let quad = block_face_quad(Vec2::new(-1, 3), Vec3::new(1, 64, 2), Vec3::unit_y());
+        let world = Vec3::new(-16.0 + 1.0, 64.0, 48.0 + 2.0);
+        for vertex in quad {
+            let pos: Vec3<f32> = Vec3::from(vertex.pos);
+            // Just above the face, and a little past its edges
+            assert!((pos.y - (world.y + 1.005)).abs() < 1e-5);
+            assert!((pos.x - (world.x + 0.5)).abs() - 0.505 < 1e-5);
+            assert!((pos.z - (world.z + 0.5)).abs() - 0.505 < 1e-5);
+        }
+        let side = block_face_quad(Vec2::zero(), Vec3::zero(), -Vec3::unit_x());
+        assert!(side
+            .iter()
+            .all(|vertex| (vertex.pos[0] + 0.005).abs() < 1e-5));
+    }
+
+    #[test]
+    pub fn highlighting_twice_keeps_one_quad() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let mut highlight = BlockHighlight::default();
+        assert!(highlight.buffer().is_none());
+        highlight.set(
+            &device,
+            &queue,
+            Vec2::zero(),
+            Vec3::new(1, 2, 3),
+            Vec3::unit_y(),
+        );
+        highlight.set(
+            &device,
+            &queue,
+            Vec2::zero(),
+            Vec3::new(4, 5, 6),
+            Vec3::unit_z(),
+        );
+        assert_eq!(highlight.buffer().unwrap().len(), 6);
+
+        highlight.clear();
+        assert!(highlight.buffer().is_none());
+    }
+
+    #[test]
+    pub fn fonts_are_reuploaded_to_a_new_egui_renderer() {
+        let context = EguiContext::default();
+        let output = context.get().run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| ui.label("Explora"));
+        });
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let mut ui_renderer =
+            egui_wgpu::Renderer::new(&device, wgpu::TextureFormat::Rgba8UnormSrgb, None, 1);
+        for (id, delta) in &output.textures_delta.set {
+            ui_renderer.update_texture(&device, &queue, *id, delta);
+        }
+        drop(ui_renderer);
+
+        // The renderer of the new backend starts without any texture
+        let mut ui_renderer =
+            egui_wgpu::Renderer::new(&device, wgpu::TextureFormat::Rgba8UnormSrgb, None, 1);
+        context.reupload_textures();
+        let output = context.get().run(egui::RawInput::default(), |_| {});
+        let font = output
+            .textures_delta
+            .set
+            .iter()
+            .find(|(id, _)| *id == egui::TextureId::default())
+            .expect("The font texture was not sent again");
+        assert!(font.1.is_whole());
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        for (id, delta) in &output.textures_delta.set {
+            ui_renderer.update_texture(&device, &queue, *id, delta);
+        }
+        device.poll(wgpu::Maintain::Wait);
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+        assert!(ui_renderer.texture(&egui::TextureId::default()).is_some());
+    }
+}
@@ -0,0 +1,20 @@
+//! Headless GPU device shared by the rendering tests.
+
+/// Acquires a headless device, honouring the `WGPU_ADAPTER_NAME` and `WGPU_BACKEND` variables.
+/// `None` if no GPU is available.
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    device_with_features(wgpu::Features::empty())
+}
+
+/// Like [`device`], with the subset of `features` the adapter supports enabled.
+pub fn device_with_features(features: wgpu::Features) -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(wgpu::util::initialize_adapter_from_env_or_default(
+        &instance, None,
+    ))?;
+    let descriptor = wgpu::DeviceDescriptor {
+        features: features & adapter.features(),
+        ..Default::default()
+    };
+    pollster::block_on(adapter.request_device(&descriptor, None)).ok()
+}
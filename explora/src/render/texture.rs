@@ -1,25 +1,125 @@
-use image::RgbaImage;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+};
+
+use image::{DynamicImage, RgbaImage};
+
+use super::{error::RenderError, format_label};
 
 pub struct Texture {
     pub(crate) view: wgpu::TextureView,
     pub(crate) sampler: wgpu::Sampler,
+    /// Width, height and number of array layers.
+    pub(crate) size: wgpu::Extent3d,
+    /// The debug label shown in GPU debugging tools.
+    pub(crate) label: Option<String>,
 }
 
 impl Texture {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, image: RgbaImage) -> Self {
+        Self::create(device, queue, image, None)
+    }
+
+    /// Creates a texture whose handle, view and sampler are labelled with `label`.
+    pub fn new_with_label(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: RgbaImage,
+        label: &str,
+    ) -> Self {
+        Self::create(device, queue, image, Some(label))
+    }
+
+    /// Creates a texture holding a normal map. Unlike colours, normals are stored linearly
+    /// and must not be converted from sRGB when sampled.
+    pub fn normal_map(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: RgbaImage,
+        label: &str,
+    ) -> Self {
+        Self::create_2d(
+            device,
+            queue,
+            &image,
+            image.width(),
+            image.height(),
+            wgpu::TextureFormat::Rgba8Unorm,
+            Some(label),
+        )
+    }
+
+    /// Creates a texture from raw pixel data, e.g. generated in a test or embedded
+    /// with `include_bytes!`, without decoding it as an image.
+    ///
+    /// # Panics
+    ///
+    /// If `format` is compressed or a depth format, or `bytes` does not hold
+    /// exactly `width * height` pixels of `format`.
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let block_size = format
+            .block_size(None)
+            .filter(|_| format.block_dimensions() == (1, 1))
+            .unwrap_or_else(|| panic!("{:?} textures can't be created from bytes", format));
+        assert_eq!(
+            bytes.len(),
+            (width * height * block_size) as usize,
+            "Expected {}x{} pixels of {:?}",
+            width,
+            height,
+            format
+        );
+        Self::create_2d(device, queue, bytes, width, height, format, None)
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: RgbaImage,
+        label: Option<&str>,
+    ) -> Self {
+        Self::create_2d(
+            device,
+            queue,
+            &image,
+            image.width(),
+            image.height(),
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            label,
+        )
+    }
+
+    fn create_2d(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: image.width(),
-            height: image.height(),
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
         let handle = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
+            label,
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -31,17 +131,21 @@ impl Texture {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &image,
+            bytes,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * image.width()),
-                rows_per_image: Some(image.height()),
+                bytes_per_row: Some(bytes.len() as u32 / height),
+                rows_per_image: Some(height),
             },
             size,
         );
 
-        let view = handle.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = handle.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -51,30 +155,149 @@ impl Texture {
             ..Default::default()
         });
 
-        Self { view, sampler }
+        Self {
+            view,
+            sampler,
+            size,
+            label: label.map(str::to_owned),
+        }
+    }
+
+    /// Creates a cube map from its six faces, in the order +X, -X, +Y, -Y, +Z, -Z.
+    ///
+    /// # Panics
+    ///
+    /// If the faces are not squares of the same size.
+    pub fn cube(device: &wgpu::Device, queue: &wgpu::Queue, faces: [DynamicImage; 6]) -> Self {
+        let faces = faces.map(|face| face.to_rgba8());
+        let side = faces[0].width();
+        assert!(
+            faces
+                .iter()
+                .all(|face| face.width() == side && face.height() == side),
+            "Cube map faces must be squares of the same size"
+        );
+        let size = wgpu::Extent3d {
+            width: side,
+            height: side,
+            depth_or_array_layers: 6,
+        };
+        let label = format_label("texture", "cube_map", 0);
+        let label = Some(label.as_str());
+        let handle = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        // Each face is a layer of the texture
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &handle,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                face,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * side),
+                    rows_per_image: Some(side),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+        let view = handle.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            view,
+            sampler,
+            size,
+            label: label.map(str::to_owned),
+        }
+    }
+
+    /// A cube map of a single colour, used until a real environment map is loaded.
+    pub fn solid_cube(device: &wgpu::Device, queue: &wgpu::Queue, color: [u8; 4]) -> Self {
+        let face = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::cube(device, queue, std::array::from_fn(|_| face.clone()))
+    }
+
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.size
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn depth(device: &wgpu::Device, width: u32, height: u32) -> Self {
+    /// Creates a depth texture with `sample_count` samples per pixel, which must match
+    /// the colour attachments it is used with.
+    ///
+    /// Only `sampled` depth textures can be read by a later pass. They are not sampled by
+    /// default since the GL backend then fails to resolve multisampled colour attachments.
+    pub fn depth(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        sampled: bool,
+    ) -> Self {
         let size = wgpu::Extent3d {
             width,
             height,
             depth_or_array_layers: 1,
         };
+        let label = format_label("texture", "depth", 0);
+        let label = Some(label.as_str());
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
+            label,
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: if sampled {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
             view_formats: &[],
         });
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            ..Default::default()
+        });
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -86,6 +309,154 @@ impl Texture {
             lod_max_clamp: 100.0,
             ..Default::default()
         });
-        Self { view, sampler }
+        Self {
+            view,
+            sampler,
+            size,
+            label: label.map(str::to_owned),
+        }
+    }
+}
+
+/// The textures loaded from disk, so each file is uploaded to the GPU only once.
+///
+/// Only weak handles are kept: the texture is released when the last [`Arc`] is dropped.
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<PathBuf, Weak<Texture>>,
+}
+
+impl TextureCache {
+    /// Returns the texture loaded from `path`, loading it if no handle to it is alive.
+    ///
+    /// Paths are canonicalised, so different paths to the same file share the texture.
+    pub fn get_or_load(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+    ) -> Result<Arc<Texture>, RenderError> {
+        let path = path.canonicalize()?;
+        if let Some(texture) = self.textures.get(&path).and_then(Weak::upgrade) {
+            return Ok(texture);
+        }
+        let label = path.display().to_string();
+        let image = image::open(&path)
+            .map_err(|e| RenderError::Texture(label.clone(), e))?
+            .to_rgba8();
+        let texture = Arc::new(Texture::new_with_label(device, queue, image, &label));
+        // Forget the textures that were released in the meantime
+        self.textures
+            .retain(|_, texture| texture.strong_count() > 0);
+        self.textures.insert(path, Arc::downgrade(&texture));
+        Ok(texture)
+    }
+
+    /// The number of cached textures that are still alive.
+    pub fn len(&self) -> usize {
+        self.textures
+            .values()
+            .filter(|texture| texture.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use image::{DynamicImage, RgbaImage};
+
+    use crate::render::test_device::device;
+
+    use super::{Texture, TextureCache};
+
+    #[test]
+    pub fn from_bytes_creates_checkerboard() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let bytes: Vec<u8> = (0..4 * 4)
+            .flat_map(|i| match (i % 4 + i / 4) % 2 {
+                0 => [0, 0, 0, 255],
+                _ => [255, 255, 255, 255],
+            })
+            .collect();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let texture = Texture::from_bytes(
+            &device,
+            &queue,
+            &bytes,
+            4,
+            4,
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+        assert_eq!(texture.size().width, 4);
+        assert_eq!(texture.size().height, 4);
+        assert_eq!(texture.size().depth_or_array_layers, 1);
+    }
+
+    #[test]
+    pub fn textures_keep_their_debug_label() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let image = RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        let labelled = Texture::new_with_label(&device, &queue, image.clone(), "Block Atlas");
+        assert_eq!(labelled.label(), Some("Block Atlas"));
+        assert_eq!(Texture::new(&device, &queue, image).label(), None);
+        let depth = Texture::depth(&device, 4, 4, 1, false);
+        assert_eq!(depth.label(), Some("texture/depth[0]"));
+    }
+
+    #[test]
+    pub fn cube_map_has_six_layers() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let faces = std::array::from_fn(|i| {
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                8,
+                8,
+                image::Rgba([i as u8, 0, 0, 255]),
+            ))
+        });
+        let cube = Texture::cube(&device, &queue, faces);
+        assert!(pollster::block_on(device.pop_error_scope()).is_none());
+        assert_eq!(cube.size().depth_or_array_layers, 6);
+        assert_eq!((cube.size().width, cube.size().height), (8, 8));
+    }
+
+    #[test]
+    pub fn cached_textures_are_shared() {
+        let Some((device, queue)) = device() else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let path = std::env::temp_dir().join("explora_texture_cache_test.png");
+        RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]))
+            .save(&path)
+            .unwrap();
+
+        let mut cache = TextureCache::default();
+        let first = cache.get_or_load(&device, &queue, &path).unwrap();
+        let second = cache.get_or_load(&device, &queue, &path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(Arc::strong_count(&first), 2);
+        assert_eq!(cache.len(), 1);
+
+        drop(first);
+        drop(second);
+        assert!(cache.is_empty());
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,189 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::{buffer::Buffer, format_label};
+
+/// Measures how long the GPU spends in the main render pass.
+///
+/// The timestamps of a frame are copied to a readback buffer after it is submitted
+/// and read on a later frame, so reading them never stalls the GPU.
+pub struct TimestampQueries {
+    pub query_set: wgpu::QuerySet,
+    pub resolve_buffer: Buffer<u64>,
+    pub readback_buffer: Buffer<u64>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    /// Present while the readback buffer is being mapped. Set to true once it can be read.
+    mapping: Option<Arc<AtomicBool>>,
+    frame_time_us: Option<f32>,
+}
+
+impl TimestampQueries {
+    const COUNT: u32 = 2;
+
+    /// Returns `None` if the device was not created with [`wgpu::Features::TIMESTAMP_QUERY`].
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some(&format_label("query_set", "timestamps", 0)),
+            ty: wgpu::QueryType::Timestamp,
+            count: Self::COUNT,
+        });
+        let resolve_buffer = Buffer::new_with_label(
+            device,
+            wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            &[0; Self::COUNT as usize],
+            &format_label("buffer", "timestamp_resolve", 0),
+        );
+        let readback_buffer = Buffer::new_with_label(
+            device,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            &[0; Self::COUNT as usize],
+            &format_label("buffer", "timestamp_readback", 0),
+        );
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            mapping: None,
+            frame_time_us: None,
+        })
+    }
+
+    /// The timestamps to write at the beginning and end of the measured pass.
+    pub fn pass_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Copies the timestamps of this frame to the readback buffer.
+    ///
+    /// Does nothing while the previous timestamps have not been read yet.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.mapping.is_some() {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..Self::COUNT, &self.resolve_buffer.buf, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer.buf,
+            0,
+            &self.readback_buffer.buf,
+            0,
+            self.resolve_buffer.buf.size(),
+        );
+    }
+
+    /// Starts mapping the readback buffer. Must be called after the frame was submitted.
+    pub fn map(&mut self) {
+        if self.mapping.is_some() {
+            return;
+        }
+        let ready = Arc::new(AtomicBool::new(false));
+        let flag = ready.clone();
+        self.readback_buffer.buf.slice(..).map_async(
+            wgpu::MapMode::Read,
+            move |result| match result {
+                Ok(()) => flag.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map timestamp buffer: {}", e),
+            },
+        );
+        self.mapping = Some(ready);
+    }
+
+    /// Reads the timestamps of a previous frame if they are available.
+    ///
+    /// Returns the latest render pass duration in microseconds.
+    pub fn read(&mut self, device: &wgpu::Device) -> Option<f32> {
+        device.poll(wgpu::Maintain::Poll);
+        let ready = match &self.mapping {
+            Some(ready) => ready.load(Ordering::Acquire),
+            None => false,
+        };
+        if ready {
+            {
+                let view = self.readback_buffer.buf.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&view);
+                let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                self.frame_time_us = Some(ticks as f32 * self.period / 1000.0);
+            }
+            self.readback_buffer.buf.unmap();
+            self.mapping = None;
+        }
+        self.frame_time_us
+    }
+
+    pub fn frame_time_us(&self) -> Option<f32> {
+        self.frame_time_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::test_device::device_with_features;
+
+    use super::TimestampQueries;
+
+    #[test]
+    pub fn unsupported_timestamps_are_disabled() {
+        let Some((device, queue)) = device_with_features(wgpu::Features::empty()) else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        assert!(TimestampQueries::new(&device, &queue).is_none());
+    }
+
+    #[test]
+    pub fn timestamps_are_read_back() {
+        let Some((device, queue)) = device_with_features(wgpu::Features::TIMESTAMP_QUERY) else {
+            eprintln!("No GPU available, skipping test");
+            return;
+        };
+        let Some(mut timestamps) = TimestampQueries::new(&device, &queue) else {
+            eprintln!("Timestamp queries are not supported, skipping test");
+            return;
+        };
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: Some(timestamps.pass_writes()),
+        });
+        timestamps.resolve(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        timestamps.map();
+        device.poll(wgpu::Maintain::Wait);
+        assert!(timestamps.read(&device).is_some());
+    }
+}
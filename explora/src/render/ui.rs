@@ -1,10 +1,100 @@
 use apecs::{anyhow::Result, *};
+use vek::Vec2;
 
-use crate::render::{
-    resources::{EguiContext, EguiSettings},
-    CommandEncoder, RenderTexture, Renderer,
+use crate::{
+    render::{
+        buffer::Buffer,
+        format_label,
+        resources::{EguiContext, EguiSettings, HudRender},
+        vertex::UiVertex,
+        CommandEncoder, RenderTexture, Renderer,
+    },
+    settings::GameplaySettings,
 };
 
+/// Thickness and length of the arms of the crosshair, in pixels.
+const CROSSHAIR_SIZE: Vec2<f32> = Vec2::new(2.0, 20.0);
+const CROSSHAIR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// The two triangles of each arm of the crosshair.
+const CROSSHAIR_INDICES: [u16; 12] = [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
+
+/// The corners of the two arms of a crosshair centered on a `width` x `height` screen,
+/// in pixels.
+pub fn crosshair_vertices(width: u32, height: u32) -> [UiVertex; 8] {
+    let centre = Vec2::new(width as f32, height as f32) / 2.0;
+    let arm = |half_size: Vec2<f32>| {
+        let min = centre - half_size;
+        let max = centre + half_size;
+        [
+            UiVertex::new(
+                Vec2::new(min.x, min.y),
+                Vec2::new(0.0, 0.0),
+                CROSSHAIR_COLOR,
+            ),
+            UiVertex::new(
+                Vec2::new(min.x, max.y),
+                Vec2::new(0.0, 1.0),
+                CROSSHAIR_COLOR,
+            ),
+            UiVertex::new(
+                Vec2::new(max.x, max.y),
+                Vec2::new(1.0, 1.0),
+                CROSSHAIR_COLOR,
+            ),
+            UiVertex::new(
+                Vec2::new(max.x, min.y),
+                Vec2::new(1.0, 0.0),
+                CROSSHAIR_COLOR,
+            ),
+        ]
+    };
+    let [a, b, c, d] = arm(Vec2::new(CROSSHAIR_SIZE.y, CROSSHAIR_SIZE.x) / 2.0);
+    let [e, f, g, h] = arm(CROSSHAIR_SIZE / 2.0);
+    [a, b, c, d, e, f, g, h]
+}
+
+/// The vertices of the crosshair, which only change when the screen is resized.
+pub struct Crosshair {
+    vertices: Buffer<UiVertex>,
+    indices: Buffer<u16>,
+}
+
+impl Crosshair {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        Self {
+            vertices: Self::create_vertices(device, width, height),
+            indices: Buffer::new_with_label(
+                device,
+                wgpu::BufferUsages::INDEX,
+                &CROSSHAIR_INDICES,
+                &format_label("buffer", "crosshair_index", 0),
+            ),
+        }
+    }
+
+    fn create_vertices(device: &wgpu::Device, width: u32, height: u32) -> Buffer<UiVertex> {
+        Buffer::new_with_label(
+            device,
+            wgpu::BufferUsages::VERTEX,
+            &crosshair_vertices(width, height),
+            &format_label("buffer", "crosshair_vertex", 0),
+        )
+    }
+
+    /// Centers the crosshair on a screen of the new size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.vertices = Self::create_vertices(device, width, height);
+    }
+
+    /// Draws the crosshair with the [`UiPipeline`](super::pipeline::UiPipeline),
+    /// which must be bound to `pass`.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(0, self.vertices.slice());
+        pass.set_index_buffer(self.indices.slice(), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed(0..self.indices.len(), 0, 0..1);
+    }
+}
+
 #[derive(CanFetch)]
 pub struct UiRenderSystem {
     encoder: Write<Option<CommandEncoder>>,
@@ -12,6 +102,8 @@ pub struct UiRenderSystem {
     renderer: Write<Renderer, NoDefault>,
     egui_context: Write<EguiContext>,
     egui_configuration: Read<EguiSettings>,
+    hud: Write<HudRender>,
+    gameplay: Read<GameplaySettings>,
 }
 
 pub fn ui_render_system(mut ui: UiRenderSystem) -> Result<ShouldContinue> {
@@ -36,8 +128,32 @@ pub fn ui_render_system(mut ui: UiRenderSystem) -> Result<ShouldContinue> {
     ui.renderer
         .update_ui_buffers(encoder, paint_jobs.as_slice(), &screen_descriptor);
 
+    let hud_buffer = (!ui.hud.vertices.is_empty()).then(|| {
+        let renderer = &ui.renderer;
+        renderer.ui_pipeline.write_screen_size(
+            &renderer.queue,
+            renderer.config.width,
+            renderer.config.height,
+        );
+        Buffer::new_with_label(
+            &renderer.device,
+            wgpu::BufferUsages::VERTEX,
+            &ui.hud.vertices,
+            &format_label("buffer", "hud_vertex", 0),
+        )
+    });
+    ui.hud.vertices.clear();
+
+    // The UI is not anti-aliased, it is drawn on top of the resolved world
+    ui.renderer
+        .anti_aliasing_pass(encoder, &texture.surface_tex_view);
+    if ui.gameplay.show_crosshair {
+        ui.renderer
+            .draw_crosshair(encoder, &texture.surface_tex_view);
+    }
+
     let mut egui_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        label: Some("Egui Render Pass"),
+        label: Some(&format_label("render_pass", "egui", 0)),
         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
             view: &texture.surface_tex_view,
             resolve_target: None,
@@ -51,9 +167,60 @@ pub fn ui_render_system(mut ui: UiRenderSystem) -> Result<ShouldContinue> {
         timestamp_writes: None,
     });
 
+    // The HUD goes below the egui windows
+    if let Some(buffer) = &hud_buffer {
+        egui_render_pass.set_pipeline(&ui.renderer.ui_pipeline.pipeline);
+        egui_render_pass.set_bind_group(0, &ui.renderer.ui_pipeline.bind_group, &[]);
+        egui_render_pass.set_vertex_buffer(0, buffer.slice());
+        egui_render_pass.draw(0..buffer.len(), 0..1);
+    }
+
     ui.renderer
         .egui_renderer
         .render(&mut egui_render_pass, &paint_jobs, &screen_descriptor);
 
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec2;
+
+    use super::{crosshair_vertices, CROSSHAIR_SIZE};
+
+    #[test]
+    pub fn crosshair_is_centered_on_the_screen() {
+        let (width, height) = (800, 600);
+        let vertices = crosshair_vertices(width, height);
+        assert_eq!(vertices.len(), 8);
+
+        // Pixels to normalized device coordinates, as in `ui.wgsl`
+        let ndc = vertices
+            .iter()
+            .map(|v| {
+                Vec2::new(
+                    v.pos[0] / width as f32 * 2.0 - 1.0,
+                    1.0 - v.pos[1] / height as f32 * 2.0,
+                )
+            })
+            .collect::<Vec<_>>();
+        for pos in &ndc {
+            assert!(
+                ndc.iter().any(|other| (*other + *pos).magnitude() < 1e-6),
+                "{:?} has no opposite vertex",
+                pos
+            );
+        }
+        // One arm is horizontal and the other vertical
+        let size = |arm: &[Vec2<f32>]| Vec2::new(arm[2].x - arm[0].x, arm[1].y - arm[0].y);
+        let pixels = vertices
+            .iter()
+            .map(|v| Vec2::from(v.pos))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            size(&pixels[..4]),
+            Vec2::new(CROSSHAIR_SIZE.y, CROSSHAIR_SIZE.x)
+        );
+        assert_eq!(size(&pixels[4..]), CROSSHAIR_SIZE);
+    }
+}
@@ -1,28 +1,166 @@
-use vek::Vec3;
+use vek::{Vec2, Vec3};
 
 use crate::render::Vertex;
 
+/// The face normals a vertex can have, indexed by the packed normal index.
+///
+/// The order matches [`common::dir::Direction`] and the `unpack_normal` function of the terrain shader.
+pub const NORMALS: [Vec3<i32>; 6] = [
+    Vec3::new(0, 0, 1),
+    Vec3::new(0, 0, -1),
+    Vec3::new(1, 0, 0),
+    Vec3::new(-1, 0, 0),
+    Vec3::new(0, 1, 0),
+    Vec3::new(0, -1, 0),
+];
+
+/// A terrain vertex packed into 16 bytes.
+///
+/// - `data[0]`: x, y and z within the chunk, 10 bits each.
+/// - `data[1]`: tile id (16 bits), normal index (3 bits), ambient occlusion (5 bits) and light level (8 bits).
+/// - `data[2]`: the position the vertex morphs toward, packed like `data[0]`.
+/// - `data[3]`: how far the vertex is morphed toward `data[2]`, as the bits of an `f32`.
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct TerrainVertex {
-    pub data: u32,
+    pub data: [u32; 4],
 }
 
 impl TerrainVertex {
-    pub fn new(position: vek::Vec3<u32>, texture_id: u16, normal: Vec3<i32>) -> Self {
-        // pack normals
-        // since normals are in the range [-1, 1], we can map it to [0, 1] by adding 1 and dividing by 2
-        let normal = normal.map(|x| (x + 1) / 2).map(|x| x as u8);
+    const POS_MASK: u32 = 0x3FF;
+    const TILE_MASK: u32 = 0xFFFF;
+    const NORMAL_MASK: u32 = 0x7;
+    const AO_MASK: u32 = 0x1F;
+    const LIGHT_MASK: u32 = 0xFF;
+
+    const NORMAL_SHIFT: u32 = 16;
+    const AO_SHIFT: u32 = 19;
+    const LIGHT_SHIFT: u32 = 24;
+
+    /// Creates a vertex at a position within the chunk, rounded to the closest block corner.
+    ///
+    /// `normal_id` is an index in [`NORMALS`], see [`TerrainVertex::normal_index`].
+    pub fn new(pos: Vec3<f32>, tile_id: u16, normal_id: u8, ao: u8, light: u8) -> Self {
+        Self::pack(
+            pos.map(|x| x.round().max(0.0) as u16),
+            tile_id,
+            normal_id,
+            ao,
+            light,
+        )
+    }
+
+    /// Copies the vertex for each corner of a quad, with the ambient occlusion of each corner.
+    pub fn with_ao(self, ao: [u8; 4]) -> [Self; 4] {
+        ao.map(|ao| {
+            let mut vertex = self;
+            vertex.data[1] = (vertex.data[1] & !(Self::AO_MASK << Self::AO_SHIFT))
+                | ((ao as u32 & Self::AO_MASK) << Self::AO_SHIFT);
+            vertex
+        })
+    }
+
+    /// Packs every attribute of a vertex. Values wider than their field are truncated.
+    pub fn pack(pos: Vec3<u16>, tile: u16, normal: u8, ao: u8, light: u8) -> Self {
+        let pos = pos.map(|x| x as u32 & Self::POS_MASK);
         Self {
-            data: (position.x << 27)
-                | (position.y << 18)
-                | (position.z << 13)
-                // pack normals. each one is 1 bits, where 1 means positive and 0 means negative
-                | ((normal.x as u32) << 12)
-                | ((normal.y as u32) << 11)
-                | ((normal.z as u32) << 10)
-                | (texture_id as u32),
+            data: [
+                pos.x | (pos.y << 10) | (pos.z << 20),
+                (tile as u32 & Self::TILE_MASK)
+                    | ((normal as u32 & Self::NORMAL_MASK) << Self::NORMAL_SHIFT)
+                    | ((ao as u32 & Self::AO_MASK) << Self::AO_SHIFT)
+                    | ((light as u32 & Self::LIGHT_MASK) << Self::LIGHT_SHIFT),
+                0,
+                0,
+            ],
+        }
+    }
+
+    /// Maps a face normal to its index in [`NORMALS`].
+    ///
+    /// Diagonal normals, like the ones of cross meshes, use their horizontal axis.
+    pub fn normal_index(normal: Vec3<i32>) -> u8 {
+        if normal.x != 0 {
+            if normal.x > 0 {
+                2
+            } else {
+                3
+            }
+        } else if normal.z != 0 {
+            if normal.z > 0 {
+                0
+            } else {
+                1
+            }
+        } else if normal.y >= 0 {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// Blends two vertices, e.g. the same corner in two levels of detail of a mesh.
+    ///
+    /// The position is interpolated in float space: the vertex keeps the position of `a` and
+    /// morphs toward the one of `b` by `t`, see [`TerrainVertex::morphed_position`].
+    /// The ambient occlusion and light are interpolated linearly. The tile and the normal
+    /// can't be blended, they are taken from the closest of the two vertices.
+    pub fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let nearest = if t < 0.5 { a } else { b };
+        let mut vertex = Self::pack(
+            a.position().map(|x| x as u16),
+            nearest.tile(),
+            nearest.normal(),
+            lerp(a.ao() as f32, b.ao() as f32).round() as u8,
+            lerp(a.light() as f32, b.light() as f32).round() as u8,
+        );
+        if t == 1.0 {
+            vertex.data[0] = b.data[0];
+        } else if t > 0.0 && a.data[0] != b.data[0] {
+            vertex.data[2] = b.data[0];
+            vertex.data[3] = t.to_bits();
         }
+        vertex
+    }
+
+    fn unpack_position(packed: u32) -> Vec3<u32> {
+        Vec3::new(packed, packed >> 10, packed >> 20).map(|x| x & Self::POS_MASK)
+    }
+
+    /// Unpacks the position of this vertex relative to its chunk, before morphing.
+    pub fn position(&self) -> Vec3<u32> {
+        Self::unpack_position(self.data[0])
+    }
+
+    /// The position drawn by the terrain shader, between [`TerrainVertex::position`] and
+    /// the morph target.
+    pub fn morphed_position(&self) -> Vec3<f32> {
+        let from = self.position().map(|x| x as f32);
+        let to = Self::unpack_position(self.data[2]).map(|x| x as f32);
+        let t = f32::from_bits(self.data[3]);
+        if t == 0.0 {
+            from
+        } else {
+            from + (to - from) * t
+        }
+    }
+
+    pub fn tile(&self) -> u16 {
+        (self.data[1] & Self::TILE_MASK) as u16
+    }
+
+    pub fn normal(&self) -> u8 {
+        ((self.data[1] >> Self::NORMAL_SHIFT) & Self::NORMAL_MASK) as u8
+    }
+
+    pub fn ao(&self) -> u8 {
+        ((self.data[1] >> Self::AO_SHIFT) & Self::AO_MASK) as u8
+    }
+
+    pub fn light(&self) -> u8 {
+        ((self.data[1] >> Self::LIGHT_SHIFT) & Self::LIGHT_MASK) as u8
     }
 }
 
@@ -31,7 +169,80 @@ impl Vertex for TerrainVertex {
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         const ATTRS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
-            0 => Uint32,
+            0 => Uint32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: Self::STRIDE,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+/// A coloured vertex in world space, used by the block highlight.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct HighlightVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl HighlightVertex {
+    pub fn new(pos: Vec3<f32>, color: [f32; 4]) -> Self {
+        Self {
+            pos: pos.into_array(),
+            color,
+        }
+    }
+}
+
+impl Vertex for HighlightVertex {
+    // A single quad, drawn without an index buffer
+    const INDEX_BUFFER: Option<wgpu::IndexFormat> = None;
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: Self::STRIDE,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+/// A vertex of a HUD element drawn by the UI pipeline.
+///
+/// The position is in pixels, with the origin in the top left corner of the screen.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct UiVertex {
+    pub pos: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl UiVertex {
+    pub fn new(pos: Vec2<f32>, uv: Vec2<f32>, color: [f32; 4]) -> Self {
+        Self {
+            pos: pos.into_array(),
+            uv: uv.into_array(),
+            color,
+        }
+    }
+}
+
+impl Vertex for UiVertex {
+    // HUD elements are few, so they are drawn without an index buffer
+    const INDEX_BUFFER: Option<wgpu::IndexFormat> = None;
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x2,
+            2 => Float32x4,
         ];
         wgpu::VertexBufferLayout {
             array_stride: Self::STRIDE,
@@ -40,3 +251,90 @@ impl Vertex for TerrainVertex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec3;
+
+    use super::{TerrainVertex, NORMALS};
+
+    #[test]
+    pub fn vertex_is_16_bytes() {
+        assert_eq!(std::mem::size_of::<TerrainVertex>(), 16);
+    }
+
+    #[test]
+    pub fn pack_round_trip() {
+        let cases = [
+            (Vec3::new(0, 0, 0), 0, 0, 0, 0),
+            (Vec3::new(16, 256, 16), 42, 4, 17, 15),
+            (Vec3::new(1023, 1023, 1023), u16::MAX, 7, 31, 255),
+            (Vec3::new(5, 700, 1), 1234, 5, 3, 128),
+        ];
+        for (pos, tile, normal, ao, light) in cases {
+            let vertex = TerrainVertex::pack(pos, tile, normal, ao, light);
+            assert_eq!(vertex.position(), pos.map(|x| x as u32));
+            assert_eq!(vertex.tile(), tile);
+            assert_eq!(vertex.normal(), normal);
+            assert_eq!(vertex.ao(), ao);
+            assert_eq!(vertex.light(), light);
+        }
+    }
+
+    #[test]
+    pub fn normal_index_matches_table() {
+        for (i, normal) in NORMALS.iter().enumerate() {
+            assert_eq!(TerrainVertex::normal_index(*normal) as usize, i);
+        }
+    }
+
+    #[test]
+    pub fn new_round_trip() {
+        let vertex = TerrainVertex::new(Vec3::new(3.0, 200.0, 16.0), 9, 2, 12, 15);
+        // Plain data that can be uploaded as is
+        let bytes: &[u8] = bytemuck::bytes_of(&vertex);
+        assert_eq!(bytemuck::pod_read_unaligned::<TerrainVertex>(bytes), vertex);
+        assert_eq!(vertex.position(), Vec3::new(3, 200, 16));
+        assert_eq!(vertex.tile(), 9);
+        assert_eq!(vertex.normal(), 2);
+        assert_eq!(vertex.ao(), 12);
+        assert_eq!(vertex.light(), 15);
+    }
+
+    #[test]
+    pub fn with_ao_only_changes_ao() {
+        let vertex = TerrainVertex::new(Vec3::new(1.0, 2.0, 3.0), 4, 5, 31, 6);
+        let corners = vertex.with_ao([0, 10, 20, 30]);
+        for (corner, ao) in corners.iter().zip([0, 10, 20, 30]) {
+            assert_eq!(corner.ao(), ao);
+            assert_eq!(corner.position(), vertex.position());
+            assert_eq!(corner.tile(), 4);
+            assert_eq!(corner.normal(), 5);
+            assert_eq!(corner.light(), 6);
+        }
+    }
+
+    #[test]
+    pub fn interpolating_a_vertex_with_itself_keeps_it() {
+        let vertex = TerrainVertex::pack(Vec3::new(5, 700, 1), 1234, 5, 3, 128);
+        for t in [0.0, 0.25, 0.5, 1.0] {
+            assert_eq!(TerrainVertex::interpolate(vertex, vertex, t), vertex);
+        }
+    }
+
+    #[test]
+    pub fn interpolation_blends_positions_and_picks_the_nearest_tile() {
+        let a = TerrainVertex::pack(Vec3::new(0, 10, 4), 1, 0, 0, 0);
+        let b = TerrainVertex::pack(Vec3::new(1, 20, 4), 2, 4, 30, 200);
+        let quarter = TerrainVertex::interpolate(a, b, 0.25);
+        // Not rounded to a block corner
+        assert_eq!(quarter.morphed_position(), Vec3::new(0.25, 12.5, 4.0));
+        assert_eq!(quarter.position(), a.position());
+        assert_eq!(quarter.tile(), 1);
+        assert_eq!(quarter.normal(), 0);
+        assert_eq!(quarter.light(), 50);
+        assert_eq!(TerrainVertex::interpolate(a, b, 0.75).tile(), 2);
+        assert_eq!(TerrainVertex::interpolate(a, b, 0.0), a);
+        assert_eq!(TerrainVertex::interpolate(a, b, 1.0), b);
+    }
+}
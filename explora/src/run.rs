@@ -1,4 +1,4 @@
-use common::{clock::Clock, event::Events};
+use common::{clock::Clock, event::Events, resources::Paused};
 use log::info;
 use vek::Vec2;
 
@@ -9,100 +9,148 @@ use winit::{
 
 use crate::{
     client::Client,
-    input::Input,
-    render::{resources::EguiContext, Renderer},
-    settings::GameplaySettings,
+    error::AppError,
+    input::{GameInput, Input},
+    render::{error::RenderError, resources::EguiContext},
+    settings::{self, GameplaySettings},
+    singleplayer::Singleplayer,
     ui::{EguiInput, EguiState},
     window::{Window, WindowEvent},
 };
 
-pub fn run(event_loop: EventLoop<()>, mut client: Client) {
+/// Runs the game until the window is closed.
+///
+/// When playing on a `singleplayer` server, the server is paused along with the client.
+/// The gameplay settings are saved when the game exits.
+/// A render error raised while the game runs, e.g. a failed block atlas load, stops it.
+pub fn run(
+    event_loop: EventLoop<()>,
+    mut client: Client,
+    singleplayer: Option<Singleplayer>,
+) -> Result<(), AppError> {
     info!("Running explora");
     event_loop.set_control_flow(ControlFlow::Poll);
     let window = client.state().resource::<Window>().platform();
     let egui_context = client.state().resource::<EguiContext>();
     let mut egui_state = EguiState::new(egui_context.get(), window);
-    event_loop
-        .run(move |event, elwt| {
-            match event {
-                winit::event::Event::AboutToWait => {
-                    let window = client.state_mut().resource_mut::<Window>();
-                    window.platform().request_redraw();
-                },
-                winit::event::Event::WindowEvent { event, window_id } => {
-                    let window = client.state_mut().resource_mut::<Window>();
-                    let response = egui_state.state.on_window_event(window.platform(), &event);
-                    if response.consumed {
-                        // If the input was consumed by egui, we don't want to process it.
-                        return;
-                    }
-                    if window.platform().id() == window_id {
-                        match event {
-                            winit::event::WindowEvent::CloseRequested => elwt.exit(),
-                            winit::event::WindowEvent::Resized(size) => {
-                                let renderer = client.state_mut().resource_mut::<Renderer>();
-                                renderer.resize(size.width, size.height);
-
-                                let events =
-                                    client.state_mut().resource_mut::<Events<WindowEvent>>();
-                                let new_size = Vec2::new(size.width, size.height);
-                                events.send(WindowEvent::Resize(new_size));
-                            },
+    let mut server_paused = false;
+    let mut failure = None;
+    let failure_slot = &mut failure;
+    event_loop.run(move |event, elwt| {
+        match event {
+            winit::event::Event::AboutToWait => {
+                let window = client.state_mut().resource_mut::<Window>();
+                window.platform().request_redraw();
+            },
+            winit::event::Event::WindowEvent { event, window_id } => {
+                let window = client.state_mut().resource_mut::<Window>();
+                let response = egui_state.state.on_window_event(window.platform(), &event);
+                if response.consumed {
+                    // If the input was consumed by egui, we don't want to process it.
+                    return;
+                }
+                if window.platform().id() == window_id {
+                    match event {
+                        winit::event::WindowEvent::CloseRequested => elwt.exit(),
+                        winit::event::WindowEvent::Resized(size) => {
+                            // The renderer picks up the new size in the pre-render stage
+                            let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
+                            let new_size = Vec2::new(size.width, size.height);
+                            events.send(WindowEvent::Resize(new_size));
+                        },
 
-                            winit::event::WindowEvent::ScaleFactorChanged { .. } => {
-                                let size = window.platform().inner_size();
-                                let events =
-                                    client.state_mut().resource_mut::<Events<WindowEvent>>();
-                                events
-                                    .send(WindowEvent::Resize(Vec2::new(size.width, size.height)));
-                            },
+                        winit::event::WindowEvent::ScaleFactorChanged { .. } => {
+                            let size = window.platform().inner_size();
+                            let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
+                            events.send(WindowEvent::Resize(Vec2::new(size.width, size.height)));
+                        },
 
-                            winit::event::WindowEvent::KeyboardInput { event, .. } => {
-                                if let PhysicalKey::Code(code) = event.physical_key {
-                                    let input = client.state_mut().resource_mut::<Input>();
-                                    match event.state {
-                                        winit::event::ElementState::Pressed => {
-                                            input.press(code);
-                                        },
-                                        winit::event::ElementState::Released => {
-                                            input.release(code);
-                                        },
-                                    }
+                        winit::event::WindowEvent::Focused(focused) => {
+                            let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
+                            events.send(WindowEvent::Focused(focused));
+                        },
+                        winit::event::WindowEvent::DroppedFile(path) => {
+                            let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
+                            events.send(WindowEvent::FileDrop(path));
+                        },
+                        winit::event::WindowEvent::KeyboardInput { event, .. } => {
+                            if let PhysicalKey::Code(code) = event.physical_key {
+                                let input = client.state_mut().resource_mut::<Input>();
+                                match event.state {
+                                    winit::event::ElementState::Pressed => {
+                                        input.press(code);
+                                        if input.just_pressed(GameInput::Quit) {
+                                            elwt.exit();
+                                        }
+                                    },
+                                    winit::event::ElementState::Released => {
+                                        input.release(code);
+                                    },
                                 }
-                            },
-                            winit::event::WindowEvent::RedrawRequested => {
-                                let clock = client.state_mut().resource_mut::<Clock>();
-                                clock.tick();
+                            }
+                        },
+                        winit::event::WindowEvent::RedrawRequested => {
+                            let clock = client.state_mut().resource_mut::<Clock>();
+                            clock.tick();
 
-                                let window = client.state_mut().resource::<Window>();
-                                let raw_input = egui_state.state.take_egui_input(window.platform());
-                                client
-                                    .state_mut()
-                                    .resource_mut::<EguiInput>()
-                                    .set(raw_input);
+                            let window = client.state_mut().resource::<Window>();
+                            let raw_input = egui_state.state.take_egui_input(window.platform());
+                            client
+                                .state_mut()
+                                .resource_mut::<EguiInput>()
+                                .set(raw_input);
 
-                                let clock = client.state().resource::<Clock>();
-                                client.tick(clock.dt());
-                            },
-                            _ => (),
-                        }
+                            let clock = client.state().resource::<Clock>();
+                            client.tick(clock.dt());
+                            if let Some(e) = client
+                                .state_mut()
+                                .resource_mut::<Option<RenderError>>()
+                                .take()
+                            {
+                                *failure_slot = Some(e);
+                                elwt.exit();
+                                return;
+                            }
+
+                            let paused = client.state().resource::<Paused>().0;
+                            if let Some(singleplayer) = &singleplayer {
+                                if paused != server_paused {
+                                    if let Err(e) = singleplayer.set_paused(paused) {
+                                        log::error!("Failed to pause the server: {:?}", e);
+                                    }
+                                    server_paused = paused;
+                                }
+                            }
+                        },
+                        _ => (),
                     }
-                },
+                }
+            },
 
-                winit::event::Event::DeviceEvent {
-                    event: winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) },
-                    ..
-                } => {
-                    let settings = client.state().resource::<GameplaySettings>();
-                    let delta = Vec2::new(
-                        dx as f32 * (settings.mouse_sensitivity as f32 / 100.0),
-                        dy as f32 * (settings.mouse_sensitivity as f32 / 100.0),
-                    );
-                    let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
-                    events.send(WindowEvent::CursorMove(delta));
-                },
-                _ => (),
-            }
-        })
-        .unwrap();
+            winit::event::Event::LoopExiting => {
+                let settings = client.state().resource::<GameplaySettings>();
+                match settings.save(settings::SETTINGS_PATH) {
+                    Ok(()) => info!("Saved settings to `{}`", settings::SETTINGS_PATH),
+                    Err(e) => log::error!("Failed to save settings: {:?}", e),
+                }
+            },
+            winit::event::Event::DeviceEvent {
+                event: winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } => {
+                let settings = client.state().resource::<GameplaySettings>();
+                let delta = Vec2::new(
+                    dx as f32 * (settings.mouse_sensitivity as f32 / 100.0),
+                    dy as f32 * (settings.mouse_sensitivity as f32 / 100.0),
+                );
+                let events = client.state_mut().resource_mut::<Events<WindowEvent>>();
+                events.send(WindowEvent::CursorMove(delta));
+            },
+            _ => (),
+        }
+    })?;
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use common::{
+    block::BlockId,
+    chunk::{compress, decompress, ChunkColumn},
+    resources::TerrainMap,
+};
+use serde::{Deserialize, Serialize};
+use vek::Vec2;
+
+use crate::{camera::Camera, settings::GameplaySettings};
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Encoding(bincode::Error),
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::Error> for SaveError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Encoding(value)
+    }
+}
+
+/// The state of a game session that can be saved to and restored from disk.
+pub struct VoxelScene {
+    pub terrain_map: TerrainMap,
+    pub camera: Camera,
+    pub settings: GameplaySettings,
+}
+
+/// The on-disk representation of a [`VoxelScene`].
+///
+/// Chunks are stored run length encoded.
+#[derive(Serialize, Deserialize)]
+struct SceneData {
+    chunks: Vec<ColumnData>,
+    camera: Camera,
+    settings: GameplaySettings,
+}
+
+/// A chunk column and its sections, from the bottom up.
+#[derive(Serialize, Deserialize)]
+struct ColumnData {
+    pos: Vec2<i32>,
+    y_offset: i32,
+    sections: Vec<Vec<(BlockId, u32)>>,
+}
+
+impl VoxelScene {
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        let data = SceneData {
+            chunks: self
+                .terrain_map
+                .chunks
+                .iter()
+                .map(|(pos, column)| ColumnData {
+                    pos: *pos,
+                    y_offset: column.world_chunk_y_offset,
+                    sections: column.sections.iter().map(compress).collect(),
+                })
+                .collect(),
+            camera: self.camera.clone(),
+            settings: self.settings.clone(),
+        };
+        let bytes = bincode::serialize(&data)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SaveError> {
+        let bytes = std::fs::read(path)?;
+        let data = bincode::deserialize::<SceneData>(&bytes)?;
+        let mut terrain_map = TerrainMap::default();
+        for column in data.chunks {
+            let sections = column
+                .sections
+                .iter()
+                .map(|data| decompress(data))
+                .collect();
+            terrain_map
+                .chunks
+                .insert(column.pos, ChunkColumn::new(sections, column.y_offset));
+        }
+        let mut camera = data.camera;
+        // The projection is not stored
+        camera.set_fov(camera.fov());
+        Ok(Self {
+            terrain_map,
+            camera,
+            settings: data.settings,
+        })
+    }
+
+    /// Captures the current scene from the ECS resources.
+    pub fn from_ecs(world: &apecs::World) -> Self {
+        let terrain = world
+            .resource::<TerrainMap>()
+            .expect("Tried to fetch an invalid resource");
+        let camera = world
+            .resource::<Camera>()
+            .expect("Tried to fetch an invalid resource");
+        let settings = world
+            .resource::<GameplaySettings>()
+            .expect("Tried to fetch an invalid resource");
+
+        let terrain_map = TerrainMap {
+            chunks: terrain.chunks.clone(),
+            ..Default::default()
+        };
+        Self {
+            terrain_map,
+            camera: camera.clone(),
+            settings: settings.clone(),
+        }
+    }
+
+    /// Replaces the ECS resources with the ones stored in this scene.
+    pub fn into_ecs(self, world: &mut apecs::World) {
+        let terrain = world
+            .resource_mut::<TerrainMap>()
+            .expect("Tried to fetch an invalid resource");
+        *terrain = self.terrain_map;
+
+        let camera = world
+            .resource_mut::<Camera>()
+            .expect("Tried to fetch an invalid resource");
+        // The aspect ratio belongs to the current window, not to the save
+        camera.set_pos(self.camera.pos());
+        camera.set_rotation(self.camera.yaw(), self.camera.pitch());
+        camera.set_fov(self.camera.fov());
+
+        let settings = world
+            .resource_mut::<GameplaySettings>()
+            .expect("Tried to fetch an invalid resource");
+        *settings = self.settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        block::BlockId,
+        chunk::{ChunkColumn, StandardChunk},
+        resources::TerrainMap,
+    };
+    use vek::{Vec2, Vec3};
+
+    use crate::{camera::Camera, settings::GameplaySettings};
+
+    use super::VoxelScene;
+
+    #[test]
+    pub fn scene_round_trip() {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(3, 40, 7), BlockId::Stone);
+        let mut terrain_map = TerrainMap::default();
+        terrain_map.chunks.insert(Vec2::new(-2, 5), chunk.into());
+        let mut column = ChunkColumn::flat(BlockId::Air, 2);
+        column.set_block(300, 1, 2, BlockId::Dirt);
+        terrain_map.chunks.insert(Vec2::new(0, 0), column);
+
+        let mut camera = Camera::default();
+        camera.set_pos(Vec3::new(12.5, 80.0, -3.25));
+        camera.set_rotation(1.0, -0.5);
+        camera.set_fov(90.0);
+        let scene = VoxelScene {
+            terrain_map,
+            camera,
+            settings: GameplaySettings::default(),
+        };
+        let path = std::env::temp_dir().join("explora_scene_round_trip.bin");
+        scene.save(&path).unwrap();
+        let loaded = VoxelScene::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.camera.pos(), scene.camera.pos());
+        assert_eq!(loaded.camera.yaw(), scene.camera.yaw());
+        assert_eq!(loaded.camera.pitch(), scene.camera.pitch());
+        assert_eq!(loaded.camera.fov(), scene.camera.fov());
+        assert_eq!(
+            loaded.terrain_map.get_block(Vec3::new(-32 + 3, 40, 80 + 7)),
+            Some(BlockId::Stone)
+        );
+        assert_eq!(
+            loaded.terrain_map.get_block(Vec3::new(1, 300, 2)),
+            Some(BlockId::Dirt)
+        );
+    }
+}
@@ -1,13 +1,24 @@
-use common::{event::Events, resources::DeltaTime, SysResult};
+use std::path::Path;
+
+use common::{
+    ecs::TryWrite,
+    edit::VoxelEditHistory,
+    event::Events,
+    resources::{DeltaTime, Paused, TerrainMap, WorldTime},
+    SysResult,
+};
 
 use apecs::*;
 
 use crate::{
+    block::BlockMap,
     input::Input,
     render::{atlas::BlockAtlas, resources::TerrainRender, Renderer, Uniforms},
+    screenshot::{self, Screenshots},
     settings::GameplaySettings,
+    sky::{self, SkyColor},
 };
-use vek::Vec3;
+use vek::{Vec2, Vec3};
 
 use crate::{
     camera::Camera,
@@ -20,15 +31,84 @@ pub struct SceneSystem {
     camera: Write<Camera>,
     events: Read<Events<WindowEvent>>,
     delta: Read<DeltaTime>,
+    paused: Read<Paused>,
     globals: Write<Uniforms>,
     terrain_render_data: Write<TerrainRender>,
     window: Write<Window, NoDefault>,
     renderer: Write<Renderer, NoDefault>,
     input: Read<Input>,
-    block_atlas: Read<BlockAtlas, NoDefault>,
+    block_atlas: TryWrite<BlockAtlas>,
+    block_map: Read<BlockMap, NoDefault>,
     gameplay_settings: Read<GameplaySettings>,
+    world_time: Write<WorldTime>,
+    sky_color: Write<SkyColor>,
+    terrain: Write<TerrainMap>,
+    edit_history: Write<VoxelEditHistory>,
+    screenshots: Write<Screenshots>,
+}
+
+/// Applies the mouse settings to a cursor movement.
+fn cursor_delta(cursor: Vec2<f32>, settings: &GameplaySettings) -> Vec2<f32> {
+    if settings.mouse_invert_y {
+        Vec2::new(cursor.x, -cursor.y)
+    } else {
+        cursor
+    }
+}
+
+/// Moves the free camera `speed` blocks per second along `dir`, unless the game is paused.
+fn move_camera(
+    camera: &mut Camera,
+    dir: Vec3<f32>,
+    speed: f32,
+    delta: &DeltaTime,
+    paused: &Paused,
+) {
+    if paused.0 {
+        return;
+    }
+    let d = dir * speed * delta.0;
+    camera.move_by(d.x, d.y, d.z);
+}
+
+/// How far away in blocks the targeted block is looked up.
+pub const LOOK_DISTANCE: u32 = 16;
+
+/// World ticks each frame of the animated blocks is shown for.
+const TICKS_PER_ANIMATION_FRAME: u64 = 8;
+
+fn animation_frame(time: &WorldTime) -> u32 {
+    (time.ticks / TICKS_PER_ANIMATION_FRAME) as u32
 }
 
+/// Replaces the block textures with the ones of the texture pack at `path`.
+///
+/// The meshes keep the tile ids of the current atlas, so the pack must provide
+/// the same textures.
+fn load_texture_pack(renderer: &mut Renderer, block_atlas: &mut Option<BlockAtlas>, path: &Path) {
+    let atlas = match BlockAtlas::load_from_zip(path) {
+        Ok(atlas) => atlas,
+        Err(e) => {
+            log::error!("Failed to load texture pack: {}", e);
+            return;
+        },
+    };
+    if let Some(current) = block_atlas.as_ref() {
+        if current.tiles != atlas.tiles {
+            log::error!(
+                "The texture pack {} does not provide the same textures as the current one",
+                path.display()
+            );
+            return;
+        }
+    }
+    renderer.set_block_atlas(&atlas);
+    *block_atlas = Some(atlas);
+    log::info!("Loaded texture pack {}", path.display());
+}
+
+pub const SCENE_UPDATE_SYSTEM: &str = "scene_update";
+
 pub fn scene_update_system(mut scene: SceneSystem) -> SysResult {
     let dir = scene.input.move_direction();
 
@@ -37,7 +117,43 @@ pub fn scene_update_system(mut scene: SceneSystem) -> SysResult {
     }
 
     if scene.input.just_pressed(GameInput::ToggleWireframe) {
-        scene.terrain_render_data.wireframe = !scene.terrain_render_data.wireframe;
+        if scene.renderer.supports(wgpu::Features::POLYGON_MODE_LINE) {
+            scene.terrain_render_data.wireframe = !scene.terrain_render_data.wireframe;
+        } else {
+            log::warn!("Wireframe rendering is not supported by this device");
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    if scene.input.just_pressed(GameInput::DumpAtlas) {
+        let path = std::path::Path::new("atlas_debug.png");
+        match scene.block_atlas.inner().as_ref() {
+            Some(atlas) => match atlas.dump_to_png(path) {
+                Ok(()) => log::info!(
+                    "Dumped block atlas to `{}`\n{}",
+                    path.display(),
+                    crate::render::atlas::atlas_diagnostics(atlas)
+                ),
+                Err(e) => log::error!("Failed to dump block atlas: {}", e),
+            },
+            None => log::warn!("The block atlas is not loaded yet"),
+        }
+    }
+
+    if scene.input.just_pressed(GameInput::Screenshot) {
+        let path = screenshot::screenshot_path(std::time::SystemTime::now());
+        scene.screenshots.request(path);
+    }
+
+    // While paused the last frame keeps being rendered, but nothing moves
+    let paused = scene.paused.0;
+
+    if !paused {
+        if scene.input.just_pressed(GameInput::Undo) {
+            scene.edit_history.undo(&mut scene.terrain);
+        } else if scene.input.just_pressed(GameInput::Redo) {
+            scene.edit_history.redo(&mut scene.terrain);
+        }
     }
 
     for event in &scene.events.events {
@@ -45,33 +161,167 @@ pub fn scene_update_system(mut scene: SceneSystem) -> SysResult {
             WindowEvent::Resize(size) => {
                 scene.camera.set_aspect_ratio(size.x as f32 / size.y as f32);
             },
-            WindowEvent::CursorMove(cursor) => {
-                if scene.window.cursor_locked() {
-                    // HACK: This is a hack to prevent the camera from moving around
-                    // when the cursor is locked.
-                    scene.camera.rotate_by(cursor.x * 0.005, cursor.y * 0.005);
+            WindowEvent::CursorMove(cursor) if !paused && scene.window.cursor_locked() => {
+                // HACK: This is a hack to prevent the camera from moving around
+                // when the cursor is locked.
+                let delta = cursor_delta(*cursor, &scene.gameplay_settings);
+                scene.camera.rotate_by(delta.x * 0.005, delta.y * 0.005);
+            },
+            WindowEvent::FileDrop(path) => {
+                if path.extension().is_some_and(|ext| ext == "zip") {
+                    load_texture_pack(&mut scene.renderer, scene.block_atlas.inner_mut(), path);
+                } else {
+                    log::warn!("Only ZIP texture packs can be dropped: {}", path.display());
                 }
             },
             _ => {},
         }
     }
-    let dx = dir.x * scene.gameplay_settings.free_camera_speed * scene.delta.0;
-    let dy = dir.y * scene.gameplay_settings.free_camera_speed * scene.delta.0;
-    let dz = dir.z * scene.gameplay_settings.free_camera_speed * scene.delta.0;
+    let speed = scene.gameplay_settings.free_camera_speed;
+    move_camera(&mut scene.camera, dir, speed, &scene.delta, &scene.paused);
 
-    scene.camera.move_by(dx, dy, dz);
+    // A ray starting inside a block has no face to highlight
+    let target = scene
+        .terrain
+        .ray_to_block(scene.camera.pos(), scene.camera.forward(), LOOK_DISTANCE)
+        .filter(|(_, face)| *face != Vec3::zero());
+    if let Some((block_pos, face)) = target {
+        scene.renderer.highlight_block_face(
+            TerrainMap::chunk_pos(block_pos),
+            TerrainMap::local_pos(block_pos),
+            face,
+        );
+    }
     let matrices = scene.camera.compute_matrices();
-    let sun_pos = Vec3::new(15.0, 300.0, 15.0);
 
-    let new_globals = Uniforms::new(
+    if !paused {
+        scene.world_time.tick();
+    }
+    let (zenith, horizon) = SkyColor::compute(&scene.world_time);
+    *scene.sky_color = SkyColor { zenith, horizon };
+    let sun_pos = sky::sun_position(&scene.world_time, Vec3::new(8.0, 0.0, 8.0), 300.0);
+    let sky = if scene.gameplay_settings.dynamic_sky {
+        scene.sky_color.horizon
+    } else {
+        Vec3::from(scene.gameplay_settings.sky_color)
+    };
+    scene.renderer.set_clear_color(wgpu::Color {
+        r: sky.x as f64,
+        g: sky.y as f64,
+        b: sky.z as f64,
+        a: 1.0,
+    });
+
+    // Without an atlas there is no terrain to texture yet
+    let (atlas_size, tile_size, padding, animated_tile_start, reflective_tile) = scene
+        .block_atlas
+        .inner()
+        .as_ref()
+        .map_or((0, Vec2::zero(), 0, u32::MAX, u32::MAX), |atlas| {
+            (
+                atlas.atlas_size,
+                atlas.tile_size,
+                atlas.padding,
+                atlas.animated_tile_start as u32,
+                scene
+                    .block_map
+                    .reflective_tile(atlas)
+                    .map_or(u32::MAX, u32::from),
+            )
+        });
+    let mut new_globals = Uniforms::new(
         matrices.view,
         matrices.proj,
         sun_pos,
         scene.globals.enable_lighting,
-        scene.block_atlas.atlas_size,
-        scene.block_atlas.tile_size,
+        atlas_size,
+        tile_size,
+        padding,
     );
+    new_globals.current_frame = animation_frame(&scene.world_time);
+    new_globals.animated_tile_start = animated_tile_start;
+    new_globals.reflective_tile = reflective_tile;
     *scene.globals = new_globals;
     scene.renderer.write_uniforms(*scene.globals);
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::{
+        event::Events,
+        resources::{DeltaTime, Paused},
+        state::State,
+    };
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        camera::Camera,
+        input::{self, Input},
+        settings::GameplaySettings,
+        window::WindowEvent,
+    };
+
+    use super::{cursor_delta, move_camera};
+
+    fn pitch_after_moving_down(invert: bool) -> f32 {
+        let settings = GameplaySettings {
+            mouse_invert_y: invert,
+            ..Default::default()
+        };
+        let mut camera = Camera::default();
+        let before = camera.forward().y;
+        let delta = cursor_delta(Vec2::new(0.0, 10.0), &settings);
+        camera.rotate_by(delta.x * 0.005, delta.y * 0.005);
+        camera.forward().y - before
+    }
+
+    #[test]
+    pub fn invert_y_flips_vertical_rotation() {
+        // Cursor Y grows downwards, so moving the mouse down looks down
+        assert!(pitch_after_moving_down(false) < 0.0);
+        assert!(pitch_after_moving_down(true) > 0.0);
+    }
+
+    #[test]
+    pub fn losing_focus_freezes_the_game() {
+        let mut state = State::client().unwrap();
+        state
+            .ecs_mut()
+            .with_default_resource::<Input>()
+            .unwrap()
+            .with_system(input::INPUT_SYSTEM, input::input_system)
+            .unwrap();
+        state.with_event::<WindowEvent>("window_event");
+        state.tick(Duration::from_millis(16));
+
+        state
+            .resource_mut::<Events<WindowEvent>>()
+            .send(WindowEvent::Focused(false));
+        state.tick(Duration::from_millis(16));
+        assert_eq!(*state.resource::<Paused>(), Paused(true));
+
+        state.tick(Duration::from_millis(500));
+        let frame = Duration::from_millis(16).as_secs_f32();
+        assert_eq!(state.resource::<DeltaTime>().0, frame);
+
+        let mut camera = Camera::default();
+        let before = camera.pos();
+        move_camera(
+            &mut camera,
+            Vec3::unit_z(),
+            10.0,
+            state.resource::<DeltaTime>(),
+            state.resource::<Paused>(),
+        );
+        assert_eq!(camera.pos(), before);
+
+        state
+            .resource_mut::<Events<WindowEvent>>()
+            .send(WindowEvent::Focused(true));
+        state.tick(Duration::from_millis(16));
+        assert_eq!(*state.resource::<Paused>(), Paused(false));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use apecs::{ok, Read, Write};
+use common::{event::Events, resources::ProgramTime, SysResult};
+use image::RgbaImage;
+
+use crate::ui::toast::Toasts;
+
+/// Sent once a screenshot has been written to disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenshotSavedEvent {
+    pub path: PathBuf,
+}
+
+/// Writes screenshots to disk on background threads, so encoding them doesn't stall a frame.
+#[derive(Debug, Default)]
+pub struct Screenshots {
+    /// The paths of the screenshots written since the last frame.
+    saved: Arc<Mutex<Vec<PathBuf>>>,
+    /// Where to save the next frame, which is only captured when requested.
+    requested: Option<PathBuf>,
+}
+
+impl Screenshots {
+    /// Captures the next rendered frame and saves it at `path`.
+    pub fn request(&mut self, path: PathBuf) {
+        self.requested = Some(path);
+    }
+
+    /// The path of the requested screenshot, if the frame being rendered must be captured.
+    pub fn take_request(&mut self) -> Option<PathBuf> {
+        self.requested.take()
+    }
+
+    /// Encodes `image` as a PNG at `path` on a new thread.
+    pub fn save(&self, image: RgbaImage, path: PathBuf) {
+        if image.width() == 0 || image.height() == 0 {
+            log::warn!("Nothing was captured, skipping screenshot");
+            return;
+        }
+        let saved = Arc::clone(&self.saved);
+        std::thread::spawn(move || {
+            let (width, height) = image.dimensions();
+            match image::save_buffer(&path, &image, width, height, image::ColorType::Rgba8) {
+                Ok(()) => saved
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(path),
+                Err(e) => log::error!("Failed to save screenshot `{}`: {}", path.display(), e),
+            }
+        });
+    }
+
+    /// Takes the paths of the screenshots written so far.
+    fn take_saved(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut *self.saved.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Where a screenshot taken at `time` is saved: the user's pictures folder if there is one,
+/// otherwise the working directory.
+pub fn screenshot_path(time: SystemTime) -> PathBuf {
+    let timestamp = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    dirs::picture_dir()
+        .unwrap_or_default()
+        .join(format!("voxel_{}.png", timestamp))
+}
+
+/// Sends a [`ScreenshotSavedEvent`] for every screenshot written to disk, and shows
+/// where it was saved.
+pub fn screenshot_saved_system(
+    (screenshots, mut events, mut toasts, time): (
+        Read<Screenshots>,
+        Write<Events<ScreenshotSavedEvent>>,
+        Write<Toasts>,
+        Read<ProgramTime>,
+    ),
+) -> SysResult {
+    for path in screenshots.take_saved() {
+        log::info!("Saved screenshot to `{}`", path.display());
+        toasts.push(format!("Saved screenshot to {}", path.display()), time.0);
+        events.send(ScreenshotSavedEvent { path });
+    }
+    ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use common::{event::Events, state::State};
+    use image::RgbaImage;
+
+    use super::{screenshot_path, screenshot_saved_system, ScreenshotSavedEvent, Screenshots};
+
+    #[test]
+    pub fn frames_are_only_captured_on_request() {
+        let mut screenshots = Screenshots::default();
+        assert_eq!(screenshots.take_request(), None);
+        screenshots.request(PathBuf::from("a.png"));
+        // Only the last request of a frame is kept
+        screenshots.request(PathBuf::from("b.png"));
+        assert_eq!(screenshots.take_request(), Some(PathBuf::from("b.png")));
+        assert_eq!(screenshots.take_request(), None);
+    }
+
+    #[test]
+    pub fn saved_screenshots_send_an_event() {
+        let mut state = State::client().unwrap();
+        state
+            .ecs_mut()
+            .with_default_resource::<Screenshots>()
+            .unwrap()
+            // Registered without its update system so the events outlive the tick
+            .with_default_resource::<Events<ScreenshotSavedEvent>>()
+            .unwrap()
+            .with_system("screenshot_saved", screenshot_saved_system)
+            .unwrap();
+
+        let name = screenshot_path(SystemTime::now());
+        let path = std::env::temp_dir().join(name.file_name().unwrap());
+        state
+            .resource::<Screenshots>()
+            .save(RgbaImage::new(4, 4), path.clone());
+
+        let start = Instant::now();
+        while state
+            .resource::<Events<ScreenshotSavedEvent>>()
+            .events
+            .is_empty()
+        {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Screenshot not saved"
+            );
+            state.tick(Duration::from_millis(16));
+        }
+        let event = &state.resource::<Events<ScreenshotSavedEvent>>().events[0];
+        assert!(!event.path.to_string_lossy().is_empty());
+        assert_eq!(event.path, path);
+        assert_eq!(image::open(&path).unwrap().width(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
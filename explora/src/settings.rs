@@ -1,6 +1,120 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Where the settings are saved, relative to the working directory.
+pub const SETTINGS_PATH: &str = "settings.toml";
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<toml::ser::Error> for SettingsError {
+    fn from(value: toml::ser::Error) -> Self {
+        Self::Serialize(value)
+    }
+}
+
+/// How the edges of the world are smoothed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AntiAliasingMode {
+    #[default]
+    None,
+    Msaa2x,
+    Msaa4x,
+    Msaa8x,
+    /// A post-processing pass, cheaper than multisampling complex scenes.
+    Fxaa,
+}
+
+impl AntiAliasingMode {
+    /// Samples per pixel the world is drawn with.
+    pub const fn sample_count(self) -> u32 {
+        match self {
+            AntiAliasingMode::None | AntiAliasingMode::Fxaa => 1,
+            AntiAliasingMode::Msaa2x => 2,
+            AntiAliasingMode::Msaa4x => 4,
+            AntiAliasingMode::Msaa8x => 8,
+        }
+    }
+
+    /// Whether the world is anti-aliased after it is drawn.
+    pub const fn is_post_process(self) -> bool {
+        matches!(self, AntiAliasingMode::Fxaa)
+    }
+
+    /// The multisampling mode drawing the world with `samples` samples per pixel.
+    pub const fn from_sample_count(samples: u32) -> Self {
+        match samples {
+            0 | 1 => AntiAliasingMode::None,
+            2 | 3 => AntiAliasingMode::Msaa2x,
+            4..=7 => AntiAliasingMode::Msaa4x,
+            _ => AntiAliasingMode::Msaa8x,
+        }
+    }
+
+    /// This mode with at most `samples` samples per pixel, for devices that can't
+    /// multisample as much as requested. FXAA and None are kept as they are.
+    pub const fn with_sample_count(self, samples: u32) -> Self {
+        if self.sample_count() > samples {
+            Self::from_sample_count(samples)
+        } else {
+            self
+        }
+    }
+}
+
+/// Reads the anti-aliasing either as a mode or as the sample count of the old
+/// `msaa_samples` setting. Only settings files can be that old, binary saves
+/// always hold a mode.
+fn deserialize_anti_aliasing<'de, D>(deserializer: D) -> Result<AntiAliasingMode, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return AntiAliasingMode::deserialize(deserializer);
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Mode(AntiAliasingMode),
+        Samples(u32),
+    }
+    Ok(match Setting::deserialize(deserializer)? {
+        Setting::Mode(mode) => mode,
+        Setting::Samples(samples) => AntiAliasingMode::from_sample_count(samples),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GameplaySettings {
     pub mouse_sensitivity: u32,
+    /// Moving the mouse up makes the camera look down.
+    pub mouse_invert_y: bool,
     pub free_camera_speed: f32,
+    /// Fill the depth buffer before shading the terrain to reduce overdraw.
+    pub depth_prepass_enabled: bool,
+    /// Follow the time of day for the sky instead of using `sky_color`.
+    pub dynamic_sky: bool,
+    pub sky_color: [f32; 3],
+    /// Only read when the game starts.
+    #[serde(alias = "msaa_samples", deserialize_with = "deserialize_anti_aliasing")]
+    pub anti_aliasing: AntiAliasingMode,
+    /// Blur what is out of focus, based on the distance to the camera.
+    pub depth_of_field_enabled: bool,
+    /// Distance in blocks from the camera that stays sharp with the depth of field.
+    pub dof_focus_distance: f32,
+    /// Draw a cross in the middle of the screen.
+    pub show_crosshair: bool,
 }
 
 impl Default for GameplaySettings {
@@ -8,7 +122,107 @@ impl Default for GameplaySettings {
         Self {
             // 100% means default sensitivity
             mouse_sensitivity: 100,
+            mouse_invert_y: false,
             free_camera_speed: 50.0,
+            depth_prepass_enabled: false,
+            dynamic_sky: true,
+            sky_color: [0.1, 0.2, 0.3],
+            anti_aliasing: AntiAliasingMode::None,
+            depth_of_field_enabled: false,
+            dof_focus_distance: 32.0,
+            show_crosshair: true,
         }
     }
 }
+
+impl GameplaySettings {
+    /// Loads the settings from a toml file.
+    ///
+    /// Missing settings keep their default value, and the defaults are used if
+    /// the file can't be read.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let Ok(file) = std::fs::read_to_string(path) else {
+            log::info!("No settings found at `{}`, using defaults", path.display());
+            return Self::default();
+        };
+        toml::from_str(&file).unwrap_or_else(|e| {
+            log::error!("Failed to parse `{}`: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SettingsError> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AntiAliasingMode, GameplaySettings};
+
+    #[test]
+    pub fn save_round_trip() {
+        let path = std::env::temp_dir().join("explora_settings_round_trip.toml");
+        let settings = GameplaySettings {
+            mouse_invert_y: true,
+            free_camera_speed: 12.0,
+            ..Default::default()
+        };
+        settings.save(&path).unwrap();
+        let loaded = GameplaySettings::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.mouse_invert_y);
+        assert_eq!(loaded.free_camera_speed, 12.0);
+    }
+
+    #[test]
+    pub fn unknown_settings_are_ignored() {
+        // Written by a newer version of the game
+        let path = std::env::temp_dir().join("explora_settings_unknown_field.toml");
+        std::fs::write(&path, "mouse_sensitivity = 50\nfuture_setting = true\n").unwrap();
+        let settings = GameplaySettings::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(settings.mouse_sensitivity, 50);
+    }
+
+    #[test]
+    pub fn missing_settings_use_defaults() {
+        let settings: GameplaySettings = toml::from_str("mouse_sensitivity = 50").unwrap();
+        assert_eq!(settings.mouse_sensitivity, 50);
+        assert!(!settings.mouse_invert_y);
+    }
+
+    #[test]
+    pub fn anti_aliasing_falls_back_to_fewer_samples() {
+        assert_eq!(
+            AntiAliasingMode::Msaa8x.with_sample_count(4),
+            AntiAliasingMode::Msaa4x
+        );
+        assert_eq!(
+            AntiAliasingMode::Msaa2x.with_sample_count(1),
+            AntiAliasingMode::None
+        );
+        assert_eq!(
+            AntiAliasingMode::Msaa4x.with_sample_count(8),
+            AntiAliasingMode::Msaa4x
+        );
+        // FXAA replaces multisampling instead of adding to it
+        assert_eq!(
+            AntiAliasingMode::Fxaa.with_sample_count(1),
+            AntiAliasingMode::Fxaa
+        );
+    }
+
+    #[test]
+    pub fn anti_aliasing_is_read_from_old_settings() {
+        let settings: GameplaySettings = toml::from_str("anti_aliasing = \"Fxaa\"").unwrap();
+        assert_eq!(settings.anti_aliasing, AntiAliasingMode::Fxaa);
+        // Saved before the setting was renamed
+        let settings: GameplaySettings = toml::from_str("msaa_samples = 4").unwrap();
+        assert_eq!(settings.anti_aliasing, AntiAliasingMode::Msaa4x);
+        let settings: GameplaySettings = toml::from_str("msaa_samples = 1").unwrap();
+        assert_eq!(settings.anti_aliasing, AntiAliasingMode::None);
+    }
+}
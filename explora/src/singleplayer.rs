@@ -1,16 +1,55 @@
-use common::clock::Clock;
+use common::{resources::Weather, state::State};
 
 use std::{net::SocketAddr, sync::mpsc};
 
-use server::{config::ServerConfig, Server};
+use server::{
+    command::Command,
+    config::ServerConfig,
+    tick::{TickScheduler, TICK_DURATION},
+    Server,
+};
+
+use crate::render::resources::TerrainRender;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    Empty,
+    UnknownCommand(String),
+    InvalidArgument(String),
+    /// The server thread is not running anymore.
+    ServerStopped,
+}
+
+/// Parses a cheat command such as `/time set 6000`, `/weather rain`, `/stats` or `/defrag`.
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let mut tokens = line.trim().trim_start_matches('/').split_whitespace();
+    let name = tokens.next().ok_or(CommandError::Empty)?;
+    let args: Vec<&str> = tokens.collect();
+    match (name, args.as_slice()) {
+        ("time", ["set", ticks]) => ticks
+            .parse()
+            .map(Command::SetTime)
+            .map_err(|_| CommandError::InvalidArgument(ticks.to_string())),
+        ("weather", [weather]) => match weather.to_lowercase().as_str() {
+            "clear" => Ok(Command::SetWeather(Weather::Clear)),
+            "rain" => Ok(Command::SetWeather(Weather::Rain)),
+            "snow" => Ok(Command::SetWeather(Weather::Snow)),
+            _ => Err(CommandError::InvalidArgument(weather.to_string())),
+        },
+        ("stats", []) => Ok(Command::Stats),
+        ("defrag", []) => Ok(Command::Defragment),
+        _ => Err(CommandError::UnknownCommand(line.trim().to_string())),
+    }
+}
 
 pub struct Singleplayer {
     init_receiver: mpsc::Receiver<SocketAddr>,
+    commands: mpsc::Sender<Command>,
 }
-
 impl Singleplayer {
     pub fn init() -> Self {
         let (tx, rx) = mpsc::channel();
+        let (command_tx, command_rx) = mpsc::channel();
         std::thread::spawn(move || {
             let config = ServerConfig::toml();
             let addr = format!("{}:{}", config.host, config.port)
@@ -21,7 +60,7 @@ impl Singleplayer {
                     if let Err(e) = tx.send(addr) {
                         log::error!("{:?}", e);
                     }
-                    self::run_singleplayer_server(server);
+                    self::run_singleplayer_server(server, command_rx);
                 },
                 Err(_) => {
                     panic!("Failed to initialize singleplayer server.");
@@ -29,7 +68,10 @@ impl Singleplayer {
             };
         });
 
-        Self { init_receiver: rx }
+        Self {
+            init_receiver: rx,
+            commands: command_tx,
+        }
     }
 
     pub fn wait_for_init(&self) -> SocketAddr {
@@ -37,13 +79,96 @@ impl Singleplayer {
             .recv()
             .expect("Failed to send initialization message")
     }
+
+    /// Parses `line` and sends the command to the server, which runs it on its next tick.
+    ///
+    /// `/defrag` is run on the client `state` instead, on its next frame.
+    pub fn send_command(&self, state: &mut State, line: &str) -> Result<(), CommandError> {
+        let command = parse_command(line)?;
+        if command == Command::Defragment {
+            state.resource_mut::<TerrainRender>().defragment_requested = true;
+            return Ok(());
+        }
+        self.commands
+            .send(command)
+            .map_err(|_| CommandError::ServerStopped)
+    }
+
+    /// Stops or resumes ticking the server, so it doesn't keep running while the game is paused.
+    pub fn set_paused(&self, paused: bool) -> Result<(), CommandError> {
+        self.commands
+            .send(Command::SetPaused(paused))
+            .map_err(|_| CommandError::ServerStopped)
+    }
 }
 
-pub fn run_singleplayer_server(mut server: Server) {
+pub fn run_singleplayer_server(mut server: Server, commands: mpsc::Receiver<Command>) {
     log::info!("Starting singleplayer server...");
-    let mut clock = Clock::default();
+    let mut scheduler = TickScheduler::new();
     loop {
-        clock.tick();
-        server.tick(clock.dt());
+        for command in commands.try_iter() {
+            server.execute(command);
+        }
+        while scheduler.consume_tick() {
+            server.tick(TICK_DURATION);
+        }
+        std::thread::sleep(scheduler.time_until_next_tick());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        event::{Events, WeatherChangedEvent},
+        resources::{CurrentWeather, Weather, WorldTime},
+        state::State,
+    };
+    use server::command::{self, Command};
+
+    use super::{parse_command, CommandError};
+
+    #[test]
+    pub fn parses_commands() {
+        assert_eq!(parse_command("/time set 6000"), Ok(Command::SetTime(6000)));
+        assert_eq!(parse_command("/stats"), Ok(Command::Stats));
+        assert_eq!(parse_command("/defrag"), Ok(Command::Defragment));
+        assert_eq!(
+            parse_command("/weather Rain"),
+            Ok(Command::SetWeather(Weather::Rain))
+        );
+        assert_eq!(
+            parse_command("/time set noon"),
+            Err(CommandError::InvalidArgument("noon".to_string()))
+        );
+        assert!(matches!(
+            parse_command("/fly"),
+            Err(CommandError::UnknownCommand(_))
+        ));
+        assert_eq!(parse_command("  "), Err(CommandError::Empty));
+    }
+
+    #[test]
+    pub fn time_set_zero_resets_ticks() {
+        let mut state = State::server().unwrap();
+        state
+            .ecs_mut()
+            .with_resource(WorldTime {
+                ticks: 1234,
+                ..Default::default()
+            })
+            .unwrap()
+            .with_default_resource::<CurrentWeather>()
+            .unwrap();
+        state.with_event::<WeatherChangedEvent>("weather_changed_events");
+
+        command::execute(&mut state, parse_command("/time set 0").unwrap());
+        assert_eq!(state.resource::<WorldTime>().ticks, 0);
+
+        command::execute(&mut state, parse_command("/weather snow").unwrap());
+        assert_eq!(state.resource::<CurrentWeather>().0, Weather::Snow);
+        assert_eq!(
+            state.resource::<Events<WeatherChangedEvent>>().events.len(),
+            1
+        );
     }
 }
@@ -0,0 +1,93 @@
+use common::resources::WorldTime;
+use vek::Vec3;
+
+/// A sky colour keyframe: the time of day it belongs to, its zenith colour and its horizon colour.
+type Keyframe = (f32, Vec3<f32>, Vec3<f32>);
+
+const KEYFRAMES: [Keyframe; 4] = [
+    // Midnight
+    (
+        0.0,
+        Vec3::new(0.01, 0.01, 0.03),
+        Vec3::new(0.03, 0.03, 0.08),
+    ),
+    // Dawn
+    (
+        0.25,
+        Vec3::new(0.35, 0.45, 0.7),
+        Vec3::new(0.95, 0.55, 0.35),
+    ),
+    // Noon
+    (0.5, Vec3::new(0.3, 0.55, 0.95), Vec3::new(0.65, 0.8, 0.95)),
+    // Dusk
+    (0.75, Vec3::new(0.3, 0.3, 0.55), Vec3::new(0.9, 0.45, 0.3)),
+];
+
+/// The colours of the sky for the current time of day.
+#[derive(Debug, Clone, Copy)]
+pub struct SkyColor {
+    pub zenith: Vec3<f32>,
+    pub horizon: Vec3<f32>,
+}
+
+impl Default for SkyColor {
+    fn default() -> Self {
+        let (zenith, horizon) = Self::compute(&WorldTime::default());
+        Self { zenith, horizon }
+    }
+}
+
+impl SkyColor {
+    /// Returns the `(zenith, horizon)` colours by blending the two closest keyframes.
+    pub fn compute(time: &WorldTime) -> (Vec3<f32>, Vec3<f32>) {
+        let day = time.day_fraction();
+        let current = KEYFRAMES
+            .iter()
+            .rposition(|(start, ..)| *start <= day)
+            .unwrap_or(0);
+        let (start, zenith_a, horizon_a) = KEYFRAMES[current];
+        let (end, zenith_b, horizon_b) = match KEYFRAMES.get(current + 1) {
+            Some(next) => *next,
+            // Wrap around to midnight
+            None => (1.0, KEYFRAMES[0].1, KEYFRAMES[0].2),
+        };
+        let t = (day - start) / (end - start);
+        // Cosine blending eases in and out of each keyframe
+        let t = (1.0 - (t * std::f32::consts::PI).cos()) / 2.0;
+        (
+            zenith_a + (zenith_b - zenith_a) * t,
+            horizon_a + (horizon_b - horizon_a) * t,
+        )
+    }
+}
+
+/// Returns the position of the sun orbiting around `centre`.
+///
+/// The sun is at its highest point at noon and below the world at midnight.
+pub fn sun_position(time: &WorldTime, centre: Vec3<f32>, radius: f32) -> Vec3<f32> {
+    let angle = (time.day_fraction() - 0.25) * std::f32::consts::TAU;
+    centre + Vec3::new(angle.cos(), angle.sin(), 0.0) * radius
+}
+
+#[cfg(test)]
+mod tests {
+    use common::resources::WorldTime;
+
+    use super::SkyColor;
+
+    #[test]
+    pub fn midnight_sky_is_dark() {
+        let time = WorldTime::default();
+        let (zenith, _) = SkyColor::compute(&time);
+        assert!(zenith.x.max(zenith.y).max(zenith.z) < 0.05);
+    }
+
+    #[test]
+    pub fn noon_sky_is_blue() {
+        let mut time = WorldTime::default();
+        time.ticks = time.ticks_per_day / 2;
+        let (zenith, _) = SkyColor::compute(&time);
+        assert!(zenith.z > 0.9);
+        assert!(zenith.z > zenith.x && zenith.z > zenith.y);
+    }
+}
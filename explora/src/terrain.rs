@@ -1,5 +1,8 @@
+use std::collections::HashSet;
+
 use common::{
-    resources::{TerrainConfig, TerrainMap},
+    ecs::TryRead,
+    resources::{Paused, TerrainConfig, TerrainMap},
     SysResult,
 };
 
@@ -11,46 +14,116 @@ use crate::{
 use apecs::*;
 use vek::Vec2;
 
-use crate::{block::BlockMap, mesh};
+use crate::{
+    block::BlockMap,
+    mesh::{BorderBlocks, ChunkMeshCache, MeshBuilder},
+};
 
 #[derive(CanFetch)]
 pub struct TerrainSystem {
     renderer: Write<Renderer, NoDefault>,
     terrain_map: Write<TerrainMap>,
     block_map: Read<BlockMap, NoDefault>,
-    atlas: Read<BlockAtlas, NoDefault>,
+    atlas: TryRead<BlockAtlas>,
     terrain_render_data: Write<TerrainRender, NoDefault>,
+    mesh_cache: Write<ChunkMeshCache>,
+    paused: Read<Paused>,
 }
 
 pub const TERRAIN_CHUNK_MESH_SYSTEM: &str = "terrain_chunk_mesh";
 
 pub fn terrain_chunk_mesh(mut system: TerrainSystem) -> SysResult {
+    if system.paused.0 {
+        return ok();
+    }
+    // Nothing can be meshed until the textures are loaded
+    let Some(atlas) = system.atlas.inner().as_ref() else {
+        return ok();
+    };
     let blocks = system.block_map.inner();
+    // Chunks that changed since they were meshed, e.g. after being re-lit
+    let mut dirty = std::mem::take(&mut system.terrain_map.dirty);
 
     let terrain = system.terrain_map.inner();
 
-    for (pos, chunk) in terrain.chunks.iter() {
-        let neighbors = [
-            terrain.chunks.get(&(pos + Vec2::new(0, 1))),
-            terrain.chunks.get(&(pos + Vec2::new(1, 0))),
-            terrain.chunks.get(&(pos + Vec2::new(0, -1))),
-            terrain.chunks.get(&(pos + Vec2::new(-1, 0))),
-        ];
-        if neighbors.iter().any(|n| n.is_none()) {
+    let mut meshed = HashSet::new();
+    for (pos, column) in terrain.chunks.iter() {
+        let is_dirty = dirty.remove(pos);
+        let Some(chunk) = column.ground() else {
             continue;
+        };
+        if is_dirty {
+            system.mesh_cache.invalidate(*pos);
         }
-        if system.terrain_render_data.chunks.get(pos).is_none() {
-            let vertices =
-                mesh::create_chunk_mesh(chunk, *pos, &system.terrain_map, blocks, &system.atlas);
-            let buffer = system.renderer.create_vertex_buffer(&vertices);
+        let is_new = !system.terrain_render_data.chunks.contains_key(pos);
+        if is_dirty || is_new {
+            let vertices = system.mesh_cache.get_or_insert_with(*pos, || {
+                let mut builder = MeshBuilder::new(chunk, blocks, atlas)
+                    .with_neighbors(BorderBlocks::from_terrain(terrain, *pos))
+                    .with_ao(true);
+                if let Some(lights) = terrain.lights.get(pos) {
+                    builder = builder.with_light(lights);
+                }
+                let (vertices, _) = builder.build();
+                vertices
+            });
+            let buffer = system.renderer.create_vertex_buffer(vertices);
             let chunk_pos = ChunkPos::new(pos.x, pos.y);
             let terrain_mesh = system.renderer.create_terrain_chunk_mesh(chunk_pos, buffer);
-            system.terrain_render_data.chunks.insert(*pos, terrain_mesh);
+            system.terrain_render_data.insert_chunk(*pos, terrain_mesh);
         }
+        if is_new {
+            meshed.insert(*pos);
+        }
+    }
+    // Re-mesh the neighbors of new chunks on the next frame
+    dirty.extend(stale_neighbors(terrain, &meshed));
+    system.terrain_map.dirty.extend(dirty);
+
+    if system.terrain_render_data.should_defragment() {
+        system.terrain_render_data.defragment(&system.renderer);
     }
     ok()
 }
 
+/// The loaded chunks next to `new_chunks` that were meshed before them.
+///
+/// Their border faces towards the new chunks were culled while the new chunks
+/// were missing, so they must be meshed again.
+pub fn stale_neighbors<'a>(
+    terrain: &'a TerrainMap,
+    new_chunks: &'a HashSet<Vec2<i32>>,
+) -> impl Iterator<Item = Vec2<i32>> + 'a {
+    new_chunks
+        .iter()
+        .flat_map(|pos| {
+            [
+                Vec2::new(0, 1),
+                Vec2::new(1, 0),
+                Vec2::new(0, -1),
+                Vec2::new(-1, 0),
+            ]
+            .map(|offset| pos + offset)
+        })
+        .filter(|neighbor| !new_chunks.contains(neighbor) && terrain.chunks.contains_key(neighbor))
+}
+
+/// Recreates the GPU buffers of every cached chunk mesh, e.g. after the renderer was recreated.
+///
+/// Only the chunks whose blocks changed since they were cached are still dirty and meshed again.
+pub fn restore_chunk_meshes(
+    renderer: &mut Renderer,
+    cache: &ChunkMeshCache,
+    terrain_render: &mut TerrainRender,
+) {
+    terrain_render.clear();
+    for (pos, vertices) in cache.iter() {
+        let buffer = renderer.create_vertex_buffer(vertices);
+        let terrain_mesh = renderer.create_terrain_chunk_mesh(ChunkPos::new(pos.x, pos.y), buffer);
+        terrain_render.insert_chunk(*pos, terrain_mesh);
+    }
+}
+
 pub const CHUNK_LOAD_SYSTEM: &str = "chunk_load";
 
 #[derive(CanFetch)]
@@ -59,9 +132,14 @@ pub struct ChunkLoadSystem {
     camera: Read<Camera>,
     terrain_render: Write<TerrainRender>,
     terrain_config: Read<TerrainConfig>,
+    mesh_cache: Write<ChunkMeshCache>,
+    paused: Read<Paused>,
 }
 
 pub fn chunk_load_system(mut system: ChunkLoadSystem) -> apecs::anyhow::Result<ShouldContinue> {
+    if system.paused.0 {
+        return ok();
+    }
     let camera_pos = system.camera.pos();
 
     let chunk_radius = system.terrain_config.visible_chunk_radius as i32;
@@ -86,20 +164,81 @@ pub fn chunk_load_system(mut system: ChunkLoadSystem) -> apecs::anyhow::Result<S
     for chunk_pos in chunks_to_remove {
         system.terrain.pending_chunks.remove(&chunk_pos);
         system.terrain.chunks.remove(&chunk_pos);
-        system.terrain_render.chunks.remove(&chunk_pos);
+        system.terrain.lights.remove(&chunk_pos);
+        system.terrain.dirty.remove(&chunk_pos);
+        system.terrain_render.remove_chunk(chunk_pos);
+        system.mesh_cache.invalidate(chunk_pos);
     }
 
     // load chunks
-    for dx in -chunk_radius..=chunk_radius {
-        for dz in -chunk_radius..=chunk_radius {
-            let pos = Vec2::new(player_chunk_pos.x + dx, player_chunk_pos.y + dz);
-            if !system.terrain.chunks.contains_key(&pos)
-                && !system.terrain.pending_chunks.contains(&pos)
-                && !system.terrain_render.chunks.contains_key(&pos)
-            {
-                system.terrain.pending_chunks.insert(pos);
-            }
+    for pos in TerrainMap::columns_in_radius(player_chunk_pos, chunk_radius as u32) {
+        if !system.terrain.chunks.contains_key(&pos)
+            && !system.terrain.pending_chunks.contains(&pos)
+            && !system.terrain_render.chunks.contains_key(&pos)
+        {
+            system.terrain.pending_chunks.insert(pos);
         }
     }
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use common::{block::BlockId, chunk::StandardChunk, dir::Direction, resources::TerrainMap};
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::BlockMap,
+        mesh::{BorderBlocks, MeshBuilder, Quad},
+        render::atlas::BlockAtlas,
+    };
+
+    use super::stale_neighbors;
+
+    /// A layer of stone covering the half of the chunk where `covered` is true.
+    fn half_layer(covered: impl Fn(i32) -> bool) -> StandardChunk {
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        for x in 0..16 {
+            for z in (0..16).filter(|z| covered(*z)) {
+                chunk.set(Vec3::new(x, 0, z), BlockId::Stone);
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    pub fn loading_a_chunk_remeshes_its_neighbors() {
+        let block_map = BlockMap::load_blocks(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/blocks"),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/textures/blocks"),
+        );
+        let atlas = BlockAtlas::create(block_map.textures()).unwrap();
+        let mesh = |terrain: &TerrainMap, pos: Vec2<i32>| -> Vec<Quad> {
+            MeshBuilder::new(terrain.chunks[&pos].ground().unwrap(), &block_map, &atlas)
+                .with_neighbors(BorderBlocks::from_terrain(terrain, pos))
+                .build_quads()
+        };
+        let faces = |quads: &[Quad], direction: Direction| {
+            let normal = direction.vec().map(|x| x as f32);
+            quads.iter().filter(|quad| quad.normal == normal).count()
+        };
+        // The chunks don't cover the same half, so each one can see the other's border
+        let (west, east) = (Vec2::new(0, 0), Vec2::new(1, 0));
+        let mut terrain = TerrainMap::default();
+        terrain.chunks.insert(west, half_layer(|z| z < 8).into());
+        let alone = mesh(&terrain, west);
+        assert_eq!(faces(&alone, Direction::East), 0);
+
+        terrain.chunks.insert(east, half_layer(|z| z >= 8).into());
+        let new_chunks = HashSet::from([east]);
+        let stale = stale_neighbors(&terrain, &new_chunks).collect::<Vec<_>>();
+        assert_eq!(stale, vec![west]);
+
+        let remeshed = mesh(&terrain, west);
+        assert_eq!(faces(&remeshed, Direction::East), 8);
+        assert!(remeshed.len() > alone.len());
+        assert_eq!(faces(&mesh(&terrain, east), Direction::West), 8);
+    }
+}
@@ -0,0 +1,203 @@
+use common::{
+    chunk::{ChunkColumn, StandardChunk},
+    resources::TerrainMap,
+};
+use image::{Rgba, RgbaImage};
+use vek::{Rgb, Vec2};
+
+use crate::render::Renderer;
+
+/// How often the minimap image is regenerated, in seconds.
+pub const MINIMAP_UPDATE_INTERVAL: f64 = 2.0;
+
+const DEEP: Rgb<f32> = Rgb::new(0.0, 0.1, 0.5);
+const GROUND: Rgb<f32> = Rgb::new(0.45, 0.3, 0.15);
+const PEAK: Rgb<f32> = Rgb::new(1.0, 1.0, 1.0);
+
+/// A top-down view of the terrain around the player, coloured by height.
+pub struct Minimap {
+    /// The number of chunks shown around the player in each direction.
+    pub radius: u32,
+    pub px_per_block: u32,
+    texture: Option<MinimapTexture>,
+    last_update: Option<f64>,
+}
+
+/// The heightmap image, registered with the UI renderer.
+struct MinimapTexture {
+    id: egui::TextureId,
+    size: egui::Vec2,
+    /// The [`Renderer::device_generation`] the texture was registered on.
+    generation: u32,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            radius: 4,
+            px_per_block: 1,
+            texture: None,
+            last_update: None,
+        }
+    }
+}
+
+impl Minimap {
+    /// Renders the columns of the chunks within `radius` of `centre`, with
+    /// `px_per_block` pixels per block. Unloaded chunks are left transparent.
+    pub fn generate_heightmap_image(
+        terrain_map: &TerrainMap,
+        centre: Vec2<i32>,
+        radius: u32,
+        px_per_block: u32,
+    ) -> RgbaImage {
+        let chunk_px = StandardChunk::SIZE.x as u32 * px_per_block;
+        let size = (2 * radius + 1) * chunk_px;
+        let mut image = RgbaImage::new(size, size);
+        for pos in TerrainMap::columns_in_radius(centre, radius) {
+            let Some(chunk) = terrain_map.chunks.get(&pos).and_then(ChunkColumn::ground) else {
+                continue;
+            };
+            let origin = (pos - centre + radius as i32).map(|x| x as u32 * chunk_px);
+            for x in 0..StandardChunk::SIZE.x as i32 {
+                for z in 0..StandardChunk::SIZE.z as i32 {
+                    let Some(height) = chunk.surface_height(x, z) else {
+                        continue;
+                    };
+                    let color = height_color(height);
+                    for dx in 0..px_per_block {
+                        for dz in 0..px_per_block {
+                            image.put_pixel(
+                                origin.x + x as u32 * px_per_block + dx,
+                                origin.y + z as u32 * px_per_block + dz,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Regenerates the minimap if it is older than [`MINIMAP_UPDATE_INTERVAL`].
+    pub fn update(
+        &mut self,
+        renderer: &mut Renderer,
+        terrain_map: &TerrainMap,
+        centre: Vec2<i32>,
+        time: f64,
+    ) {
+        self.forget_stale_texture(renderer.device_generation());
+        if self
+            .last_update
+            .is_some_and(|last| time - last < MINIMAP_UPDATE_INTERVAL)
+        {
+            return;
+        }
+        self.last_update = Some(time);
+        let image =
+            Self::generate_heightmap_image(terrain_map, centre, self.radius, self.px_per_block);
+        let size = egui::vec2(image.width() as f32, image.height() as f32);
+        let texture = renderer.create_texture_from_image(image);
+        let texture = MinimapTexture {
+            id: renderer.register_ui_texture(&texture),
+            size,
+            generation: renderer.device_generation(),
+        };
+        if let Some(old) = self.texture.replace(texture) {
+            renderer.free_ui_texture(old.id);
+        }
+    }
+
+    /// Drops a texture registered on a device that has since been recreated, and regenerates
+    /// the minimap right away. The old id is not freed as it may belong to another texture now.
+    fn forget_stale_texture(&mut self, generation: u32) {
+        if self
+            .texture
+            .as_ref()
+            .is_some_and(|texture| texture.generation != generation)
+        {
+            self.texture = None;
+            self.last_update = None;
+        }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        match &self.texture {
+            Some(texture) => {
+                ui.add(egui::Image::new(egui::load::SizedTexture::new(
+                    texture.id,
+                    texture.size,
+                )));
+            },
+            None => {
+                ui.label("Generating minimap...");
+            },
+        }
+    }
+}
+
+/// Deep blue at the bottom of the world, brown at mid height and white at the peaks.
+fn height_color(height: i32) -> Rgba<u8> {
+    let t = height as f32 / (StandardChunk::SIZE.y - 1) as f32;
+    let color = if t < 0.5 {
+        Rgb::lerp(DEEP, GROUND, t * 2.0)
+    } else {
+        Rgb::lerp(GROUND, PEAK, (t - 0.5) * 2.0)
+    };
+    let color = color.map(|c| (c * 255.0).round() as u8);
+    Rgba([color.r, color.g, color.b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{block::BlockId, chunk::StandardChunk, resources::TerrainMap};
+    use image::Rgba;
+    use vek::{Vec2, Vec3};
+
+    use super::{height_color, Minimap, MinimapTexture};
+
+    #[test]
+    pub fn heightmap_image_has_correct_size() {
+        let mut terrain = TerrainMap::default();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(0, 255, 0), BlockId::Stone);
+        terrain.chunks.insert(Vec2::new(3, 3), chunk.into());
+
+        let image = Minimap::generate_heightmap_image(&terrain, Vec2::new(3, 3), 2, 3);
+        assert_eq!(image.dimensions(), (5 * 16 * 3, 5 * 16 * 3));
+        // The loaded chunk is in the middle, its top-left block covers 3x3 pixels
+        let origin = 2 * 16 * 3;
+        assert_eq!(*image.get_pixel(origin + 2, origin + 2), height_color(255));
+        assert_eq!(*image.get_pixel(origin + 3, origin), Rgba([0; 4]));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0; 4]));
+    }
+
+    #[test]
+    pub fn height_colors() {
+        assert_eq!(height_color(255), Rgba([255; 4]));
+        let low = height_color(0);
+        assert!(low.0[2] > low.0[0] && low.0[2] > low.0[1]);
+    }
+
+    #[test]
+    pub fn stale_texture_is_dropped_without_freeing() {
+        let mut minimap = Minimap {
+            texture: Some(MinimapTexture {
+                id: egui::TextureId::User(0),
+                size: egui::vec2(16.0, 16.0),
+                generation: 0,
+            }),
+            last_update: Some(1.0),
+            ..Default::default()
+        };
+        minimap.forget_stale_texture(0);
+        assert!(minimap.texture.is_some());
+
+        // The backend changed
+        minimap.forget_stale_texture(1);
+        assert!(minimap.texture.is_none());
+        assert_eq!(minimap.last_update, None);
+    }
+}
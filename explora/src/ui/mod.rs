@@ -1,21 +1,28 @@
+pub mod minimap;
+pub mod toast;
+
 use common::{
     clock::Clock,
-    resources::{GameMode, Ping, TerrainConfig, TerrainMap},
+    coords,
+    resources::{GameMode, Paused, PerformanceStats, Ping, ProgramTime, TerrainConfig, TerrainMap},
     SysResult,
 };
 
 use apecs::{NoDefault, Read};
+use egui_plot::{Bar, BarChart, Plot};
 
 use crate::{
     render::resources::{EguiContext, EguiSettings},
-    settings::GameplaySettings,
+    scene::LOOK_DISTANCE,
+    settings::{self, GameplaySettings},
 };
-use vek::Vec2;
 
-use crate::render::{Renderer, Uniforms};
+use crate::render::{RenderStats, Renderer, Uniforms};
 
 use crate::{camera::Camera, window::Window};
 
+use self::{minimap::Minimap, toast::Toasts};
+
 pub struct EguiState {
     pub state: egui_winit::State,
 }
@@ -50,6 +57,7 @@ pub struct EguiRenderSystem {
     clock: Read<Clock>,
     camera: Write<Camera>,
     renderer: Write<Renderer, NoDefault>,
+    render_stats: Read<RenderStats, NoDefault>,
     window: Read<Window, NoDefault>,
     globals: Write<Uniforms>,
     ping: Read<Ping>,
@@ -57,6 +65,11 @@ pub struct EguiRenderSystem {
     terrain_config: Write<TerrainConfig>,
     terrain: Read<TerrainMap>,
     gameplay: Write<GameplaySettings>,
+    stats: Read<PerformanceStats>,
+    minimap: Write<Minimap>,
+    program_time: Read<ProgramTime>,
+    paused: Read<Paused>,
+    toasts: Write<Toasts>,
 }
 
 // This system must run before the render system
@@ -85,17 +98,47 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
                 "World Position: ({:.2}, {:.2}, {:.2})",
                 pos.x, pos.y, pos.z
             ));
-            let chunk_pos = Vec2::new((pos.x / 16.0).floor() as i32, (pos.z / 16.0).floor() as i32);
+            let chunk_pos = TerrainMap::chunk_pos(coords::world_to_block(pos));
 
             ui.label(format!(
                 "Chunk Position: (X: {}, Z: {})",
                 chunk_pos.x, chunk_pos.y
             ));
+            let target = system
+                .terrain
+                .ray_to_block(pos, player_camera.forward(), LOOK_DISTANCE)
+                .and_then(|(block_pos, _)| system.terrain.get_block(block_pos));
+            if let Some(block) = target {
+                ui.label(format!("Looking at: {}", block.name()));
+            }
             ui.separator();
             ui.label(format!(
                 "Graphics backend: {}",
                 system.renderer.graphics_backend
             ));
+            let stats = &system.render_stats;
+            ui.label(format!(
+                "Frame {}: {}x{} {:?}{}, {:?}",
+                stats.frame_number,
+                stats.surface_width,
+                stats.surface_height,
+                stats.surface_format,
+                if stats.is_hdr() { " (HDR)" } else { "" },
+                stats.last_present_mode
+            ));
+            ui.horizontal(|ui| {
+                ui.label("Switch to");
+                for (name, backends) in [
+                    ("Vulkan", wgpu::Backends::VULKAN),
+                    ("DX12", wgpu::Backends::DX12),
+                    ("Metal", wgpu::Backends::METAL),
+                    ("OpenGL", wgpu::Backends::GL),
+                ] {
+                    if ui.button(name).clicked() {
+                        system.renderer.request_backend(backends);
+                    }
+                }
+            });
             ui.separator();
             // tweak camera speed
             ui.label("Camera speed");
@@ -109,6 +152,11 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
                 &mut system.gameplay.mouse_sensitivity,
                 1..=200,
             ));
+            ui.checkbox(
+                &mut system.gameplay.mouse_invert_y,
+                "Invert Mouse Y".to_string(),
+            );
+            ui.checkbox(&mut system.gameplay.show_crosshair, "Crosshair".to_string());
             ui.label("Camera Field of View");
             ui.add(egui::Slider::new(&mut camera_fov, 0.0..=180.0));
             ui.separator();
@@ -116,6 +164,32 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
             ui.label("Lighting");
             // add box
             ui.checkbox(&mut lighting, "Voxel Lighting".to_string());
+            ui.checkbox(
+                &mut system.gameplay.depth_prepass_enabled,
+                "Depth Prepass".to_string(),
+            );
+            ui.checkbox(
+                &mut system.gameplay.depth_of_field_enabled,
+                "Depth of Field".to_string(),
+            );
+            ui.add(
+                egui::Slider::new(&mut system.gameplay.dof_focus_distance, 1.0..=256.0)
+                    .text("Focus Distance"),
+            );
+            ui.checkbox(
+                &mut system.gameplay.dynamic_sky,
+                "Day/Night Sky".to_string(),
+            );
+            ui.horizontal(|ui| {
+                ui.label("Sky Color");
+                ui.color_edit_button_rgb(&mut system.gameplay.sky_color);
+            });
+            if ui.button("Save Settings").clicked() {
+                match system.gameplay.save(settings::SETTINGS_PATH) {
+                    Ok(()) => log::info!("Saved settings to `{}`", settings::SETTINGS_PATH),
+                    Err(e) => log::error!("Failed to save settings: {:?}", e),
+                }
+            }
             ui.separator();
             ui.label("Terrain");
             ui.add(
@@ -124,7 +198,54 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
             );
             // loaded chunks
             ui.label(format!("Loaded Chunks: {}", system.terrain.chunks.len()));
+            ui.label(format!(
+                "Meshed Chunks: {} ({} vertices, {:.0} per chunk)",
+                system.stats.terrain_chunks,
+                system.stats.terrain_vertices,
+                system.stats.average_vertices_per_chunk
+            ));
+            ui.separator();
+            match system.stats.gpu_frame_time_us {
+                Some(time) => ui.label(format!("GPU render pass: {:.0}µs", time)),
+                None => ui.label("GPU render pass: unsupported"),
+            };
+            ui.label("Stage timings (µs)");
+            let mut timings = system
+                .stats
+                .stage_times
+                .iter()
+                .map(|(stage, time)| (*stage, *time))
+                .collect::<Vec<_>>();
+            timings.sort_unstable();
+            let bars = timings
+                .iter()
+                .enumerate()
+                .map(|(i, (stage, time))| Bar::new(i as f64, *time as f64).name(stage))
+                .collect();
+            Plot::new("stage_timings")
+                .height(120.0)
+                .show(ui, |plot| plot.bar_chart(BarChart::new(bars)));
         });
+    let player_chunk = TerrainMap::chunk_pos(coords::world_to_block(player_camera.pos()));
+    system.minimap.update(
+        &mut system.renderer,
+        &system.terrain,
+        player_chunk,
+        system.program_time.0,
+    );
+    egui::Window::new("Minimap")
+        .default_pos((10.0, 400.0))
+        .show(system.egui_context.get(), |ui| system.minimap.show(ui));
+    if system.paused.0 {
+        egui::Area::new("paused")
+            .anchor(egui::Align2::CENTER_CENTER, (0.0, 0.0))
+            .show(system.egui_context.get(), |ui| {
+                ui.heading("Paused");
+            });
+    }
+    system
+        .toasts
+        .show(system.egui_context.get(), system.program_time.0);
     player_camera.set_fov(camera_fov);
     system.globals.enable_lighting = lighting as u32;
 
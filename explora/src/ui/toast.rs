@@ -0,0 +1,59 @@
+/// How long a toast stays on screen, in seconds.
+pub const TOAST_DURATION: f64 = 3.0;
+
+/// Short messages shown at the bottom of the screen for a few seconds.
+#[derive(Debug, Default)]
+pub struct Toasts {
+    /// The text of each toast and the time it was pushed at.
+    toasts: Vec<(String, f64)>,
+}
+
+impl Toasts {
+    /// Shows `text` from `time`, in seconds since the start of the program.
+    pub fn push(&mut self, text: impl Into<String>, time: f64) {
+        self.toasts.push((text.into(), time));
+    }
+
+    /// Forgets the toasts older than [`TOAST_DURATION`] at `time`.
+    pub fn expire(&mut self, time: f64) {
+        self.toasts
+            .retain(|(_, pushed)| time - pushed < TOAST_DURATION);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.toasts.iter().map(|(text, _)| text.as_str())
+    }
+
+    /// Draws the toasts that haven't expired at `time`, the most recent one at the bottom.
+    pub fn show(&mut self, ctx: &egui::Context, time: f64) {
+        self.expire(time);
+        if self.toasts.is_empty() {
+            return;
+        }
+        egui::Area::new("toasts")
+            .anchor(egui::Align2::CENTER_BOTTOM, (0.0, -40.0))
+            .show(ctx, |ui| {
+                for text in self.iter() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(text));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Toasts, TOAST_DURATION};
+
+    #[test]
+    pub fn toasts_expire_after_their_duration() {
+        let mut toasts = Toasts::default();
+        toasts.push("first", 1.0);
+        toasts.push("second", 2.0);
+        toasts.expire(1.0 + TOAST_DURATION - 0.1);
+        assert_eq!(toasts.iter().collect::<Vec<_>>(), ["first", "second"]);
+        toasts.expire(1.0 + TOAST_DURATION);
+        assert_eq!(toasts.iter().collect::<Vec<_>>(), ["second"]);
+        toasts.expire(2.0 + TOAST_DURATION);
+        assert_eq!(toasts.iter().count(), 0);
+    }
+}
@@ -1,10 +1,21 @@
-use crate::error::Error;
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use apecs::*;
+use common::{clock::Clock, SysResult};
+
+use crate::{error::Error, render::resources::TerrainRender};
 
 use vek::Vec2;
 use winit::event_loop::EventLoop;
 
+/// How often the FPS in the window title is refreshed.
+pub const TITLE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Represents the various window events that are relevant for the game.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum WindowEvent {
     /// The window has been requested to close.
     Close,
@@ -12,11 +23,16 @@ pub enum WindowEvent {
     Resize(Vec2<u32>),
     /// The cursor has been moved.
     CursorMove(Vec2<f32>),
+    /// The window gained (`true`) or lost (`false`) the focus.
+    Focused(bool),
+    /// A file has been dragged and dropped onto the window.
+    FileDrop(PathBuf),
 }
 
 pub struct Window {
     platform: winit::window::Window,
     cursor_grabbed: bool,
+    last_title_update: Option<Instant>,
 }
 
 impl Window {
@@ -30,6 +46,7 @@ impl Window {
         let mut this = Self {
             platform,
             cursor_grabbed: true,
+            last_title_update: None,
         };
         this.grab_cursor(true);
         Ok((this, event_loop))
@@ -63,4 +80,78 @@ impl Window {
     pub fn cursor_locked(&self) -> bool {
         self.cursor_grabbed
     }
+
+    pub fn set_title(&self, title: &str) {
+        self.platform.set_title(title);
+    }
+
+    /// Shows the FPS and loaded chunks in the title bar.
+    ///
+    /// Setting the title may cause the OS to redraw the decorations,
+    /// so it is updated at most once every `interval`.
+    pub fn update_title_if_needed(&mut self, fps: f32, chunk_count: usize, interval: Duration) {
+        if throttle(&mut self.last_title_update, Instant::now(), interval) {
+            self.set_title(&format!(
+                "Explora — {:.0} FPS | Chunks: {}",
+                fps, chunk_count
+            ));
+        }
+    }
+}
+
+/// Returns true if at least `interval` has passed since the last time it returned true.
+fn throttle(last: &mut Option<Instant>, now: Instant, interval: Duration) -> bool {
+    match last {
+        Some(last) if now.duration_since(*last) < interval => false,
+        _ => {
+            *last = Some(now);
+            true
+        },
+    }
+}
+
+#[derive(CanFetch)]
+pub struct WindowTitleSystem {
+    window: Write<Window, NoDefault>,
+    clock: Read<Clock>,
+    terrain_render: Read<TerrainRender>,
+}
+
+pub fn window_title_system(mut system: WindowTitleSystem) -> SysResult {
+    let fps = system.clock.fps();
+    let chunk_count = system.terrain_render.chunks.len();
+    system
+        .window
+        .update_title_if_needed(fps, chunk_count, TITLE_UPDATE_INTERVAL);
+    ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::throttle;
+
+    #[test]
+    pub fn title_updates_are_throttled() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(250);
+        let mut last = None;
+        let mut updates = Vec::new();
+        // 100 frames at 60 FPS
+        for frame in 0..100u32 {
+            let now = start + Duration::from_micros(16_667) * frame;
+            if throttle(&mut last, now, interval) {
+                updates.push(now);
+            }
+        }
+        // The first frame always updates, then one every interval
+        assert_eq!(updates.first(), Some(&start));
+        for pair in updates.windows(2) {
+            assert!(pair[1] - pair[0] >= interval);
+        }
+        let elapsed = Duration::from_micros(16_667) * 99;
+        assert!(updates.len() as u32 <= elapsed.as_millis() as u32 / 250 + 1);
+        assert!(updates.len() > 1);
+    }
 }
@@ -0,0 +1,112 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use common::net::{
+    connection::Connection,
+    packet::{ClientHello, ClientPacket, ServerPacket, PROTOCOL_VERSION},
+};
+use explora::client::{self, error::ConnectError};
+use server::{config::ServerConfig, Server, SPAWN_POS};
+
+/// Starts a server on an ephemeral loopback port and returns its address.
+fn start_server(running: Arc<AtomicBool>) -> std::net::SocketAddr {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let config = ServerConfig {
+            port: 0,
+            host: "127.0.0.1".to_string(),
+            timeout: 30,
+            chunk_cache_size: 16,
+            world_gen: Default::default(),
+            metrics_port: None,
+        };
+        let mut server = Server::new(config).expect("Failed to start server");
+        tx.send(server.local_addr()).unwrap();
+        while running.load(Ordering::Relaxed) {
+            server.tick(Duration::from_millis(1));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    });
+    rx.recv().expect("Server thread died")
+}
+
+#[test]
+pub fn handshake_over_loopback() {
+    let running = Arc::new(AtomicBool::new(true));
+    let addr = start_server(running.clone());
+
+    let (_, welcome) = client::connect(addr, "tester").expect("Handshake failed");
+    assert_eq!(welcome.spawn_pos, SPAWN_POS);
+
+    let (_, second) = client::connect(addr, "tester2").expect("Handshake failed");
+    assert_ne!(welcome.player_id, second.player_id);
+
+    running.store(false, Ordering::Relaxed);
+}
+
+#[test]
+pub fn players_receive_their_state_every_tick() {
+    let running = Arc::new(AtomicBool::new(true));
+    let addr = start_server(running.clone());
+
+    let (connection, _) = client::connect(addr, "tester").expect("Handshake failed");
+    let instant = std::time::Instant::now();
+    let mut ticks = Vec::new();
+    while ticks.len() < 2 {
+        if let Ok((ServerPacket::PlayerState(state), _)) = connection.recv() {
+            assert_eq!(state.pos, SPAWN_POS);
+            ticks.push(state.tick);
+        }
+        assert!(
+            instant.elapsed() < Duration::from_secs(5),
+            "Server timed out"
+        );
+    }
+    assert!(ticks[0] < ticks[1]);
+
+    running.store(false, Ordering::Relaxed);
+}
+
+#[test]
+pub fn handshake_rejects_other_versions() {
+    let running = Arc::new(AtomicBool::new(true));
+    let addr = start_server(running.clone());
+
+    let connection: Connection<ClientPacket, ServerPacket> = Connection::connect(addr).unwrap();
+    let mut hello = ClientHello::new("tester");
+    hello.version = PROTOCOL_VERSION + 1;
+    connection.send(ClientPacket::Hello(hello)).unwrap();
+
+    let instant = std::time::Instant::now();
+    let packet = loop {
+        if let Ok((packet, _)) = connection.recv() {
+            break packet;
+        }
+        assert!(
+            instant.elapsed() < Duration::from_secs(5),
+            "Server timed out"
+        );
+    };
+    assert!(matches!(
+        packet,
+        ServerPacket::VersionMismatch { server_version } if server_version == PROTOCOL_VERSION
+    ));
+
+    running.store(false, Ordering::Relaxed);
+}
+
+#[test]
+pub fn connect_times_out_without_server() {
+    // Bind a socket that never answers
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = silent.local_addr().unwrap();
+    assert!(matches!(
+        client::connect(addr, "tester"),
+        Err(ConnectError::Timeout)
+    ));
+}
@@ -1,15 +1,20 @@
-use common::clock::Clock;
-use server::{config::ServerConfig, Server};
+use server::{
+    config::ServerConfig,
+    tick::{TickScheduler, TICK_DURATION},
+    Server,
+};
 
 fn main() {
     common::init_logger("");
 
     let config = ServerConfig::toml();
     let mut server = Server::new(config).unwrap();
-    let mut clock = Clock::default();
+    let mut scheduler = TickScheduler::new();
 
     loop {
-        server.tick(clock.dt());
-        clock.tick();
+        while scheduler.consume_tick() {
+            server.tick(TICK_DURATION);
+        }
+        std::thread::sleep(scheduler.time_until_next_tick());
     }
 }
@@ -0,0 +1,107 @@
+use common::block::BlockId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Biome {
+    Desert,
+    Jungle,
+    Tundra,
+    Forest,
+    Plains,
+}
+
+/// Below this temperature everything is tundra.
+const COLD: f64 = -0.3;
+/// Above this temperature the moisture picks between desert and jungle.
+const HOT: f64 = 0.3;
+
+impl Biome {
+    /// Picks a biome from a simplified Whittaker diagram.
+    ///
+    /// Both `temperature` and `moisture` are expected in the range [-1, 1].
+    pub fn select(temperature: f64, moisture: f64) -> Self {
+        if temperature < COLD {
+            Biome::Tundra
+        } else if temperature > HOT {
+            if moisture < 0.0 {
+                Biome::Desert
+            } else {
+                Biome::Jungle
+            }
+        } else if moisture > 0.0 {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+
+    /// The block that replaces the grass on top of the terrain.
+    pub fn surface_block(self) -> BlockId {
+        match self {
+            Biome::Desert => BlockId::Dirt,
+            Biome::Tundra => BlockId::Stone,
+            Biome::Jungle | Biome::Forest | Biome::Plains => BlockId::Grass,
+        }
+    }
+
+    /// The colour of the biome on map previews.
+    pub fn map_colour(self) -> [u8; 3] {
+        match self {
+            Biome::Desert => [222, 200, 140],
+            Biome::Jungle => [40, 120, 40],
+            Biome::Tundra => [210, 220, 230],
+            Biome::Forest => [60, 140, 70],
+            Biome::Plains => [130, 180, 90],
+        }
+    }
+
+    /// Average number of trees in a chunk.
+    pub fn tree_density(self) -> f32 {
+        match self {
+            Biome::Desert => 0.0,
+            Biome::Tundra => 0.5,
+            Biome::Plains => 1.0,
+            Biome::Forest => 6.0,
+            Biome::Jungle => 12.0,
+        }
+    }
+
+    /// Number of veins attempted in a chunk.
+    pub fn veins_per_chunk(self) -> u32 {
+        match self {
+            Biome::Desert | Biome::Tundra => 20,
+            Biome::Plains => 12,
+            Biome::Forest | Biome::Jungle => 8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Biome;
+
+    #[test]
+    pub fn whittaker_quadrants() {
+        let cases = [
+            // (temperature, moisture, biome)
+            (0.8, -0.8, Biome::Desert),
+            (0.8, -0.1, Biome::Desert),
+            (0.8, 0.8, Biome::Jungle),
+            (0.5, 0.1, Biome::Jungle),
+            (-0.8, -0.8, Biome::Tundra),
+            (-0.8, 0.8, Biome::Tundra),
+            (0.0, 0.5, Biome::Forest),
+            (0.2, 0.9, Biome::Forest),
+            (0.0, -0.5, Biome::Plains),
+            (-0.2, -0.9, Biome::Plains),
+        ];
+        for (temperature, moisture, biome) in cases {
+            assert_eq!(
+                Biome::select(temperature, moisture),
+                biome,
+                "temperature: {}, moisture: {}",
+                temperature,
+                moisture
+            );
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+};
+
+/// The addresses of the connected clients, by player id, to send them the same message.
+#[derive(Debug, Default)]
+pub struct Broadcast {
+    clients: HashMap<u64, SocketAddr>,
+}
+
+impl Broadcast {
+    /// Adds a client, replacing the address it was registered with before.
+    pub fn register(&mut self, id: u64, addr: SocketAddr) {
+        self.clients.insert(id, addr);
+    }
+
+    /// Removes a client, returning its address if it was registered.
+    pub fn deregister(&mut self, id: u64) -> Option<SocketAddr> {
+        self.clients.remove(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Sends `msg` to every client and returns how many sends succeeded.
+    pub fn send_to_all(&self, socket: &UdpSocket, msg: &[u8]) -> usize {
+        self.send_where(socket, msg, |_| true)
+    }
+
+    /// Sends `msg` to every client except `exclude_id`, e.g. the sender of a movement update,
+    /// and returns how many sends succeeded.
+    pub fn send_to_all_except(&self, socket: &UdpSocket, exclude_id: u64, msg: &[u8]) -> usize {
+        self.send_where(socket, msg, |id| id != exclude_id)
+    }
+
+    fn send_where<F: Fn(u64) -> bool>(&self, socket: &UdpSocket, msg: &[u8], filter: F) -> usize {
+        let mut sent = 0;
+        for (id, addr) in self.clients.iter().filter(|(id, _)| filter(**id)) {
+            match socket.send_to(msg, addr) {
+                Ok(_) => sent += 1,
+                // One unreachable client must not stop the others from receiving the message
+                Err(e) => log::warn!("Failed to send to client {} ({}): {:?}", id, addr, e),
+            }
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::Broadcast;
+
+    fn socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        socket
+    }
+
+    fn received(socket: &UdpSocket) -> Option<Vec<u8>> {
+        let mut buf = [0; 64];
+        socket.recv(&mut buf).ok().map(|len| buf[..len].to_vec())
+    }
+
+    #[test]
+    pub fn excluded_client_is_skipped() {
+        let server = socket();
+        let clients = [socket(), socket(), socket()];
+        let mut broadcast = Broadcast::default();
+        for (id, client) in clients.iter().enumerate() {
+            broadcast.register(id as u64, client.local_addr().unwrap());
+        }
+
+        assert_eq!(broadcast.send_to_all_except(&server, 1, b"moved"), 2);
+        assert_eq!(received(&clients[0]).as_deref(), Some(&b"moved"[..]));
+        assert_eq!(received(&clients[2]).as_deref(), Some(&b"moved"[..]));
+        clients[1].set_nonblocking(true).unwrap();
+        assert_eq!(received(&clients[1]), None);
+    }
+
+    #[test]
+    pub fn deregistered_clients_receive_nothing() {
+        let server = socket();
+        let clients = [socket(), socket()];
+        let mut broadcast = Broadcast::default();
+        broadcast.register(7, clients[0].local_addr().unwrap());
+        broadcast.register(8, clients[1].local_addr().unwrap());
+        assert_eq!(
+            broadcast.deregister(7),
+            Some(clients[0].local_addr().unwrap())
+        );
+        assert_eq!(broadcast.deregister(7), None);
+
+        assert_eq!(broadcast.send_to_all(&server, b"hello"), 1);
+        assert_eq!(received(&clients[1]).as_deref(), Some(&b"hello"[..]));
+        clients[0].set_nonblocking(true).unwrap();
+        assert_eq!(received(&clients[0]), None);
+    }
+}
@@ -0,0 +1,53 @@
+use common::{
+    event::{Events, WeatherChangedEvent},
+    resources::{CurrentWeather, Paused, Weather, WorldTime},
+    state::State,
+};
+
+/// A cheat command sent to the server, e.g. from the debug console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetTime(u64),
+    SetWeather(Weather),
+    /// Logs how many chunks are loaded and the memory they use.
+    Stats,
+    /// Stops or resumes the simulation, e.g. while the singleplayer window is unfocused.
+    SetPaused(bool),
+    /// Packs the terrain vertex buffers. They only exist on the client, which runs it
+    /// instead of sending it to the server.
+    Defragment,
+}
+
+/// Applies `command` to the server state.
+pub fn execute(state: &mut State, command: Command) {
+    match command {
+        Command::SetTime(ticks) => {
+            state.resource_mut::<WorldTime>().ticks = ticks;
+            log::info!("Time set to {}", ticks);
+        },
+        Command::SetWeather(weather) => {
+            let current = state.resource_mut::<CurrentWeather>();
+            let old = std::mem::replace(&mut current.0, weather);
+            if old != weather {
+                state
+                    .resource_mut::<Events<WeatherChangedEvent>>()
+                    .send(WeatherChangedEvent { old, new: weather });
+            }
+            log::info!("Weather set to {:?}", weather);
+        },
+        Command::Stats => {
+            let terrain = state.terrain();
+            log::info!(
+                "{} chunks loaded, {} blocks, about {} KiB",
+                terrain.loaded_chunk_count(),
+                terrain.total_block_count(),
+                terrain.memory_estimate_bytes() / 1024
+            );
+        },
+        Command::SetPaused(paused) => {
+            state.resource_mut::<Paused>().0 = paused;
+            log::info!("{}", if paused { "Paused" } else { "Resumed" });
+        },
+        Command::Defragment => {},
+    }
+}
@@ -1,10 +1,25 @@
 use serde::{Deserialize, Serialize};
 
+use crate::world::WorldGenConfig;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub host: String,
     pub timeout: u64,
+    /// Maximum number of generated chunks kept in memory
+    #[serde(default = "default_chunk_cache_size")]
+    pub chunk_cache_size: usize,
+    #[serde(default)]
+    pub world_gen: WorldGenConfig,
+    /// Port of the Prometheus metrics endpoint, on the same host as the server.
+    /// Only served when the server is built with the `metrics` feature.
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+}
+
+fn default_chunk_cache_size() -> usize {
+    4096
 }
 
 const CONFIG_PATH: &str = "server_config.toml";
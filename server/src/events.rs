@@ -1,5 +1,7 @@
 use common::{event::Events, resources::EntityMap, uid::Uid, SysResult};
 
+use crate::broadcast::Broadcast;
+
 use apecs::{ok, Write, *};
 
 pub enum ServerEvent {
@@ -11,6 +13,7 @@ pub struct HandleServerEvents {
     events: Write<Events<ServerEvent>>,
     entities: Write<Entities>,
     entity_map: Write<EntityMap>,
+    broadcast: Write<Broadcast, NoDefault>,
 }
 
 pub fn handle_server_events(mut system: HandleServerEvents) -> SysResult {
@@ -20,6 +23,7 @@ pub fn handle_server_events(mut system: HandleServerEvents) -> SysResult {
                 if let Some(entity) = system.entity_map.entity(*uid) {
                     system.entities.destroy(entity);
                     system.entity_map.remove(*uid);
+                    system.broadcast.deregister(uid.0);
                     log::info!("Client {} disconnected.", uid);
                 } else {
                     log::error!(
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use apecs::*;
+use common::{
+    block::BlockId,
+    chunk::StandardChunk,
+    event::{BlockUpdateEvent, Events},
+    resources::TerrainMap,
+    SysResult,
+};
+use vek::{Vec2, Vec3};
+
+/// Blocks that fall when there is air below them.
+pub const FALLING_BLOCKS: &[BlockId] = &[BlockId::Sand, BlockId::Gravel];
+
+/// The positions that may hold a falling block with air below it, so only those are checked
+/// instead of every loaded block.
+#[derive(Default)]
+pub struct FallingCandidates {
+    positions: HashSet<Vec3<i32>>,
+    /// The chunks whose blocks were all checked once after they were loaded.
+    scanned_chunks: HashSet<Vec2<i32>>,
+}
+
+impl FallingCandidates {
+    /// Checks the block at `world_pos` and the one above it, which may have lost its support.
+    pub fn block_changed(&mut self, world_pos: Vec3<i32>) {
+        self.positions.insert(world_pos);
+        self.positions.insert(world_pos + Vec3::unit_y());
+    }
+
+    /// Adds the unsupported falling blocks of the chunks loaded since the last call.
+    fn scan_new_chunks(&mut self, terrain: &TerrainMap) {
+        self.scanned_chunks
+            .retain(|pos| terrain.chunks.contains_key(pos));
+        for (chunk_pos, column) in &terrain.chunks {
+            if !self.scanned_chunks.insert(*chunk_pos) {
+                continue;
+            }
+            let width = StandardChunk::SIZE.x as i32;
+            let origin = Vec3::new(chunk_pos.x * width, 0, chunk_pos.y * width);
+            let falling = column
+                .iter_all()
+                .filter(|(_, id)| FALLING_BLOCKS.contains(id));
+            self.positions.extend(falling.map(|(pos, _)| origin + pos));
+        }
+    }
+}
+
+#[derive(CanFetch)]
+pub struct FallingBlockSystem {
+    terrain: Write<TerrainMap>,
+    events: Write<Events<BlockUpdateEvent>>,
+    candidates: Write<FallingCandidates>,
+}
+
+/// Moves the unsupported falling blocks one block down. Only the blocks of newly loaded
+/// chunks and the blocks around this tick's block updates are checked.
+///
+/// The chunks of the moved blocks are marked dirty, so `send_chunk_updates` sends them to
+/// every client at the end of the tick.
+pub fn falling_block_system(mut sys: FallingBlockSystem) -> SysResult {
+    for event in &sys.events.events {
+        sys.candidates.block_changed(event.world_pos);
+    }
+    for event in fall_one_block(&mut sys.terrain, &mut sys.candidates) {
+        sys.events.send(event);
+    }
+    ok()
+}
+
+/// Moves the falling blocks among the `candidates` with air below them down by one block,
+/// returning the changes.
+///
+/// The blocks to move are found before any of them moves, so a block only falls one block
+/// per tick even when the one below it falls too. Blocks stop at the bottom of the world.
+/// The moved blocks and the blocks above them stay candidates for the next tick.
+pub fn fall_one_block(
+    terrain: &mut TerrainMap,
+    candidates: &mut FallingCandidates,
+) -> Vec<BlockUpdateEvent> {
+    candidates.scan_new_chunks(terrain);
+    let mut falling = Vec::new();
+    for world_pos in candidates.positions.drain() {
+        // The blocks at y = 0 can't fall any further
+        if world_pos.y <= 0 {
+            continue;
+        }
+        let Some(id) = terrain.get_block(world_pos) else {
+            continue;
+        };
+        if FALLING_BLOCKS.contains(&id)
+            && terrain
+                .get_block(world_pos - Vec3::unit_y())
+                .is_some_and(BlockId::is_air)
+        {
+            falling.push((world_pos, id));
+        }
+    }
+
+    let mut events = Vec::with_capacity(falling.len() * 2);
+    for (world_pos, id) in falling {
+        let below = world_pos - Vec3::unit_y();
+        terrain.set_block(world_pos, BlockId::Air);
+        terrain.set_block(below, id);
+        candidates.block_changed(below);
+        candidates.block_changed(world_pos);
+        events.push(BlockUpdateEvent {
+            world_pos,
+            old_id: id,
+            new_id: BlockId::Air,
+        });
+        events.push(BlockUpdateEvent {
+            world_pos: below,
+            old_id: BlockId::Air,
+            new_id: id,
+        });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use common::{
+        block::BlockId,
+        chunk::StandardChunk,
+        event::{BlockUpdateEvent, Events},
+        net::{
+            connection::Connection,
+            packet::{ClientPacket, ServerPacket},
+        },
+        resources::TerrainMap,
+    };
+    use vek::{Vec2, Vec3};
+
+    use crate::{broadcast::Broadcast, ServerConnection};
+
+    use super::{fall_one_block, falling_block_system, FallingCandidates};
+
+    #[test]
+    pub fn sand_falls_one_block_per_tick_until_the_ground() {
+        let mut terrain = TerrainMap::default();
+        let chunk_pos = Vec2::new(-1, 2);
+        terrain
+            .chunks
+            .insert(chunk_pos, StandardChunk::flat(BlockId::Air).into());
+        let column = Vec3::new(-16 + 3, 0, 32 + 7);
+        terrain.set_block(column + Vec3::unit_y() * 10, BlockId::Sand);
+        terrain.dirty.clear();
+
+        let mut candidates = FallingCandidates::default();
+        for tick in 1..=10 {
+            let events = fall_one_block(&mut terrain, &mut candidates);
+            assert_eq!(events.len(), 2);
+            assert!(terrain.dirty.contains(&chunk_pos));
+            let height = 10 - tick;
+            assert_eq!(
+                terrain.get_block(column + Vec3::unit_y() * height),
+                Some(BlockId::Sand)
+            );
+            assert_eq!(
+                terrain.get_block(column + Vec3::unit_y() * (height + 1)),
+                Some(BlockId::Air)
+            );
+        }
+        // Resting on the bottom of the world
+        assert!(fall_one_block(&mut terrain, &mut candidates).is_empty());
+        assert_eq!(terrain.get_block(column), Some(BlockId::Sand));
+    }
+
+    #[test]
+    pub fn supported_blocks_do_not_fall() {
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::zero(), StandardChunk::flat(BlockId::Air).into());
+        terrain.set_block(Vec3::new(0, 4, 0), BlockId::Stone);
+        terrain.set_block(Vec3::new(0, 5, 0), BlockId::Gravel);
+        // Only sand and gravel fall
+        terrain.set_block(Vec3::new(1, 5, 0), BlockId::Dirt);
+        assert!(fall_one_block(&mut terrain, &mut FallingCandidates::default()).is_empty());
+    }
+
+    #[test]
+    pub fn only_updated_blocks_are_checked_after_loading() {
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::zero(), StandardChunk::flat(BlockId::Air).into());
+        let mut candidates = FallingCandidates::default();
+        assert!(fall_one_block(&mut terrain, &mut candidates).is_empty());
+
+        // Changed without a block update, so the loaded chunk is not scanned again
+        let pos = Vec3::new(2, 6, 2);
+        terrain.set_block(pos, BlockId::Sand);
+        assert!(fall_one_block(&mut terrain, &mut candidates).is_empty());
+
+        // Removing the block below is what a player would do
+        candidates.block_changed(pos - Vec3::unit_y());
+        assert_eq!(fall_one_block(&mut terrain, &mut candidates).len(), 2);
+        assert_eq!(terrain.get_block(pos), Some(BlockId::Air));
+        // Keeps falling without any other update
+        assert_eq!(fall_one_block(&mut terrain, &mut candidates).len(), 2);
+        assert_eq!(
+            terrain.get_block(pos - Vec3::unit_y() * 2),
+            Some(BlockId::Sand)
+        );
+    }
+
+    #[test]
+    pub fn newly_loaded_chunks_are_scanned() {
+        let mut terrain = TerrainMap::default();
+        let mut candidates = FallingCandidates::default();
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        chunk.set(Vec3::new(4, 9, 4), BlockId::Gravel);
+        terrain.chunks.insert(Vec2::new(1, 0), chunk.into());
+
+        assert_eq!(fall_one_block(&mut terrain, &mut candidates).len(), 2);
+        assert_eq!(
+            terrain.get_block(Vec3::new(16 + 4, 8, 4)),
+            Some(BlockId::Gravel)
+        );
+    }
+
+    #[test]
+    pub fn falling_blocks_are_sent_to_clients() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let server: ServerConnection = Connection::listen(addr).unwrap();
+        let client: Connection<ClientPacket, ServerPacket> = Connection::listen(addr).unwrap();
+        let mut broadcast = Broadcast::default();
+        broadcast.register(1, client.local_addr().unwrap());
+
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::zero(), StandardChunk::flat(BlockId::Air).into());
+        terrain.set_block(Vec3::new(3, 10, 3), BlockId::Sand);
+        terrain.dirty.clear();
+
+        let mut world = apecs::World::default();
+        world
+            .with_resource(server)
+            .unwrap()
+            .with_resource(broadcast)
+            .unwrap()
+            .with_resource(terrain)
+            .unwrap()
+            .with_default_resource::<Events<BlockUpdateEvent>>()
+            .unwrap()
+            .with_system_with_dependencies("falling_blocks", falling_block_system, &[], &[])
+            .unwrap()
+            .with_system_with_dependencies(
+                "send_chunk_updates",
+                crate::send_chunk_updates,
+                &["falling_blocks"],
+                &[],
+            )
+            .unwrap();
+        world.tick().unwrap();
+
+        let instant = Instant::now();
+        let (pos, data) = loop {
+            if let Ok((ServerPacket::ChunkUpdate { pos, data }, _)) = client.recv() {
+                break (pos, data);
+            }
+            assert!(
+                instant.elapsed() < Duration::from_secs(5),
+                "No chunk update received"
+            );
+        };
+        assert_eq!(pos, Vec2::zero());
+        let chunk: StandardChunk = common::chunk::decompress(&data);
+        assert_eq!(chunk.get(Vec3::new(3, 9, 3)), Some(BlockId::Sand));
+        assert_eq!(chunk.get(Vec3::new(3, 10, 3)), Some(BlockId::Air));
+        assert!(world.resource::<TerrainMap>().unwrap().dirty.is_empty());
+    }
+}
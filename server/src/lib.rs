@@ -1,27 +1,41 @@
+pub mod biome;
+pub mod broadcast;
+pub mod command;
 pub mod config;
 pub mod events;
+pub mod falling;
+pub mod lighting;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod tick;
 pub mod world;
 
-use std::{
-    net::SocketAddr,
-    time::{Duration, Instant},
-};
+use std::{net::SocketAddr, time::Duration};
 
 use apecs::CanFetch;
+use command::Command;
 use common::{
-    event::Events,
+    chunk::{ChunkColumn, StandardChunk},
+    components::Pos,
+    event::{BlockUpdateEvent, Events, WeatherChangedEvent},
     net::connection::Connection,
-    net::packet::{ClientPacket, PingPacket, ServerPacket},
-    resources::{EntityMap, ProgramTime, TerrainMap},
+    net::packet::{
+        ClientPacket, PingPacket, PlayerState, ServerPacket, ServerWelcome, PROTOCOL_VERSION,
+    },
+    resources::{CurrentWeather, EntityMap, Paused, ProgramTime, TerrainMap, Weather, WorldTime},
     state::State,
     uid::Uid,
     SysResult,
 };
 use config::ServerConfig;
 use log::info;
+use vek::Vec3;
 
 type ServerConnection = Connection<ServerPacket, ClientPacket>;
 
+/// Where new players appear, just above the highest possible terrain.
+pub const SPAWN_POS: Vec3<f32> = Vec3::new(0.0, 257.0, 0.0);
+
 pub struct RemoteClient {
     addr: SocketAddr,
     last_ping: f64,
@@ -34,9 +48,18 @@ pub struct Server {
 #[allow(clippy::new_without_default)]
 impl Server {
     pub fn new(config: ServerConfig) -> anyhow::Result<Self> {
+        if config.world_gen.chunk_height != StandardChunk::HEIGHT {
+            anyhow::bail!(
+                "Unsupported chunk height {}, only {} is supported",
+                config.world_gen.chunk_height,
+                StandardChunk::HEIGHT
+            );
+        }
         let addr = format!("{}:{}", config.host, config.port)
             .parse::<SocketAddr>()
             .expect("Failed to parse server address");
+        #[cfg(feature = "metrics")]
+        let metrics_port = config.metrics_port;
         let con: ServerConnection = Connection::listen(addr).unwrap();
         log::info!("Server listening on {}", addr);
         let mut state = State::server().unwrap();
@@ -44,8 +67,16 @@ impl Server {
         state
             .ecs_mut()
             .with_resource(con)?
+            .with_resource(WorldCache::new(config.chunk_cache_size))?
+            .with_resource(WorldGenerator::with_config(
+                WorldGenerator::DEFAULT_SEED,
+                config.world_gen.clone(),
+            ))?
             .with_resource(config)?
-            .with_resource(WorldGenerator::new())?
+            .with_default_resource::<WorldTime>()?
+            .with_default_resource::<CurrentWeather>()?
+            .with_default_resource::<Broadcast>()?
+            .with_default_resource::<TickCount>()?
             .with_system_with_dependencies(
                 "handle_incoming_packets",
                 handle_incoming_packets,
@@ -63,22 +94,88 @@ impl Server {
                 events::handle_server_events,
                 &[],
                 &["server_events-update"],
+            )?
+            .with_system_with_dependencies(
+                "falling_blocks",
+                falling::falling_block_system,
+                &["lighting"],
+                &["block_update_events-update"],
+            )?
+            .with_system_with_dependencies(
+                "lighting",
+                lighting::lighting_system,
+                &[],
+                &["block_update_events-update"],
+            )?
+            .with_system_with_dependencies(
+                "send_chunk_updates",
+                send_chunk_updates,
+                &["handle_incoming_packets", "falling_blocks"],
+                &[],
+            )?
+            .with_system_with_dependencies(
+                "send_player_states",
+                send_player_states,
+                &["handle_incoming_packets", "falling_blocks"],
+                &[],
             )?;
 
+        #[cfg(feature = "metrics")]
+        if let Some(port) = metrics_port {
+            let exporter = metrics::MetricsExporter::bind(SocketAddr::new(addr.ip(), port))?;
+            state
+                .ecs_mut()
+                .with_resource(exporter)?
+                .with_system_with_dependencies("metrics", metrics::metrics_system, &[], &[])?;
+        }
+
         state.with_event::<ServerEvent>("server_events");
+        state.with_event::<BlockUpdateEvent>("block_update_events");
+        state.with_event::<WeatherChangedEvent>("weather_changed_events");
         common::state::print_system_schedule(state.ecs_mut());
 
         Ok(Self { state })
     }
 
     pub fn tick(&mut self, dt: Duration) {
+        // Neither the world generation nor the physics run while paused
+        if self.state.resource::<Paused>().0 {
+            return;
+        }
         self.state.tick(dt);
     }
+
+    /// The address the server is listening on.
+    ///
+    /// Useful when the server was bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.state
+            .resource::<ServerConnection>()
+            .local_addr()
+            .expect("Server socket is not bound")
+    }
+
+    pub fn set_time(&mut self, ticks: u64) {
+        self.execute(Command::SetTime(ticks));
+    }
+
+    pub fn set_weather(&mut self, weather: Weather) {
+        self.execute(Command::SetWeather(weather));
+    }
+
+    pub fn execute(&mut self, command: Command) {
+        command::execute(&mut self.state, command);
+    }
 }
 
 use apecs::*;
 
-use crate::{events::ServerEvent, world::WorldGenerator};
+use crate::{
+    broadcast::Broadcast,
+    events::ServerEvent,
+    tick::TickCount,
+    world::{WorldCache, WorldGenerator},
+};
 
 #[derive(CanFetch)]
 pub struct HandleIncomingPacketsSystem {
@@ -88,12 +185,31 @@ pub struct HandleIncomingPacketsSystem {
     global_time: Read<ProgramTime>,
     terrain: Write<TerrainMap>,
     terrain_generator: Read<WorldGenerator, NoDefault>,
+    world_cache: Write<WorldCache, NoDefault>,
+    broadcast: Write<Broadcast, NoDefault>,
 }
 
 pub fn handle_incoming_packets(mut sys: HandleIncomingPacketsSystem) -> SysResult {
-    if let Ok((packet, addr)) = sys.connection.recv() {
+    // Packets keep arriving between ticks, read all of them
+    while let Ok((packet, addr)) = sys.connection.recv() {
         match packet {
-            ClientPacket::Connect => {
+            ClientPacket::Hello(hello) => {
+                if hello.version != PROTOCOL_VERSION {
+                    log::warn!(
+                        "Rejected {} ({}): protocol version {} != {}",
+                        hello.name(),
+                        addr,
+                        hello.version,
+                        PROTOCOL_VERSION
+                    );
+                    let packet = ServerPacket::VersionMismatch {
+                        server_version: PROTOCOL_VERSION,
+                    };
+                    if let Err(e) = sys.connection.send_to(packet, addr) {
+                        log::error!("Failed to send version mismatch packet to client: {:?}", e);
+                    }
+                    continue;
+                }
                 let mut client = sys.entities.create();
                 let uid = sys.entity_map.insert_entity(client.clone());
 
@@ -102,14 +218,18 @@ pub fn handle_incoming_packets(mut sys: HandleIncomingPacketsSystem) -> SysResul
                     last_ping: sys.global_time.0,
                 };
 
-                client.insert_bundle((uid, remote));
+                client.insert_bundle((uid, remote, Pos(SPAWN_POS)));
+                sys.broadcast.register(uid.0, addr);
 
-                let sync_packet = ServerPacket::ClientSync { uid };
+                let welcome = ServerPacket::Welcome(ServerWelcome {
+                    player_id: uid.0,
+                    spawn_pos: SPAWN_POS,
+                });
 
-                if let Err(e) = sys.connection.send_to(sync_packet, addr) {
-                    log::error!("Failed to send sync packet to client: {:?}", e);
+                if let Err(e) = sys.connection.send_to(welcome, addr) {
+                    log::error!("Failed to send welcome packet to client: {:?}", e);
                 }
-                info!("New client connected.");
+                info!("{} connected.", hello.name());
             },
             ClientPacket::Disconnect => {
                 // TODO: send server event
@@ -126,23 +246,28 @@ pub fn handle_incoming_packets(mut sys: HandleIncomingPacketsSystem) -> SysResul
                 PingPacket::Pong => {},
             },
 
-            ClientPacket::ChunkRequest(pos) => match sys.terrain.chunks.get(&pos) {
-                Some(t) => {
-                    let c = common::chunk::compress(t);
-                    let packet = ServerPacket::ChunkUpdate { pos, data: c };
-                    if let Err(e) = sys.connection.send_to(packet, addr) {
-                        log::error!("Failed to send chunk update packet to client: {:?}", e);
-                    }
-                },
-                None => {
-                    let chunk = sys.terrain_generator.generate_chunk(pos);
-                    let c = common::chunk::compress(&chunk);
-                    let packet = ServerPacket::ChunkUpdate { pos, data: c };
-                    sys.terrain.chunks.insert(pos, chunk);
-                    if let Err(e) = sys.connection.send_to(packet, addr) {
-                        log::error!("Failed to send chunk update packet to client: {:?}", e);
-                    }
-                },
+            ClientPacket::ChunkRequest(pos) => {
+                match sys.terrain.chunks.get(&pos).and_then(ChunkColumn::ground) {
+                    Some(t) => {
+                        let c = common::chunk::compress(t);
+                        let packet = ServerPacket::ChunkUpdate { pos, data: c };
+                        if let Err(e) = sys.connection.send_to(packet, addr) {
+                            log::error!("Failed to send chunk update packet to client: {:?}", e);
+                        }
+                    },
+                    None => {
+                        let chunk = sys
+                            .world_cache
+                            .get_or_generate(pos, &sys.terrain_generator)
+                            .clone();
+                        let c = common::chunk::compress(&chunk);
+                        let packet = ServerPacket::ChunkUpdate { pos, data: c };
+                        sys.terrain.chunks.insert(pos, chunk.into());
+                        if let Err(e) = sys.connection.send_to(packet, addr) {
+                            log::error!("Failed to send chunk update packet to client: {:?}", e);
+                        }
+                    },
+                }
             },
         }
     }
@@ -165,10 +290,62 @@ pub fn handle_client_ping(mut sys: HandleClientPing) -> SysResult {
         let delta = sys.global_time.0 - client.last_ping;
 
         if delta > sys.config.timeout as f64 {
-            log::info!("Client {} timed out.", uid.0);
+            log::info!("Client {} ({}) timed out.", uid.0, client.addr);
             sys.events.send(ServerEvent::ClientDisconnect(**uid));
         }
         // TODO: maybe try pinging if timeout is getting close ?
     }
     ok()
 }
+
+#[derive(CanFetch)]
+pub struct SendPlayerStates {
+    connection: Read<ServerConnection, NoDefault>,
+    players: Query<(&'static Pos, &'static RemoteClient)>,
+    tick: Write<TickCount>,
+}
+
+/// Sends every client the state of its player at the end of the tick,
+/// the client moves its player between the two latest ones.
+pub fn send_player_states(mut sys: SendPlayerStates) -> SysResult {
+    sys.tick.0 += 1;
+    let mut query = sys.players.query();
+    for (pos, client) in query.iter_mut() {
+        let state = PlayerState {
+            tick: sys.tick.0,
+            pos: pos.0,
+        };
+        if let Err(e) = sys
+            .connection
+            .send_to(ServerPacket::PlayerState(state), client.addr)
+        {
+            log::error!("Failed to send player state to client: {:?}", e);
+        }
+    }
+    ok()
+}
+
+#[derive(CanFetch)]
+pub struct SendChunkUpdates {
+    connection: Read<ServerConnection, NoDefault>,
+    terrain: Write<TerrainMap>,
+    broadcast: Read<Broadcast, NoDefault>,
+}
+
+/// Sends the chunks that changed this tick, e.g. because they were re-lit, to every client.
+pub fn send_chunk_updates(mut sys: SendChunkUpdates) -> SysResult {
+    for pos in std::mem::take(&mut sys.terrain.dirty) {
+        let Some(chunk) = sys.terrain.chunks.get(&pos).and_then(ChunkColumn::ground) else {
+            continue;
+        };
+        let packet = ServerPacket::ChunkUpdate {
+            pos,
+            data: common::chunk::compress(chunk),
+        };
+        sys.broadcast.send_to_all(
+            sys.connection.socket(),
+            &ServerConnection::serialize(&packet),
+        );
+    }
+    ok()
+}
@@ -0,0 +1,86 @@
+use apecs::*;
+use common::{event::BlockUpdateEvent, event::Events, resources::TerrainMap, SysResult};
+
+#[derive(CanFetch)]
+pub struct LightingSystem {
+    events: Read<Events<BlockUpdateEvent>>,
+    terrain: Write<TerrainMap>,
+}
+
+/// Recomputes the lighting around every block that was placed or removed this tick.
+///
+/// The re-lit chunks are marked dirty, so they are sent to the clients.
+pub fn lighting_system(mut sys: LightingSystem) -> SysResult {
+    for event in &sys.events.events {
+        // Block light reaches across chunk borders, so the neighbours are re-lit too
+        sys.terrain
+            .relight_around(TerrainMap::chunk_pos(event.world_pos));
+    }
+    ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        block::BlockId,
+        chunk::StandardChunk,
+        event::{BlockUpdateEvent, Events},
+        light::MAX_LIGHT,
+        resources::TerrainMap,
+    };
+    use vek::{Vec2, Vec3};
+
+    use super::lighting_system;
+
+    #[test]
+    pub fn removing_surface_block_lights_block_below() {
+        let mut terrain = TerrainMap::default();
+        for pos in [Vec2::zero(), Vec2::unit_x()] {
+            let mut chunk = StandardChunk::flat(BlockId::Air);
+            for pos in chunk.iter() {
+                if pos.y <= 4 {
+                    chunk.set(pos, BlockId::Stone);
+                }
+            }
+            terrain.chunks.insert(pos, chunk.into());
+            terrain.relight(pos);
+        }
+        let below = Vec3::new(8, 3, 8);
+        assert_eq!(terrain.light_at(below), Some(0));
+        let surface = Vec3::new(8, 4, 8);
+        terrain.set_block(surface, BlockId::Air);
+        // Buried next to the border with the east chunk
+        let torch = Vec3::new(15, 3, 8);
+        terrain.set_block(torch, BlockId::Torch);
+        terrain.dirty.clear();
+
+        let mut events = Events::default();
+        events.send(BlockUpdateEvent {
+            world_pos: surface,
+            old_id: BlockId::Stone,
+            new_id: BlockId::Air,
+        });
+        events.send(BlockUpdateEvent {
+            world_pos: torch,
+            old_id: BlockId::Stone,
+            new_id: BlockId::Torch,
+        });
+        let mut world = apecs::World::default();
+        world
+            .with_resource(terrain)
+            .unwrap()
+            .with_resource(events)
+            .unwrap()
+            .with_system("lighting", lighting_system)
+            .unwrap();
+        world.tick().unwrap();
+
+        let terrain = world.resource::<TerrainMap>().unwrap();
+        assert_eq!(terrain.light_at(below), Some(MAX_LIGHT));
+        assert_eq!(terrain.light_at(Vec3::new(16, 3, 8)), Some(13));
+        // Only loaded chunks are re-lit and sent again
+        let mut dirty = terrain.dirty.iter().copied().collect::<Vec<_>>();
+        dirty.sort_by_key(|pos| (pos.x, pos.y));
+        assert_eq!(dirty, vec![Vec2::zero(), Vec2::unit_x()]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    io::{Read as _, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use apecs::*;
+use common::{resources::TerrainMap, SysResult};
+
+/// Serves the server metrics in the Prometheus text format to any HTTP request.
+///
+/// The requests are answered on a thread of their own, with the metrics last published by
+/// the server tick, so a slow client never holds up the tick.
+pub struct MetricsExporter {
+    addr: SocketAddr,
+    metrics: Arc<Mutex<String>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsExporter {
+    pub fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let metrics = Arc::new(Mutex::new(String::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let metrics = Arc::clone(&metrics);
+            let stopped = Arc::clone(&stopped);
+            std::thread::Builder::new()
+                .name("metrics".to_owned())
+                .spawn(move || serve(listener, &metrics, &stopped))?
+        };
+        log::info!("Serving metrics on http://{}/metrics", addr);
+        Ok(Self {
+            addr,
+            metrics,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    /// Replaces the metrics sent to the next requests with the current ones.
+    pub fn publish(&self, terrain: &TerrainMap) {
+        let body = render(terrain);
+        *self.metrics.lock().unwrap() = body;
+    }
+}
+
+impl Drop for MetricsExporter {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wakes the thread up from accepting connections
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers every connection to `listener` with the `metrics`, until `stopped` is set.
+fn serve(listener: TcpListener, metrics: &Mutex<String>, stopped: &AtomicBool) {
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::error!("Failed to accept metrics connection: {:?}", e);
+                continue;
+            },
+        };
+        let body = metrics.lock().unwrap().clone();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        // Whatever the request is, it is read so closing the socket doesn't reset it
+        let mut request = [0; 1024];
+        let result = stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .and_then(|_| stream.read(&mut request))
+            .and_then(|_| stream.write_all(response.as_bytes()));
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to send metrics to {:?}: {:?}",
+                stream.peer_addr(),
+                e
+            );
+        }
+    }
+}
+
+/// Formats the terrain statistics in the Prometheus text format.
+pub fn render(terrain: &TerrainMap) -> String {
+    let gauges = [
+        (
+            "explora_loaded_chunks",
+            "Number of chunks loaded by the server.",
+            terrain.loaded_chunk_count() as u64,
+        ),
+        (
+            "explora_loaded_blocks",
+            "Number of blocks stored by the loaded chunks.",
+            terrain.total_block_count(),
+        ),
+        (
+            "explora_terrain_memory_bytes",
+            "Estimated memory used by the loaded chunks.",
+            terrain.memory_estimate_bytes(),
+        ),
+    ];
+    let mut output = String::new();
+    for (name, help, value) in gauges {
+        output.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+        ));
+    }
+    output
+}
+
+#[derive(CanFetch)]
+pub struct MetricsSystem {
+    exporter: Read<MetricsExporter, NoDefault>,
+    terrain: Read<TerrainMap>,
+}
+
+pub fn metrics_system(sys: MetricsSystem) -> SysResult {
+    sys.exporter.publish(&sys.terrain);
+    ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use common::{block::BlockId, chunk::StandardChunk, resources::TerrainMap};
+    use vek::Vec2;
+
+    use super::{render, MetricsExporter};
+
+    fn terrain(chunks: i32) -> TerrainMap {
+        let mut terrain = TerrainMap::default();
+        for x in 0..chunks {
+            terrain
+                .chunks
+                .insert(Vec2::new(x, 0), StandardChunk::flat(BlockId::Air).into());
+        }
+        terrain
+    }
+
+    #[test]
+    pub fn metrics_are_formatted_as_gauges() {
+        let output = render(&terrain(4));
+        assert!(output.contains("# TYPE explora_loaded_chunks gauge\nexplora_loaded_chunks 4\n"));
+        assert!(output.contains(&format!("explora_loaded_blocks {}\n", 4 * 16 * 256 * 16)));
+        assert_eq!(output.lines().filter(|l| !l.starts_with('#')).count(), 3);
+    }
+
+    #[test]
+    pub fn requests_are_answered_with_the_metrics() {
+        let exporter = MetricsExporter::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let terrain = terrain(2);
+        exporter.publish(&terrain);
+        let mut stream = TcpStream::connect(exporter.local_addr().unwrap()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&render(&terrain)));
+    }
+}
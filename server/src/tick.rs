@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+pub use common::clock::{TICK_DURATION, TICK_RATE};
+
+/// The number of ticks the server has run, stamped on the snapshots it sends.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TickCount(pub u64);
+
+/// Decides when the server should update the world, at a fixed [`TICK_RATE`]
+/// regardless of how often the main loop runs.
+///
+/// The time elapsed since the last update is accumulated, and every [`TICK_DURATION`]
+/// of it is one pending tick. A slow loop catches up by running several ticks in a row.
+pub struct TickScheduler {
+    last_update: Instant,
+    accumulated: Duration,
+}
+
+impl Default for TickScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TickScheduler {
+    pub fn new() -> Self {
+        Self {
+            last_update: Instant::now(),
+            accumulated: Duration::ZERO,
+        }
+    }
+
+    /// Adds the time elapsed since the last call.
+    pub fn update(&mut self) {
+        self.update_at(Instant::now());
+    }
+
+    /// Adds the time elapsed between the last update and `now`.
+    fn update_at(&mut self, now: Instant) {
+        self.accumulated += now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+    }
+
+    /// Number of whole ticks in the accumulated time, including the time elapsed
+    /// since the last [`TickScheduler::update`].
+    pub fn pending_ticks(&self) -> u32 {
+        self.pending_ticks_at(Instant::now())
+    }
+
+    fn pending_ticks_at(&self, now: Instant) -> u32 {
+        let accumulated = self.accumulated + now.saturating_duration_since(self.last_update);
+        (accumulated.as_nanos() / TICK_DURATION.as_nanos()) as u32
+    }
+
+    /// Removes one tick from the accumulated time, returning whether there was one to run.
+    pub fn consume_tick(&mut self) -> bool {
+        self.update();
+        if self.accumulated < TICK_DURATION {
+            return false;
+        }
+        self.accumulated -= TICK_DURATION;
+        true
+    }
+
+    /// How far we are into the next tick, between 0 and 1.
+    pub fn alpha(&self) -> f32 {
+        let partial = self.accumulated.as_nanos() % TICK_DURATION.as_nanos();
+        partial as f32 / TICK_DURATION.as_nanos() as f32
+    }
+
+    /// The time left until the next tick is due.
+    pub fn time_until_next_tick(&self) -> Duration {
+        TICK_DURATION.saturating_sub(self.accumulated + self.last_update.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{TickScheduler, TICK_DURATION};
+
+    #[test]
+    pub fn three_ticks_after_150_ms() {
+        let mut scheduler = TickScheduler::new();
+        let start = scheduler.last_update;
+        scheduler.update_at(start + Duration::from_millis(149));
+        assert_eq!(
+            scheduler.pending_ticks_at(start + Duration::from_millis(149)),
+            2
+        );
+        scheduler.update_at(start + Duration::from_millis(150));
+        assert_eq!(
+            scheduler.pending_ticks_at(start + Duration::from_millis(150)),
+            3
+        );
+    }
+
+    #[test]
+    pub fn consuming_ticks_keeps_the_remainder() {
+        let mut scheduler = TickScheduler::new();
+        scheduler.accumulated = TICK_DURATION * 2 + TICK_DURATION / 2;
+        assert!(scheduler.consume_tick());
+        assert!(scheduler.consume_tick());
+        assert!(!scheduler.consume_tick());
+        assert!(scheduler.alpha() >= 0.5 && scheduler.alpha() < 1.0);
+    }
+}
@@ -1,21 +1,675 @@
-use common::chunk::Chunk;
+use std::collections::HashMap;
 
-use noise::{BasicMulti, Perlin};
-use vek::Vec2;
+use common::{
+    block::BlockId,
+    chunk::{self, Chunk, StandardChunk},
+};
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use noise::{BasicMulti, Fbm, MultiFractal, NoiseFn, Perlin, ScalePoint, Seedable, Turbulence};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use vek::{Vec2, Vec3};
+
+use crate::biome::Biome;
+
+/// Maximum number of blocks placed by a single vein.
+const VEIN_SIZE: u32 = 8;
+/// Size in blocks of the features of the climate noise.
+const CLIMATE_SCALE: f64 = 600.0;
+/// How many times the turbulence changes direction per unit of height noise.
+const TURBULENCE_FREQUENCY: f64 = 4.0;
+/// Distance the height noise is distorted at a turbulence strength of 1, in noise units.
+const TURBULENCE_POWER: f64 = 0.25;
+/// Size in blocks of the ridges and trenches of the ocean floor.
+const OCEAN_FLOOR_SCALE: f64 = 200.0;
+/// How far the ocean floor is raised or lowered from the terrain, in blocks.
+const OCEAN_FLOOR_AMPLITUDE: f64 = 8.0;
+/// Number of sand or gravel blocks covering the ocean floor.
+const SEABED_DEPTH: i32 = 3;
+/// Below this many blocks of water the seabed is gravel instead of sand.
+const GRAVEL_DEPTH: i32 = 24;
+/// The colour of the columns below sea level on map previews.
+const OCEAN_COLOUR: [u8; 3] = [40, 80, 170];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenConfig {
+    /// The height of the ocean surface. Columns below it are filled with water.
+    pub sea_level: u8,
+    /// How much the terrain is distorted, 0 disables the turbulence.
+    pub turbulence_strength: f64,
+    /// Scale applied to the coordinates of the height noise.
+    /// Larger values produce smaller hills.
+    pub scale: f64,
+    /// Surface height of the lowest valleys.
+    pub min_height: i32,
+    /// Surface height of the highest mountains.
+    pub max_height: i32,
+    /// The height of the generated chunks. Only [`StandardChunk::HEIGHT`] is
+    /// supported by the terrain and the network protocol for now.
+    pub chunk_height: usize,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        Self {
+            sea_level: 64,
+            turbulence_strength: 1.0,
+            scale: 1.0,
+            min_height: 0,
+            max_height: 256,
+            chunk_height: StandardChunk::HEIGHT,
+        }
+    }
+}
+
+/// The noise the surface height is sampled from.
+type HeightNoise = ScalePoint<Turbulence<BasicMulti<Perlin>, Perlin>>;
 
 pub struct WorldGenerator {
-    gen: BasicMulti<Perlin>,
+    seed: u64,
+    config: WorldGenConfig,
+    gen: HeightNoise,
+    temperature: Fbm<Perlin>,
+    moisture: Fbm<Perlin>,
+    ocean_floor: Fbm<Perlin>,
+}
+
+/// Creates a deterministic RNG for the chunk at `chunk_pos`.
+///
+/// The seed and chunk position are mixed together so the sequences of
+/// neighbouring chunks are unrelated to each other.
+pub fn chunk_rng(master_seed: u64, chunk_pos: Vec2<i32>) -> SmallRng {
+    let mut hash = mix(master_seed);
+    hash = mix(hash ^ chunk_pos.x as u32 as u64);
+    hash = mix(hash ^ ((chunk_pos.y as u32 as u64) << 32));
+    SmallRng::seed_from_u64(hash)
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl Default for WorldGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WorldGenerator {
+    pub const DEFAULT_SEED: u64 = 88;
+
     pub fn new() -> Self {
+        Self::with_seed(Self::DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_config(seed, WorldGenConfig::default())
+    }
+
+    pub fn with_config(seed: u64, config: WorldGenConfig) -> Self {
+        // The other layers use their own seeds so they are independent from the terrain
         Self {
-            gen: BasicMulti::new(88),
+            seed,
+            gen: Self::height_noise(seed, &config),
+            temperature: Fbm::<Perlin>::new(seed.wrapping_add(1) as u32).set_octaves(3),
+            moisture: Fbm::<Perlin>::new(seed.wrapping_add(2) as u32).set_octaves(3),
+            ocean_floor: Fbm::<Perlin>::new(seed.wrapping_add(3) as u32).set_octaves(2),
+            config,
         }
     }
 
+    fn height_noise(seed: u64, config: &WorldGenConfig) -> HeightNoise {
+        let turbulence = Turbulence::new(BasicMulti::new(seed as u32))
+            .set_seed(seed.wrapping_add(4) as u32)
+            .set_frequency(TURBULENCE_FREQUENCY)
+            .set_power(config.turbulence_strength * TURBULENCE_POWER);
+        let scale = config.scale;
+        ScalePoint::new(turbulence).set_all_scales(scale, scale, scale, scale)
+    }
+
+    pub fn config(&self) -> &WorldGenConfig {
+        &self.config
+    }
+
+    /// Temperature at the given world column, in the range [-1, 1].
+    pub fn temperature_at(&self, x: i32, z: i32) -> f64 {
+        let value = self
+            .temperature
+            .get([x as f64 / CLIMATE_SCALE, z as f64 / CLIMATE_SCALE]);
+        value.clamp(-1.0, 1.0)
+    }
+
+    /// Moisture at the given world column, in the range [-1, 1].
+    pub fn moisture_at(&self, x: i32, z: i32) -> f64 {
+        let value = self
+            .moisture
+            .get([x as f64 / CLIMATE_SCALE, z as f64 / CLIMATE_SCALE]);
+        value.clamp(-1.0, 1.0)
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        Biome::select(self.temperature_at(x, z), self.moisture_at(x, z))
+    }
+
+    /// Surface height of the bare terrain at the given world column, without generating its chunk.
+    pub fn surface_height_at(&self, x: i32, z: i32) -> i32 {
+        chunk::column_height(
+            &self.gen,
+            x,
+            z,
+            self.config.min_height,
+            self.config.max_height,
+        )
+    }
+
+    /// Renders the terrain height of the columns from `min` included to `max` excluded,
+    /// where `y` is the z axis, from black at `min_height` to white at `max_height`.
+    ///
+    /// Only the height noise is evaluated, no chunk is generated.
+    pub fn preview_heightmap(&self, min: Vec2<i32>, max: Vec2<i32>) -> GrayImage {
+        let size = (max - min).map(|x| x.max(0) as u32);
+        GrayImage::from_fn(size.x, size.y, |x, z| {
+            let height = self.surface_height_at(min.x + x as i32, min.y + z as i32);
+            Luma([(self.height_fraction(height) * 255.0).round() as u8])
+        })
+    }
+
+    /// Like [`WorldGenerator::preview_heightmap`], coloured by biome with the oceans in blue.
+    /// Higher columns are brighter.
+    pub fn preview_heightmap_colour(&self, min: Vec2<i32>, max: Vec2<i32>) -> RgbaImage {
+        let size = (max - min).map(|x| x.max(0) as u32);
+        let sea_level = self.config.sea_level as i32;
+        RgbaImage::from_fn(size.x, size.y, |x, z| {
+            let (x, z) = (min.x + x as i32, min.y + z as i32);
+            let height = self.surface_height_at(x, z);
+            let colour = if height < sea_level {
+                OCEAN_COLOUR
+            } else {
+                self.biome_at(x, z).map_colour()
+            };
+            let brightness = 0.5 + 0.5 * self.height_fraction(height);
+            let [r, g, b] = colour.map(|c| (c as f64 * brightness).round() as u8);
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// Where `height` lies between the lowest and the highest surface, from 0 to 1.
+    fn height_fraction(&self, height: i32) -> f64 {
+        let range = (self.config.max_height - self.config.min_height).max(1) as f64;
+        ((height - self.config.min_height) as f64 / range).clamp(0.0, 1.0)
+    }
+
     pub fn generate_chunk(&self, offset: Vec2<i32>) -> Chunk {
-        Chunk::generate(&self.gen, offset)
-        // Chunk::flat(common::block::BlockId::Dirt)
+        let mut chunk = self.generate_terrain(offset);
+        self.apply_biomes(&mut chunk, offset);
+        self.fill_oceans(&mut chunk, offset);
+        self.finish_chunk(&mut chunk, offset);
+        chunk
+    }
+
+    /// Generates the same chunk as [`WorldGenerator::generate_chunk`], spreading the
+    /// columns of the chunk across the rayon thread pool.
+    pub fn generate_chunk_par(&self, offset: Vec2<i32>) -> Chunk {
+        let size = StandardChunk::SIZE.map(|x| x as i32);
+        let columns: Vec<_> = (0..size.x * size.z)
+            .into_par_iter()
+            .map(|idx| {
+                let world =
+                    offset * Vec2::new(size.x, size.z) + Vec2::new(idx % size.x, idx / size.x);
+                let mut column = StandardChunk::generate_column(
+                    &self.gen,
+                    world.x,
+                    world.y,
+                    self.config.min_height,
+                    self.config.max_height,
+                );
+                self.apply_biome(&mut column, world);
+                self.fill_ocean(&mut column, world);
+                column
+            })
+            .collect();
+
+        let mut chunk = StandardChunk::flat(BlockId::Air);
+        for (idx, column) in columns.iter().enumerate() {
+            let idx = idx as i32;
+            chunk.set_column(idx % size.x, idx / size.x, column);
+        }
+        // The veins share one RNG sequence, so they are placed after the columns
+        self.finish_chunk(&mut chunk, offset);
+        chunk
+    }
+
+    /// Places the ores of the biome at the center of the chunk.
+    fn finish_chunk(&self, chunk: &mut Chunk, offset: Vec2<i32>) {
+        let size = StandardChunk::SIZE.map(|x| x as i32);
+        let center = offset * Vec2::new(size.x, size.z) + Vec2::new(size.x, size.z) / 2;
+        let biome = self.biome_at(center.x, center.y);
+        let mut rng = chunk_rng(self.seed, offset);
+        Self::place_veins(chunk, &mut rng, biome.veins_per_chunk());
+    }
+
+    /// Generates the bare terrain of a chunk, before biomes, oceans and ores.
+    pub fn generate_terrain(&self, offset: Vec2<i32>) -> Chunk {
+        StandardChunk::generate(
+            &self.gen,
+            offset,
+            self.config.min_height,
+            self.config.max_height,
+        )
+    }
+
+    /// Replaces the surface of every column with the block of its biome.
+    fn apply_biomes(&self, chunk: &mut Chunk, offset: Vec2<i32>) {
+        self.for_each_column(chunk, offset, Self::apply_biome);
+    }
+
+    /// Replaces the surface of the column at the world position `world` with the block of its biome.
+    fn apply_biome(&self, column: &mut [BlockId], world: Vec2<i32>) {
+        if let Some(surface) = column.iter_mut().rev().find(|id| **id == BlockId::Grass) {
+            *surface = self.biome_at(world.x, world.y).surface_block();
+        }
+    }
+
+    /// Reshapes the columns below sea level into an ocean floor covered with water.
+    fn fill_oceans(&self, chunk: &mut Chunk, offset: Vec2<i32>) {
+        self.for_each_column(chunk, offset, Self::fill_ocean);
+    }
+
+    /// Reshapes the column at the world position `world` into an ocean floor covered with
+    /// water if it is below sea level.
+    ///
+    /// A coarse noise raises or lowers the floor from the terrain height,
+    /// forming gradual ridges and trenches.
+    fn fill_ocean(&self, column: &mut [BlockId], world: Vec2<i32>) {
+        let sea_level = self.config.sea_level as i32;
+        let height = column
+            .iter()
+            .rposition(|id| !id.is_air())
+            .map_or(0, |y| y as i32);
+        if height >= sea_level {
+            return;
+        }
+        let noise = self.ocean_floor.get([
+            world.x as f64 / OCEAN_FLOOR_SCALE,
+            world.y as f64 / OCEAN_FLOOR_SCALE,
+        ]);
+        let floor = (height + (noise * OCEAN_FLOOR_AMPLITUDE) as i32).clamp(0, sea_level - 1);
+        let seabed = if sea_level - floor > GRAVEL_DEPTH {
+            BlockId::Gravel
+        } else {
+            BlockId::Sand
+        };
+        let mut set = |y: i32, id: BlockId| {
+            if let Some(block) = column.get_mut(y as usize) {
+                *block = id;
+            }
+        };
+        // Ridges raise the floor above the terrain
+        for y in height + 1..=floor {
+            set(y, BlockId::Stone);
+        }
+        for y in (floor - SEABED_DEPTH + 1).max(0)..=floor {
+            set(y, seabed);
+        }
+        for y in floor + 1..=sea_level {
+            set(y, BlockId::Water);
+        }
+    }
+
+    /// Runs `f` on every column of the chunk at `offset`, along with its world position.
+    fn for_each_column<F>(&self, chunk: &mut Chunk, offset: Vec2<i32>, f: F)
+    where
+        F: Fn(&Self, &mut [BlockId], Vec2<i32>),
+    {
+        let size = StandardChunk::SIZE.map(|x| x as i32);
+        for x in 0..size.x {
+            for z in 0..size.z {
+                let Some(mut column) = chunk.column(x, z) else {
+                    continue;
+                };
+                f(
+                    self,
+                    &mut column,
+                    offset * Vec2::new(size.x, size.z) + Vec2::new(x, z),
+                );
+                chunk.set_column(x, z, &column);
+            }
+        }
+    }
+
+    /// Scatters small dirt veins through the stone layer.
+    fn place_veins(chunk: &mut Chunk, rng: &mut SmallRng, count: u32) {
+        let size = StandardChunk::SIZE.map(|x| x as i32);
+        for _ in 0..count {
+            let mut pos = Vec3::new(
+                rng.gen_range(0..size.x),
+                rng.gen_range(0..size.y),
+                rng.gen_range(0..size.z),
+            );
+            for _ in 0..VEIN_SIZE {
+                if chunk.get(pos) == Some(BlockId::Stone) {
+                    chunk.set(pos, BlockId::Dirt);
+                }
+                // Random walk to the next block of the vein
+                pos[rng.gen_range(0..3)] += if rng.gen_bool(0.5) { 1 } else { -1 };
+            }
+        }
+    }
+}
+
+/// Keeps the most recently used generated chunks so they are never generated twice
+/// while they stay in memory.
+pub struct WorldCache {
+    chunks: HashMap<Vec2<i32>, (Chunk, u64)>,
+    max_chunks: usize,
+    /// Incremented on every access, used to find the least recently used chunk.
+    clock: u64,
+    evicted: usize,
+}
+
+impl WorldCache {
+    pub fn new(max_chunks: usize) -> Self {
+        Self {
+            chunks: HashMap::new(),
+            max_chunks: max_chunks.max(1),
+            clock: 0,
+            evicted: 0,
+        }
+    }
+
+    pub fn get_or_generate(&mut self, pos: Vec2<i32>, gen: &WorldGenerator) -> &Chunk {
+        self.get_or_insert_with(pos, || gen.generate_chunk(pos))
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> Chunk>(&mut self, pos: Vec2<i32>, generate: F) -> &Chunk {
+        self.clock += 1;
+        if !self.chunks.contains_key(&pos) && self.chunks.len() >= self.max_chunks {
+            self.evict_oldest();
+        }
+        let clock = self.clock;
+        let (chunk, last_used) = self
+            .chunks
+            .entry(pos)
+            .or_insert_with(|| (generate(), clock));
+        *last_used = clock;
+        chunk
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .chunks
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(pos, _)| *pos);
+        if let Some(pos) = oldest {
+            self.chunks.remove(&pos);
+            self.evicted += 1;
+        }
+    }
+
+    /// The number of chunks evicted since the cache was created.
+    pub fn evict_count(&self) -> usize {
+        self.evicted
+    }
+
+    pub fn contains(&self, pos: Vec2<i32>) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{block::BlockId, chunk::StandardChunk};
+    use noise::{BasicMulti, Perlin, ScalePoint};
+    use rand::RngCore;
+    use vek::{Vec2, Vec3};
+
+    use super::{chunk_rng, WorldCache, WorldGenConfig, WorldGenerator};
+
+    #[test]
+    pub fn cached_chunks_are_generated_once() {
+        let mut cache = WorldCache::new(4);
+        let mut calls = 0;
+        for _ in 0..2 {
+            cache.get_or_insert_with(Vec2::zero(), || {
+                calls += 1;
+                StandardChunk::flat(BlockId::Air)
+            });
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    pub fn least_recently_used_chunk_is_evicted() {
+        let mut cache = WorldCache::new(2);
+        let generate = || StandardChunk::flat(BlockId::Air);
+        cache.get_or_insert_with(Vec2::new(0, 0), generate);
+        cache.get_or_insert_with(Vec2::new(1, 0), generate);
+        // Touch the first chunk so the second one becomes the oldest
+        cache.get_or_insert_with(Vec2::new(0, 0), generate);
+        cache.get_or_insert_with(Vec2::new(2, 0), generate);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evict_count(), 1);
+        assert!(cache.contains(Vec2::new(0, 0)));
+        assert!(!cache.contains(Vec2::new(1, 0)));
+        assert!(cache.contains(Vec2::new(2, 0)));
+    }
+
+    fn sequence(seed: u64, pos: Vec2<i32>) -> Vec<u64> {
+        let mut rng = chunk_rng(seed, pos);
+        (0..8).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    pub fn adjacent_chunks_have_different_sequences() {
+        let origin = sequence(88, Vec2::zero());
+        for pos in [
+            Vec2::new(1, 0),
+            Vec2::new(-1, 0),
+            Vec2::new(0, 1),
+            Vec2::new(0, -1),
+        ] {
+            assert_ne!(origin, sequence(88, pos));
+        }
+        // Swapping the axes must not produce the same chunk either
+        assert_ne!(sequence(88, Vec2::new(1, 2)), sequence(88, Vec2::new(2, 1)));
+    }
+
+    #[test]
+    pub fn same_seed_produces_same_sequence() {
+        assert_eq!(sequence(88, Vec2::zero()), sequence(88, Vec2::zero()));
+        assert_ne!(sequence(88, Vec2::zero()), sequence(89, Vec2::zero()));
+    }
+
+    #[test]
+    pub fn columns_below_sea_level_are_filled_with_water() {
+        // A high sea level puts most of the terrain under water
+        let sea_level = 200;
+        let generator = WorldGenerator::with_config(
+            88,
+            WorldGenConfig {
+                sea_level,
+                ..Default::default()
+            },
+        );
+        let sea_level = sea_level as i32;
+        let terrain = generator.generate_terrain(Vec2::zero());
+        let chunk = generator.generate_chunk(Vec2::zero());
+
+        let mut ocean_columns = 0;
+        for x in 0..16 {
+            for z in 0..16 {
+                let height = terrain.surface_height(x, z).unwrap_or(0);
+                if height >= sea_level {
+                    continue;
+                }
+                ocean_columns += 1;
+                assert_eq!(chunk.surface_height(x, z), Some(sea_level));
+                // Water goes down from the sea level to the floor, which is sand or gravel
+                let floor = (0..=sea_level)
+                    .rev()
+                    .find(|y| chunk.get(Vec3::new(x, *y, z)) != Some(BlockId::Water))
+                    .unwrap();
+                assert!(floor < sea_level);
+                assert!(matches!(
+                    chunk.get(Vec3::new(x, floor, z)),
+                    Some(BlockId::Sand | BlockId::Gravel)
+                ));
+                assert_eq!(
+                    chunk.get(Vec3::new(x, sea_level + 1, z)),
+                    Some(BlockId::Air)
+                );
+            }
+        }
+        assert!(ocean_columns > 0, "Seed 88 has no ocean at the origin");
+    }
+
+    #[test]
+    pub fn parallel_generation_matches_serial() {
+        let generator = WorldGenerator::with_seed(88);
+        let offset = Vec2::new(5, 5);
+        let serial = generator.generate_chunk(offset);
+        let parallel = generator.generate_chunk_par(offset);
+        for x in 0..16 {
+            for z in 0..16 {
+                assert_eq!(serial.column(x, z), parallel.column(x, z));
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    pub fn bench_generate_100_chunks() {
+        let generator = WorldGenerator::new();
+        let offsets: Vec<_> = (0..100).map(|i| Vec2::new(i % 10, i / 10)).collect();
+
+        let start = std::time::Instant::now();
+        for offset in &offsets {
+            generator.generate_chunk(*offset);
+        }
+        let serial = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for offset in &offsets {
+            generator.generate_chunk_par(*offset);
+        }
+        let parallel = start.elapsed();
+        eprintln!(
+            "100 chunks: serial {:?} ({:?}/chunk), parallel {:?} ({:?}/chunk)",
+            serial,
+            serial / 100,
+            parallel,
+            parallel / 100
+        );
+    }
+
+    fn with_turbulence(turbulence_strength: f64) -> WorldGenerator {
+        WorldGenerator::with_config(
+            88,
+            WorldGenConfig {
+                turbulence_strength,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn surface_heights(generator: &WorldGenerator) -> Vec<f64> {
+        let mut heights = Vec::new();
+        for cx in 0..4 {
+            for cz in 0..4 {
+                let chunk = generator.generate_terrain(Vec2::new(cx, cz));
+                for x in 0..16 {
+                    for z in 0..16 {
+                        heights.push(chunk.surface_height(x, z).unwrap_or(0) as f64);
+                    }
+                }
+            }
+        }
+        heights
+    }
+
+    /// The mean height difference between neighbouring columns along z.
+    fn roughness(heights: &[f64]) -> f64 {
+        let steps = heights
+            .chunks(16)
+            .flat_map(|column| column.windows(2).map(|pair| (pair[1] - pair[0]).abs()))
+            .collect::<Vec<_>>();
+        steps.iter().sum::<f64>() / steps.len() as f64
+    }
+
+    #[test]
+    pub fn zero_turbulence_matches_plain_noise() {
+        let config = WorldGenConfig::default();
+        let plain =
+            ScalePoint::new(BasicMulti::<Perlin>::new(88)).set_all_scales(1.0, 1.0, 1.0, 1.0);
+        let generator = with_turbulence(0.0);
+        for pos in [Vec2::new(0, 0), Vec2::new(5, -3)] {
+            let expected =
+                StandardChunk::generate(&plain, pos, config.min_height, config.max_height);
+            let terrain = generator.generate_terrain(pos);
+            for x in 0..16 {
+                for z in 0..16 {
+                    assert_eq!(terrain.surface_height(x, z), expected.surface_height(x, z));
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn turbulence_makes_terrain_rougher() {
+        let smooth = roughness(&surface_heights(&with_turbulence(0.0)));
+        let rough = roughness(&surface_heights(&with_turbulence(1.0)));
+        assert!(
+            rough >= smooth * 2.0,
+            "Roughness went from {} to {}",
+            smooth,
+            rough
+        );
+    }
+
+    #[test]
+    pub fn heightmap_preview_covers_the_region() {
+        let generator = WorldGenerator::new();
+        let (min, max) = (Vec2::new(-20, 5), Vec2::new(44, 37));
+        let gray = generator.preview_heightmap(min, max);
+        assert_eq!(gray.dimensions(), (64, 32));
+        let colour = generator.preview_heightmap_colour(min, max);
+        assert_eq!(colour.dimensions(), (64, 32));
+        // An inverted region is empty
+        assert_eq!(generator.preview_heightmap(max, min).dimensions(), (0, 0));
+    }
+
+    #[test]
+    pub fn heightmap_preview_matches_generated_terrain() {
+        let generator = WorldGenerator::new();
+        let config = generator.config();
+        let terrain = generator.generate_terrain(Vec2::new(1, -1));
+        let preview = generator.preview_heightmap(Vec2::new(16, -16), Vec2::new(32, 0));
+        let range = (config.max_height - config.min_height) as f64;
+        for x in 0..16 {
+            for z in 0..16 {
+                let height = terrain.surface_height(x, z).unwrap();
+                let expected = (height - config.min_height) as f64 / range * 255.0;
+                let pixel = preview.get_pixel(x as u32, z as u32).0[0];
+                assert_eq!(pixel, expected.round() as u8);
+            }
+        }
     }
 }